@@ -0,0 +1,51 @@
+## Confirmation email
+
+confirm-email-subject = Bestätigen Sie Ihr Abonnement
+confirm-email-text = Willkommen! Besuchen Sie { $link }, um Ihr Abonnement zu bestätigen.
+confirm-email-html = Willkommen! Klicken Sie <a href="{ $link }">hier</a>, um Ihr Abonnement zu bestätigen.
+
+## Issues
+
+issue-footer-text = Abmelden: { $link }
+issue-footer-html = <p>Von diesen E-Mails <a href="{ $link }">abmelden</a>.</p>
+
+## Subscribe page
+
+subscribe-title = { $list } abonnieren
+subscribe-name = Name
+subscribe-email = E-Mail
+subscribe-typo = Meinten Sie { $suggestion }?
+subscribe-keep-address = Meine Adresse stimmt
+subscribe-button = Abonnieren
+subscribe-rate-limited = Zu viele Versuche, bitte versuchen Sie es später erneut.
+
+## Problems with the fields of the subscribe form, by error code
+
+field-error-empty = Bitte füllen Sie dieses Feld aus.
+field-error-too_long = Die Eingabe ist zu lang.
+field-error-forbidden_char = Die Eingabe enthält unzulässige Zeichen.
+field-error-invalid_syntax = Das ist keine E-Mail-Adresse.
+field-error-invalid_domain = Die Domain dieser Adresse ist ungültig.
+field-error-disposable_domain = Bitte verwenden Sie eine dauerhafte Adresse, keine Wegwerfadresse.
+field-error-possible_typo = Diese Adresse enthält vielleicht einen Tippfehler.
+field-error-no_mail_server = Diese Domain empfängt keine E-Mails.
+field-error-unknown_attribute = { $field } ist kein Feld dieser Liste.
+field-error-missing_attribute = { $field } ist erforderlich.
+field-error-invalid_attribute = { $field } ist ungültig.
+field-error-invalid_tag = Tags dürfen nur Buchstaben, Ziffern, Binde- und Unterstriche enthalten.
+field-error-too_many_tags = Es sind zu viele Tags.
+
+## Other pages
+
+check-inbox-title = Sehen Sie in Ihr Postfach
+check-inbox-body = Wir haben Ihnen eine E-Mail geschickt. Folgen Sie dem Link darin, um Ihr Abonnement von { $list } zu bestätigen.
+confirmed-title = Sie sind angemeldet
+confirmed-body = Danke für die Bestätigung, Sie erhalten ab jetzt { $list }.
+invalid-token-title = Dieser Link ist ungültig
+invalid-token-body = Er ist vielleicht falsch abgetippt oder unvollständig. Melden Sie sich erneut an, um einen neuen zu erhalten.
+unsubscribe-title = { $list } abbestellen?
+unsubscribe-button = Abbestellen
+unsubscribed-title = Sie sind abgemeldet
+unsubscribed-body = Sie erhalten { $list } nicht mehr.
+not-found-title = Nicht gefunden
+not-found-body = Diese Liste gibt es nicht.
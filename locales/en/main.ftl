@@ -0,0 +1,51 @@
+## Confirmation email
+
+confirm-email-subject = Confirm your subscription
+confirm-email-text = Welcome! Visit { $link } to confirm your subscription.
+confirm-email-html = Welcome! Click <a href="{ $link }">here</a> to confirm your subscription.
+
+## Issues
+
+issue-footer-text = Unsubscribe: { $link }
+issue-footer-html = <p><a href="{ $link }">Unsubscribe</a> from these emails.</p>
+
+## Subscribe page
+
+subscribe-title = Subscribe to { $list }
+subscribe-name = Name
+subscribe-email = Email
+subscribe-typo = Did you mean { $suggestion }?
+subscribe-keep-address = My address is right
+subscribe-button = Subscribe
+subscribe-rate-limited = Too many attempts, please try again later.
+
+## Problems with the fields of the subscribe form, by error code
+
+field-error-empty = Please fill this in.
+field-error-too_long = This is too long.
+field-error-forbidden_char = This contains characters that are not allowed.
+field-error-invalid_syntax = This is not an email address.
+field-error-invalid_domain = The domain of this address is not valid.
+field-error-disposable_domain = Please use an address you keep, not a throwaway one.
+field-error-possible_typo = This address may be mistyped.
+field-error-no_mail_server = This domain does not receive email.
+field-error-unknown_attribute = { $field } is not a field of this list.
+field-error-missing_attribute = { $field } is required.
+field-error-invalid_attribute = { $field } is not valid.
+field-error-invalid_tag = Tags may only contain letters, digits, hyphens and underscores.
+field-error-too_many_tags = There are too many tags.
+
+## Other pages
+
+check-inbox-title = Check your inbox
+check-inbox-body = We sent you an email. Follow the link in it to confirm your subscription to { $list }.
+confirmed-title = You're subscribed
+confirmed-body = Thanks for confirming, you will now receive { $list }.
+invalid-token-title = This link is not valid
+invalid-token-body = It may have been mistyped or cut off. Subscribe again to get a new one.
+unsubscribe-title = Unsubscribe from { $list }?
+unsubscribe-button = Unsubscribe
+unsubscribed-title = You're unsubscribed
+unsubscribed-body = You will no longer receive { $list }.
+not-found-title = Not found
+not-found-body = There is no such list.
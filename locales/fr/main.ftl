@@ -0,0 +1,51 @@
+## Confirmation email
+
+confirm-email-subject = Confirmez votre abonnement
+confirm-email-text = Bienvenue ! Rendez-vous sur { $link } pour confirmer votre abonnement.
+confirm-email-html = Bienvenue ! Cliquez <a href="{ $link }">ici</a> pour confirmer votre abonnement.
+
+## Issues
+
+issue-footer-text = Se désabonner : { $link }
+issue-footer-html = <p><a href="{ $link }">Se désabonner</a> de ces emails.</p>
+
+## Subscribe page
+
+subscribe-title = S'abonner à { $list }
+subscribe-name = Nom
+subscribe-email = E-mail
+subscribe-typo = Vouliez-vous dire { $suggestion } ?
+subscribe-keep-address = Mon adresse est correcte
+subscribe-button = S'abonner
+subscribe-rate-limited = Trop de tentatives, veuillez réessayer plus tard.
+
+## Problems with the fields of the subscribe form, by error code
+
+field-error-empty = Veuillez remplir ce champ.
+field-error-too_long = C'est trop long.
+field-error-forbidden_char = Ce champ contient des caractères non autorisés.
+field-error-invalid_syntax = Ce n'est pas une adresse e-mail.
+field-error-invalid_domain = Le domaine de cette adresse n'est pas valide.
+field-error-disposable_domain = Veuillez utiliser une adresse durable, pas une adresse jetable.
+field-error-possible_typo = Cette adresse contient peut-être une faute de frappe.
+field-error-no_mail_server = Ce domaine ne reçoit pas d'e-mails.
+field-error-unknown_attribute = { $field } n'est pas un champ de cette liste.
+field-error-missing_attribute = { $field } est obligatoire.
+field-error-invalid_attribute = { $field } n'est pas valide.
+field-error-invalid_tag = Les étiquettes ne contiennent que lettres, chiffres, tirets et tirets bas.
+field-error-too_many_tags = Il y a trop d'étiquettes.
+
+## Other pages
+
+check-inbox-title = Consultez votre boîte de réception
+check-inbox-body = Nous vous avons envoyé un e-mail. Suivez le lien qu'il contient pour confirmer votre abonnement à { $list }.
+confirmed-title = Vous êtes abonné
+confirmed-body = Merci de votre confirmation, vous recevrez désormais { $list }.
+invalid-token-title = Ce lien n'est pas valide
+invalid-token-body = Il a peut-être été mal saisi ou tronqué. Abonnez-vous à nouveau pour en recevoir un nouveau.
+unsubscribe-title = Se désabonner de { $list } ?
+unsubscribe-button = Se désabonner
+unsubscribed-title = Vous êtes désabonné
+unsubscribed-body = Vous ne recevrez plus { $list }.
+not-found-title = Introuvable
+not-found-body = Cette liste n'existe pas.
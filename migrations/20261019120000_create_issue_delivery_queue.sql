@@ -0,0 +1,18 @@
+-- Add migration script here
+create table newsletter_issues(
+  newsletter_issue_id uuid not null,
+  primary key (newsletter_issue_id),
+  title text not null,
+  text_content text not null,
+  html_content text not null,
+  published_at timestamptz not null
+);
+create table issue_delivery_queue(
+  newsletter_issue_id uuid not null
+    references newsletter_issues (newsletter_issue_id),
+  subscriber_id uuid not null
+    references subscriptions (id),
+  n_retries integer not null default 0,
+  execute_after timestamptz not null default now(),
+  primary key (newsletter_issue_id, subscriber_id)
+);
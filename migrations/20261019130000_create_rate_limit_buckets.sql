@@ -0,0 +1,7 @@
+-- Add migration script here
+create table rate_limit_buckets(
+  key text not null,
+  primary key (key),
+  window_start timestamptz not null,
+  hits integer not null
+);
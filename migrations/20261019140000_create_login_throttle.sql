@@ -0,0 +1,17 @@
+-- Add migration script here
+create table login_throttle(
+  key text not null,
+  primary key (key),
+  failures integer not null,
+  last_failure_at timestamptz not null,
+  locked_until timestamptz
+);
+create table login_failures(
+  id uuid not null,
+  primary key (id),
+  username text not null,
+  ip text not null,
+  reason text not null,
+  attempted_at timestamptz not null
+);
+create index login_failures_attempted_at on login_failures (attempted_at);
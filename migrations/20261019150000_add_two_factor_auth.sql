@@ -0,0 +1,19 @@
+-- Add migration script here
+alter table users
+  add column totp_secret text,
+  add column totp_enabled boolean not null default false,
+  add column totp_last_step bigint;
+create table recovery_codes(
+  user_id uuid not null
+    references users (user_id) on delete cascade,
+  code_hash text not null,
+  primary key (user_id, code_hash),
+  used_at timestamptz
+);
+create table admin_settings(
+  id boolean not null default true,
+  primary key (id),
+  constraint admin_settings_single_row check (id),
+  require_two_factor boolean not null default false
+);
+insert into admin_settings default values;
@@ -0,0 +1,12 @@
+-- Add migration script here
+create table login_sessions(
+  session_id uuid not null,
+  primary key (session_id),
+  user_id uuid not null
+    references users (user_id) on delete cascade,
+  token_hash text not null unique,
+  created_at timestamptz not null,
+  expires_at timestamptz not null,
+  ended_at timestamptz
+);
+create index login_sessions_user_id on login_sessions (user_id);
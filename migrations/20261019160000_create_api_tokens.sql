@@ -0,0 +1,15 @@
+-- Add migration script here
+create table api_tokens(
+  token_id uuid not null,
+  primary key (token_id),
+  user_id uuid not null
+    references users (user_id) on delete cascade,
+  name text not null,
+  token_hash text not null unique,
+  scopes text[] not null,
+  created_at timestamptz not null,
+  expires_at timestamptz,
+  last_used_at timestamptz,
+  revoked_at timestamptz
+);
+create index api_tokens_user_id on api_tokens (user_id);
@@ -0,0 +1,40 @@
+-- Add migration script here
+create table lists(
+  list_id uuid not null,
+  primary key (list_id),
+  name text not null unique,
+  created_at timestamptz not null
+);
+insert into lists (list_id, name, created_at)
+values ('00000000-0000-0000-0000-000000000001', 'default', now());
+
+alter table subscriptions
+  add column list_id uuid not null
+    default '00000000-0000-0000-0000-000000000001'
+    references lists (list_id),
+  drop constraint subscriptions_email_key,
+  add constraint subscriptions_list_id_email_key unique (list_id, email);
+
+alter table newsletter_issues
+  add column list_id uuid not null
+    default '00000000-0000-0000-0000-000000000001'
+    references lists (list_id),
+  add column status text not null default 'published',
+  add column created_by uuid references users (user_id) on delete set null,
+  alter column published_at drop not null;
+
+-- Everybody could do everything so far, existing users keep doing so.
+alter table users
+  add column role text not null default 'owner'
+    check (role in ('owner', 'editor', 'viewer'));
+alter table users alter column role set default 'viewer';
+
+create table list_roles(
+  user_id uuid not null
+    references users (user_id) on delete cascade,
+  list_id uuid not null
+    references lists (list_id) on delete cascade,
+  primary key (user_id, list_id),
+  role text not null
+    check (role in ('owner', 'editor', 'viewer'))
+);
@@ -0,0 +1,34 @@
+-- Add migration script here
+-- No foreign keys: events outlive the users and tokens they mention.
+create table audit_events (
+    audit_event_id bigserial primary key,
+    occurred_at timestamptz not null default now(),
+    actor_id uuid,
+    actor_name text,
+    api_token_id uuid,
+    action text not null,
+    target_type text,
+    target_id text,
+    ip text,
+    user_agent text,
+    payload jsonb not null default '{}'
+);
+
+create index audit_events_actor_idx on audit_events (actor_id, audit_event_id);
+create index audit_events_action_idx on audit_events (action, audit_event_id);
+create index audit_events_target_idx on audit_events (target_type, target_id);
+
+-- The log is append-only, even for the application's own role.
+create function reject_audit_event_change() returns trigger as $$
+begin
+    raise exception 'audit_events is append-only';
+end;
+$$ language plpgsql;
+
+create trigger audit_events_append_only
+    before update or delete on audit_events
+    for each row execute function reject_audit_event_change();
+
+create trigger audit_events_no_truncate
+    before truncate on audit_events
+    for each statement execute function reject_audit_event_change();
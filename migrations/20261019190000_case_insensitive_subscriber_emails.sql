@@ -0,0 +1,49 @@
+-- Add migration script here
+-- Addresses differing only in case are the same subscriber. Keep the
+-- confirmed one, or else the oldest, of every group of duplicates. The
+-- others are copied to the archived_* tables before they are deleted, with
+-- the id of the subscriber that was kept, so that they can be looked up or
+-- restored by hand.
+create temporary table duplicate_subscriptions as
+select id, kept_subscriber_id from (
+  select
+    id,
+    first_value(id) over duplicates as kept_subscriber_id,
+    row_number() over duplicates as rank
+  from subscriptions
+  window duplicates as (
+    partition by list_id, lower(email)
+    order by status = 'confirmed' desc, subscribed_at, id
+  )
+) ranked
+where rank > 1;
+
+create table archived_duplicate_subscriptions as
+select s.*, d.kept_subscriber_id, now() as archived_at
+from subscriptions s
+join duplicate_subscriptions d on d.id = s.id;
+create table archived_duplicate_subscription_tokens as
+select t.*, now() as archived_at
+from subscription_tokens t
+join duplicate_subscriptions d on d.id = t.subscriber_id;
+create table archived_duplicate_issue_delivery_queue as
+select q.*, now() as archived_at
+from issue_delivery_queue q
+join duplicate_subscriptions d on d.id = q.subscriber_id;
+
+delete from subscription_tokens
+where subscriber_id in (select id from duplicate_subscriptions);
+delete from issue_delivery_queue
+where subscriber_id in (select id from duplicate_subscriptions);
+delete from subscriptions
+where id in (select id from duplicate_subscriptions);
+drop table duplicate_subscriptions;
+
+-- Domains are stored in lowercase from now on.
+update subscriptions
+set email = substring(email from '^(.*)@') || '@' || lower(substring(email from '[^@]*$'))
+where email like '%@%';
+
+alter table subscriptions drop constraint subscriptions_list_id_email_key;
+create unique index subscriptions_list_id_lower_email_key
+  on subscriptions (list_id, lower(email));
@@ -0,0 +1,12 @@
+alter table subscriptions
+  add column locale text not null default 'en';
+
+create table newsletter_issue_translations (
+  newsletter_issue_id uuid not null
+    references newsletter_issues (newsletter_issue_id) on delete cascade,
+  locale text not null,
+  title text not null,
+  text_content text not null,
+  html_content text not null,
+  primary key (newsletter_issue_id, locale)
+);
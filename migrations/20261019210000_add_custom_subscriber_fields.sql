@@ -0,0 +1,19 @@
+create table list_fields (
+  list_id uuid not null
+    references lists (list_id) on delete cascade,
+  key text not null,
+  field_type text not null
+    check (field_type in ('string', 'number', 'date', 'boolean', 'enum')),
+  -- Allowed values of enum fields.
+  options text[] not null default '{}',
+  required boolean not null default false,
+  created_at timestamptz not null,
+  primary key (list_id, key)
+);
+
+alter table subscriptions
+  add column attributes jsonb not null default '{}';
+
+-- Subscribers whose attributes contain these receive the issue.
+alter table newsletter_issues
+  add column recipient_attributes jsonb not null default '{}';
@@ -0,0 +1,28 @@
+create table segments (
+  segment_id uuid primary key,
+  list_id uuid not null
+    references lists (list_id) on delete cascade,
+  name text not null,
+  rules jsonb not null,
+  created_by uuid references users (user_id) on delete set null,
+  created_at timestamptz not null,
+  unique (list_id, name)
+);
+
+-- Opens and clicks of delivered issues, for segments on engagement.
+create table engagement_events (
+  subscriber_id uuid not null
+    references subscriptions (id) on delete cascade,
+  newsletter_issue_id uuid not null
+    references newsletter_issues (newsletter_issue_id) on delete cascade,
+  kind text not null check (kind in ('opened', 'clicked')),
+  occurred_at timestamptz not null
+);
+create index engagement_events_subscriber_id_kind_occurred_at_idx
+  on engagement_events (subscriber_id, kind, occurred_at);
+
+-- Rules are copied when an issue is stored, so that a draft goes to the
+-- segment as it was when it was written.
+alter table newsletter_issues
+  add column segment_id uuid references segments (segment_id) on delete set null,
+  add column segment_rules jsonb;
@@ -0,0 +1,20 @@
+-- Issues as they went out to each subscriber. Open pixels and tracked links
+-- carry the token, which tells the issue and the subscriber.
+create table issue_deliveries (
+  tracking_token text primary key,
+  newsletter_issue_id uuid not null
+    references newsletter_issues (newsletter_issue_id) on delete cascade,
+  subscriber_id uuid not null
+    references subscriptions (id) on delete cascade,
+  unique (newsletter_issue_id, subscriber_id)
+);
+
+-- Links of delivered issues. Clicks only redirect to links stored here, so
+-- the redirect can't send people anywhere else.
+create table issue_links (
+  link_id uuid primary key,
+  newsletter_issue_id uuid not null
+    references newsletter_issues (newsletter_issue_id) on delete cascade,
+  url text not null,
+  unique (newsletter_issue_id, url)
+);
@@ -0,0 +1,12 @@
+create table subscriber_tags (
+  subscriber_id uuid not null
+    references subscriptions (id) on delete cascade,
+  tag text not null,
+  created_at timestamptz not null,
+  primary key (subscriber_id, tag)
+);
+create index subscriber_tags_tag_idx on subscriber_tags (tag);
+
+-- Subscribers with all of these tags receive the issue.
+alter table newsletter_issues
+  add column recipient_tags text[] not null default '{}';
@@ -0,0 +1,13 @@
+-- Emails lists send when something happens to a subscriber, like a welcome
+-- email after confirmation. Their content is stored as newsletter issues
+-- with the status 'automation'.
+create table automations (
+  list_id uuid not null
+    references lists (list_id) on delete cascade,
+  event text not null check (event in ('confirmed', 'unsubscribed')),
+  newsletter_issue_id uuid not null
+    references newsletter_issues (newsletter_issue_id),
+  updated_by uuid references users (user_id) on delete set null,
+  updated_at timestamptz not null,
+  primary key (list_id, event)
+);
@@ -0,0 +1,49 @@
+-- Series of emails sent at day offsets after a subscriber confirms or gets
+-- a tag. Like automations, steps store their email as an issue with the
+-- status 'automation'.
+create table sequences (
+  sequence_id uuid primary key,
+  list_id uuid not null
+    references lists (list_id) on delete cascade,
+  name text not null,
+  trigger text not null check (trigger in ('confirmed', 'tagged')),
+  trigger_tag text,
+  segment_id uuid references segments (segment_id) on delete set null,
+  segment_rules jsonb,
+  created_by uuid references users (user_id) on delete set null,
+  created_at timestamptz not null,
+  updated_at timestamptz not null,
+  unique (list_id, name),
+  check ((trigger = 'tagged') = (trigger_tag is not null))
+);
+create index sequences_list_id_trigger_idx on sequences (list_id, trigger);
+
+create table sequence_steps (
+  sequence_id uuid not null
+    references sequences (sequence_id) on delete cascade,
+  position integer not null,
+  delay_days integer not null,
+  -- Issues can't be deleted while a step sends them, the step has to be
+  -- removed from its sequence first.
+  newsletter_issue_id uuid not null
+    references newsletter_issues (newsletter_issue_id) on delete restrict,
+  primary key (sequence_id, position)
+);
+
+-- Where each subscriber is in a sequence. Step delays count from
+-- enrolled_at; next_step_at is null once the enrolment has ended.
+create table sequence_enrolments (
+  sequence_id uuid not null
+    references sequences (sequence_id) on delete cascade,
+  subscriber_id uuid not null
+    references subscriptions (id) on delete cascade,
+  enrolled_at timestamptz not null,
+  next_step integer not null,
+  next_step_at timestamptz,
+  status text not null check (status in ('active', 'completed', 'exited')),
+  exit_reason text,
+  updated_at timestamptz not null,
+  primary key (sequence_id, subscriber_id)
+);
+create index sequence_enrolments_next_step_at_idx
+  on sequence_enrolments (next_step_at) where status = 'active';
@@ -0,0 +1,44 @@
+-- Issues sent as variants to a random share of their recipients first, and
+-- as the variant that did best to the rest once the test window is over.
+create table issue_ab_tests (
+  newsletter_issue_id uuid primary key
+    references newsletter_issues (newsletter_issue_id) on delete cascade,
+  test_percent integer not null,
+  window_hours integer not null,
+  metric text not null check (metric in ('opened', 'clicked')),
+  -- Set when the issue is published.
+  decide_at timestamptz,
+  winner integer,
+  decided_at timestamptz
+);
+create index issue_ab_tests_decide_at_idx on issue_ab_tests (decide_at) where winner is null;
+
+create table issue_variants (
+  newsletter_issue_id uuid not null
+    references issue_ab_tests (newsletter_issue_id) on delete cascade,
+  variant integer not null,
+  title text not null,
+  text_content text not null,
+  html_content text not null,
+  -- Results, stored when the winner is picked.
+  recipients integer,
+  engaged integer,
+  primary key (newsletter_issue_id, variant)
+);
+
+-- Recipients of a tested issue, picked when it is published. Those outside
+-- the test share have no variant until the winner is picked.
+create table issue_variant_assignments (
+  newsletter_issue_id uuid not null
+    references issue_ab_tests (newsletter_issue_id) on delete cascade,
+  subscriber_id uuid not null
+    references subscriptions (id) on delete cascade,
+  variant integer,
+  primary key (newsletter_issue_id, subscriber_id)
+);
+
+alter table issue_delivery_queue add column variant integer;
+
+-- Opens and clicks count towards the variant that was delivered.
+alter table issue_deliveries add column variant integer;
+alter table engagement_events add column variant integer;
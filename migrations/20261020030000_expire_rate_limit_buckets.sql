@@ -0,0 +1,6 @@
+-- Buckets are deleted once their window is over, so that keys that are never
+-- seen again don't stay around. The end of the windows in progress isn't
+-- known, so their counters start over.
+delete from rate_limit_buckets;
+alter table rate_limit_buckets add column window_end timestamptz not null;
+create index rate_limit_buckets_window_end_idx on rate_limit_buckets (window_end);
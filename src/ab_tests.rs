@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Tests decided per transaction of the scheduler.
+pub const BATCH_SIZE: i64 = 10;
+
+/// What makes a variant of an issue better than the others.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AbTestMetric {
+    /// Share of its recipients who opened it.
+    #[default]
+    Opened,
+    /// Share of its recipients who clicked a link in it.
+    Clicked,
+}
+
+impl AbTestMetric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AbTestMetric::Opened => "opened",
+            AbTestMetric::Clicked => "clicked",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "opened" => Some(AbTestMetric::Opened),
+            "clicked" => Some(AbTestMetric::Clicked),
+            _ => None,
+        }
+    }
+}
+
+/// How a variant did so far.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct VariantResult {
+    pub variant: i32,
+    /// Subscribers the variant was sent to during the test.
+    pub recipients: i64,
+    /// Recipients who opened or clicked it, depending on the metric.
+    pub engaged: i64,
+}
+
+/// The variant with the highest share of engaged recipients, the first of
+/// those if several do equally well.
+pub fn pick_winner(results: &[VariantResult]) -> Option<i32> {
+    let rate = |result: &VariantResult| {
+        if result.recipients == 0 {
+            0.0
+        } else {
+            result.engaged as f64 / result.recipients as f64
+        }
+    };
+    results
+        .iter()
+        .fold(None, |best: Option<&VariantResult>, result| match best {
+            Some(best) if rate(best) >= rate(result) => Some(best),
+            _ => Some(result),
+        })
+        .map(|result| result.variant)
+}
+
+/// How the variants of an issue did, counting the engagement events of the
+/// kind of `metric`.
+#[tracing::instrument(name = "Measure the variants of an issue", skip(executor))]
+pub async fn variant_results<'e>(
+    executor: impl PgExecutor<'e>,
+    issue_id: Uuid,
+    metric: AbTestMetric,
+) -> Result<Vec<VariantResult>, sqlx::Error> {
+    sqlx::query_as!(
+        VariantResult,
+        r#"
+        select v.variant,
+            count(a.subscriber_id) as "recipients!",
+            count(a.subscriber_id) filter (where exists (
+                select 1 from engagement_events e
+                where e.subscriber_id = a.subscriber_id
+                    and e.newsletter_issue_id = v.newsletter_issue_id
+                    and e.variant = v.variant
+                    and e.kind = $2
+            )) as "engaged!"
+        from issue_variants v
+        left join issue_variant_assignments a
+            on a.newsletter_issue_id = v.newsletter_issue_id and a.variant = v.variant
+        where v.newsletter_issue_id = $1
+        group by v.variant
+        order by v.variant
+        "#,
+        issue_id,
+        metric.as_str()
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// Picks the winner of up to a batch of tests whose window is over, stores
+/// the results and queues the winner for the recipients outside the test
+/// share who are still confirmed. Returns how many tests were decided.
+#[tracing::instrument(name = "Decide due A/B tests", skip_all, err)]
+pub async fn decide_due_tests(pool: &PgPool) -> Result<usize, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let due = sqlx::query!(
+        r#"
+        select newsletter_issue_id, metric
+        from issue_ab_tests
+        where winner is null and decide_at <= now()
+        order by decide_at
+        for update
+        skip locked
+        limit $1
+        "#,
+        BATCH_SIZE
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    for test in &due {
+        let metric = AbTestMetric::parse(&test.metric).unwrap_or_default();
+        let results = variant_results(&mut transaction, test.newsletter_issue_id, metric).await?;
+        let winner = pick_winner(&results).unwrap_or(0);
+        for result in &results {
+            sqlx::query!(
+                r#"
+                update issue_variants set recipients = $3, engaged = $4
+                where newsletter_issue_id = $1 and variant = $2
+                "#,
+                test.newsletter_issue_id,
+                result.variant,
+                result.recipients as i32,
+                result.engaged as i32
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        sqlx::query!(
+            r#"
+            update issue_ab_tests set winner = $2, decided_at = now()
+            where newsletter_issue_id = $1
+            "#,
+            test.newsletter_issue_id,
+            winner
+        )
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query!(
+            r#"
+            with rest as (
+                update issue_variant_assignments set variant = $2
+                where newsletter_issue_id = $1 and variant is null
+                returning subscriber_id
+            )
+            insert into issue_delivery_queue (newsletter_issue_id, subscriber_id, variant)
+            select $1, rest.subscriber_id, $2
+            from rest
+            join subscriptions s on s.id = rest.subscriber_id
+            where s.status = 'confirmed'
+            on conflict do nothing
+            "#,
+            test.newsletter_issue_id,
+            winner
+        )
+        .execute(&mut transaction)
+        .await?;
+        tracing::info!(newsletter_issue_id = %test.newsletter_issue_id, winner,
+            "Picked the winner of an A/B test");
+    }
+    transaction.commit().await?;
+    Ok(due.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pick_winner, VariantResult};
+
+    fn result(variant: i32, recipients: i64, engaged: i64) -> VariantResult {
+        VariantResult {
+            variant,
+            recipients,
+            engaged,
+        }
+    }
+
+    #[test]
+    fn the_variant_with_the_highest_rate_wins() {
+        let results = [result(0, 100, 20), result(1, 50, 15), result(2, 0, 0)];
+        assert_eq!(pick_winner(&results), Some(1));
+    }
+
+    #[test]
+    fn ties_go_to_the_first_variant() {
+        assert_eq!(pick_winner(&[result(0, 10, 1), result(1, 20, 2)]), Some(0));
+        assert_eq!(pick_winner(&[result(0, 0, 0), result(1, 0, 0)]), Some(0));
+        assert_eq!(pick_winner(&[]), None);
+    }
+}
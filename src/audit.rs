@@ -0,0 +1,277 @@
+use crate::authentication::{app_data, Credential};
+use crate::authorization::AuthenticatedUser;
+use crate::rate_limit::RateLimiter;
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgExecutor;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Actions recorded in the audit log. The names are stored, so they must not
+/// change once released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    /// A session was opened, or a request was authenticated with a second
+    /// factor. Plain Basic credentials are checked on every request and are
+    /// not recorded as logins.
+    LoginSucceeded,
+    LoginFailed,
+    LoginLockedOut,
+    LockoutLifted,
+    NewsletterDrafted,
+    NewsletterPublished,
+    ListCreated,
+    ListFieldCreated,
+    ListFieldDeleted,
+    SegmentCreated,
+    SegmentDeleted,
+    AutomationSet,
+    AutomationDeleted,
+    SequenceCreated,
+    SequenceUpdated,
+    SequenceDeleted,
+    SubscribersExported,
+    SubscribersImported,
+    SubscribersTagged,
+    UserRoleChanged,
+    ListRoleGranted,
+    ListRoleRevoked,
+    SettingsChanged,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    RecoveryCodesRegenerated,
+    ApiTokenCreated,
+    ApiTokenRevoked,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LoginSucceeded => "login.succeeded",
+            AuditAction::LoginFailed => "login.failed",
+            AuditAction::LoginLockedOut => "login.locked_out",
+            AuditAction::LockoutLifted => "lockout.lifted",
+            AuditAction::NewsletterDrafted => "newsletter.drafted",
+            AuditAction::NewsletterPublished => "newsletter.published",
+            AuditAction::ListCreated => "list.created",
+            AuditAction::ListFieldCreated => "list.field_created",
+            AuditAction::ListFieldDeleted => "list.field_deleted",
+            AuditAction::SegmentCreated => "segment.created",
+            AuditAction::SegmentDeleted => "segment.deleted",
+            AuditAction::AutomationSet => "automation.set",
+            AuditAction::AutomationDeleted => "automation.deleted",
+            AuditAction::SequenceCreated => "sequence.created",
+            AuditAction::SequenceUpdated => "sequence.updated",
+            AuditAction::SequenceDeleted => "sequence.deleted",
+            AuditAction::SubscribersExported => "subscribers.exported",
+            AuditAction::SubscribersImported => "subscribers.imported",
+            AuditAction::SubscribersTagged => "subscribers.tagged",
+            AuditAction::UserRoleChanged => "user.role_changed",
+            AuditAction::ListRoleGranted => "user.list_role_granted",
+            AuditAction::ListRoleRevoked => "user.list_role_revoked",
+            AuditAction::SettingsChanged => "settings.changed",
+            AuditAction::TwoFactorEnabled => "two_factor.enabled",
+            AuditAction::TwoFactorDisabled => "two_factor.disabled",
+            AuditAction::RecoveryCodesRegenerated => "two_factor.recovery_codes_regenerated",
+            AuditAction::ApiTokenCreated => "api_token.created",
+            AuditAction::ApiTokenRevoked => "api_token.revoked",
+        }
+    }
+}
+
+/// An entry of the append-only audit log, built from the request that caused
+/// it and stored with `record`.
+#[derive(Debug)]
+pub struct AuditEvent {
+    action: AuditAction,
+    actor_id: Option<Uuid>,
+    actor_name: Option<String>,
+    api_token_id: Option<Uuid>,
+    target: Option<(&'static str, String)>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    payload: serde_json::Value,
+}
+
+impl AuditEvent {
+    /// An event caused by `request`, without a known actor yet.
+    pub fn from_request(request: &HttpRequest, action: AuditAction) -> Self {
+        AuditEvent {
+            action,
+            actor_id: None,
+            actor_name: None,
+            api_token_id: None,
+            target: None,
+            ip: Some(app_data::<RateLimiter>(request).client_ip(request)),
+            user_agent: user_agent(request),
+            payload: serde_json::Value::Object(Default::default()),
+        }
+    }
+
+    /// An event caused by an authenticated user.
+    pub fn by(user: &AuthenticatedUser, action: AuditAction) -> Self {
+        let api_token_id = match &user.credential {
+            Credential::Password | Credential::Session { .. } => None,
+            Credential::ApiToken { token_id, .. } => Some(*token_id),
+        };
+        AuditEvent {
+            action,
+            actor_id: Some(user.user_id),
+            actor_name: None,
+            api_token_id,
+            target: None,
+            ip: Some(user.client_ip.clone()),
+            user_agent: user.user_agent.clone(),
+            payload: serde_json::Value::Object(Default::default()),
+        }
+    }
+
+    pub fn actor(mut self, user_id: Uuid) -> Self {
+        self.actor_id = Some(user_id);
+        self
+    }
+
+    /// The username given by an actor that may not exist, e.g. on failed
+    /// logins.
+    pub fn actor_name(mut self, name: &str) -> Self {
+        self.actor_name = Some(name.to_string());
+        self
+    }
+
+    pub fn target(mut self, target_type: &'static str, target_id: impl ToString) -> Self {
+        self.target = Some((target_type, target_id.to_string()));
+        self
+    }
+
+    pub fn payload(mut self, payload: serde_json::Value) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Stores the event. Pass the transaction of the audited change where
+    /// there is one, so that both are committed together.
+    #[tracing::instrument(
+        name = "Record an audit event",
+        skip_all,
+        fields(action = self.action.as_str())
+    )]
+    pub async fn record<'e>(self, executor: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+        let (target_type, target_id) = self.target.unzip();
+        sqlx::query!(
+            r#"
+            insert into audit_events (
+                actor_id, actor_name, api_token_id, action, target_type, target_id,
+                ip, user_agent, payload
+            )
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+            self.actor_id,
+            self.actor_name,
+            self.api_token_id,
+            self.action.as_str(),
+            target_type,
+            target_id,
+            self.ip,
+            self.user_agent,
+            self.payload
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
+
+pub(crate) fn user_agent(request: &HttpRequest) -> Option<String> {
+    request
+        .headers()
+        .get(header::USER_AGENT)?
+        .to_str()
+        .ok()
+        .map(str::to_string)
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StoredAuditEvent {
+    pub audit_event_id: i64,
+    pub occurred_at: DateTime<Utc>,
+    #[schema(value_type = Option<String>, format = "uuid")]
+    pub actor_id: Option<Uuid>,
+    pub actor_name: Option<String>,
+    #[schema(value_type = Option<String>, format = "uuid")]
+    pub api_token_id: Option<Uuid>,
+    pub action: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+}
+
+/// Filters for `find_events`, all optional. `before` pages backwards through
+/// the log by event id.
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub actor_id: Option<Uuid>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub before: Option<i64>,
+    pub limit: i64,
+}
+
+/// Matching events, newest first.
+pub async fn find_events(
+    executor: impl PgExecutor<'_>,
+    filter: &AuditFilter,
+) -> Result<Vec<StoredAuditEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredAuditEvent,
+        r#"
+        select audit_event_id, occurred_at, actor_id, actor_name, api_token_id, action,
+            target_type, target_id, ip, user_agent, payload
+        from audit_events
+        where ($1::uuid is null or actor_id = $1)
+            and ($2::text is null or action = $2)
+            and ($3::text is null or target_type = $3)
+            and ($4::text is null or target_id = $4)
+            and ($5::timestamptz is null or occurred_at >= $5)
+            and ($6::timestamptz is null or occurred_at < $6)
+            and ($7::bigint is null or audit_event_id < $7)
+        order by audit_event_id desc
+        limit $8
+        "#,
+        filter.actor_id,
+        filter.action,
+        filter.target_type,
+        filter.target_id,
+        filter.since,
+        filter.until,
+        filter.before,
+        filter.limit
+    )
+    .fetch_all(executor)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditAction;
+
+    #[test]
+    fn action_names_are_namespaced() {
+        for action in [
+            AuditAction::LoginFailed,
+            AuditAction::NewsletterPublished,
+            AuditAction::SubscribersExported,
+            AuditAction::SettingsChanged,
+        ] {
+            let (namespace, name) = action.as_str().split_once('.').unwrap();
+            assert!(!namespace.is_empty() && !name.is_empty());
+        }
+    }
+}
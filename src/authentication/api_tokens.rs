@@ -0,0 +1,209 @@
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sha3::Digest;
+use sqlx::PgPool;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Prefix making tokens recognisable, e.g. for secret scanners.
+const TOKEN_PREFIX: &str = "emt_";
+const TOKEN_LENGTH: usize = 40;
+
+/// What a token may be used for. Tokens never grant access to account
+/// management, which always requires an interactive login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum TokenScope {
+    #[serde(rename = "newsletters:publish")]
+    NewslettersPublish,
+    #[serde(rename = "subscribers:read")]
+    SubscribersRead,
+}
+
+impl TokenScope {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "newsletters:publish" => Some(TokenScope::NewslettersPublish),
+            "subscribers:read" => Some(TokenScope::SubscribersRead),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::NewslettersPublish => "newsletters:publish",
+            TokenScope::SubscribersRead => "subscribers:read",
+        }
+    }
+}
+
+impl std::fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiToken {
+    #[schema(value_type = String, format = "uuid")]
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+pub struct NewApiToken<'a> {
+    pub name: &'a str,
+    pub scopes: &'a [TokenScope],
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Stores a new token for `user_id` and returns it together with the plain
+/// token, which is not retrievable afterwards.
+#[tracing::instrument(name = "Create an API token", skip(pool, token), fields(name = token.name))]
+pub async fn create_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token: NewApiToken<'_>,
+) -> Result<(ApiToken, String), sqlx::Error> {
+    let plain = generate_token();
+    let scopes: Vec<String> = token.scopes.iter().map(|s| s.as_str().to_string()).collect();
+    let created = sqlx::query_as!(
+        ApiToken,
+        r#"
+        insert into api_tokens (
+            token_id, user_id, name, token_hash, scopes, created_at, expires_at
+        )
+        values ($1, $2, $3, $4, $5, now(), $6)
+        returning token_id, name, scopes, created_at, expires_at, last_used_at
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        token.name,
+        hash_token(&plain),
+        &scopes[..],
+        token.expires_at
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((created, plain))
+}
+
+/// Tokens of `user_id` that are neither revoked nor expired.
+pub async fn list_tokens(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiToken>, sqlx::Error> {
+    sqlx::query_as!(
+        ApiToken,
+        r#"
+        select token_id, name, scopes, created_at, expires_at, last_used_at
+        from api_tokens
+        where user_id = $1
+            and revoked_at is null
+            and (expires_at is null or expires_at > now())
+        order by created_at
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns whether a token of `user_id` was revoked.
+#[tracing::instrument(name = "Revoke an API token", skip(pool))]
+pub async fn revoke_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        update api_tokens set revoked_at = now()
+        where token_id = $1 and user_id = $2 and revoked_at is null
+        "#,
+        token_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub struct ValidToken {
+    pub token_id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: Vec<TokenScope>,
+}
+
+/// Looks up a presented token and records its use when it is valid.
+pub async fn find_token(pool: &PgPool, token: &str) -> Result<Option<ValidToken>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        update api_tokens set last_used_at = now()
+        where token_hash = $1
+            and revoked_at is null
+            and (expires_at is null or expires_at > now())
+        returning token_id, user_id, scopes
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| ValidToken {
+        token_id: row.token_id,
+        user_id: row.user_id,
+        scopes: row
+            .scopes
+            .iter()
+            .filter_map(|s| TokenScope::parse(s))
+            .collect(),
+    }))
+}
+
+fn generate_token() -> String {
+    random_token(TOKEN_PREFIX)
+}
+
+pub(super) fn random_token(prefix: &str) -> String {
+    let random: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(TOKEN_LENGTH)
+        .collect();
+    format!("{}{}", prefix, random)
+}
+
+/// Tokens are long random strings, so unlike passwords a fast hash is enough.
+pub(super) fn hash_token(token: &str) -> String {
+    format!("{:x}", sha3::Sha3_256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_prefixed_and_unique() {
+        let first = generate_token();
+        let second = generate_token();
+        assert!(first.starts_with(TOKEN_PREFIX));
+        assert_eq!(first.len(), TOKEN_PREFIX.len() + TOKEN_LENGTH);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn scopes_use_their_wire_names() {
+        let scopes: Vec<TokenScope> =
+            serde_json::from_str(r#"["newsletters:publish", "subscribers:read"]"#).unwrap();
+        assert_eq!(
+            scopes,
+            vec![TokenScope::NewslettersPublish, TokenScope::SubscribersRead]
+        );
+        assert!(serde_json::from_str::<TokenScope>(r#""admin""#).is_err());
+        for scope in scopes {
+            assert_eq!(serde_json::to_string(&scope).unwrap(), format!("\"{}\"", scope));
+            assert_eq!(TokenScope::parse(scope.as_str()), Some(scope));
+        }
+    }
+}
@@ -0,0 +1,700 @@
+pub mod api_tokens;
+pub mod sessions;
+mod totp;
+pub mod two_factor;
+
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authorization::Permission;
+use crate::config::{LoginThrottleConfig, TwoFactorConfig};
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use crate::rate_limit::RateLimiter;
+use crate::secret::Secret;
+use actix_http::header::{self, HeaderMap, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use sha3::Digest;
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+pub use api_tokens::TokenScope;
+pub use totp::TotpSecret;
+pub use two_factor::SecondFactor;
+
+/// Header carrying the TOTP or recovery code of users with two-factor
+/// authentication enabled, next to their Basic credentials. Codes are single
+/// use, so clients making more than one request open a session with them.
+pub const TWO_FACTOR_HEADER: &str = "X-Two-Factor-Code";
+
+/// How often recorded login failures past their retention are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The message of `InvalidCredentials` explains what was wrong for the logs,
+/// clients only ever get a bare 401.
+#[derive(thiserror::Error)]
+pub enum AuthError {
+    #[error("{0}")]
+    InvalidCredentials(String),
+    #[error("Too many failed login attempts")]
+    LockedOut { retry_after: Duration },
+    #[error("A two-factor code is required")]
+    SecondFactorRequired,
+    #[error("Two-factor authentication must be enabled for this account")]
+    EnrolmentRequired,
+    #[error("The API token lacks the {0} scope")]
+    MissingScope(TokenScope),
+    #[error("Not allowed to {0}")]
+    Forbidden(Permission),
+    #[error("Failed to authenticate")]
+    UnexpectedError(#[source] sqlx::Error),
+}
+
+impl std::fmt::Debug for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::InvalidCredentials(_) | AuthError::SecondFactorRequired => {
+                StatusCode::UNAUTHORIZED
+            }
+            AuthError::EnrolmentRequired
+            | AuthError::MissingScope(_)
+            | AuthError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AuthError::LockedOut { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AuthError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let basic_challenge = HeaderValue::from_static("Basic realm=\"publish\"");
+        let problem = match self {
+            // The reason is only logged, it would help guessing credentials.
+            AuthError::InvalidCredentials(_) => Problem::new(status, "invalid_credentials")
+                .header(header::WWW_AUTHENTICATE, basic_challenge),
+            AuthError::LockedOut { retry_after } => Problem::new(status, "locked_out")
+                .detail(self.to_string())
+                .retry_after(*retry_after),
+            AuthError::SecondFactorRequired => Problem::new(status, "second_factor_required")
+                .detail(self.to_string())
+                .header(header::WWW_AUTHENTICATE, basic_challenge),
+            AuthError::EnrolmentRequired => {
+                Problem::new(status, "two_factor_enrolment_required").detail(self.to_string())
+            }
+            AuthError::MissingScope(_) => {
+                Problem::new(status, "missing_scope").detail(self.to_string())
+            }
+            AuthError::Forbidden(_) => Problem::new(status, "forbidden").detail(self.to_string()),
+            AuthError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+#[derive(Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret,
+    pub second_factor: Option<Secret>,
+}
+
+pub fn basic_auth(headers: &HeaderMap) -> Result<Credentials, AuthError> {
+    let header = match headers.get("Authorization") {
+        Some(header) => header.to_str().map_err(|e| {
+            AuthError::InvalidCredentials(format!(
+                "Authorization header is not a valid UTF-8 string: {}",
+                e
+            ))
+        })?,
+        None => {
+            return Err(AuthError::InvalidCredentials(
+                "Authorization header is missing".to_string(),
+            ))
+        }
+    };
+    let encoded = match header.strip_prefix("Basic ") {
+        Some(encoded) => encoded,
+        None => {
+            return Err(AuthError::InvalidCredentials(
+                "Authorization scheme was not 'Basic'".to_string(),
+            ))
+        }
+    };
+    let decoded = base64::decode_config(encoded, base64::STANDARD)
+        .map_err(|e| AuthError::InvalidCredentials(format!("Faild to decode base64: {}", e)))?;
+    let decoded_credentials = String::from_utf8(decoded).map_err(|e| {
+        AuthError::InvalidCredentials(format!("Decoded credentials are not valid UTF-8: {}", e))
+    })?;
+
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| AuthError::InvalidCredentials("Username must be provided".to_string()))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| AuthError::InvalidCredentials("Password must be provided".to_string()))?
+        .to_string()
+        .into();
+    let second_factor = headers
+        .get(TWO_FACTOR_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| Secret::new(value.to_string()));
+    Ok(Credentials {
+        username,
+        password,
+        second_factor,
+    })
+}
+
+pub async fn validate_credentials(
+    credentials: &Credentials,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let password_hash = sha3::Sha3_256::digest(credentials.password.expose_secret().as_bytes());
+    let password_hash = format!("{:x}", password_hash);
+    let user_id: Option<_> = sqlx::query!(
+        "select user_id from users where username = $1 and password_hash = $2",
+        credentials.username,
+        password_hash
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(AuthError::UnexpectedError)?;
+    user_id
+        .map(|row| row.user_id)
+        .ok_or_else(|| AuthError::InvalidCredentials("Invalid username or password".to_string()))
+}
+
+/// Whether a user who hasn't enrolled in two-factor authentication may log
+/// in while it is required for everyone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TwoFactorPolicy {
+    Enforce,
+    AllowEnrolment,
+}
+
+/// How a request was authenticated.
+#[derive(Debug, Clone)]
+pub enum Credential {
+    Password,
+    Session {
+        session_id: Uuid,
+    },
+    ApiToken {
+        token_id: Uuid,
+        scopes: Vec<TokenScope>,
+    },
+}
+
+/// Authenticates `request` either with an API token or, without one, like
+/// `login`.
+pub async fn authenticate_request(request: &HttpRequest) -> Result<(Uuid, Credential), AuthError> {
+    let token = match bearer_token(request.headers()) {
+        Some(token) if !sessions::is_session_token(token) => token,
+        _ => return interactive_login(request, TwoFactorPolicy::Enforce).await,
+    };
+    let pool = app_data::<PgPool>(request);
+    match api_tokens::find_token(pool, token)
+        .await
+        .map_err(AuthError::UnexpectedError)?
+    {
+        Some(token) => {
+            tracing::Span::current().record("user_id", tracing::field::display(token.user_id));
+            tracing::info!(token_id = %token.token_id, "Authenticated with an API token");
+            Ok((
+                token.user_id,
+                Credential::ApiToken {
+                    token_id: token.token_id,
+                    scopes: token.scopes,
+                },
+            ))
+        }
+        None => {
+            let reason = "Invalid, expired or revoked API token";
+            AuditEvent::from_request(request, AuditAction::LoginFailed)
+                .payload(serde_json::json!({ "reason": reason }))
+                .record(pool)
+                .await
+                .map_err(AuthError::UnexpectedError)?;
+            Err(AuthError::InvalidCredentials(reason.to_string()))
+        }
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Authenticates `request` with a session token or, without one, with its
+/// Basic credentials like `password_login`.
+pub async fn login(request: &HttpRequest, policy: TwoFactorPolicy) -> Result<Uuid, AuthError> {
+    let (user_id, _) = interactive_login(request, policy).await?;
+    Ok(user_id)
+}
+
+async fn interactive_login(
+    request: &HttpRequest,
+    policy: TwoFactorPolicy,
+) -> Result<(Uuid, Credential), AuthError> {
+    let token = match bearer_token(request.headers()) {
+        Some(token) if sessions::is_session_token(token) => token,
+        _ => {
+            let login = password_login(request, policy).await?;
+            // Codes are single use, so each of these is a login of its own.
+            // Plain Basic credentials authenticate every request and only
+            // show up as the actor of what is done with them.
+            if login.second_factor {
+                AuditEvent::from_request(request, AuditAction::LoginSucceeded)
+                    .actor(login.user_id)
+                    .payload(serde_json::json!({ "second_factor": true }))
+                    .record(app_data::<PgPool>(request))
+                    .await
+                    .map_err(AuthError::UnexpectedError)?;
+            }
+            return Ok((login.user_id, Credential::Password));
+        }
+    };
+    let pool = app_data::<PgPool>(request);
+    let session = match sessions::find_session(pool, token)
+        .await
+        .map_err(AuthError::UnexpectedError)?
+    {
+        Some(session) => session,
+        None => {
+            let reason = "Invalid, expired or ended session";
+            AuditEvent::from_request(request, AuditAction::LoginFailed)
+                .payload(serde_json::json!({ "reason": reason }))
+                .record(pool)
+                .await
+                .map_err(AuthError::UnexpectedError)?;
+            return Err(AuthError::InvalidCredentials(reason.to_string()));
+        }
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(session.user_id));
+    // The second factor was checked when the session was opened, but two-factor
+    // authentication may have become required since.
+    if policy == TwoFactorPolicy::Enforce && enrolment_missing(pool, session.user_id).await? {
+        return Err(AuthError::EnrolmentRequired);
+    }
+    Ok((
+        session.user_id,
+        Credential::Session {
+            session_id: session.session_id,
+        },
+    ))
+}
+
+/// A user whose credentials were verified.
+#[derive(Debug, Clone, Copy)]
+pub struct Login {
+    pub user_id: Uuid,
+    /// Whether a TOTP or recovery code was verified as well.
+    pub second_factor: bool,
+}
+
+/// Authenticates the Basic credentials of `request` with `authenticate`,
+/// taking the client IP, pool and settings from the app data. Failed and
+/// locked out attempts go to the audit log, recording successful ones is up
+/// to the caller.
+pub async fn password_login(
+    request: &HttpRequest,
+    policy: TwoFactorPolicy,
+) -> Result<Login, AuthError> {
+    let credentials = basic_auth(request.headers())?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let username = credentials.username.clone();
+    let client_ip = app_data::<RateLimiter>(request).client_ip(request);
+    let pool = app_data::<PgPool>(request);
+    let result = authenticate(
+        credentials,
+        &client_ip,
+        pool,
+        app_data::<LoginThrottleConfig>(request),
+        app_data::<TwoFactorConfig>(request),
+        policy,
+    )
+    .await;
+    let event = match &result {
+        Ok(login) => {
+            tracing::Span::current().record("user_id", tracing::field::display(login.user_id));
+            None
+        }
+        Err(AuthError::InvalidCredentials(reason)) => Some(
+            AuditEvent::from_request(request, AuditAction::LoginFailed)
+                .payload(serde_json::json!({ "reason": reason })),
+        ),
+        Err(AuthError::LockedOut { .. }) => Some(AuditEvent::from_request(
+            request,
+            AuditAction::LoginLockedOut,
+        )),
+        Err(_) => None,
+    };
+    if let Some(event) = event {
+        event
+            .actor_name(&username)
+            .record(pool)
+            .await
+            .map_err(AuthError::UnexpectedError)?;
+    }
+    result
+}
+
+pub(crate) fn app_data<T: 'static>(request: &HttpRequest) -> &T {
+    request
+        .app_data::<web::Data<T>>()
+        .unwrap_or_else(|| panic!("{} is not registered as app data", std::any::type_name::<T>()))
+}
+
+/// Validates credentials while keeping track of failed attempts per username
+/// and per client IP. Failures beyond the free attempts lock the username or
+/// IP out for an exponentially growing period. Unknown usernames are counted
+/// and locked exactly like existing ones, so responses don't reveal which
+/// usernames exist. Users with two-factor authentication enabled must also
+/// provide a valid code, wrong codes count as failures. Replaying a code
+/// that was already used is rejected, but does not count: it needs the right
+/// password and cannot be used to guess codes.
+#[tracing::instrument(
+    name = "Authenticate",
+    skip(credentials, pool, throttle_config, two_factor_config)
+)]
+pub async fn authenticate(
+    credentials: Credentials,
+    client_ip: &str,
+    pool: &PgPool,
+    throttle_config: &LoginThrottleConfig,
+    two_factor_config: &TwoFactorConfig,
+    policy: TwoFactorPolicy,
+) -> Result<Login, AuthError> {
+    let user_key = format!("user:{}", credentials.username);
+    let ip_key = format!("ip:{}", client_ip);
+
+    // Attempts while locked out are rejected before checking anything, so
+    // they are not recorded: that would let a locked out client grow the
+    // table without bound.
+    if let Some(locked_until) = locked_until(pool, &[&user_key, &ip_key]).await? {
+        let retry_after = (locked_until - Utc::now()).to_std().unwrap_or_default();
+        return Err(AuthError::LockedOut { retry_after });
+    }
+
+    let (reason, error) = match validate_credentials(&credentials, pool).await {
+        Ok(user_id) => {
+            match check_second_factor(pool, user_id, &credentials, two_factor_config, policy)
+                .await?
+            {
+                checked @ (None | Some(SecondFactor::Valid)) => {
+                    clear_failures(pool, &[&user_key]).await?;
+                    return Ok(Login {
+                        user_id,
+                        second_factor: checked.is_some(),
+                    });
+                }
+                Some(SecondFactor::Replayed) => {
+                    record_failure(pool, &credentials.username, client_ip, "replayed_second_factor")
+                        .await?;
+                    return Err(AuthError::InvalidCredentials(
+                        "Replayed two-factor code".to_string(),
+                    ));
+                }
+                Some(SecondFactor::Invalid) => (
+                    "invalid_second_factor",
+                    AuthError::InvalidCredentials("Invalid two-factor code".to_string()),
+                ),
+            }
+        }
+        Err(AuthError::InvalidCredentials(reason)) => {
+            ("invalid_credentials", AuthError::InvalidCredentials(reason))
+        }
+        Err(e) => return Err(e),
+    };
+    record_failure(pool, &credentials.username, client_ip, reason).await?;
+    register_failure(
+        pool,
+        &user_key,
+        throttle_config.free_attempts_per_username,
+        throttle_config,
+    )
+    .await?;
+    register_failure(pool, &ip_key, throttle_config.free_attempts_per_ip, throttle_config).await?;
+    Err(error)
+}
+
+/// `None` for users without two-factor authentication.
+async fn check_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    credentials: &Credentials,
+    config: &TwoFactorConfig,
+    policy: TwoFactorPolicy,
+) -> Result<Option<SecondFactor>, AuthError> {
+    let state = two_factor::two_factor_state(pool, user_id)
+        .await
+        .map_err(AuthError::UnexpectedError)?;
+    if !state.enabled {
+        let required = two_factor::two_factor_required(pool)
+            .await
+            .map_err(AuthError::UnexpectedError)?;
+        return match (required, policy) {
+            (true, TwoFactorPolicy::Enforce) => Err(AuthError::EnrolmentRequired),
+            _ => Ok(None),
+        };
+    }
+    let code = match &credentials.second_factor {
+        Some(code) => code,
+        None => return Err(AuthError::SecondFactorRequired),
+    };
+    two_factor::verify_second_factor(pool, user_id, &state, code.expose_secret(), config)
+        .await
+        .map(Some)
+        .map_err(AuthError::UnexpectedError)
+}
+
+async fn enrolment_missing(pool: &PgPool, user_id: Uuid) -> Result<bool, AuthError> {
+    let state = two_factor::two_factor_state(pool, user_id)
+        .await
+        .map_err(AuthError::UnexpectedError)?;
+    if state.enabled {
+        return Ok(false);
+    }
+    two_factor::two_factor_required(pool)
+        .await
+        .map_err(AuthError::UnexpectedError)
+}
+
+/// Lockout applied after `failures` consecutive failures.
+pub fn lockout_duration(
+    failures: i32,
+    free_attempts: i32,
+    config: &LoginThrottleConfig,
+) -> Option<Duration> {
+    if failures <= free_attempts {
+        return None;
+    }
+    let exponent = (failures - free_attempts - 1).clamp(0, 30) as u32;
+    let seconds = config
+        .base_lockout_seconds
+        .saturating_mul(2u64.saturating_pow(exponent))
+        .min(config.max_lockout_seconds);
+    Some(Duration::from_secs(seconds))
+}
+
+async fn locked_until(pool: &PgPool, keys: &[&str]) -> Result<Option<DateTime<Utc>>, AuthError> {
+    let keys: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+    let row = sqlx::query!(
+        r#"
+        select max(locked_until) as locked_until
+        from login_throttle
+        where key = any($1) and locked_until > now()
+        "#,
+        &keys[..]
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(AuthError::UnexpectedError)?;
+    Ok(row.locked_until)
+}
+
+async fn register_failure(
+    pool: &PgPool,
+    key: &str,
+    free_attempts: i32,
+    config: &LoginThrottleConfig,
+) -> Result<(), AuthError> {
+    let reset_before = Utc::now() - chrono::Duration::seconds(config.reset_after_seconds as i64);
+    let row = sqlx::query!(
+        r#"
+        insert into login_throttle (key, failures, last_failure_at)
+        values ($1, 1, now())
+        on conflict (key) do update set
+            failures = case
+                when login_throttle.last_failure_at < $2 then 1
+                else login_throttle.failures + 1
+            end,
+            last_failure_at = now()
+        returning failures
+        "#,
+        key,
+        reset_before
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(AuthError::UnexpectedError)?;
+
+    if let Some(lockout) = lockout_duration(row.failures, free_attempts, config) {
+        tracing::warn!(
+            key,
+            failures = row.failures,
+            "Locking out after failed logins"
+        );
+        let locked_until = Utc::now() + chrono::Duration::from_std(lockout).unwrap_or_default();
+        sqlx::query!(
+            "update login_throttle set locked_until = $2 where key = $1",
+            key,
+            locked_until
+        )
+        .execute(pool)
+        .await
+        .map_err(AuthError::UnexpectedError)?;
+    }
+    Ok(())
+}
+
+async fn clear_failures(pool: &PgPool, keys: &[&str]) -> Result<(), AuthError> {
+    let keys: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+    sqlx::query!("delete from login_throttle where key = any($1)", &keys[..])
+        .execute(pool)
+        .await
+        .map_err(AuthError::UnexpectedError)?;
+    Ok(())
+}
+
+async fn record_failure(
+    pool: &PgPool,
+    username: &str,
+    client_ip: &str,
+    reason: &str,
+) -> Result<(), AuthError> {
+    sqlx::query!(
+        r#"
+        insert into login_failures (id, username, ip, reason, attempted_at)
+        values ($1, $2, $3, $4, now())
+        "#,
+        Uuid::new_v4(),
+        username,
+        client_ip,
+        reason
+    )
+    .execute(pool)
+    .await
+    .map_err(AuthError::UnexpectedError)?;
+    Ok(())
+}
+
+/// Deletes recorded failures older than `failure_retention_seconds`,
+/// returning how many were deleted.
+pub async fn prune_failures(
+    pool: &PgPool,
+    config: &LoginThrottleConfig,
+) -> Result<u64, sqlx::Error> {
+    let retain_after =
+        Utc::now() - chrono::Duration::seconds(config.failure_retention_seconds as i64);
+    let result = sqlx::query!("delete from login_failures where attempted_at < $1", retain_after)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Prunes recorded failures every `PRUNE_INTERVAL` until `shutdown` is set.
+pub async fn prune_failures_until_stopped(
+    pool: PgPool,
+    config: LoginThrottleConfig,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    while !*shutdown.borrow() {
+        match prune_failures(&pool, &config).await {
+            Ok(pruned) => tracing::debug!(pruned, "Pruned recorded login failures"),
+            Err(e) => tracing::error!(error.cause_chain = ?e, "Failed to prune login failures"),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(PRUNE_INTERVAL) => {}
+            _ = shutdown.changed() => {}
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct Lockout {
+    pub key: String,
+    pub failures: i32,
+    pub locked_until: DateTime<Utc>,
+}
+
+pub async fn active_lockouts(pool: &PgPool) -> Result<Vec<Lockout>, sqlx::Error> {
+    sqlx::query_as!(
+        Lockout,
+        r#"
+        select key, failures, locked_until as "locked_until!"
+        from login_throttle
+        where locked_until > now()
+        order by locked_until desc
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Lifts the lockout of a username and/or an IP. Returns the number of
+/// cleared entries.
+pub async fn unlock(
+    pool: &PgPool,
+    username: Option<&str>,
+    ip: Option<&str>,
+) -> Result<u64, sqlx::Error> {
+    let keys: Vec<String> = username
+        .map(|u| format!("user:{}", u))
+        .into_iter()
+        .chain(ip.map(|ip| format!("ip:{}", ip)))
+        .collect();
+    let result = sqlx::query!("delete from login_throttle where key = any($1)", &keys[..])
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LoginThrottleConfig {
+        LoginThrottleConfig {
+            free_attempts_per_username: 3,
+            free_attempts_per_ip: 10,
+            base_lockout_seconds: 2,
+            max_lockout_seconds: 60,
+            reset_after_seconds: 3600,
+            failure_retention_seconds: 86400,
+        }
+    }
+
+    #[test]
+    fn no_lockout_within_free_attempts() {
+        assert_eq!(lockout_duration(0, 3, &config()), None);
+        assert_eq!(lockout_duration(3, 3, &config()), None);
+    }
+
+    #[test]
+    fn lockout_grows_exponentially() {
+        assert_eq!(
+            lockout_duration(4, 3, &config()),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            lockout_duration(5, 3, &config()),
+            Some(Duration::from_secs(4))
+        );
+        assert_eq!(
+            lockout_duration(6, 3, &config()),
+            Some(Duration::from_secs(8))
+        );
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        assert_eq!(
+            lockout_duration(100, 3, &config()),
+            Some(Duration::from_secs(60))
+        );
+    }
+}
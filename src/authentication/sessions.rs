@@ -0,0 +1,93 @@
+use super::api_tokens::{hash_token, random_token};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Prefix telling session tokens apart from API tokens, both are sent as
+/// Bearer tokens.
+const SESSION_PREFIX: &str = "ems_";
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Session {
+    #[schema(value_type = String, format = "uuid")]
+    pub session_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub fn is_session_token(token: &str) -> bool {
+    token.starts_with(SESSION_PREFIX)
+}
+
+/// Opens a session for `user_id` lasting `ttl` and returns it together with
+/// the plain token, which is not retrievable afterwards. Expired sessions of
+/// the user are dropped on the way.
+#[tracing::instrument(name = "Open a session", skip(pool))]
+pub async fn open_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    ttl: Duration,
+) -> Result<(Session, String), sqlx::Error> {
+    let plain = random_token(SESSION_PREFIX);
+    let expires_at = Utc::now() + chrono::Duration::from_std(ttl).unwrap_or_default();
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        delete from login_sessions
+        where user_id = $1 and (expires_at <= now() or ended_at is not null)
+        "#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    let session = sqlx::query_as!(
+        Session,
+        r#"
+        insert into login_sessions (session_id, user_id, token_hash, created_at, expires_at)
+        values ($1, $2, $3, now(), $4)
+        returning session_id, expires_at
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        hash_token(&plain),
+        expires_at
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok((session, plain))
+}
+
+pub struct ValidSession {
+    pub session_id: Uuid,
+    pub user_id: Uuid,
+}
+
+/// Looks up a presented session token that has neither expired nor ended.
+pub async fn find_session(pool: &PgPool, token: &str) -> Result<Option<ValidSession>, sqlx::Error> {
+    sqlx::query_as!(
+        ValidSession,
+        r#"
+        select session_id, user_id
+        from login_sessions
+        where token_hash = $1 and ended_at is null and expires_at > now()
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Returns whether the session was still open.
+#[tracing::instrument(name = "End a session", skip(pool))]
+pub async fn end_session(pool: &PgPool, session_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "update login_sessions set ended_at = now() where session_id = $1 and ended_at is null",
+        session_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
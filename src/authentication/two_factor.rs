@@ -0,0 +1,246 @@
+use super::totp::TotpSecret;
+use crate::config::TwoFactorConfig;
+use chrono::Utc;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha3::Digest;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct TwoFactorState {
+    pub enabled: bool,
+    secret: Option<String>,
+    last_step: Option<i64>,
+}
+
+pub async fn two_factor_state(pool: &PgPool, user_id: Uuid) -> Result<TwoFactorState, sqlx::Error> {
+    let row = sqlx::query!(
+        "select totp_enabled, totp_secret, totp_last_step from users where user_id = $1",
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(TwoFactorState {
+        enabled: row.totp_enabled,
+        secret: row.totp_secret,
+        last_step: row.totp_last_step,
+    })
+}
+
+pub async fn two_factor_required(pool: &PgPool) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!("select require_two_factor from admin_settings")
+        .fetch_one(pool)
+        .await?;
+    Ok(row.require_two_factor)
+}
+
+#[tracing::instrument(name = "Change the two-factor requirement", skip(pool))]
+pub async fn set_two_factor_required(pool: &PgPool, required: bool) -> Result<(), sqlx::Error> {
+    sqlx::query!("update admin_settings set require_two_factor = $1", required)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Outcome of checking a second factor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecondFactor {
+    Valid,
+    /// A TOTP code of a step that was already used.
+    Replayed,
+    Invalid,
+}
+
+/// Checks a TOTP code, or failing that a recovery code, for a user with
+/// two-factor authentication enabled. Both kinds of codes are single use.
+#[tracing::instrument(name = "Verify second factor", skip(pool, state, code, config))]
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    state: &TwoFactorState,
+    code: &str,
+    config: &TwoFactorConfig,
+) -> Result<SecondFactor, sqlx::Error> {
+    if let Some(secret) = state.secret.as_deref().and_then(|s| TotpSecret::parse(s).ok()) {
+        if let Some(step) = secret.verify(code, Utc::now().timestamp(), config.skew_steps) {
+            if state.last_step.is_some_and(|last| step <= last) {
+                tracing::warn!("Rejected a replayed TOTP code");
+                return Ok(SecondFactor::Replayed);
+            }
+            // The condition guards against two concurrent logins using the
+            // same code.
+            let result = sqlx::query!(
+                r#"
+                update users set totp_last_step = $2
+                where user_id = $1 and (totp_last_step is null or totp_last_step < $2)
+                "#,
+                user_id,
+                step
+            )
+            .execute(pool)
+            .await?;
+            return Ok(match result.rows_affected() {
+                1 => SecondFactor::Valid,
+                _ => SecondFactor::Replayed,
+            });
+        }
+    }
+    match use_recovery_code(pool, user_id, code).await? {
+        true => Ok(SecondFactor::Valid),
+        false => Ok(SecondFactor::Invalid),
+    }
+}
+
+async fn use_recovery_code(pool: &PgPool, user_id: Uuid, code: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        update recovery_codes set used_at = now()
+        where user_id = $1 and code_hash = $2 and used_at is null
+        "#,
+        user_id,
+        hash_recovery_code(code)
+    )
+    .execute(pool)
+    .await?;
+    if result.rows_affected() == 1 {
+        tracing::info!("Used a recovery code");
+    }
+    Ok(result.rows_affected() == 1)
+}
+
+/// Stores a fresh pending secret. It only takes effect once a code generated
+/// from it has been confirmed.
+#[tracing::instrument(name = "Begin TOTP enrolment", skip(pool))]
+pub async fn begin_enrolment(pool: &PgPool, user_id: Uuid) -> Result<TotpSecret, sqlx::Error> {
+    let secret = TotpSecret::generate();
+    sqlx::query!(
+        r#"
+        update users set totp_secret = $2, totp_last_step = null
+        where user_id = $1 and not totp_enabled
+        "#,
+        user_id,
+        secret.as_base32()
+    )
+    .execute(pool)
+    .await?;
+    Ok(secret)
+}
+
+/// Enables two-factor authentication if `code` matches the pending secret
+/// and returns a new set of recovery codes.
+#[tracing::instrument(name = "Confirm TOTP enrolment", skip(pool, code, config))]
+pub async fn confirm_enrolment(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+    config: &TwoFactorConfig,
+) -> Result<Option<Vec<String>>, sqlx::Error> {
+    let state = two_factor_state(pool, user_id).await?;
+    let secret = match state.secret.as_deref().map(TotpSecret::parse) {
+        Some(Ok(secret)) if !state.enabled => secret,
+        _ => return Ok(None),
+    };
+    let step = match secret.verify(code, Utc::now().timestamp(), config.skew_steps) {
+        Some(step) => step,
+        None => return Ok(None),
+    };
+    sqlx::query!(
+        "update users set totp_enabled = true, totp_last_step = $2 where user_id = $1",
+        user_id,
+        step
+    )
+    .execute(pool)
+    .await?;
+    regenerate_recovery_codes(pool, user_id, config).await.map(Some)
+}
+
+/// Replaces all recovery codes of a user. Only the hashes are stored, the
+/// plain codes are returned to be shown once.
+#[tracing::instrument(name = "Regenerate recovery codes", skip(pool, config))]
+pub async fn regenerate_recovery_codes(
+    pool: &PgPool,
+    user_id: Uuid,
+    config: &TwoFactorConfig,
+) -> Result<Vec<String>, sqlx::Error> {
+    let codes: Vec<String> = (0..config.recovery_codes)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<String> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+    let mut transaction = pool.begin().await?;
+    sqlx::query!("delete from recovery_codes where user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query!(
+        r#"
+        insert into recovery_codes (user_id, code_hash)
+        select $1, unnest($2::text[])
+        "#,
+        user_id,
+        &hashes[..]
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(codes)
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(pool))]
+pub async fn disable(pool: &PgPool, user_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        update users set totp_enabled = false, totp_secret = null, totp_last_step = null
+        where user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!("delete from recovery_codes where user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await
+}
+
+fn generate_recovery_code() -> String {
+    let raw: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .filter(|c| !c.is_ascii_uppercase())
+        .take(10)
+        .collect();
+    format!("{}-{}", &raw[..5], &raw[5..])
+}
+
+/// Recovery codes are compared case-insensitively and without the dash, as
+/// people tend to retype them.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .trim()
+        .chars()
+        .filter(|c| *c != '-')
+        .collect::<String>()
+        .to_lowercase();
+    format!("{:x}", sha3::Sha3_256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_codes_are_formatted_in_two_groups() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(code.chars().nth(5), Some('-'));
+        assert!(code
+            .chars()
+            .all(|c| c == '-' || c.is_ascii_lowercase() || c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn recovery_code_hash_ignores_case_and_dash() {
+        assert_eq!(hash_recovery_code("abcde-12345"), hash_recovery_code(" ABCDE12345 "));
+        assert_ne!(hash_recovery_code("abcde-12345"), hash_recovery_code("abcde-12346"));
+    }
+}
@@ -0,0 +1,227 @@
+use crate::audit::user_agent;
+use crate::authentication::{app_data, authenticate_request, AuthError, Credential, TokenScope};
+use crate::rate_limit::RateLimiter;
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::future::Future;
+use std::pin::Pin;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// The list every subscriber and issue belongs to unless told otherwise.
+pub const DEFAULT_LIST_ID: Uuid = Uuid::from_u128(1);
+
+/// Roles are ordered, each one can do everything the previous one can.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "viewer" => Some(Role::Viewer),
+            "editor" => Some(Role::Editor),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ViewSubscribers,
+    DraftIssues,
+    PublishIssues,
+    ExportSubscribers,
+    ManageLists,
+    ManageUsers,
+    ManageSettings,
+    ViewAuditLog,
+}
+
+impl Permission {
+    pub fn minimum_role(&self) -> Role {
+        match self {
+            Permission::ViewSubscribers => Role::Viewer,
+            Permission::DraftIssues => Role::Editor,
+            Permission::PublishIssues
+            | Permission::ExportSubscribers
+            | Permission::ManageLists
+            | Permission::ManageUsers
+            | Permission::ManageSettings
+            | Permission::ViewAuditLog => Role::Owner,
+        }
+    }
+
+    /// Scope an API token needs for this permission. Permissions without one
+    /// require an interactive login.
+    pub fn token_scope(&self) -> Option<TokenScope> {
+        match self {
+            Permission::DraftIssues | Permission::PublishIssues => {
+                Some(TokenScope::NewslettersPublish)
+            }
+            Permission::ViewSubscribers | Permission::ExportSubscribers => {
+                Some(TokenScope::SubscribersRead)
+            }
+            Permission::ManageLists
+            | Permission::ManageUsers
+            | Permission::ManageSettings
+            | Permission::ViewAuditLog => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self {
+            Permission::ViewSubscribers => "view subscribers",
+            Permission::DraftIssues => "draft issues",
+            Permission::PublishIssues => "publish issues",
+            Permission::ExportSubscribers => "export subscribers",
+            Permission::ManageLists => "manage lists",
+            Permission::ManageUsers => "manage users",
+            Permission::ManageSettings => "manage settings",
+            Permission::ViewAuditLog => "view the audit log",
+        };
+        f.write_str(action)
+    }
+}
+
+/// A user authenticated with Basic credentials, a session or an API token.
+/// Extracting it rejects unauthenticated requests, `require` then checks what
+/// the user may do.
+#[derive(Debug)]
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub credential: Credential,
+    pub client_ip: String,
+    pub user_agent: Option<String>,
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AuthError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let (user_id, credential) = authenticate_request(&req).await?;
+            let pool = app_data::<PgPool>(&req);
+            let role = user_role(pool, user_id).await?;
+            Ok(AuthenticatedUser {
+                user_id,
+                role,
+                credential,
+                client_ip: app_data::<RateLimiter>(&req).client_ip(&req),
+                user_agent: user_agent(&req),
+            })
+        })
+    }
+}
+
+impl AuthenticatedUser {
+    /// Checks that the user may exercise `permission`, on `list_id` if given.
+    /// A role granted on a list adds to the user's role for that list.
+    pub async fn require(
+        &self,
+        pool: &PgPool,
+        permission: Permission,
+        list_id: Option<Uuid>,
+    ) -> Result<(), AuthError> {
+        if let Credential::ApiToken { scopes, .. } = &self.credential {
+            match permission.token_scope() {
+                Some(scope) if scopes.contains(&scope) => {}
+                Some(scope) => return Err(AuthError::MissingScope(scope)),
+                None => return Err(AuthError::Forbidden(permission)),
+            }
+        }
+        let list_role = match list_id {
+            Some(list_id) => list_role(pool, self.user_id, list_id).await?,
+            None => None,
+        };
+        let role = list_role.map_or(self.role, |list_role| list_role.max(self.role));
+        if role < permission.minimum_role() {
+            tracing::warn!(
+                user_id = %self.user_id,
+                role = role.as_str(),
+                %permission,
+                "Rejected an unauthorized action"
+            );
+            return Err(AuthError::Forbidden(permission));
+        }
+        Ok(())
+    }
+}
+
+async fn user_role(pool: &PgPool, user_id: Uuid) -> Result<Role, AuthError> {
+    let row = sqlx::query!("select role from users where user_id = $1", user_id)
+        .fetch_one(pool)
+        .await
+        .map_err(AuthError::UnexpectedError)?;
+    Ok(Role::parse(&row.role).unwrap_or(Role::Viewer))
+}
+
+async fn list_role(pool: &PgPool, user_id: Uuid, list_id: Uuid) -> Result<Option<Role>, AuthError> {
+    let row = sqlx::query!(
+        "select role from list_roles where user_id = $1 and list_id = $2",
+        user_id,
+        list_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(AuthError::UnexpectedError)?;
+    Ok(row.and_then(|row| Role::parse(&row.role)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_are_ordered_by_privilege() {
+        assert!(Role::Owner > Role::Editor);
+        assert!(Role::Editor > Role::Viewer);
+    }
+
+    #[test]
+    fn editors_can_draft_but_not_publish() {
+        assert!(Role::Editor >= Permission::DraftIssues.minimum_role());
+        assert!(Role::Editor < Permission::PublishIssues.minimum_role());
+        assert!(Role::Editor < Permission::ExportSubscribers.minimum_role());
+    }
+
+    #[test]
+    fn management_is_not_available_to_tokens() {
+        assert_eq!(Permission::ManageUsers.token_scope(), None);
+        assert_eq!(Permission::ManageSettings.token_scope(), None);
+        assert_eq!(
+            Permission::PublishIssues.token_scope(),
+            Some(TokenScope::NewslettersPublish)
+        );
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_names() {
+        for role in [Role::Viewer, Role::Editor, Role::Owner] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse("admin"), None);
+    }
+}
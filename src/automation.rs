@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgExecutor;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Something that happens to a subscriber that lists can send an email for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AutomationEvent {
+    /// The subscriber followed the confirmation link, e.g. for a welcome
+    /// email.
+    Confirmed,
+    Unsubscribed,
+}
+
+impl AutomationEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AutomationEvent::Confirmed => "confirmed",
+            AutomationEvent::Unsubscribed => "unsubscribed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "confirmed" => Some(AutomationEvent::Confirmed),
+            "unsubscribed" => Some(AutomationEvent::Unsubscribed),
+            _ => None,
+        }
+    }
+}
+
+/// Queues the email the list of the subscriber has for `event`, if any.
+/// The delivery workers send it like an issue, in the language of the
+/// subscriber and with its merge tags filled in.
+///
+/// Call it in the transaction that records the event, so that the email is
+/// queued if and only if the event happened.
+#[tracing::instrument(name = "Trigger an automation", skip(executor))]
+pub async fn trigger<'e>(
+    executor: impl PgExecutor<'e>,
+    event: AutomationEvent,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        insert into issue_delivery_queue (newsletter_issue_id, subscriber_id)
+        select a.newsletter_issue_id, s.id
+        from subscriptions s
+        join automations a on a.list_id = s.list_id and a.event = $2
+        where s.id = $1
+        on conflict do nothing
+        "#,
+        subscriber_id,
+        event.as_str()
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
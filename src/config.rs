@@ -1,27 +1,96 @@
-use crate::domain::SubscriberEmail;
+use crate::domain::{SubscriberEmail, SubscriberEmailError};
+use crate::email_client::EmailClient;
+use crate::error::error_chain_fmt;
+use crate::localisation::canonical_locale;
+use crate::secret::Secret;
+use reqwest::Url;
 use serde::Deserialize;
-use serde_aux::field_attributes::deserialize_number_from_string;
-use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgSslMode};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::time::Duration;
 
 #[derive(Deserialize)]
 pub struct Config {
     pub application: AppConfig,
     pub database: DatabaseConfig,
     pub email_client: EmailClientConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub delivery: DeliveryConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub spam_trap: SpamTrapConfig,
+    #[serde(default)]
+    pub login_throttle: LoginThrottleConfig,
+    #[serde(default)]
+    pub two_factor: TwoFactorConfig,
+    #[serde(default)]
+    pub deliverability: DeliverabilityConfig,
+    #[serde(default)]
+    pub localisation: LocalisationConfig,
 }
 
 #[derive(Deserialize)]
 pub struct DatabaseConfig {
     pub username: String,
-    pub password: String,
+    pub password: Secret,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
     pub database_name: String,
     pub require_ssl: bool,
+    #[serde(
+        default = "default_max_connections",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub max_connections: u32,
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    pub min_connections: u32,
+    #[serde(
+        default = "default_acquire_timeout_milliseconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub acquire_timeout_milliseconds: u64,
+    /// Open connections on first use instead of at startup, so the app can
+    /// boot while Postgres is briefly unavailable.
+    #[serde(default = "default_true")]
+    pub connect_lazy: bool,
+}
+
+fn default_max_connections() -> u32 {
+    10
+}
+
+fn default_acquire_timeout_milliseconds() -> u64 {
+    2000
+}
+
+fn default_true() -> bool {
+    true
 }
 
 impl DatabaseConfig {
+    pub fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .connect_timeout(Duration::from_millis(self.acquire_timeout_milliseconds))
+    }
+
+    pub async fn connect(&self) -> Result<PgPool, sqlx::Error> {
+        if self.connect_lazy {
+            Ok(self.pool_options().connect_lazy_with(self.with_db()))
+        } else {
+            self.pool_options().connect_with(self.with_db()).await
+        }
+    }
+
     pub fn with_db(&self) -> PgConnectOptions {
         self.without_db().database(&self.database_name)
     }
@@ -34,7 +103,7 @@ impl DatabaseConfig {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password)
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
@@ -46,27 +115,596 @@ pub struct AppConfig {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    /// Number of actix workers, defaults to the number of physical cores.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub workers: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub keep_alive_seconds: Option<u64>,
+    /// Maximum size of JSON, form and raw request bodies.
+    #[serde(
+        default = "default_payload_limit_bytes",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub payload_limit_bytes: usize,
+    /// How long in-flight requests and delivery workers get to finish after a
+    /// shutdown signal before they are cut off.
+    #[serde(
+        default = "default_shutdown_grace_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub shutdown_grace_seconds: u64,
+    /// Serve a Swagger UI page rendering the OpenAPI document at `/api/docs/`.
+    #[serde(default)]
+    pub serve_api_docs: bool,
+    /// Directory with the templates of the hosted pages. Templates in
+    /// `lists/<list_id>/` override the default ones for that list.
+    #[serde(default = "default_templates_dir")]
+    pub templates_dir: String,
+    #[serde(default)]
+    pub redirects: PageRedirects,
+}
+
+fn default_templates_dir() -> String {
+    "templates/pages".to_string()
+}
+
+/// Pages of our own site the hosted pages send people to instead of showing
+/// their own success page.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PageRedirects {
+    pub after_subscribe: Option<String>,
+    pub after_confirm: Option<String>,
+    pub after_unsubscribe: Option<String>,
+}
+
+fn default_payload_limit_bytes() -> usize {
+    256 * 1024
+}
+
+fn default_shutdown_grace_seconds() -> u64 {
+    30
 }
 
 impl AppConfig {
     pub fn address(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
+
+    pub fn keep_alive(&self) -> Option<Duration> {
+        self.keep_alive_seconds.map(Duration::from_secs)
+    }
+
+    pub fn shutdown_grace(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_seconds)
+    }
 }
 
 #[derive(Deserialize)]
 pub struct EmailClientConfig {
     pub base_url: String,
     pub sender_email: String,
-    pub auth_token: String,
+    pub auth_token: Secret,
+    #[serde(default)]
+    pub check_on_readiness: bool,
+    #[serde(
+        default = "default_timeout_milliseconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub timeout_milliseconds: u64,
+}
+
+fn default_timeout_milliseconds() -> u64 {
+    5000
 }
 
 impl EmailClientConfig {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+    pub fn sender(&self) -> Result<SubscriberEmail, SubscriberEmailError> {
         self.sender_email.clone().try_into()
     }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+
+    /// Only for configurations that passed [`Config::validate`].
+    pub fn client(&self) -> EmailClient {
+        EmailClient::new(
+            self.base_url.clone(),
+            self.sender().expect("Config::validate checks the sender email"),
+            self.auth_token.clone(),
+            self.timeout(),
+        )
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeliveryConfig {
+    /// Number of background workers sending queued newsletter issues.
+    #[serde(default = "default_delivery_workers", deserialize_with = "deserialize_number_from_string")]
+    pub workers: usize,
+    #[serde(
+        default = "default_poll_interval_milliseconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub poll_interval_milliseconds: u64,
+    /// Failed sends are retried with exponential backoff this many times
+    /// before the task is dropped.
+    #[serde(default = "default_max_retries", deserialize_with = "deserialize_number_from_string")]
+    pub max_retries: i32,
+}
+
+fn default_delivery_workers() -> usize {
+    1
+}
+
+fn default_poll_interval_milliseconds() -> u64 {
+    1000
+}
+
+fn default_max_retries() -> i32 {
+    5
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            workers: default_delivery_workers(),
+            poll_interval_milliseconds: default_poll_interval_milliseconds(),
+            max_retries: default_max_retries(),
+        }
+    }
+}
+
+impl DeliveryConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_milliseconds)
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub store: RateLimitStoreKind,
+    /// Take the client IP from `X-Forwarded-For`. Only enable this behind a
+    /// proxy that overwrites the header, otherwise clients can pick their IP.
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+    /// Limits keyed by route name, e.g. `subscriptions`.
+    #[serde(default)]
+    pub routes: HashMap<String, RouteLimits>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStoreKind {
+    /// Per instance counters, enough for a single instance deployment.
+    #[default]
+    Memory,
+    /// Counters shared by all instances through the database.
+    Postgres,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, Debug)]
+pub struct RouteLimits {
+    pub per_ip: Option<Limit>,
+    /// Applied to the email address the request targets.
+    pub per_email: Option<Limit>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_requests: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+}
+
+#[derive(Deserialize, Default, Clone)]
+pub struct SpamTrapConfig {
+    /// Forms that include the `form_rendered_at` field and are submitted
+    /// faster than this are silently dropped. `0` disables the check.
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    pub min_form_fill_seconds: u64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LoginThrottleConfig {
+    /// Failed logins allowed for a username before it gets locked out.
+    #[serde(
+        default = "default_free_attempts_per_username",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub free_attempts_per_username: i32,
+    #[serde(
+        default = "default_free_attempts_per_ip",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub free_attempts_per_ip: i32,
+    /// First lockout duration, doubled with every further failure.
+    #[serde(
+        default = "default_base_lockout_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub base_lockout_seconds: u64,
+    #[serde(
+        default = "default_max_lockout_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub max_lockout_seconds: u64,
+    /// Failure counters start over after this long without failures.
+    #[serde(
+        default = "default_reset_after_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub reset_after_seconds: u64,
+    /// Recorded failures are kept this long for investigations, then
+    /// deleted in the background. Lockouts don't depend on them.
+    #[serde(
+        default = "default_failure_retention_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub failure_retention_seconds: u64,
+}
+
+fn default_free_attempts_per_username() -> i32 {
+    5
+}
+
+fn default_free_attempts_per_ip() -> i32 {
+    20
+}
+
+fn default_base_lockout_seconds() -> u64 {
+    1
+}
+
+fn default_max_lockout_seconds() -> u64 {
+    15 * 60
+}
+
+fn default_reset_after_seconds() -> u64 {
+    60 * 60
+}
+
+fn default_failure_retention_seconds() -> u64 {
+    90 * 24 * 60 * 60
+}
+
+impl Default for LoginThrottleConfig {
+    fn default() -> Self {
+        Self {
+            free_attempts_per_username: default_free_attempts_per_username(),
+            free_attempts_per_ip: default_free_attempts_per_ip(),
+            base_lockout_seconds: default_base_lockout_seconds(),
+            max_lockout_seconds: default_max_lockout_seconds(),
+            reset_after_seconds: default_reset_after_seconds(),
+            failure_retention_seconds: default_failure_retention_seconds(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TwoFactorConfig {
+    /// Issuer shown by authenticator apps next to the account name.
+    #[serde(default = "default_totp_issuer")]
+    pub issuer: String,
+    /// Codes of this many 30 second steps before and after the current one
+    /// are accepted, to tolerate clock drift.
+    #[serde(
+        default = "default_totp_skew_steps",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub skew_steps: i64,
+    #[serde(
+        default = "default_recovery_codes",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub recovery_codes: usize,
+    /// How long a session opened with the password and a code stays valid,
+    /// so that admins enter a code once rather than on every request.
+    #[serde(
+        default = "default_session_minutes",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub session_minutes: u64,
+}
+
+impl TwoFactorConfig {
+    pub fn session_ttl(&self) -> Duration {
+        Duration::from_secs(self.session_minutes * 60)
+    }
+}
+
+fn default_totp_issuer() -> String {
+    "emailer".to_string()
+}
+
+fn default_totp_skew_steps() -> i64 {
+    1
+}
+
+fn default_recovery_codes() -> usize {
+    10
+}
+
+fn default_session_minutes() -> u64 {
+    60
+}
+
+impl Default for TwoFactorConfig {
+    fn default() -> Self {
+        Self {
+            issuer: default_totp_issuer(),
+            skew_steps: default_totp_skew_steps(),
+            recovery_codes: default_recovery_codes(),
+            session_minutes: default_session_minutes(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DeliverabilityConfig {
+    /// Domains of throwaway address providers, subdomains included.
+    #[serde(default)]
+    pub disposable_domains: Vec<String>,
+    /// Reject addresses at domains a typo away from a large provider's until
+    /// the subscriber confirms them.
+    #[serde(default = "default_suggest_typos")]
+    pub suggest_typos: bool,
+    #[serde(default)]
+    pub mx_check: MxCheckConfig,
+}
+
+fn default_suggest_typos() -> bool {
+    true
+}
+
+impl Default for DeliverabilityConfig {
+    fn default() -> Self {
+        Self {
+            disposable_domains: Vec::new(),
+            suggest_typos: default_suggest_typos(),
+            mx_check: MxCheckConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LocalisationConfig {
+    /// Directory with one subdirectory of Fluent catalogues per locale.
+    #[serde(default = "default_catalogues_dir")]
+    pub catalogues_dir: String,
+    /// Locale of subscribers who don't ask for one we have, and the last
+    /// stop of every fallback chain.
+    #[serde(default = "default_locale")]
+    pub default_locale: String,
+}
+
+fn default_catalogues_dir() -> String {
+    "locales".to_string()
+}
+
+fn default_locale() -> String {
+    "en".to_string()
+}
+
+impl Default for LocalisationConfig {
+    fn default() -> Self {
+        Self {
+            catalogues_dir: default_catalogues_dir(),
+            default_locale: default_locale(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct MxCheckConfig {
+    #[serde(default)]
+    pub resolver: MxResolverKind,
+    #[serde(
+        default = "default_mx_timeout_milliseconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub timeout_milliseconds: u64,
+    /// Domains the stub resolver reports as not accepting mail.
+    #[serde(default)]
+    pub stub_domains_without_mx: Vec<String>,
+}
+
+fn default_mx_timeout_milliseconds() -> u64 {
+    2000
+}
+
+impl MxCheckConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+impl Default for MxCheckConfig {
+    fn default() -> Self {
+        Self {
+            resolver: MxResolverKind::default(),
+            timeout_milliseconds: default_mx_timeout_milliseconds(),
+            stub_domains_without_mx: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MxResolverKind {
+    /// Don't look up mail servers.
+    #[default]
+    Disabled,
+    /// Ask the nameservers of the system resolver configuration.
+    Dns,
+    /// Answer from `stub_domains_without_mx`, for local development.
+    Stub,
+}
+
+#[derive(Deserialize, Default)]
+pub struct TelemetryConfig {
+    /// Full OTLP/HTTP traces endpoint, e.g. `http://localhost:4318/v1/traces`.
+    /// Spans are only exported when this is set.
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Config {
+    /// Checks every field and returns all problems found, so that a broken
+    /// deployment can be fixed in one go.
+    pub fn validate(&self, env: Environment) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        if self.application.host.trim().is_empty() {
+            problems.push("application.host must not be empty".to_string());
+        }
+        if env == Environment::Production && self.application.port == 0 {
+            problems.push("application.port must not be 0 in production".to_string());
+        }
+        let allowed_schemes: &[&str] = match env {
+            Environment::Local => &["http", "https"],
+            Environment::Production => &["https"],
+        };
+        check_url(
+            &mut problems,
+            "application.base_url",
+            &self.application.base_url,
+            allowed_schemes,
+        );
+        let redirects = &self.application.redirects;
+        for (name, value) in [
+            ("application.redirects.after_subscribe", &redirects.after_subscribe),
+            ("application.redirects.after_confirm", &redirects.after_confirm),
+            ("application.redirects.after_unsubscribe", &redirects.after_unsubscribe),
+        ] {
+            if let Some(url) = value {
+                check_url(&mut problems, name, url, allowed_schemes);
+            }
+        }
+
+        for (name, value) in [
+            ("database.host", &self.database.host),
+            ("database.username", &self.database.username),
+            ("database.database_name", &self.database.database_name),
+        ] {
+            if value.trim().is_empty() {
+                problems.push(format!("{} must not be empty", name));
+            }
+        }
+        if self.database.port == 0 {
+            problems.push("database.port must not be 0".to_string());
+        }
+        if self.database.max_connections == 0 {
+            problems.push("database.max_connections must not be 0".to_string());
+        }
+        if self.database.min_connections > self.database.max_connections {
+            problems.push(
+                "database.min_connections must not be greater than database.max_connections"
+                    .to_string(),
+            );
+        }
+        if self.database.acquire_timeout_milliseconds == 0 {
+            problems.push("database.acquire_timeout_milliseconds must not be 0".to_string());
+        }
+        if self.application.workers == Some(0) {
+            problems.push("application.workers must not be 0".to_string());
+        }
+        if self.application.payload_limit_bytes == 0 {
+            problems.push("application.payload_limit_bytes must not be 0".to_string());
+        }
+
+        check_url(
+            &mut problems,
+            "email_client.base_url",
+            &self.email_client.base_url,
+            &["http", "https"],
+        );
+        if self.delivery.poll_interval_milliseconds == 0 {
+            problems.push("delivery.poll_interval_milliseconds must not be 0".to_string());
+        }
+        if self.delivery.max_retries < 0 {
+            problems.push("delivery.max_retries must not be negative".to_string());
+        }
+        for (route, limits) in &self.rate_limit.routes {
+            for (scope, limit) in [("per_ip", limits.per_ip), ("per_email", limits.per_email)] {
+                if let Some(limit) = limit {
+                    if limit.max_requests == 0 || limit.window_seconds == 0 {
+                        problems.push(format!(
+                            "rate_limit.routes.{}.{} must have non-zero max_requests and window_seconds",
+                            route, scope
+                        ));
+                    }
+                }
+            }
+        }
+        if self.login_throttle.free_attempts_per_username < 0
+            || self.login_throttle.free_attempts_per_ip < 0
+        {
+            problems.push("login_throttle free attempts must not be negative".to_string());
+        }
+        if self.login_throttle.base_lockout_seconds > self.login_throttle.max_lockout_seconds {
+            problems.push(
+                "login_throttle.base_lockout_seconds must not be greater than max_lockout_seconds"
+                    .to_string(),
+            );
+        }
+        if self.two_factor.issuer.trim().is_empty() {
+            problems.push("two_factor.issuer must not be empty".to_string());
+        }
+        if !(0..=10).contains(&self.two_factor.skew_steps) {
+            problems.push("two_factor.skew_steps must be between 0 and 10".to_string());
+        }
+        if self.two_factor.recovery_codes == 0 {
+            problems.push("two_factor.recovery_codes must not be 0".to_string());
+        }
+        if !(1..=1440).contains(&self.two_factor.session_minutes) {
+            problems.push("two_factor.session_minutes must be between 1 and 1440".to_string());
+        }
+        if self.deliverability.mx_check.timeout_milliseconds == 0 {
+            problems.push("deliverability.mx_check.timeout_milliseconds must not be 0".to_string());
+        }
+        if canonical_locale(&self.localisation.default_locale).is_none() {
+            problems.push("localisation.default_locale must be a language tag".to_string());
+        }
+        if self.email_client.timeout_milliseconds == 0 {
+            problems.push("email_client.timeout_milliseconds must not be 0".to_string());
+        }
+        if let Err(e) = self.email_client.sender() {
+            problems.push(format!("email_client.sender_email is not valid: {}", e));
+        }
+
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint {
+            check_url(
+                &mut problems,
+                "telemetry.otlp_endpoint",
+                endpoint,
+                &["http", "https"],
+            );
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
 }
 
+fn check_url(problems: &mut Vec<String>, name: &str, value: &str, schemes: &[&str]) {
+    match Url::parse(value) {
+        Ok(url) if schemes.contains(&url.scheme()) => {}
+        Ok(url) => problems.push(format!(
+            "{} must use one of the schemes {:?}, got `{}`",
+            name,
+            schemes,
+            url.scheme()
+        )),
+        Err(e) => problems.push(format!("{} is not a valid URL `{}`: {}", name, value, e)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Environment {
     Local,
     Production,
@@ -95,18 +733,264 @@ impl TryFrom<String> for Environment {
     }
 }
 
-pub fn read_config() -> Result<Config, config::ConfigError> {
-    let curr_dir = std::env::current_dir().expect("Failed to determine the current directory");
+#[derive(thiserror::Error)]
+pub enum ReadConfigError {
+    #[error("Failed to determine the current directory")]
+    CurrentDirError(#[source] std::io::Error),
+    #[error("Failed to parse APP_ENV: {0}")]
+    EnvironmentError(String),
+    #[error("Failed to load configuration")]
+    LoadConfigError(#[source] config::ConfigError),
+    #[error("Invalid configuration:\n  - {}", .0.join("\n  - "))]
+    InvalidConfigError(Vec<String>),
+}
+
+impl std::fmt::Debug for ReadConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub fn read_config() -> Result<Config, ReadConfigError> {
+    let curr_dir = std::env::current_dir().map_err(ReadConfigError::CurrentDirError)?;
     let config_dir = curr_dir.join("config");
 
     let env: Environment = std::env::var("APP_ENV")
         .unwrap_or_else(|_| Into::<&str>::into(Environment::Local).to_string())
         .try_into()
-        .expect("Failed to parse APP_ENV");
+        .map_err(ReadConfigError::EnvironmentError)?;
 
-    let builder = config::Config::builder()
+    let mut builder = config::Config::builder()
         .add_source(config::File::from(config_dir.join("base.yaml")).required(true))
         .add_source(config::File::from(config_dir.join(Into::<&str>::into(env))).required(true))
         .add_source(config::Environment::with_prefix("app").separator("__"));
-    builder.build()?.try_deserialize()
+    for (key, value) in secret_files(std::env::vars()).map_err(ReadConfigError::LoadConfigError)? {
+        builder = builder
+            .set_override(key, value)
+            .map_err(ReadConfigError::LoadConfigError)?;
+    }
+    let config: Config = builder
+        .build()
+        .and_then(|c| c.try_deserialize())
+        .map_err(ReadConfigError::LoadConfigError)?;
+    config
+        .validate(env)
+        .map_err(ReadConfigError::InvalidConfigError)?;
+    Ok(config)
+}
+
+/// Values passed as `APP__SECTION__FIELD_FILE=/path` environment variables are
+/// read from the file and override `section.field`. This is how Docker and
+/// Kubernetes mount secrets.
+fn secret_files(
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<Vec<(String, String)>, config::ConfigError> {
+    vars.filter_map(|(name, path)| secret_file_key(&name).map(|key| (key, path)))
+        .map(|(key, path)| {
+            let value = std::fs::read_to_string(&path).map_err(|e| {
+                config::ConfigError::Message(format!(
+                    "Failed to read secret file {} for `{}`: {}",
+                    path, key, e
+                ))
+            })?;
+            Ok((key, value.trim_end_matches(['\r', '\n']).to_string()))
+        })
+        .collect()
+}
+
+fn secret_file_key(var_name: &str) -> Option<String> {
+    let key = var_name
+        .strip_prefix("APP__")?
+        .strip_suffix("_FILE")?
+        .to_lowercase()
+        .replace("__", ".");
+    (!key.is_empty()).then_some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_config() -> Config {
+        Config {
+            application: AppConfig {
+                port: 8080,
+                host: "0.0.0.0".to_string(),
+                base_url: "https://example.com".to_string(),
+                workers: None,
+                keep_alive_seconds: None,
+                payload_limit_bytes: default_payload_limit_bytes(),
+                shutdown_grace_seconds: default_shutdown_grace_seconds(),
+                serve_api_docs: false,
+                templates_dir: default_templates_dir(),
+                redirects: PageRedirects::default(),
+            },
+            database: DatabaseConfig {
+                username: "postgres".to_string(),
+                password: Secret::new("password".to_string()),
+                port: 5432,
+                host: "localhost".to_string(),
+                database_name: "newsletter".to_string(),
+                require_ssl: true,
+                max_connections: default_max_connections(),
+                min_connections: 0,
+                acquire_timeout_milliseconds: default_acquire_timeout_milliseconds(),
+                connect_lazy: true,
+            },
+            email_client: EmailClientConfig {
+                base_url: "https://api.postmarkapp.com".to_string(),
+                sender_email: "sender@example.com".to_string(),
+                auth_token: Secret::new("token".to_string()),
+                check_on_readiness: false,
+                timeout_milliseconds: default_timeout_milliseconds(),
+            },
+            telemetry: TelemetryConfig::default(),
+            delivery: DeliveryConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            spam_trap: SpamTrapConfig::default(),
+            login_throttle: LoginThrottleConfig::default(),
+            two_factor: TwoFactorConfig::default(),
+            deliverability: DeliverabilityConfig::default(),
+            localisation: LocalisationConfig::default(),
+        }
+    }
+
+    #[test]
+    fn valid_config_passes_validation() {
+        assert!(valid_config().validate(Environment::Production).is_ok());
+        assert!(valid_config().validate(Environment::Local).is_ok());
+    }
+
+    #[test]
+    fn validation_reports_all_problems() {
+        let mut config = valid_config();
+        config.application.port = 0;
+        config.application.base_url = "ftp://example.com".to_string();
+        config.email_client.base_url = "localhost".to_string();
+        config.email_client.sender_email = "not an email".to_string();
+
+        let problems = config.validate(Environment::Production).unwrap_err();
+
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+    }
+
+    #[test]
+    fn pool_sizes_are_validated() {
+        let mut config = valid_config();
+        config.database.min_connections = 20;
+        config.database.max_connections = 10;
+        assert!(config.validate(Environment::Local).is_err());
+
+        config.database.min_connections = 0;
+        config.database.max_connections = 0;
+        assert!(config.validate(Environment::Local).is_err());
+    }
+
+    #[test]
+    fn tuning_settings_use_defaults_when_omitted() {
+        let config: Config = config::Config::builder()
+            .add_source(config::File::from_str(
+                r#"
+application:
+  port: 8080
+  host: "127.0.0.1"
+  base_url: "http://127.0.0.1"
+database:
+  host: "localhost"
+  port: 5432
+  username: "postgres"
+  password: "password"
+  database_name: "newsletter"
+  require_ssl: false
+email_client:
+  base_url: "http://localhost"
+  sender_email: "test@gmail.com"
+  auth_token: "auth_token"
+"#,
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        assert_eq!(config.database.max_connections, 10);
+        assert_eq!(config.database.min_connections, 0);
+        assert!(config.database.connect_lazy);
+        assert_eq!(config.email_client.timeout(), Duration::from_secs(5));
+        assert_eq!(config.application.workers, None);
+        assert_eq!(config.application.payload_limit_bytes, 256 * 1024);
+    }
+
+    #[test]
+    fn port_zero_is_allowed_locally() {
+        let mut config = valid_config();
+        config.application.port = 0;
+        assert!(config.validate(Environment::Local).is_ok());
+    }
+
+    #[test]
+    fn plain_http_base_url_is_rejected_in_production() {
+        let mut config = valid_config();
+        config.application.base_url = "http://example.com".to_string();
+        assert!(config.validate(Environment::Local).is_ok());
+        assert!(config.validate(Environment::Production).is_err());
+    }
+
+    #[test]
+    fn redirects_must_be_urls_like_the_base_url() {
+        let mut config = valid_config();
+        config.application.redirects.after_confirm = Some("http://example.com/thanks".to_string());
+        assert!(config.validate(Environment::Local).is_ok());
+        assert!(config.validate(Environment::Production).is_err());
+
+        config.application.redirects.after_confirm = Some("/thanks".to_string());
+        assert!(config.validate(Environment::Local).is_err());
+    }
+
+    #[test]
+    fn secret_file_key_maps_env_name_to_config_key() {
+        assert_eq!(
+            secret_file_key("APP__DATABASE__PASSWORD_FILE"),
+            Some("database.password".to_string())
+        );
+        assert_eq!(
+            secret_file_key("APP__EMAIL_CLIENT__AUTH_TOKEN_FILE"),
+            Some("email_client.auth_token".to_string())
+        );
+    }
+
+    #[test]
+    fn secret_file_key_ignores_other_vars() {
+        assert_eq!(secret_file_key("APP__DATABASE__PASSWORD"), None);
+        assert_eq!(secret_file_key("DATABASE_PASSWORD_FILE"), None);
+        assert_eq!(secret_file_key("APP___FILE"), None);
+    }
+
+    #[test]
+    fn secret_files_reads_file_content() {
+        let path = std::env::temp_dir().join(format!("secret-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "hunter2\n").unwrap();
+        let vars = vec![(
+            "APP__DATABASE__PASSWORD_FILE".to_string(),
+            path.to_string_lossy().to_string(),
+        )];
+
+        let files = secret_files(vars.into_iter()).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            files,
+            vec![("database.password".to_string(), "hunter2".to_string())]
+        );
+    }
+
+    #[test]
+    fn secret_files_fails_on_missing_file() {
+        let vars = vec![(
+            "APP__DATABASE__PASSWORD_FILE".to_string(),
+            "/nonexistent/secret".to_string(),
+        )];
+        assert!(secret_files(vars.into_iter()).is_err());
+    }
 }
@@ -0,0 +1,57 @@
+use super::MxResolver;
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::proto::op::ResponseCode;
+use hickory_resolver::system_conf::read_system_conf;
+use hickory_resolver::TokioAsyncResolver;
+use std::time::Duration;
+
+pub use hickory_resolver::error::ResolveError;
+
+/// Looks up mail servers with the nameservers and options of the system
+/// configuration, `/etc/resolv.conf` on Unix.
+pub struct DnsResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl DnsResolver {
+    pub fn from_system_conf(timeout: Duration) -> Result<Self, ResolveError> {
+        let (config, mut options) = read_system_conf()?;
+        options.timeout = timeout;
+        Ok(Self {
+            resolver: TokioAsyncResolver::tokio(config, options),
+        })
+    }
+}
+
+/// Domains accept mail when they have MX records other than a null MX
+/// (RFC 7505) or, lacking those, an address record (RFC 5321 implicit MX).
+#[async_trait::async_trait]
+impl MxResolver for DnsResolver {
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, ResolveError> {
+        // Fully qualified, the domain of an address is never relative to the
+        // search domains.
+        let domain = format!("{}.", domain.trim_end_matches('.'));
+        match self.resolver.mx_lookup(domain.as_str()).await {
+            Ok(records) => return Ok(records.iter().any(|mx| !mx.exchange().is_root())),
+            Err(e) => match no_records(&e) {
+                Some(ResponseCode::NXDomain) => return Ok(false),
+                Some(_) => {}
+                None => return Err(e),
+            },
+        }
+        match self.resolver.lookup_ip(domain.as_str()).await {
+            Ok(addresses) => Ok(addresses.iter().next().is_some()),
+            Err(e) if no_records(&e).is_some() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Response code of an answer without records, `NXDomain` when the domain
+/// does not exist at all.
+fn no_records(error: &ResolveError) -> Option<ResponseCode> {
+    match error.kind() {
+        ResolveErrorKind::NoRecordsFound { response_code, .. } => Some(*response_code),
+        _ => None,
+    }
+}
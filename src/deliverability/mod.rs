@@ -0,0 +1,288 @@
+mod dns;
+
+use crate::config::{DeliverabilityConfig, MxResolverKind};
+use crate::domain::SubscriberEmail;
+use std::collections::HashSet;
+
+pub use dns::{DnsResolver, ResolveError};
+
+/// Domains of large providers. Addresses at domains a typo away from one of
+/// them get a suggestion, addresses at these domains never do.
+const COMMON_DOMAINS: &[&str] = &[
+    "aol.com",
+    "gmail.com",
+    "gmx.com",
+    "gmx.de",
+    "gmx.net",
+    "googlemail.com",
+    "hotmail.co.uk",
+    "hotmail.com",
+    "hotmail.fr",
+    "icloud.com",
+    "live.com",
+    "mac.com",
+    "mail.com",
+    "me.com",
+    "msn.com",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+    "web.de",
+    "yahoo.co.uk",
+    "yahoo.com",
+    "yahoo.fr",
+    "ymail.com",
+];
+
+/// Largest edit distance between a domain and the one suggested instead.
+const MAX_TYPO_DISTANCE: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DeliverabilityError {
+    #[error("email addresses at {0} are not accepted")]
+    DisposableDomain(String),
+    #[error("did you mean {suggestion}?")]
+    PossibleTypo { suggestion: String },
+    #[error("{0} does not accept email")]
+    NoMailServer(String),
+}
+
+impl DeliverabilityError {
+    /// Stable identifier of the problem, e.g. for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DeliverabilityError::DisposableDomain(_) => "disposable_domain",
+            DeliverabilityError::PossibleTypo { .. } => "possible_typo",
+            DeliverabilityError::NoMailServer(_) => "no_mail_server",
+        }
+    }
+}
+
+/// Tells whether a domain has servers accepting mail for it.
+#[async_trait::async_trait]
+pub trait MxResolver: Send + Sync {
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, ResolveError>;
+}
+
+/// Resolver answering from a fixed list, for local development and tests.
+pub struct StubResolver {
+    domains_without_mail_servers: HashSet<String>,
+}
+
+impl StubResolver {
+    pub fn new(domains_without_mail_servers: &[String]) -> Self {
+        Self {
+            domains_without_mail_servers: domains_without_mail_servers
+                .iter()
+                .map(|d| d.to_lowercase())
+                .collect(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl MxResolver for StubResolver {
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, ResolveError> {
+        Ok(!self.domains_without_mail_servers.contains(domain))
+    }
+}
+
+/// Checks that go beyond the syntax of an address, run before subscribing.
+pub struct Deliverability {
+    disposable_domains: HashSet<String>,
+    suggest_typos: bool,
+    resolver: Option<Box<dyn MxResolver>>,
+}
+
+impl Deliverability {
+    pub fn new(config: &DeliverabilityConfig) -> Result<Self, ResolveError> {
+        let resolver: Option<Box<dyn MxResolver>> = match config.mx_check.resolver {
+            MxResolverKind::Disabled => None,
+            MxResolverKind::Dns => Some(Box::new(DnsResolver::from_system_conf(
+                config.mx_check.timeout(),
+            )?)),
+            MxResolverKind::Stub => Some(Box::new(StubResolver::new(
+                &config.mx_check.stub_domains_without_mx,
+            ))),
+        };
+        Ok(Self::with_resolver(config, resolver))
+    }
+
+    pub fn with_resolver(
+        config: &DeliverabilityConfig,
+        resolver: Option<Box<dyn MxResolver>>,
+    ) -> Self {
+        Self {
+            disposable_domains: config
+                .disposable_domains
+                .iter()
+                .map(|d| d.trim().to_lowercase())
+                .collect(),
+            suggest_typos: config.suggest_typos,
+            resolver,
+        }
+    }
+
+    /// Rejects disposable domains, suggests fixes for likely typos unless
+    /// `skip_typo_check` is set and, when configured, looks up mail servers.
+    /// Lookup failures let the address through, a flaky resolver must not
+    /// stop people from subscribing.
+    #[tracing::instrument(name = "Check deliverability", skip(self, email))]
+    pub async fn check(
+        &self,
+        email: &SubscriberEmail,
+        skip_typo_check: bool,
+    ) -> Result<(), DeliverabilityError> {
+        let domain = email.domain();
+        if self.is_disposable(domain) {
+            return Err(DeliverabilityError::DisposableDomain(domain.to_string()));
+        }
+        if self.suggest_typos && !skip_typo_check {
+            if let Some(suggested) = suggest_domain(domain) {
+                return Err(DeliverabilityError::PossibleTypo {
+                    suggestion: format!("{}@{}", email.local_part(), suggested),
+                });
+            }
+        }
+        if let Some(resolver) = &self.resolver {
+            match resolver.accepts_mail(domain).await {
+                Ok(true) => {}
+                Ok(false) => return Err(DeliverabilityError::NoMailServer(domain.to_string())),
+                Err(e) => {
+                    tracing::warn!(error.cause_chain = ?e, domain, "Failed to look up mail servers")
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Subdomains of a blocked domain are blocked too.
+    fn is_disposable(&self, domain: &str) -> bool {
+        let mut candidate = domain;
+        loop {
+            if self.disposable_domains.contains(candidate) {
+                return true;
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => candidate = parent,
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// The common domain `domain` most likely is a typo of, if any.
+fn suggest_domain(domain: &str) -> Option<&'static str> {
+    if COMMON_DOMAINS.contains(&domain) {
+        return None;
+    }
+    COMMON_DOMAINS
+        .iter()
+        .map(|common| (edit_distance(domain, common), *common))
+        .filter(|(distance, _)| *distance <= MAX_TYPO_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, common)| common)
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions
+/// and transpositions of adjacent characters all count as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MxCheckConfig;
+
+    fn config() -> DeliverabilityConfig {
+        DeliverabilityConfig {
+            disposable_domains: vec!["mailinator.com".to_string()],
+            suggest_typos: true,
+            mx_check: MxCheckConfig::default(),
+        }
+    }
+
+    fn email(value: &str) -> SubscriberEmail {
+        SubscriberEmail::try_from(value.to_string()).unwrap()
+    }
+
+    #[test]
+    fn transpositions_count_as_one_edit() {
+        assert_eq!(edit_distance("gmial.com", "gmail.com"), 1);
+        assert_eq!(edit_distance("gmail.con", "gmail.com"), 1);
+        assert_eq!(edit_distance("example.org", "gmail.com"), 8);
+    }
+
+    #[test]
+    fn typos_of_common_domains_get_a_suggestion() {
+        assert_eq!(suggest_domain("gmial.com"), Some("gmail.com"));
+        assert_eq!(suggest_domain("hotmial.com"), Some("hotmail.com"));
+        assert_eq!(suggest_domain("yaho.com"), Some("yahoo.com"));
+    }
+
+    #[test]
+    fn known_and_unrelated_domains_get_no_suggestion() {
+        assert_eq!(suggest_domain("gmail.com"), None);
+        assert_eq!(suggest_domain("ymail.com"), None);
+        assert_eq!(suggest_domain("example.org"), None);
+    }
+
+    #[tokio::test]
+    async fn disposable_domains_and_their_subdomains_are_rejected() {
+        let deliverability = Deliverability::new(&config()).unwrap();
+        for address in ["pog@mailinator.com", "pog@eu.MAILINATOR.com"] {
+            assert_eq!(
+                deliverability.check(&email(address), false).await,
+                Err(DeliverabilityError::DisposableDomain(
+                    email(address).domain().to_string()
+                ))
+            );
+        }
+        assert!(deliverability.check(&email("pog@example.com"), false).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn the_suggestion_keeps_the_local_part() {
+        let deliverability = Deliverability::new(&config()).unwrap();
+        assert_eq!(
+            deliverability.check(&email("Pog.Dog@gmial.com"), false).await,
+            Err(DeliverabilityError::PossibleTypo {
+                suggestion: "Pog.Dog@gmail.com".to_string()
+            })
+        );
+        assert!(deliverability.check(&email("Pog.Dog@gmial.com"), true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn domains_without_mail_servers_are_rejected() {
+        let resolver = StubResolver::new(&["example.org".to_string()]);
+        let deliverability = Deliverability::with_resolver(&config(), Some(Box::new(resolver)));
+        assert_eq!(
+            deliverability.check(&email("pog@example.org"), false).await,
+            Err(DeliverabilityError::NoMailServer("example.org".to_string()))
+        );
+        assert!(deliverability.check(&email("pog@example.com"), false).await.is_ok());
+    }
+}
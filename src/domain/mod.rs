@@ -1,7 +1,11 @@
 pub mod new_subscriber;
+pub mod subscriber_attributes;
 pub mod subscriber_email;
 pub mod subscriber_name;
+pub mod subscriber_tag;
 
 pub use new_subscriber::*;
+pub use subscriber_attributes::*;
 pub use subscriber_email::*;
 pub use subscriber_name::*;
+pub use subscriber_tag::*;
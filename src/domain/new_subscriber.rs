@@ -1,15 +1,42 @@
-use super::SubscriberEmail;
-use super::SubscriberName;
+use super::{SubscriberEmail, SubscriberEmailError};
+use super::{SubscriberName, SubscriberNameError};
 
 pub struct NewSubscriber {
     pub name: SubscriberName,
     pub email: SubscriberEmail,
 }
 
+/// Everything wrong with a new subscriber, so that all problems can be
+/// reported at once.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid subscriber")]
+pub struct NewSubscriberError {
+    pub name: Option<SubscriberNameError>,
+    pub email: Option<SubscriberEmailError>,
+}
+
 impl NewSubscriber {
-    pub fn new(name: String, email: String) -> Result<Self, String> {
-        let name = SubscriberName::try_from(name)?;
-        let email = SubscriberEmail::try_from(email)?;
-        Ok(Self { name, email })
+    pub fn new(name: String, email: String) -> Result<Self, NewSubscriberError> {
+        match (SubscriberName::try_from(name), SubscriberEmail::try_from(email)) {
+            (Ok(name), Ok(email)) => Ok(Self { name, email }),
+            (name, email) => Err(NewSubscriberError {
+                name: name.err(),
+                email: email.err(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let error = NewSubscriber::new("".to_string(), "pogdog.log".to_string())
+            .err()
+            .unwrap();
+        assert_eq!(error.name, Some(SubscriberNameError::Empty));
+        assert_eq!(error.email, Some(SubscriberEmailError::InvalidSyntax));
     }
 }
@@ -0,0 +1,362 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Keys that merge tags already use for the built-in fields.
+const RESERVED_KEYS: [&str; 2] = ["name", "email"];
+
+const MAX_KEY_LENGTH: usize = 40;
+
+/// Longest string value accepted, in characters.
+pub const MAX_ATTRIBUTE_LENGTH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    String,
+    Number,
+    /// A calendar day, `YYYY-MM-DD`.
+    Date,
+    Boolean,
+    /// One of the `options` of the field.
+    Enum,
+}
+
+impl FieldType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldType::String => "string",
+            FieldType::Number => "number",
+            FieldType::Date => "date",
+            FieldType::Boolean => "boolean",
+            FieldType::Enum => "enum",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "string" => Some(FieldType::String),
+            "number" => Some(FieldType::Number),
+            "date" => Some(FieldType::Date),
+            "boolean" => Some(FieldType::Boolean),
+            "enum" => Some(FieldType::Enum),
+            _ => None,
+        }
+    }
+}
+
+/// A custom field of the subscribers of a list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomField {
+    pub key: String,
+    pub field_type: FieldType,
+    pub options: Vec<String>,
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CustomFieldError {
+    #[error(
+        "key must start with a lowercase letter and have at most {MAX_KEY_LENGTH} lowercase \
+         letters, digits and underscores"
+    )]
+    InvalidKey,
+    #[error("{0} is reserved")]
+    ReservedKey(String),
+    #[error("enum fields need at least one option, other fields none")]
+    InvalidOptions,
+}
+
+impl CustomField {
+    pub fn new(
+        key: String,
+        field_type: FieldType,
+        options: Vec<String>,
+        required: bool,
+    ) -> Result<Self, CustomFieldError> {
+        let mut chars = key.chars();
+        let valid_key = chars.next().is_some_and(|c| c.is_ascii_lowercase())
+            && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            && key.len() <= MAX_KEY_LENGTH;
+        if !valid_key {
+            return Err(CustomFieldError::InvalidKey);
+        }
+        if RESERVED_KEYS.contains(&key.as_str()) {
+            return Err(CustomFieldError::ReservedKey(key));
+        }
+        if (field_type == FieldType::Enum) == options.is_empty() {
+            return Err(CustomFieldError::InvalidOptions);
+        }
+        Ok(Self {
+            key,
+            field_type,
+            options,
+            required,
+        })
+    }
+
+    /// `value` as it is stored: numbers and booleans as such, dates and
+    /// everything else as strings. Strings are accepted for every type, as
+    /// forms send nothing else.
+    pub fn coerce(&self, value: &Value) -> Result<Value, AttributeError> {
+        let invalid = || AttributeError::Invalid {
+            key: self.key.clone(),
+            expected: self.field_type,
+        };
+        let coerced = match (self.field_type, value) {
+            (FieldType::String, Value::String(s)) => {
+                if s.chars().count() > MAX_ATTRIBUTE_LENGTH {
+                    return Err(AttributeError::TooLong(self.key.clone()));
+                }
+                Value::String(s.clone())
+            }
+            (FieldType::Number, Value::Number(n)) => Value::Number(n.clone()),
+            (FieldType::Number, Value::String(s)) => s
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(Value::Number)
+                .ok_or_else(invalid)?,
+            (FieldType::Date, Value::String(s)) => NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d")
+                .map(|date| Value::String(date.to_string()))
+                .map_err(|_| invalid())?,
+            (FieldType::Boolean, Value::Bool(b)) => Value::Bool(*b),
+            (FieldType::Boolean, Value::String(s)) => match s.trim() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => return Err(invalid()),
+            },
+            (FieldType::Enum, Value::String(s)) if self.options.contains(s) => {
+                Value::String(s.clone())
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(coerced)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AttributeError {
+    #[error("{0} is not a field of this list")]
+    Unknown(String),
+    #[error("{0} is required")]
+    Missing(String),
+    #[error("{key} must be a {}", expected.as_str())]
+    Invalid { key: String, expected: FieldType },
+    #[error("{0} must have at most {MAX_ATTRIBUTE_LENGTH} characters")]
+    TooLong(String),
+}
+
+impl AttributeError {
+    /// Stable identifier of the problem, e.g. for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AttributeError::Unknown(_) => "unknown_attribute",
+            AttributeError::Missing(_) => "missing_attribute",
+            AttributeError::Invalid { .. } => "invalid_attribute",
+            AttributeError::TooLong(_) => "too_long",
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        match self {
+            AttributeError::Unknown(key)
+            | AttributeError::Missing(key)
+            | AttributeError::Invalid { key, .. }
+            | AttributeError::TooLong(key) => key,
+        }
+    }
+}
+
+/// Everything wrong with the attributes of a subscriber, so that all
+/// problems can be reported at once.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid attributes")]
+pub struct SubscriberAttributesError(pub Vec<AttributeError>);
+
+/// Custom attributes checked against the fields of a list, ready to be
+/// stored.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SubscriberAttributes(Map<String, Value>);
+
+impl SubscriberAttributes {
+    /// Null and empty values count as left out.
+    pub fn parse(
+        fields: &[CustomField],
+        values: &HashMap<String, Value>,
+    ) -> Result<Self, SubscriberAttributesError> {
+        Self::parse_with(fields, values, true)
+    }
+
+    /// Like [`SubscriberAttributes::parse`], but required fields may be left
+    /// out, as when picking subscribers by some of their attributes.
+    pub fn parse_partial(
+        fields: &[CustomField],
+        values: &HashMap<String, Value>,
+    ) -> Result<Self, SubscriberAttributesError> {
+        Self::parse_with(fields, values, false)
+    }
+
+    fn parse_with(
+        fields: &[CustomField],
+        values: &HashMap<String, Value>,
+        enforce_required: bool,
+    ) -> Result<Self, SubscriberAttributesError> {
+        let mut errors: Vec<_> = values
+            .keys()
+            .filter(|key| !fields.iter().any(|field| &field.key == *key))
+            .map(|key| AttributeError::Unknown(key.clone()))
+            .collect();
+        errors.sort_by(|a, b| a.key().cmp(b.key()));
+        let mut attributes = Map::new();
+        for field in fields {
+            match values.get(&field.key) {
+                None | Some(Value::Null) => {}
+                Some(Value::String(s)) if s.trim().is_empty() => {}
+                Some(value) => {
+                    match field.coerce(value) {
+                        Ok(value) => {
+                            attributes.insert(field.key.clone(), value);
+                        }
+                        Err(e) => errors.push(e),
+                    }
+                    continue;
+                }
+            }
+            if field.required && enforce_required {
+                errors.push(AttributeError::Missing(field.key.clone()));
+            }
+        }
+        if errors.is_empty() {
+            Ok(Self(attributes))
+        } else {
+            Err(SubscriberAttributesError(errors))
+        }
+    }
+
+    pub fn into_json(self) -> Value {
+        Value::Object(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields() -> Vec<CustomField> {
+        vec![
+            CustomField::new("company".into(), FieldType::String, vec![], false).unwrap(),
+            CustomField::new("seats".into(), FieldType::Number, vec![], false).unwrap(),
+            CustomField::new("since".into(), FieldType::Date, vec![], false).unwrap(),
+            CustomField::new("beta".into(), FieldType::Boolean, vec![], false).unwrap(),
+            CustomField::new(
+                "plan".into(),
+                FieldType::Enum,
+                vec!["free".into(), "pro".into()],
+                true,
+            )
+            .unwrap(),
+        ]
+    }
+
+    fn values(value: Value) -> HashMap<String, Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn values_are_stored_by_type() {
+        let attributes = SubscriberAttributes::parse(
+            &fields(),
+            &values(json!({
+                "company": "Acme",
+                "seats": "12",
+                "since": "2026-01-31",
+                "beta": true,
+                "plan": "pro",
+            })),
+        )
+        .unwrap();
+
+        assert_eq!(
+            attributes.into_json(),
+            json!({
+                "company": "Acme",
+                "seats": 12.0,
+                "since": "2026-01-31",
+                "beta": true,
+                "plan": "pro",
+            })
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let error = SubscriberAttributes::parse(
+            &fields(),
+            &values(json!({
+                "seats": "a dozen",
+                "since": "31/01/2026",
+                "beta": "yes",
+                "colour": "red",
+            })),
+        )
+        .unwrap_err();
+
+        let codes: Vec<_> = error.0.iter().map(|e| (e.key(), e.code())).collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("colour", "unknown_attribute"),
+                ("seats", "invalid_attribute"),
+                ("since", "invalid_attribute"),
+                ("beta", "invalid_attribute"),
+                ("plan", "missing_attribute"),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_values_count_as_left_out() {
+        let error = SubscriberAttributes::parse(
+            &fields(),
+            &values(json!({"company": "", "seats": null, "plan": " "})),
+        )
+        .unwrap_err();
+
+        assert_eq!(error.0, vec![AttributeError::Missing("plan".into())]);
+    }
+
+    #[test]
+    fn partial_attributes_may_leave_out_required_fields() {
+        let attributes =
+            SubscriberAttributes::parse_partial(&fields(), &values(json!({"seats": 3}))).unwrap();
+
+        assert_eq!(attributes.into_json(), json!({"seats": 3}));
+    }
+
+    #[test]
+    fn enum_values_must_be_options() {
+        let error = SubscriberAttributes::parse(&fields(), &values(json!({"plan": "gold"})))
+            .unwrap_err();
+
+        assert_eq!(error.0[0].code(), "invalid_attribute");
+    }
+
+    #[test]
+    fn field_keys_are_checked() {
+        let field = |key: &str| CustomField::new(key.into(), FieldType::String, vec![], false);
+        assert!(field("signup_source").is_ok());
+        assert_eq!(field("Company"), Err(CustomFieldError::InvalidKey));
+        assert_eq!(field("2fa"), Err(CustomFieldError::InvalidKey));
+        assert_eq!(field(""), Err(CustomFieldError::InvalidKey));
+        assert_eq!(field("email"), Err(CustomFieldError::ReservedKey("email".into())));
+        assert_eq!(
+            CustomField::new("plan".into(), FieldType::Enum, vec![], false),
+            Err(CustomFieldError::InvalidOptions)
+        );
+    }
+}
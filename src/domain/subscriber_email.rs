@@ -1,15 +1,64 @@
 use validator::validate_email;
 
+/// An email address in canonical form: trimmed, with the domain lowercased
+/// and converted to ASCII (punycode). The local part is kept as is, mail
+/// servers are allowed to treat it case-sensitively.
 pub struct SubscriberEmail(String);
 
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SubscriberEmailError {
+    #[error("email must not be empty")]
+    Empty,
+    #[error("email is not a valid email address")]
+    InvalidSyntax,
+    #[error("email has an invalid domain")]
+    InvalidDomain,
+}
+
+impl SubscriberEmailError {
+    /// Stable identifier of the problem, e.g. for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriberEmailError::Empty => "empty",
+            SubscriberEmailError::InvalidSyntax => "invalid_syntax",
+            SubscriberEmailError::InvalidDomain => "invalid_domain",
+        }
+    }
+}
+
+impl SubscriberEmail {
+    pub fn local_part(&self) -> &str {
+        self.split().0
+    }
+
+    pub fn domain(&self) -> &str {
+        self.split().1
+    }
+
+    fn split(&self) -> (&str, &str) {
+        // Always present, the address was validated.
+        self.0.rsplit_once('@').unwrap()
+    }
+}
+
 impl TryFrom<String> for SubscriberEmail {
-    type Error = String;
+    type Error = SubscriberEmailError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        if validate_email(&value) {
-            Ok(Self(value))
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(SubscriberEmailError::Empty);
+        }
+        let (local_part, domain) = value
+            .rsplit_once('@')
+            .ok_or(SubscriberEmailError::InvalidSyntax)?;
+        let domain = idna::domain_to_ascii(domain.trim_end_matches('.'))
+            .map_err(|_| SubscriberEmailError::InvalidDomain)?;
+        let email = format!("{}@{}", local_part, domain);
+        if validate_email(&email) {
+            Ok(Self(email))
         } else {
-            Err(format!("invalid email: {}", value))
+            Err(SubscriberEmailError::InvalidSyntax)
         }
     }
 }
@@ -33,18 +82,47 @@ mod tests {
     #[test]
     fn whitespase_only_invalid() {
         let email = " ".repeat(10);
-        assert!(SubscriberEmail::try_from(email).is_err());
+        assert_eq!(
+            SubscriberEmail::try_from(email).err(),
+            Some(SubscriberEmailError::Empty)
+        );
     }
 
     #[test]
     fn empty_invalid() {
         let email = "".to_string();
-        assert!(SubscriberEmail::try_from(email).is_err());
+        assert_eq!(
+            SubscriberEmail::try_from(email).err(),
+            Some(SubscriberEmailError::Empty)
+        );
     }
 
     #[test]
     fn missing_symbol_invalid() {
         let email = "pogdog.log".to_string();
+        assert_eq!(
+            SubscriberEmail::try_from(email).err(),
+            Some(SubscriberEmailError::InvalidSyntax)
+        );
+    }
+
+    #[test]
+    fn domain_is_canonicalised() {
+        let email = SubscriberEmail::try_from(" Pog.Dog@Example.COM ".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "Pog.Dog@example.com");
+        assert_eq!(email.local_part(), "Pog.Dog");
+        assert_eq!(email.domain(), "example.com");
+    }
+
+    #[test]
+    fn international_domains_are_converted_to_punycode() {
+        let email = SubscriberEmail::try_from("pog@Bücher.de".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "pog@xn--bcher-kva.de");
+    }
+
+    #[test]
+    fn invalid_domain_invalid() {
+        let email = "pog@exa mple.com".to_string();
         assert!(SubscriberEmail::try_from(email).is_err());
     }
 
@@ -1,21 +1,51 @@
 use unicode_segmentation::UnicodeSegmentation;
 
+/// Longest name accepted, in graphemes.
+pub const MAX_NAME_LENGTH: usize = 256;
+
+const FORBIDDEN_CHARS: [char; 9] = ['/', '"', '\\', '(', ')', '{', '}', '<', '>'];
+
 pub struct SubscriberName(String);
 
-impl TryFrom<String> for SubscriberName {
-    type Error = String;
-    fn try_from(value: String) -> Result<Self, String> {
-        let is_empty_or_whitespase = value.trim().is_empty();
-        let is_too_long = value.graphemes(true).count() > 256;
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SubscriberNameError {
+    #[error("name must not be empty")]
+    Empty,
+    #[error("name must have at most {max} characters, it has {actual}")]
+    TooLong { max: usize, actual: usize },
+    #[error("name must not contain {0:?}")]
+    ForbiddenChar(char),
+}
 
-        let forbidden_chars = vec!['/', '"', '\\', '(', ')', '{', '}', '<', '>'];
-        let contains_forbidden = value.chars().any(|c| forbidden_chars.contains(&c));
+impl SubscriberNameError {
+    /// Stable identifier of the problem, e.g. for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriberNameError::Empty => "empty",
+            SubscriberNameError::TooLong { .. } => "too_long",
+            SubscriberNameError::ForbiddenChar(_) => "forbidden_char",
+        }
+    }
+}
+
+impl TryFrom<String> for SubscriberName {
+    type Error = SubscriberNameError;
 
-        if is_empty_or_whitespase || is_too_long || contains_forbidden {
-            Err(format!("invalid name: {}", value))
-        } else {
-            Ok(Self(value))
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.trim().is_empty() {
+            return Err(SubscriberNameError::Empty);
+        }
+        let length = value.graphemes(true).count();
+        if length > MAX_NAME_LENGTH {
+            return Err(SubscriberNameError::TooLong {
+                max: MAX_NAME_LENGTH,
+                actual: length,
+            });
+        }
+        if let Some(c) = value.chars().find(|c| FORBIDDEN_CHARS.contains(c)) {
+            return Err(SubscriberNameError::ForbiddenChar(c));
         }
+        Ok(Self(value))
     }
 }
 
@@ -44,26 +74,41 @@ mod tests {
     #[test]
     fn long_name_longer_then_256_invalid() {
         let name = "a".repeat(257);
-        assert!(SubscriberName::try_from(name).is_err());
+        assert_eq!(
+            SubscriberName::try_from(name).err(),
+            Some(SubscriberNameError::TooLong {
+                max: 256,
+                actual: 257
+            })
+        );
     }
 
     #[test]
     fn whitespase_only_invalid() {
         let name = " ".repeat(10);
-        assert!(SubscriberName::try_from(name).is_err());
+        assert_eq!(
+            SubscriberName::try_from(name).err(),
+            Some(SubscriberNameError::Empty)
+        );
     }
 
     #[test]
     fn empty_invalid() {
         let name = "".to_string();
-        assert!(SubscriberName::try_from(name).is_err());
+        assert_eq!(
+            SubscriberName::try_from(name).err(),
+            Some(SubscriberNameError::Empty)
+        );
     }
 
     #[test]
     fn contains_forbidden_chars_invalid() {
         for c in ['/', '"', '\\', '(', ')', '{', '}', '<', '>'] {
-            let name = c.to_string();
-            assert!(SubscriberName::try_from(name).is_err());
+            let name = format!("pog{}dog", c);
+            assert_eq!(
+                SubscriberName::try_from(name).err(),
+                Some(SubscriberNameError::ForbiddenChar(c))
+            );
         }
     }
 }
@@ -0,0 +1,102 @@
+use std::collections::BTreeSet;
+
+/// Longest tag accepted, in characters.
+pub const MAX_TAG_LENGTH: usize = 50;
+
+/// Most tags a subscriber can be given at once.
+pub const MAX_TAGS: usize = 20;
+
+/// A label like `beta-tester`, stored in lowercase.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriberTag(String);
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SubscriberTagError {
+    #[error("tags must have at most {MAX_TAG_LENGTH} characters")]
+    TooLong,
+    #[error("{0:?} may only contain letters, digits, hyphens and underscores")]
+    InvalidTag(String),
+    #[error("at most {MAX_TAGS} tags can be given at once")]
+    TooMany,
+}
+
+impl SubscriberTagError {
+    /// Stable identifier of the problem, e.g. for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriberTagError::TooLong => "too_long",
+            SubscriberTagError::InvalidTag(_) => "invalid_tag",
+            SubscriberTagError::TooMany => "too_many_tags",
+        }
+    }
+}
+
+impl TryFrom<String> for SubscriberTag {
+    type Error = SubscriberTagError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let tag = value.trim().to_lowercase();
+        if tag.chars().count() > MAX_TAG_LENGTH {
+            return Err(SubscriberTagError::TooLong);
+        }
+        let valid = !tag.is_empty()
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(SubscriberTagError::InvalidTag(value));
+        }
+        Ok(Self(tag))
+    }
+}
+
+impl SubscriberTag {
+    /// Distinct tags from `values`, leaving out blank ones.
+    pub fn parse_all(
+        values: impl IntoIterator<Item = String>,
+    ) -> Result<Vec<SubscriberTag>, SubscriberTagError> {
+        let tags = values
+            .into_iter()
+            .filter(|value| !value.trim().is_empty())
+            .map(SubscriberTag::try_from)
+            .collect::<Result<BTreeSet<_>, _>>()?;
+        if tags.len() > MAX_TAGS {
+            return Err(SubscriberTagError::TooMany);
+        }
+        Ok(tags.into_iter().collect())
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(values: &[&str]) -> Result<Vec<String>, SubscriberTagError> {
+        let tags = SubscriberTag::parse_all(values.iter().map(|v| v.to_string()))?;
+        Ok(tags.iter().map(|tag| tag.as_ref().to_string()).collect())
+    }
+
+    #[test]
+    fn tags_are_lowercased_and_deduplicated() {
+        assert_eq!(
+            parse(&["Beta-Tester", " conference_2026 ", "beta-tester", ""]),
+            Ok(vec!["beta-tester".to_string(), "conference_2026".to_string()])
+        );
+    }
+
+    #[test]
+    fn invalid_tags_are_rejected() {
+        assert_eq!(parse(&["beta tester"]).unwrap_err().code(), "invalid_tag");
+        assert_eq!(parse(&["ünicode"]).unwrap_err().code(), "invalid_tag");
+        assert_eq!(parse(&[&"a".repeat(51)]), Err(SubscriberTagError::TooLong));
+        let many: Vec<_> = (0..=MAX_TAGS).map(|i| format!("tag{}", i)).collect();
+        let many: Vec<&str> = many.iter().map(String::as_str).collect();
+        assert_eq!(parse(&many), Err(SubscriberTagError::TooMany));
+    }
+}
@@ -1,12 +1,16 @@
 use crate::domain::SubscriberEmail;
+use crate::secret::Secret;
+use opentelemetry_http::HeaderInjector;
+use reqwest::header::HeaderMap;
 use reqwest::Client;
 use serde::Serialize;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 pub struct EmailClient {
     client: Client,
     base_url: String,
     sender: SubscriberEmail,
-    auth_token: String,
+    auth_token: Secret,
 }
 
 #[derive(Serialize)]
@@ -24,7 +28,7 @@ impl EmailClient {
     pub fn new(
         base_url: String,
         sender: SubscriberEmail,
-        auth_token: String,
+        auth_token: Secret,
         timeout: std::time::Duration,
     ) -> Self {
         let client = Client::builder().timeout(timeout).build().unwrap();
@@ -53,13 +57,32 @@ impl EmailClient {
         };
         self.client
             .post(&url)
-            .header("X-Email-Server-Token", &self.auth_token)
+            .headers(trace_context_headers())
+            .header("X-Email-Server-Token", self.auth_token.expose_secret())
             .json(&request_body)
             .send()
             .await?
             .error_for_status()?;
         Ok(())
     }
+
+    /// Checks that the email provider can be reached. Any HTTP response counts
+    /// as reachable, only connection level failures are reported.
+    pub async fn ping(&self) -> Result<(), reqwest::Error> {
+        self.client.head(&self.base_url).send().await?;
+        Ok(())
+    }
+}
+
+/// W3C trace context of the current span, so that the provider call shows up
+/// in the same trace as the request that triggered it.
+fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = tracing::Span::current().context();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
 }
 
 #[cfg(test)]
@@ -95,7 +118,7 @@ mod tests {
         let email_client = EmailClient::new(
             mock_server.uri(),
             sender,
-            Faker.fake(),
+            Secret::new(Faker.fake()),
             std::time::Duration::from_millis(500),
         );
 
@@ -127,7 +150,7 @@ mod tests {
         let email_client = EmailClient::new(
             mock_server.uri(),
             sender,
-            Faker.fake(),
+            Secret::new(Faker.fake()),
             std::time::Duration::from_millis(500),
         );
 
@@ -159,7 +182,7 @@ mod tests {
         let email_client = EmailClient::new(
             mock_server.uri(),
             sender,
-            Faker.fake(),
+            Secret::new(Faker.fake()),
             std::time::Duration::from_millis(500),
         );
 
@@ -183,4 +206,47 @@ mod tests {
 
         assert!(result.is_err())
     }
+
+    #[tokio::test]
+    async fn send_email_propagates_trace_context() {
+        use opentelemetry::sdk::propagation::TraceContextPropagator;
+        use opentelemetry::trace::TracerProvider;
+        use tracing::Instrument;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let tracer = provider.tracer("test");
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mock_server = MockServer::start().await;
+        let sender = SubscriberEmail::try_from(SafeEmail().fake::<String>()).unwrap();
+        let email_client = EmailClient::new(
+            mock_server.uri(),
+            sender,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(500),
+        );
+
+        Mock::given(header_exists("traceparent"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let subscriber_email = SubscriberEmail::try_from(SafeEmail().fake::<String>()).unwrap();
+        let subject: String = Sentence(1..2).fake();
+        let content: String = Paragraph(1..10).fake();
+
+        let result = email_client
+            .send_email(subscriber_email, &subject, &content, &content)
+            .instrument(tracing::info_span!("test span"))
+            .await;
+
+        assert!(result.is_ok())
+    }
 }
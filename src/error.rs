@@ -0,0 +1,14 @@
+/// Writes an error followed by the chain of its sources, one per line, for
+/// the `Debug` implementations of error types.
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    writeln!(f, "\n{}", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "caused by:\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest};
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::pin::Pin;
+
+/// A request body sent either as JSON or as a URL-encoded form, told apart by
+/// its content type. Anything that isn't JSON is read as a form, so existing
+/// form clients keep working.
+///
+/// Limits and error handlers come from the `JsonConfig` and `FormConfig` of
+/// the app.
+#[derive(Debug)]
+pub struct FormOrJson<T>(pub T);
+
+impl<T> FormOrJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for FormOrJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for FormOrJson<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if is_json(req) {
+            let json = web::Json::<T>::from_request(req, payload);
+            Box::pin(async move { Ok(FormOrJson(json.await?.into_inner())) })
+        } else {
+            let form = web::Form::<T>::from_request(req, payload);
+            Box::pin(async move { Ok(FormOrJson(form.await?.into_inner())) })
+        }
+    }
+}
+
+/// `application/json` and its `+json` relatives.
+fn is_json(req: &HttpRequest) -> bool {
+    match req.mime_type() {
+        Ok(Some(mime)) => mime.subtype() == "json" || mime.suffix().is_some_and(|s| s == "json"),
+        _ => false,
+    }
+}
@@ -0,0 +1,367 @@
+use crate::automation::AutomationEvent;
+use crate::config::DeliveryConfig;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::localisation::{fallback_chain, Translations};
+use crate::merge_tags::{IssueTemplate, Unsubscribe};
+use crate::routes::generate_sub_token;
+use crate::tracking;
+use chrono::Utc;
+use fluent_bundle::FluentArgs;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Picks queued deliveries one by one until `shutdown` flips to `true`.
+/// A task that is being sent when shutdown is requested is allowed to finish;
+/// everything else stays in `issue_delivery_queue` for the next start.
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_client: actix_web::web::Data<EmailClient>,
+    context: Arc<DeliveryContext>,
+    config: DeliveryWorkerConfig,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        if *shutdown.borrow() {
+            break;
+        }
+        match try_execute_task(&pool, &email_client, &context, config.max_retries).await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::select! {
+                    _ = tokio::time::sleep(config.poll_interval) => {}
+                    _ = shutdown.changed() => {}
+                }
+            }
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, "Failed to execute a delivery task");
+                tokio::select! {
+                    _ = tokio::time::sleep(config.poll_interval) => {}
+                    _ = shutdown.changed() => {}
+                }
+            }
+        }
+    }
+    tracing::info!("Delivery worker stopped");
+}
+
+#[derive(Clone, Copy)]
+pub struct DeliveryWorkerConfig {
+    pub poll_interval: std::time::Duration,
+    pub max_retries: i32,
+}
+
+impl From<&DeliveryConfig> for DeliveryWorkerConfig {
+    fn from(config: &DeliveryConfig) -> Self {
+        Self {
+            poll_interval: config.poll_interval(),
+            max_retries: config.max_retries,
+        }
+    }
+}
+
+/// What the worker needs to add the unsubscribe footer and the tracking to
+/// issues.
+pub struct DeliveryContext {
+    /// Where the hosted pages are served, see `AppBaseUrl`.
+    pub base_url: String,
+    pub translations: Arc<Translations>,
+}
+
+impl DeliveryContext {
+    fn unsubscribe_url(&self, sub_token: &str) -> String {
+        format!("{}/unsubscribe?sub_token={}", self.base_url, sub_token)
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id=tracing::field::Empty, subscriber_id=tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    context: &DeliveryContext,
+    max_retries: i32,
+) -> Result<ExecutionOutcome, sqlx::Error> {
+    let task = dequeue_task(pool).await?;
+    let (mut transaction, task) = match task {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    tracing::Span::current()
+        .record("newsletter_issue_id", tracing::field::display(task.issue_id))
+        .record("subscriber_id", tracing::field::display(task.subscriber_id));
+
+    if !task.deliverable {
+        tracing::info!("Skipping a sub who unsubscribed after the issue was queued");
+        delete_task(&mut transaction, task.issue_id, task.subscriber_id).await?;
+        transaction.commit().await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+
+    let email = match SubscriberEmail::try_from(task.email.clone()) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e,
+                "Skipping a confirmed sub bacause of invalid email");
+            delete_task(&mut transaction, task.issue_id, task.subscriber_id).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    let issue = match task.variant {
+        Some(variant) => get_variant(pool, task.issue_id, variant).await?,
+        None => get_issue(pool, task.issue_id, &task.locale).await?,
+    };
+    let sub_token = match &task.sub_token {
+        Some(sub_token) => sub_token.clone(),
+        None => store_sub_token(&mut transaction, task.subscriber_id).await?,
+    };
+    let unsubscribe_url = context.unsubscribe_url(&sub_token);
+    let mut args = FluentArgs::new();
+    args.set("link", unsubscribe_url.clone());
+    let translations = &context.translations;
+    let html_footer = translations.message(&task.locale, "issue-footer-html", Some(&args));
+    let text_footer = translations.message(&task.locale, "issue-footer-text", Some(&args));
+    let unsubscribe = Unsubscribe {
+        url: &unsubscribe_url,
+        html_footer: &format!("\n{}", html_footer),
+        text_footer: &format!("\n\n{}", text_footer),
+    };
+    let template = IssueTemplate {
+        title: &issue.title,
+        html: &issue.html_content,
+        text: &issue.text_content,
+    };
+    // Issues are checked when they are stored, so only a broken filter or
+    // the like can get here. Retrying would not fix it.
+    let issue = match template.render(&task.name, &task.email, &task.attributes, &unsubscribe) {
+        Ok(issue) => issue,
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e,
+                "Failed to fill in the merge tags of an issue, giving up");
+            delete_task(&mut transaction, task.issue_id, task.subscriber_id).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let tracking_token =
+        tracking::store_delivery(&mut transaction, task.issue_id, task.subscriber_id, task.variant)
+            .await?;
+    let links = tracking::tracked_links(&issue.html, &[&unsubscribe_url]);
+    let link_ids = tracking::store_links(pool, task.issue_id, &links).await?;
+    let html = tracking::track_html(&issue.html, &context.base_url, &tracking_token, &link_ids);
+    match email_client
+        .send_email(email, &issue.title, &html, &issue.text)
+        .await
+    {
+        Ok(()) => delete_task(&mut transaction, task.issue_id, task.subscriber_id).await?,
+        Err(e) if task.n_retries < max_retries => {
+            tracing::warn!(error.cause_chain = ?e, n_retries = task.n_retries,
+                "Failed to deliver issue to a confirmed sub, retrying later");
+            postpone_task(&mut transaction, &task).await?;
+        }
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, n_retries = task.n_retries,
+                "Failed to deliver issue to a confirmed sub, giving up");
+            delete_task(&mut transaction, task.issue_id, task.subscriber_id).await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct Task {
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    email: String,
+    name: String,
+    attributes: serde_json::Value,
+    locale: String,
+    /// Any token of the sub, for the unsubscribe link.
+    sub_token: Option<String>,
+    /// Variant of a tested issue the sub gets.
+    variant: Option<i32>,
+    /// Whether the sub is still confirmed, or the issue is the goodbye email
+    /// of their list.
+    deliverable: bool,
+    n_retries: i32,
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let row = sqlx::query!(
+        r#"
+        select q.newsletter_issue_id, q.subscriber_id, q.n_retries, q.variant,
+            s.email, s.name, s.attributes, s.locale,
+            (
+                select t.subscription_token from subscription_tokens t
+                where t.subscriber_id = s.id
+                limit 1
+            ) as sub_token,
+            s.status = 'confirmed' or exists (
+                select 1 from automations a
+                where a.newsletter_issue_id = q.newsletter_issue_id and a.event = $1
+            ) as "deliverable!"
+        from issue_delivery_queue q
+        join subscriptions s on s.id = q.subscriber_id
+        where q.execute_after <= now()
+        for update of q
+        skip locked
+        limit 1
+        "#,
+        AutomationEvent::Unsubscribed.as_str()
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    Ok(row.map(|r| {
+        (
+            transaction,
+            Task {
+                issue_id: r.newsletter_issue_id,
+                subscriber_id: r.subscriber_id,
+                email: r.email,
+                name: r.name,
+                attributes: r.attributes,
+                locale: r.locale,
+                sub_token: r.sub_token,
+                variant: r.variant,
+                deliverable: r.deliverable,
+                n_retries: r.n_retries,
+            },
+        )
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        delete from issue_delivery_queue
+        where newsletter_issue_id = $1 and subscriber_id = $2
+        "#,
+        issue_id,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Subs added without subscribing themselves, like imported ones, get their
+/// token when they are first sent an issue.
+#[tracing::instrument(skip_all)]
+async fn store_sub_token(
+    transaction: &mut PgTransaction,
+    subscriber_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    let sub_token = generate_sub_token();
+    sqlx::query!(
+        "insert into subscription_tokens (subscription_token, subscriber_id) values ($1, $2)",
+        sub_token,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(sub_token)
+}
+
+#[tracing::instrument(skip_all)]
+async fn postpone_task(transaction: &mut PgTransaction, task: &Task) -> Result<(), sqlx::Error> {
+    let backoff = chrono::Duration::seconds(2i64.pow(task.n_retries.clamp(0, 16) as u32));
+    sqlx::query!(
+        r#"
+        update issue_delivery_queue
+        set n_retries = n_retries + 1, execute_after = $3
+        where newsletter_issue_id = $1 and subscriber_id = $2
+        "#,
+        task.issue_id,
+        task.subscriber_id,
+        Utc::now() + backoff
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+/// A variant of a tested issue. Tested issues have no translations.
+#[tracing::instrument(skip_all)]
+async fn get_variant(
+    pool: &PgPool,
+    issue_id: Uuid,
+    variant: i32,
+) -> Result<NewsletterIssue, sqlx::Error> {
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        select title, text_content, html_content
+        from issue_variants
+        where newsletter_issue_id = $1 and variant = $2
+        "#,
+        issue_id,
+        variant
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// The issue in the translation closest to `locale`, or as it was written if
+/// there is none on the fallback chain of `locale`.
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+    locale: &str,
+) -> Result<NewsletterIssue, sqlx::Error> {
+    let translation = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        select title, text_content, html_content
+        from newsletter_issue_translations
+        where newsletter_issue_id = $1 and locale = any($2)
+        order by array_position($2, locale)
+        limit 1
+        "#,
+        issue_id,
+        &fallback_chain(locale)[..]
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(translation) = translation {
+        return Ok(translation);
+    }
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        select title, text_content, html_content
+        from newsletter_issues
+        where newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+}
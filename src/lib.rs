@@ -1,6 +1,26 @@
+pub mod ab_tests;
+pub mod audit;
+pub mod automation;
+pub mod authentication;
+pub mod authorization;
 pub mod config;
+pub mod deliverability;
 pub mod domain;
 pub mod email_client;
+pub mod error;
+pub mod extract;
+pub mod issue_delivery_worker;
+pub mod localisation;
+pub mod merge_tags;
+pub mod openapi;
+pub mod pages;
+pub mod problem;
+pub mod rate_limit;
 pub mod routes;
+pub mod scheduler;
+pub mod segments;
+pub mod secret;
+pub mod sequences;
 pub mod startup;
 pub mod telemetry;
+pub mod tracking;
@@ -0,0 +1,270 @@
+use crate::config::LocalisationConfig;
+use crate::error::error_chain_fmt;
+use actix_web::http::header::ACCEPT_LANGUAGE;
+use actix_web::HttpRequest;
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use std::collections::HashMap;
+use std::path::Path;
+use unic_langid::LanguageIdentifier;
+
+#[derive(thiserror::Error)]
+pub enum LocalisationError {
+    #[error("Failed to read {path}")]
+    ReadError {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Invalid catalogue {path}: {errors}")]
+    ParseError { path: String, errors: String },
+    #[error("{0} is not a language tag")]
+    InvalidLocale(String),
+    #[error("There is no catalogue for the default locale {0}")]
+    MissingDefaultLocale(String),
+}
+
+impl std::fmt::Debug for LocalisationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Fluent catalogues of the text subscribers see, one directory of `.ftl`
+/// files per locale.
+///
+/// Lookups walk the fallback chain of the locale, so `de-AT` falls back to
+/// `de` and then to the default locale, one message at a time.
+pub struct Translations {
+    bundles: HashMap<String, FluentBundle<FluentResource>>,
+    default_locale: String,
+}
+
+impl Translations {
+    pub fn load(config: &LocalisationConfig) -> Result<Self, LocalisationError> {
+        let default_locale = canonical_locale(&config.default_locale)
+            .ok_or_else(|| LocalisationError::InvalidLocale(config.default_locale.clone()))?;
+        let read_error = |path: &Path| {
+            let path = path.display().to_string();
+            move |source| LocalisationError::ReadError { path, source }
+        };
+        let dir = Path::new(&config.catalogues_dir);
+        let mut bundles = HashMap::new();
+        for entry in std::fs::read_dir(dir).map_err(read_error(dir))? {
+            let locale_dir = entry.map_err(read_error(dir))?.path();
+            if !locale_dir.is_dir() {
+                continue;
+            }
+            let name = locale_dir.file_name().unwrap_or_default().to_string_lossy();
+            let langid: LanguageIdentifier = name
+                .parse()
+                .map_err(|_| LocalisationError::InvalidLocale(name.to_string()))?;
+            let mut bundle = FluentBundle::new_concurrent(vec![langid.clone()]);
+            // Isolation marks would end up inside the links of the emails.
+            bundle.set_use_isolating(false);
+            for file in std::fs::read_dir(&locale_dir).map_err(read_error(&locale_dir))? {
+                let path = file.map_err(read_error(&locale_dir))?.path();
+                if path.extension() != Some("ftl".as_ref()) {
+                    continue;
+                }
+                let source = std::fs::read_to_string(&path).map_err(read_error(&path))?;
+                let parse_error = |errors: String| LocalisationError::ParseError {
+                    path: path.display().to_string(),
+                    errors,
+                };
+                let resource = FluentResource::try_new(source)
+                    .map_err(|(_, errors)| parse_error(format!("{:?}", errors)))?;
+                bundle
+                    .add_resource(resource)
+                    .map_err(|errors| parse_error(format!("{:?}", errors)))?;
+            }
+            bundles.insert(langid.to_string(), bundle);
+        }
+        if !bundles.contains_key(&default_locale) {
+            return Err(LocalisationError::MissingDefaultLocale(default_locale));
+        }
+        Ok(Self {
+            bundles,
+            default_locale,
+        })
+    }
+
+    pub fn default_locale(&self) -> &str {
+        &self.default_locale
+    }
+
+    /// The first of the requested locales there is a catalogue for, trying
+    /// the fallback chain of each in turn, or the default locale.
+    pub fn negotiate(&self, requested: &[String]) -> String {
+        requested
+            .iter()
+            .flat_map(|locale| fallback_chain(locale))
+            .find(|locale| self.bundles.contains_key(locale))
+            .unwrap_or_else(|| self.default_locale.clone())
+    }
+
+    /// The message `id` in `locale`, if any catalogue on its fallback chain
+    /// has it.
+    pub fn lookup(&self, locale: &str, id: &str, args: Option<&FluentArgs>) -> Option<String> {
+        let chain = fallback_chain(locale)
+            .into_iter()
+            .chain(std::iter::once(self.default_locale.clone()));
+        for locale in chain {
+            let bundle = match self.bundles.get(&locale) {
+                Some(bundle) => bundle,
+                None => continue,
+            };
+            let pattern = match bundle.get_message(id).and_then(|m| m.value()) {
+                Some(pattern) => pattern,
+                None => continue,
+            };
+            let mut errors = Vec::new();
+            let message = bundle.format_pattern(pattern, args, &mut errors);
+            if !errors.is_empty() {
+                tracing::warn!(?errors, locale, id, "Failed to format a message");
+            }
+            return Some(message.into_owned());
+        }
+        None
+    }
+
+    /// Like [`Translations::lookup`], but shows the id of messages missing
+    /// from every catalogue rather than nothing.
+    pub fn message(&self, locale: &str, id: &str, args: Option<&FluentArgs>) -> String {
+        self.lookup(locale, id, args).unwrap_or_else(|| {
+            tracing::warn!(locale, id, "Missing translation");
+            id.to_string()
+        })
+    }
+}
+
+/// `locale` spelled the way the catalogues and the database spell it,
+/// e.g. `pt-BR` for `pt_br`.
+pub fn canonical_locale(locale: &str) -> Option<String> {
+    locale
+        .parse::<LanguageIdentifier>()
+        .ok()
+        .map(|langid| langid.to_string())
+}
+
+/// `locale` followed by ever less specific versions of it, down to the bare
+/// language. Empty if `locale` is not a language tag.
+pub fn fallback_chain(locale: &str) -> Vec<String> {
+    let mut langid: LanguageIdentifier = match locale.parse() {
+        Ok(langid) => langid,
+        Err(_) => return Vec::new(),
+    };
+    let mut chain = vec![langid.to_string()];
+    langid.clear_variants();
+    langid.region = None;
+    chain.push(langid.to_string());
+    langid.script = None;
+    chain.push(langid.to_string());
+    chain.dedup();
+    chain
+}
+
+/// Locales asked for explicitly, if any, followed by those of the
+/// `Accept-Language` header from the most to the least preferred.
+pub fn requested_locales(explicit: Option<&str>, req: &HttpRequest) -> Vec<String> {
+    let header = req
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    explicit
+        .map(str::to_string)
+        .into_iter()
+        .chain(parse_accept_language(header))
+        .collect()
+}
+
+fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut weighted: Vec<(f32, String)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then(|| (quality, tag.to_string()))
+        })
+        .collect();
+    // Stable, so equally preferred locales keep the order they were sent in.
+    weighted.sort_by(|a, b| b.0.total_cmp(&a.0));
+    weighted.into_iter().map(|(_, tag)| tag).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shipped() -> Translations {
+        Translations::load(&LocalisationConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn fallback_chains_drop_region_then_script() {
+        assert_eq!(fallback_chain("zh_Hant_tw"), vec!["zh-Hant-TW", "zh-Hant", "zh"]);
+        assert_eq!(fallback_chain("de"), vec!["de"]);
+        assert!(fallback_chain("not a tag").is_empty());
+    }
+
+    #[test]
+    fn accept_language_is_ordered_by_quality() {
+        assert_eq!(
+            parse_accept_language("fr;q=0.5, de-AT, en;q=0.8, *;q=0.1, es;q=0"),
+            vec!["de-AT", "en", "fr"]
+        );
+        assert!(parse_accept_language("").is_empty());
+    }
+
+    #[test]
+    fn negotiation_falls_back_to_the_language_then_the_default() {
+        let translations = shipped();
+
+        assert_eq!(translations.negotiate(&["de-AT".to_string()]), "de");
+        assert_eq!(translations.negotiate(&["xx".to_string(), "fr".to_string()]), "fr");
+        assert_eq!(translations.negotiate(&["xx".to_string()]), "en");
+        assert_eq!(translations.negotiate(&[]), "en");
+    }
+
+    #[test]
+    fn messages_missing_from_a_catalogue_come_from_the_default_one() {
+        let dir = std::env::temp_dir().join(format!("locales-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("en")).unwrap();
+        std::fs::create_dir_all(dir.join("de")).unwrap();
+        std::fs::write(dir.join("en/main.ftl"), "hello = Hello\nbye = Bye { $name }\n").unwrap();
+        std::fs::write(dir.join("de/main.ftl"), "hello = Hallo\n").unwrap();
+        let translations = Translations::load(&LocalisationConfig {
+            catalogues_dir: dir.to_str().unwrap().to_string(),
+            default_locale: "en".to_string(),
+        })
+        .unwrap();
+        let mut args = FluentArgs::new();
+        args.set("name", "Ada");
+
+        assert_eq!(translations.message("de-CH", "hello", None), "Hallo");
+        assert_eq!(translations.message("de", "bye", Some(&args)), "Bye Ada");
+        assert_eq!(translations.message("de", "missing", None), "missing");
+    }
+
+    #[test]
+    fn shipped_catalogues_translate_every_default_message() {
+        let translations = shipped();
+        let source = std::fs::read_to_string("locales/en/main.ftl").unwrap();
+        let ids: Vec<_> = source
+            .lines()
+            .filter(|line| !line.starts_with([' ', '#']))
+            .filter_map(|line| line.split_once(" = ").map(|(id, _)| id))
+            .collect();
+        assert!(!ids.is_empty());
+
+        for (locale, bundle) in &translations.bundles {
+            for id in &ids {
+                assert!(bundle.has_message(id), "{} lacks {}", locale, id);
+            }
+        }
+    }
+}
@@ -1,11 +1,33 @@
-use emailer::{config::read_config, startup::AppServer, telemetry::init_logging};
+use emailer::config::read_config;
+use emailer::startup::{AppServer, StartupError};
+use emailer::telemetry::{init_telemetry, shutdown_telemetry};
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    init_logging("emailer", "info", std::io::stdout);
+async fn main() -> Result<(), StartupError> {
+    let check_config = std::env::args().skip(1).any(|arg| arg == "--check-config");
+    if check_config {
+        return match read_config() {
+            Ok(_) => {
+                println!("Configuration is valid");
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("{:?}", e);
+                std::process::exit(1);
+            }
+        };
+    }
 
-    let config = read_config().expect("Failed to read config");
-    let server = AppServer::build(config).await?;
-    server.run().await?;
-    Ok(())
+    let config = read_config()?;
+    init_telemetry("emailer", "info", std::io::stdout, &config.telemetry)
+        .map_err(StartupError::TelemetryError)?;
+
+    // Spans of a failed start are flushed too.
+    let result = async {
+        let server = AppServer::build(config).await?;
+        server.run().await.map_err(StartupError::ServerError)
+    }
+    .await;
+    shutdown_telemetry();
+    result
 }
@@ -0,0 +1,148 @@
+use minijinja::{AutoEscape, Environment};
+use serde_json::{Map, Value};
+
+/// Title and bodies of an issue, with merge tags like `{{ name }}` or
+/// `{{ company | default("your team") }}` that are filled in with the fields
+/// of each subscriber. Values are escaped in the HTML body only.
+pub struct IssueTemplate<'a> {
+    pub title: &'a str,
+    pub html: &'a str,
+    pub text: &'a str,
+}
+
+/// Where a subscriber leaves the list. Bodies that don't place the link
+/// themselves with `{{ unsubscribe_url }}` get the footer appended.
+pub struct Unsubscribe<'a> {
+    pub url: &'a str,
+    pub html_footer: &'a str,
+    pub text_footer: &'a str,
+}
+
+pub struct RenderedIssue {
+    pub title: String,
+    pub html: String,
+    pub text: String,
+}
+
+impl<'a> IssueTemplate<'a> {
+    /// Fails on syntax errors, so that issues can be checked before they are
+    /// stored rather than when they are sent.
+    pub fn check(&self) -> Result<(), minijinja::Error> {
+        self.environment().map(|_| ())
+    }
+
+    /// `name`, `email`, `unsubscribe_url` and every custom attribute of the
+    /// subscriber are available as merge tags. Tags of attributes a
+    /// subscriber lacks are left empty.
+    pub fn render(
+        &self,
+        name: &str,
+        email: &str,
+        attributes: &Value,
+        unsubscribe: &Unsubscribe,
+    ) -> Result<RenderedIssue, minijinja::Error> {
+        let mut fields = match attributes {
+            Value::Object(attributes) => attributes.clone(),
+            _ => Map::new(),
+        };
+        fields.insert("name".to_string(), Value::from(name));
+        fields.insert("email".to_string(), Value::from(email));
+        // Made of the base URL and an alphanumeric token, nothing to escape.
+        let fields = minijinja::context! {
+            unsubscribe_url => minijinja::Value::from_safe_string(unsubscribe.url.to_string()),
+            ..minijinja::Value::from_serialize(&fields)
+        };
+        let env = self.environment()?;
+        let render = |name| env.get_template(name)?.render(&fields);
+        let render_with_footer = |name, footer: &str| {
+            let template = env.get_template(name)?;
+            let mut body = template.render(&fields)?;
+            if !template.undeclared_variables(false).contains("unsubscribe_url") {
+                body.push_str(footer);
+            }
+            Ok::<_, minijinja::Error>(body)
+        };
+        Ok(RenderedIssue {
+            title: render("title.txt")?,
+            html: render_with_footer("content.html", unsubscribe.html_footer)?,
+            text: render_with_footer("content.txt", unsubscribe.text_footer)?,
+        })
+    }
+
+    fn environment(&self) -> Result<Environment<'a>, minijinja::Error> {
+        let mut env = Environment::new();
+        env.set_auto_escape_callback(|name| {
+            if name.ends_with(".html") {
+                AutoEscape::Html
+            } else {
+                AutoEscape::None
+            }
+        });
+        env.add_template("title.txt", self.title)?;
+        env.add_template("content.html", self.html)?;
+        env.add_template("content.txt", self.text)?;
+        Ok(env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const UNSUBSCRIBE: Unsubscribe = Unsubscribe {
+        url: "https://example.com/unsubscribe?sub_token=abc",
+        html_footer: "<footer>Unsubscribe</footer>",
+        text_footer: "\n-- Unsubscribe",
+    };
+
+    #[test]
+    fn tags_are_filled_in_with_the_fields_of_the_subscriber() {
+        let template = IssueTemplate {
+            title: "News for {{ company }}",
+            html: "<p>Hi {{ name }} from {{ company }}</p>",
+            text: "Hi {{ name }}, {{ seats }} seats on {{ plan | default(\"free\") }}",
+        };
+
+        let issue = template
+            .render(
+                "Ada",
+                "ada@example.com",
+                &json!({"company": "<Acme>", "seats": 3}),
+                &UNSUBSCRIBE,
+            )
+            .unwrap();
+
+        assert_eq!(issue.title, "News for <Acme>");
+        assert_eq!(issue.html, "<p>Hi Ada from &lt;Acme&gt;</p><footer>Unsubscribe</footer>");
+        assert_eq!(issue.text, "Hi Ada, 3 seats on free\n-- Unsubscribe");
+    }
+
+    #[test]
+    fn bodies_placing_the_unsubscribe_link_get_no_footer() {
+        let template = IssueTemplate {
+            title: "Title",
+            html: "<a href=\"{{ unsubscribe_url }}\">Leave</a>",
+            text: "Text",
+        };
+
+        let issue = template.render("Ada", "ada@example.com", &json!({}), &UNSUBSCRIBE).unwrap();
+
+        assert_eq!(
+            issue.html,
+            "<a href=\"https://example.com/unsubscribe?sub_token=abc\">Leave</a>"
+        );
+        assert_eq!(issue.text, "Text\n-- Unsubscribe");
+    }
+
+    #[test]
+    fn syntax_errors_are_caught_before_sending() {
+        let template = IssueTemplate {
+            title: "Title",
+            html: "<p>{{ name </p>",
+            text: "Text",
+        };
+
+        assert!(template.check().is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::problem::{FieldError, Problem, ProblemBody};
+use crate::routes;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpResponse, ResponseError};
+use std::sync::Arc;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::Config;
+
+/// Path of the OpenAPI document.
+pub const OPENAPI_PATH: &str = "/api/openapi.json";
+
+/// Where the docs page is served, see `api_docs`.
+pub const API_DOCS_PATH: &str = "/api/docs/";
+
+/// Routes that programs use, served under `/api/v1`. The unversioned paths
+/// they are also served at are left out on purpose.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        routes::subscribe,
+        routes::confirm,
+        routes::publish_newsletter,
+        routes::draft_newsletter,
+        routes::publish_draft,
+        routes::get_ab_test,
+        routes::list_lists,
+        routes::create_list,
+        routes::list_list_fields,
+        routes::create_list_field,
+        routes::delete_list_field,
+        routes::list_segments,
+        routes::create_segment,
+        routes::delete_segment,
+        routes::preview_segment,
+        routes::preview_rules,
+        routes::list_automations,
+        routes::set_automation,
+        routes::delete_automation,
+        routes::list_sequences,
+        routes::create_sequence,
+        routes::update_sequence,
+        routes::delete_sequence,
+        routes::list_sequence_enrolments,
+        routes::list_subscribers,
+        routes::import_subscribers,
+        routes::update_subscriber_tags,
+        routes::export_subscribers,
+        routes::set_user_role,
+        routes::grant_list_role,
+        routes::revoke_list_role,
+        routes::list_lockouts,
+        routes::unlock_lockout,
+        routes::list_audit_events,
+        routes::open_session,
+        routes::end_session,
+        routes::begin_two_factor_enrolment,
+        routes::confirm_two_factor_enrolment,
+        routes::regenerate_recovery_codes,
+        routes::disable_two_factor,
+        routes::list_api_tokens,
+        routes::create_api_token,
+        routes::revoke_api_token,
+        routes::set_two_factor_requirement,
+    ),
+    components(schemas(ProblemBody, FieldError), responses(Problem))
+)]
+struct V1Api;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "emailer", description = "Newsletter subscriptions and delivery."),
+    paths(routes::health_check, routes::liveness, routes::readiness),
+    nest((path = "/api/v1", api = V1Api)),
+    modifiers(&SecuritySchemes)
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        let mut basic = Http::new(HttpAuthScheme::Basic);
+        basic.description = Some(
+            "Username and password. Admins with two-factor authentication also send the \
+             current code in `X-Two-Factor-Code`, which is single use, so they open a \
+             session with `POST /admin/sessions` and send its token instead."
+                .to_string(),
+        );
+        components.add_security_scheme("basic", SecurityScheme::Http(basic));
+        let mut bearer = Http::new(HttpAuthScheme::Bearer);
+        bearer.description = Some(
+            "API token, limited to the scopes it was created with, or session token.".into(),
+        );
+        components.add_security_scheme("bearer", SecurityScheme::Http(bearer));
+    }
+}
+
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Swagger UI rendering the OpenAPI document, only served when
+/// `application.serve_api_docs` is set. The UI is compiled into the binary,
+/// so the page loads no scripts from elsewhere.
+pub async fn api_docs(file: web::Path<String>) -> HttpResponse {
+    match utoipa_swagger_ui::serve(&file, Arc::new(Config::from(OPENAPI_PATH))) {
+        Ok(Some(file)) => HttpResponse::Ok()
+            .content_type(file.content_type)
+            .body(file.bytes.into_owned()),
+        Ok(None) => Problem::new(StatusCode::NOT_FOUND, "not_found").error_response(),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to serve the docs page");
+            Problem::internal().error_response()
+        }
+    }
+}
+
+/// The files of the docs page are linked relative to it, so it needs the
+/// trailing slash.
+pub async fn api_docs_redirect() -> HttpResponse {
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, API_DOCS_PATH))
+        .finish()
+}
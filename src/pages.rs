@@ -0,0 +1,156 @@
+use crate::localisation::Translations;
+use fluent_bundle::{FluentArgs, FluentValue};
+use minijinja::value::{Kwargs, Value};
+use minijinja::{path_loader, Environment, ErrorKind};
+use serde::Serialize;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Templates every installation must have, checked at startup.
+const REQUIRED_TEMPLATES: &[&str] = &[
+    "subscribe.html",
+    "check_inbox.html",
+    "confirmed.html",
+    "invalid_token.html",
+    "unsubscribe.html",
+    "unsubscribed.html",
+    "not_found.html",
+];
+
+/// The list a page is about, templates can use it to adapt their look.
+#[derive(Debug, Clone, Serialize)]
+pub struct PageList {
+    pub list_id: Uuid,
+    pub name: String,
+}
+
+/// Renders the hosted pages. A template in `lists/<list_id>/` of the
+/// templates directory replaces the default one of the same name for that
+/// list, so lists can be themed one page at a time.
+///
+/// Templates get their text from the catalogues through `t`, as in
+/// `{{ t("confirmed-body", list=list.name) }}`.
+pub struct Pages {
+    env: Environment<'static>,
+    translations: Arc<Translations>,
+}
+
+impl Pages {
+    pub fn new(
+        templates_dir: &str,
+        translations: Arc<Translations>,
+    ) -> Result<Self, minijinja::Error> {
+        let mut env = Environment::new();
+        env.set_loader(path_loader(templates_dir));
+        for name in REQUIRED_TEMPLATES {
+            env.get_template(name)?;
+        }
+        Ok(Self { env, translations })
+    }
+
+    pub fn render(
+        &self,
+        list: &PageList,
+        locale: &str,
+        name: &str,
+        context: impl Serialize,
+    ) -> Result<String, minijinja::Error> {
+        let themed = format!("lists/{}/{}", list.list_id, name);
+        let template = match self.env.get_template(&themed) {
+            Ok(template) => template,
+            Err(e) if e.kind() == ErrorKind::TemplateNotFound => self.env.get_template(name)?,
+            Err(e) => return Err(e),
+        };
+        let translations = self.translations.clone();
+        let message_locale = locale.to_string();
+        let t = Value::from_function(move |id: &str, kwargs: Kwargs| {
+            let mut args = FluentArgs::new();
+            for key in kwargs.args() {
+                let value: Value = kwargs.get(key)?;
+                let value = match f64::try_from(value.clone()) {
+                    Ok(number) if value.is_number() => FluentValue::from(number),
+                    _ => FluentValue::from(value.to_string()),
+                };
+                args.set(key.to_string(), value);
+            }
+            Ok::<_, minijinja::Error>(translations.message(&message_locale, id, Some(&args)))
+        });
+        template.render(minijinja::context! { list, locale, t, ..Value::from_serialize(context) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LocalisationConfig;
+
+    fn pages(dir: &std::path::Path) -> Result<Pages, minijinja::Error> {
+        let translations = Translations::load(&LocalisationConfig::default()).unwrap();
+        Pages::new(dir.to_str().unwrap(), Arc::new(translations))
+    }
+
+    fn templates() -> (std::path::PathBuf, PageList) {
+        let dir = std::env::temp_dir().join(format!("pages-{}", Uuid::new_v4()));
+        let list = PageList {
+            list_id: Uuid::new_v4(),
+            name: "Weekly <news>".to_string(),
+        };
+        std::fs::create_dir_all(dir.join(format!("lists/{}", list.list_id))).unwrap();
+        for name in REQUIRED_TEMPLATES {
+            std::fs::write(dir.join(name), "default {{ list.name }} {{ email }}").unwrap();
+        }
+        (dir, list)
+    }
+
+    #[test]
+    fn lists_without_their_own_templates_get_the_default_ones() {
+        let (dir, list) = templates();
+        let pages = pages(&dir).unwrap();
+
+        let page = pages
+            .render(&list, "en", "confirmed.html", minijinja::context! { email => "a@b.c" })
+            .unwrap();
+
+        assert_eq!(page, "default Weekly &lt;news&gt; a@b.c");
+    }
+
+    #[test]
+    fn list_templates_replace_the_default_ones() {
+        let (dir, list) = templates();
+        let themed = dir.join(format!("lists/{}/confirmed.html", list.list_id));
+        std::fs::write(themed, "themed {{ list.name }}").unwrap();
+        let pages = pages(&dir).unwrap();
+
+        let page = pages.render(&list, "en", "confirmed.html", ()).unwrap();
+
+        assert_eq!(page, "themed Weekly &lt;news&gt;");
+    }
+
+    #[test]
+    fn templates_translate_into_the_locale_of_the_page() {
+        let (dir, list) = templates();
+        let text = r#"{{ t("confirmed-body", list=list.name) }}"#;
+        std::fs::write(dir.join("confirmed.html"), text).unwrap();
+        let pages = pages(&dir).unwrap();
+
+        let german = pages.render(&list, "de-AT", "confirmed.html", ()).unwrap();
+        let unknown = pages.render(&list, "xx", "confirmed.html", ()).unwrap();
+
+        assert_eq!(
+            german,
+            "Danke für die Bestätigung, Sie erhalten ab jetzt Weekly &lt;news&gt;."
+        );
+        assert_eq!(
+            unknown,
+            "Thanks for confirming, you will now receive Weekly &lt;news&gt;."
+        );
+    }
+
+    #[test]
+    fn missing_templates_are_reported_at_startup() {
+        let (dir, _) = templates();
+        std::fs::remove_file(dir.join("unsubscribe.html")).unwrap();
+
+        assert!(pages(&dir).is_err());
+    }
+}
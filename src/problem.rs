@@ -0,0 +1,261 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpResponse, ResponseError};
+use serde::Serialize;
+use tracing_actix_web::RequestId;
+use utoipa::openapi::{ContentBuilder, Ref, RefOr, Response, ResponseBuilder};
+use utoipa::{ToResponse, ToSchema};
+use uuid::Uuid;
+
+/// Header echoing the id of the request, the same id the logs carry.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+tokio::task_local! {
+    static REQUEST_ID: Uuid;
+}
+
+/// An RFC 7807 problem detail. Every error response of the API is one.
+///
+/// `code` is a stable, machine readable identifier of the problem, `detail`
+/// a message meant for humans. Neither may contain internal causes, those
+/// only go to the logs.
+#[derive(Debug)]
+pub struct Problem {
+    status: StatusCode,
+    code: &'static str,
+    detail: Option<String>,
+    errors: Vec<FieldError>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+/// What is wrong with one field of the request.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+    /// A value the client may want to send instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+impl FieldError {
+    pub fn new(
+        field: impl Into<String>,
+        code: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            field: field.into(),
+            code: code.into(),
+            message: message.into(),
+            suggestion: None,
+        }
+    }
+
+    pub fn suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+#[schema(as = Problem)]
+pub(crate) struct ProblemBody<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    #[schema(value_type = Vec<FieldError>)]
+    errors: &'a [FieldError],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, format = "uuid")]
+    request_id: Option<Uuid>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, code: &'static str) -> Self {
+        Self {
+            status,
+            code,
+            detail: None,
+            errors: Vec::new(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// A 500 that tells the client nothing but the request id to report.
+    pub fn internal() -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error")
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn field_errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// Sets `Retry-After` in whole seconds, never less than one.
+    pub fn retry_after(self, duration: std::time::Duration) -> Self {
+        let seconds = HeaderValue::from(duration.as_secs().max(1));
+        self.header(header::RETRY_AFTER, seconds)
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{}: {}", self.code, detail),
+            None => f.write_str(self.code),
+        }
+    }
+}
+
+impl std::error::Error for Problem {}
+
+/// Lets operations document their error responses as `response = Problem`.
+impl<'r> ToResponse<'r> for Problem {
+    fn response() -> (&'r str, RefOr<Response>) {
+        let response = ResponseBuilder::new()
+            .description("A problem detail, `code` tells what went wrong.")
+            .content(
+                PROBLEM_JSON,
+                ContentBuilder::new()
+                    .schema(Some(Ref::from_schema_name("Problem")))
+                    .build(),
+            )
+            .build();
+        ("Problem", response.into())
+    }
+}
+
+impl ResponseError for Problem {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let body = ProblemBody {
+            // The code identifies the problem, so the type carries nothing more.
+            problem_type: "about:blank",
+            title: self.status.canonical_reason().unwrap_or("Error"),
+            status: self.status.as_u16(),
+            code: self.code,
+            detail: self.detail.as_deref(),
+            errors: &self.errors,
+            request_id: REQUEST_ID.try_with(|id| *id).ok(),
+        };
+        let mut response = HttpResponse::build(self.status);
+        response.content_type(PROBLEM_JSON);
+        for (name, value) in &self.headers {
+            response.insert_header((name.clone(), value.clone()));
+        }
+        response.json(body)
+    }
+}
+
+/// Makes the request id of `TracingLogger` available to problem responses
+/// and echoes it in a header. Errors returned by inner middleware are
+/// rendered here, while the id is still known.
+pub async fn with_request_id<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, actix_web::Error> {
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .map(|id| **id)
+        .unwrap_or_else(Uuid::new_v4);
+    let header_value = HeaderValue::from_str(&request_id.to_string()).unwrap();
+    let header_name = HeaderName::from_static(REQUEST_ID_HEADER);
+    REQUEST_ID
+        .scope(request_id, async move {
+            match next.call(req).await {
+                Ok(mut response) => {
+                    response.headers_mut().insert(header_name, header_value);
+                    Ok(response)
+                }
+                Err(e) => {
+                    let mut response = e.error_response();
+                    response.headers_mut().insert(header_name, header_value);
+                    Err(InternalError::from_response(e, response).into())
+                }
+            }
+        })
+        .await
+}
+
+/// Error handler for the body, query and path extractors.
+pub fn invalid_request(error: impl ResponseError) -> actix_web::Error {
+    let status = error.status_code();
+    let code = match status {
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        _ => "invalid_request",
+    };
+    Problem::new(status, code).detail(error.to_string()).into()
+}
+
+/// Response for routes that don't exist.
+pub async fn not_found() -> HttpResponse {
+    Problem::new(StatusCode::NOT_FOUND, "not_found").error_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+
+    async fn render(problem: Problem) -> (HttpResponse<()>, serde_json::Value) {
+        let (response, body) = problem.error_response().into_parts();
+        let body = to_bytes(body).await.unwrap();
+        (response, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn problems_are_rendered_as_problem_json() {
+        let (response, body) =
+            render(Problem::new(StatusCode::CONFLICT, "conflict").detail("Already taken")).await;
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), PROBLEM_JSON);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Conflict",
+                "status": 409,
+                "code": "conflict",
+                "detail": "Already taken",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn the_request_id_is_included_when_known() {
+        let request_id = Uuid::new_v4();
+        let problem = Problem::internal()
+            .field_errors(vec![FieldError::new("name", "empty", "name is empty")]);
+        let (_, body) = REQUEST_ID.scope(request_id, render(problem)).await;
+        assert_eq!(body["request_id"], request_id.to_string());
+        assert_eq!(body["errors"][0]["field"], "name");
+        assert!(body.get("detail").is_none());
+    }
+}
@@ -0,0 +1,382 @@
+use crate::config::{Limit, RateLimitConfig, RateLimitStoreKind, RouteLimits};
+use crate::problem::Problem;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, TimeZone, Utc};
+use sqlx::PgPool;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Counter storage for fixed window rate limiting.
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Records a hit for `key` in the window from `window_start` to
+    /// `window_end` and returns the number of hits in that window, including
+    /// this one.
+    async fn hit(
+        &self,
+        key: &str,
+        window_start: DateTime<Utc>,
+        window_end: DateTime<Utc>,
+    ) -> Result<u32, sqlx::Error>;
+}
+
+/// Keeps the counters in process, for a single instance. Keys come from
+/// clients, so the map is bounded: counters are dropped once their window is
+/// over, and the one closest to its end makes room when the map is full.
+#[derive(Default)]
+pub struct InMemoryStore {
+    buckets: Mutex<Buckets>,
+}
+
+const IN_MEMORY_MAX_BUCKETS: usize = 100_000;
+
+#[derive(Default)]
+struct Buckets {
+    counters: HashMap<String, Counter>,
+    /// Keys by the end of their window, to find expired counters without
+    /// scanning the whole map.
+    expiries: BTreeSet<(DateTime<Utc>, String)>,
+}
+
+struct Counter {
+    window_start: DateTime<Utc>,
+    window_end: DateTime<Utc>,
+    hits: u32,
+}
+
+impl Buckets {
+    fn hit(
+        &mut self,
+        key: &str,
+        window_start: DateTime<Utc>,
+        window_end: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> u32 {
+        while self.expiries.first().is_some_and(|(expiry, _)| *expiry <= now) {
+            self.evict_first();
+        }
+        if !self.counters.contains_key(key) && self.counters.len() >= IN_MEMORY_MAX_BUCKETS {
+            self.evict_first();
+        }
+        if let Some(counter) = self.counters.get_mut(key) {
+            if counter.window_start == window_start {
+                counter.hits += 1;
+                return counter.hits;
+            }
+            self.expiries.remove(&(counter.window_end, key.to_string()));
+        }
+        self.expiries.insert((window_end, key.to_string()));
+        self.counters.insert(
+            key.to_string(),
+            Counter {
+                window_start,
+                window_end,
+                hits: 1,
+            },
+        );
+        1
+    }
+
+    fn evict_first(&mut self) {
+        if let Some((_, key)) = self.expiries.pop_first() {
+            self.counters.remove(&key);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn hit(
+        &self,
+        key: &str,
+        window_start: DateTime<Utc>,
+        window_end: DateTime<Utc>,
+    ) -> Result<u32, sqlx::Error> {
+        let mut buckets = self.buckets.lock().unwrap();
+        Ok(buckets.hit(key, window_start, window_end, Utc::now()))
+    }
+}
+
+/// Keeps the counters in `rate_limit_buckets`, shared by every instance.
+/// Counters whose window is over are deleted on the next hit of any key.
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for PostgresStore {
+    async fn hit(
+        &self,
+        key: &str,
+        window_start: DateTime<Utc>,
+        window_end: DateTime<Utc>,
+    ) -> Result<u32, sqlx::Error> {
+        sqlx::query!("delete from rate_limit_buckets where window_end <= now()")
+            .execute(&self.pool)
+            .await?;
+        let row = sqlx::query!(
+            r#"
+            insert into rate_limit_buckets (key, window_start, window_end, hits)
+            values ($1, $2, $3, 1)
+            on conflict (key) do update set
+                hits = case
+                    when rate_limit_buckets.window_start = excluded.window_start
+                    then rate_limit_buckets.hits + 1
+                    else 1
+                end,
+                window_start = excluded.window_start,
+                window_end = excluded.window_end
+            returning hits
+            "#,
+            key,
+            window_start,
+            window_end
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.hits.max(0) as u32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Ip,
+    Email,
+}
+
+impl Scope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Ip => "ip",
+            Scope::Email => "email",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Too many requests, retry after {} seconds", .retry_after.as_secs())]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl ResponseError for RateLimited {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        Problem::new(self.status_code(), "rate_limited")
+            .detail(self.to_string())
+            .retry_after(self.retry_after)
+            .error_response()
+    }
+}
+
+pub struct RateLimiter {
+    store: Box<dyn RateLimitStore>,
+    routes: HashMap<String, RouteLimits>,
+    trust_forwarded_for: bool,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig, pool: PgPool) -> Self {
+        let store: Box<dyn RateLimitStore> = match config.store {
+            RateLimitStoreKind::Memory => Box::new(InMemoryStore::default()),
+            RateLimitStoreKind::Postgres => Box::new(PostgresStore::new(pool)),
+        };
+        Self::with_store(config, store)
+    }
+
+    pub fn with_store(config: &RateLimitConfig, store: Box<dyn RateLimitStore>) -> Self {
+        Self {
+            store,
+            routes: config.routes.clone(),
+            trust_forwarded_for: config.trust_forwarded_for,
+        }
+    }
+
+    /// Counts a request against the `scope` limit of `route`. Store failures
+    /// are logged and let the request through.
+    #[tracing::instrument(name = "Check rate limit", skip(self, key))]
+    pub async fn check(&self, route: &str, scope: Scope, key: &str) -> Result<(), RateLimited> {
+        let limit = match self.limit(route, scope) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let now = Utc::now();
+        let window_start = window_start(now, limit);
+        let window_end = window_start + chrono::Duration::seconds(limit.window_seconds as i64);
+        let bucket = format!("{}:{}:{}", route, scope.as_str(), key);
+        match self.store.hit(&bucket, window_start, window_end).await {
+            Ok(hits) if hits > limit.max_requests => {
+                let retry_after = (window_end - now).to_std().unwrap_or_default();
+                tracing::warn!(route, scope = scope.as_str(), "Rate limit exceeded");
+                Err(RateLimited { retry_after })
+            }
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, "Failed to update rate limit counter");
+                Ok(())
+            }
+        }
+    }
+
+    pub fn client_ip(&self, request: &HttpRequest) -> String {
+        let forwarded = self
+            .trust_forwarded_for
+            .then(|| request.connection_info().realip_remote_addr().map(str::to_string))
+            .flatten();
+        forwarded
+            .or_else(|| request.peer_addr().map(|addr| addr.ip().to_string()))
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    fn limit(&self, route: &str, scope: Scope) -> Option<Limit> {
+        let limits = self.routes.get(route)?;
+        match scope {
+            Scope::Ip => limits.per_ip,
+            Scope::Email => limits.per_email,
+        }
+    }
+}
+
+fn window_start(now: DateTime<Utc>, limit: Limit) -> DateTime<Utc> {
+    let window = limit.window_seconds.max(1) as i64;
+    let start = now.timestamp() - now.timestamp().rem_euclid(window);
+    Utc.timestamp_opt(start, 0).unwrap()
+}
+
+/// Middleware applying the per IP limit of `route`.
+pub async fn limit_by_ip<B: MessageBody>(
+    route: &'static str,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, actix_web::Error> {
+    if let Some(limiter) = req.app_data::<web::Data<RateLimiter>>() {
+        let ip = limiter.client_ip(req.request());
+        limiter.check(route, Scope::Ip, &ip).await?;
+    }
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(limits: RouteLimits) -> RateLimiter {
+        let config = RateLimitConfig {
+            store: RateLimitStoreKind::Memory,
+            trust_forwarded_for: false,
+            routes: HashMap::from([("subscriptions".to_string(), limits)]),
+        };
+        RateLimiter::with_store(&config, Box::new(InMemoryStore::default()))
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_limit_are_rejected() {
+        let limiter = limiter(RouteLimits {
+            per_ip: Some(Limit {
+                max_requests: 2,
+                window_seconds: 60,
+            }),
+            per_email: None,
+        });
+
+        assert!(limiter.check("subscriptions", Scope::Ip, "1.1.1.1").await.is_ok());
+        assert!(limiter.check("subscriptions", Scope::Ip, "1.1.1.1").await.is_ok());
+        let limited = limiter
+            .check("subscriptions", Scope::Ip, "1.1.1.1")
+            .await
+            .unwrap_err();
+        assert!(limited.retry_after <= Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn keys_and_scopes_are_counted_separately() {
+        let limiter = limiter(RouteLimits {
+            per_ip: Some(Limit {
+                max_requests: 1,
+                window_seconds: 60,
+            }),
+            per_email: Some(Limit {
+                max_requests: 1,
+                window_seconds: 60,
+            }),
+        });
+
+        assert!(limiter.check("subscriptions", Scope::Ip, "1.1.1.1").await.is_ok());
+        assert!(limiter.check("subscriptions", Scope::Ip, "2.2.2.2").await.is_ok());
+        assert!(limiter.check("subscriptions", Scope::Email, "1.1.1.1").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn routes_without_limits_are_not_limited() {
+        let limiter = limiter(RouteLimits::default());
+
+        for _ in 0..10 {
+            assert!(limiter.check("subscriptions", Scope::Ip, "1.1.1.1").await.is_ok());
+            assert!(limiter.check("newsletters", Scope::Ip, "1.1.1.1").await.is_ok());
+        }
+    }
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn new_window_resets_the_counter() {
+        let mut buckets = Buckets::default();
+
+        assert_eq!(buckets.hit("key", at(0), at(60), at(1)), 1);
+        assert_eq!(buckets.hit("key", at(0), at(60), at(2)), 2);
+        assert_eq!(buckets.hit("key", at(60), at(120), at(61)), 1);
+        assert_eq!(buckets.expiries.len(), 1);
+    }
+
+    #[test]
+    fn counters_are_dropped_when_their_own_window_ends() {
+        let mut buckets = Buckets::default();
+        buckets.hit("minute", at(0), at(60), at(1));
+        buckets.hit("hour", at(0), at(3600), at(1));
+
+        buckets.hit("other", at(60), at(120), at(61));
+
+        assert!(!buckets.counters.contains_key("minute"));
+        assert_eq!(buckets.hit("hour", at(0), at(3600), at(62)), 2);
+    }
+
+    #[test]
+    fn a_full_store_makes_room_for_new_keys() {
+        let mut buckets = Buckets::default();
+        for i in 0..IN_MEMORY_MAX_BUCKETS + 10 {
+            buckets.hit(&i.to_string(), at(0), at(60 + i as i64), at(1));
+        }
+
+        assert_eq!(buckets.counters.len(), IN_MEMORY_MAX_BUCKETS);
+        assert_eq!(buckets.expiries.len(), IN_MEMORY_MAX_BUCKETS);
+        assert!(!buckets.counters.contains_key("0"));
+    }
+
+    #[test]
+    fn window_start_is_aligned_to_window() {
+        let limit = Limit {
+            max_requests: 1,
+            window_seconds: 60,
+        };
+        assert_eq!(
+            window_start(Utc.timestamp_opt(125, 0).unwrap(), limit),
+            Utc.timestamp_opt(120, 0).unwrap()
+        );
+    }
+}
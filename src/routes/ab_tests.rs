@@ -0,0 +1,166 @@
+use crate::ab_tests::{variant_results, AbTestMetric, VariantResult};
+use crate::authentication::AuthError;
+use crate::authorization::{AuthenticatedUser, Permission};
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum AbTestError {
+    #[error("The issue does not exist or is not tested")]
+    AbTestNotFound,
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to look up the A/B test")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for AbTestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AbTestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AbTestError::AbTestNotFound => StatusCode::NOT_FOUND,
+            AbTestError::AuthError(e) => e.status_code(),
+            AbTestError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            AbTestError::AbTestNotFound => {
+                Problem::new(status, "ab_test_not_found").detail(self.to_string())
+            }
+            AbTestError::AuthError(e) => return e.error_response(),
+            AbTestError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct AbTestReport {
+    metric: AbTestMetric,
+    test_percent: i32,
+    window_hours: i32,
+    /// When the winner is picked, null while the issue is a draft.
+    decide_at: Option<DateTime<Utc>>,
+    decided_at: Option<DateTime<Utc>>,
+    winner: Option<i32>,
+    /// Recipients waiting for the winner.
+    waiting: i64,
+    variants: Vec<VariantReport>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct VariantReport {
+    title: String,
+    /// Results so far, or when the winner was picked once it was.
+    #[serde(flatten)]
+    result: VariantResult,
+}
+
+/// How the variants of a tested issue did, and which one won.
+#[utoipa::path(
+    get,
+    path = "/newsletters/{issue_id}/ab-test",
+    tag = "newsletters",
+    summary = "Show the results of an A/B test",
+    params(("issue_id" = String, Path, format = "uuid", description = "Id of the issue")),
+    responses(
+        (status = 200, description = "The test and its results", body = AbTestReport),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(name = "Show an A/B test", skip(user, pool), fields(user_id = %user.user_id))]
+pub async fn get_ab_test(
+    user: AuthenticatedUser,
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AbTestError> {
+    let issue_id = issue_id.into_inner();
+    let test = sqlx::query!(
+        r#"
+        select i.list_id, t.metric, t.test_percent, t.window_hours, t.decide_at, t.decided_at,
+            t.winner,
+            (
+                select count(*) from issue_variant_assignments a
+                where a.newsletter_issue_id = t.newsletter_issue_id and a.variant is null
+            ) as "waiting!"
+        from issue_ab_tests t
+        join newsletter_issues i on i.newsletter_issue_id = t.newsletter_issue_id
+        where t.newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(AbTestError::AbTestNotFound)?;
+    user.require(&pool, Permission::DraftIssues, Some(test.list_id))
+        .await?;
+    let metric = AbTestMetric::parse(&test.metric).unwrap_or_default();
+    let variants = sqlx::query!(
+        r#"
+        select variant, title, recipients, engaged
+        from issue_variants
+        where newsletter_issue_id = $1
+        order by variant
+        "#,
+        issue_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+    // Once decided, the winner went to everybody, so only the stored
+    // results compare the variants fairly.
+    let mut live: HashMap<i32, VariantResult> = match test.winner {
+        Some(_) => HashMap::new(),
+        None => variant_results(pool.get_ref(), issue_id, metric)
+            .await?
+            .into_iter()
+            .map(|result| (result.variant, result))
+            .collect(),
+    };
+    let variants = variants
+        .into_iter()
+        .map(|row| VariantReport {
+            title: row.title,
+            result: match (row.recipients, row.engaged) {
+                (Some(recipients), Some(engaged)) => VariantResult {
+                    variant: row.variant,
+                    recipients: recipients.into(),
+                    engaged: engaged.into(),
+                },
+                _ => live.remove(&row.variant).unwrap_or(VariantResult {
+                    variant: row.variant,
+                    recipients: 0,
+                    engaged: 0,
+                }),
+            },
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(AbTestReport {
+        metric,
+        test_percent: test.test_percent,
+        window_hours: test.window_hours,
+        decide_at: test.decide_at,
+        decided_at: test.decided_at,
+        winner: test.winner,
+        waiting: test.waiting,
+        variants,
+    }))
+}
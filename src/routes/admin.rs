@@ -0,0 +1,201 @@
+use crate::audit::{self, AuditAction, AuditEvent, AuditFilter, StoredAuditEvent};
+use crate::authentication::{self, AuthError};
+use crate::authorization::{AuthenticatedUser, Permission};
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to access admin data")]
+    UnexpectedError(#[source] sqlx::Error),
+}
+
+impl std::fmt::Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AdminError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AdminError::AuthError(e) => e.status_code(),
+            AdminError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AdminError::AuthError(e) => e.error_response(),
+            AdminError::ValidationError(message) => {
+                Problem::new(self.status_code(), "validation_error")
+                    .detail(message.clone())
+                    .error_response()
+            }
+            AdminError::UnexpectedError(_) => Problem::internal().error_response(),
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/lockouts",
+    tag = "admin",
+    summary = "List active login lockouts",
+    responses(
+        (status = 200, description = "Lockouts in force", body = Vec<authentication::Lockout>),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(name = "List login lockouts", skip_all, fields(user_id = %user.user_id))]
+pub async fn list_lockouts(
+    user: AuthenticatedUser,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    user.require(&pool, Permission::ManageUsers, None).await?;
+    let lockouts = authentication::active_lockouts(&pool)
+        .await
+        .map_err(AdminError::UnexpectedError)?;
+    Ok(HttpResponse::Ok().json(lockouts))
+}
+
+/// At least one of the two must be given.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UnlockRequest {
+    username: Option<String>,
+    ip: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct UnlockResponse {
+    /// Number of lockouts lifted.
+    cleared: u64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/lockouts/unlock",
+    tag = "admin",
+    summary = "Lift login lockouts of a username or an IP",
+    request_body = UnlockRequest,
+    responses(
+        (status = 200, description = "Lockouts lifted", body = UnlockResponse),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Unlock a login lockout",
+    skip(pool, user),
+    fields(user_id = %user.user_id)
+)]
+pub async fn unlock_lockout(
+    user: AuthenticatedUser,
+    body: web::Json<UnlockRequest>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    user.require(&pool, Permission::ManageUsers, None).await?;
+    if body.username.is_none() && body.ip.is_none() {
+        return Err(AdminError::ValidationError(
+            "Either username or ip must be provided".to_string(),
+        ));
+    }
+    let cleared = authentication::unlock(&pool, body.username.as_deref(), body.ip.as_deref())
+        .await
+        .map_err(AdminError::UnexpectedError)?;
+    AuditEvent::by(&user, AuditAction::LockoutLifted)
+        .payload(serde_json::json!({
+            "username": body.username,
+            "ip": body.ip,
+            "cleared": cleared,
+        }))
+        .record(pool.get_ref())
+        .await
+        .map_err(AdminError::UnexpectedError)?;
+    Ok(HttpResponse::Ok().json(UnlockResponse { cleared }))
+}
+
+const DEFAULT_AUDIT_PAGE_SIZE: i64 = 50;
+const MAX_AUDIT_PAGE_SIZE: i64 = 500;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    #[param(value_type = Option<String>, format = "uuid")]
+    actor_id: Option<Uuid>,
+    action: Option<String>,
+    target_type: Option<String>,
+    target_id: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    /// Only events older than this one, to page through the log.
+    before: Option<i64>,
+    /// Between 1 and 500, 50 if left out.
+    limit: Option<i64>,
+}
+
+/// Audit events matching the query, newest first.
+#[utoipa::path(
+    get,
+    path = "/admin/audit_events",
+    tag = "admin",
+    summary = "Search the audit log",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Matching events", body = Vec<StoredAuditEvent>),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "List audit events",
+    skip(pool, user),
+    fields(user_id = %user.user_id)
+)]
+pub async fn list_audit_events(
+    user: AuthenticatedUser,
+    query: web::Query<AuditQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    user.require(&pool, Permission::ViewAuditLog, None).await?;
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_AUDIT_PAGE_SIZE);
+    if !(1..=MAX_AUDIT_PAGE_SIZE).contains(&limit) {
+        return Err(AdminError::ValidationError(format!(
+            "limit must be between 1 and {}",
+            MAX_AUDIT_PAGE_SIZE
+        )));
+    }
+    let filter = AuditFilter {
+        actor_id: query.actor_id,
+        action: query.action,
+        target_type: query.target_type,
+        target_id: query.target_id,
+        since: query.since,
+        until: query.until,
+        before: query.before,
+        limit,
+    };
+    let events = audit::find_events(pool.get_ref(), &filter)
+        .await
+        .map_err(AdminError::UnexpectedError)?;
+    Ok(HttpResponse::Ok().json(events))
+}
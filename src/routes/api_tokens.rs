@@ -0,0 +1,211 @@
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authentication::api_tokens::{self, ApiToken, NewApiToken, TokenScope};
+use crate::authentication::{login, AuthError, TwoFactorPolicy};
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum ApiTokenError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("API token not found")]
+    NotFound,
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to access API tokens")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for ApiTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiTokenError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiTokenError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ApiTokenError::NotFound => StatusCode::NOT_FOUND,
+            ApiTokenError::AuthError(e) => e.status_code(),
+            ApiTokenError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            ApiTokenError::AuthError(e) => return e.error_response(),
+            ApiTokenError::ValidationError(message) => {
+                Problem::new(status, "validation_error").detail(message.clone())
+            }
+            ApiTokenError::NotFound => {
+                Problem::new(status, "api_token_not_found").detail(self.to_string())
+            }
+            ApiTokenError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewTokenData {
+    name: String,
+    scopes: Vec<TokenScope>,
+    /// Tokens without expiry stay valid until revoked.
+    expires_in_days: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CreatedToken {
+    #[serde(flatten)]
+    details: ApiToken,
+    /// Shown only once, store it right away.
+    token: String,
+}
+
+/// Creates a token for the logged in user. The token itself is only part of
+/// this response.
+#[utoipa::path(
+    post,
+    path = "/admin/api_tokens",
+    tag = "api_tokens",
+    summary = "Create an API token",
+    request_body = NewTokenData,
+    responses(
+        (status = 201, description = "Created", body = CreatedToken),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Create an API token",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn create_api_token(
+    body: web::Json<NewTokenData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiTokenError> {
+    let user_id = login(&request, TwoFactorPolicy::Enforce).await?;
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ApiTokenError::ValidationError(format!(
+            "name must have between 1 and {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+    if body.scopes.is_empty() {
+        return Err(ApiTokenError::ValidationError(
+            "at least one scope is required".to_string(),
+        ));
+    }
+    let expires_at = match body.expires_in_days {
+        Some(0) => {
+            return Err(ApiTokenError::ValidationError(
+                "expires_in_days must not be 0".to_string(),
+            ))
+        }
+        Some(days) => Some(Utc::now() + chrono::Duration::days(days as i64)),
+        None => None,
+    };
+    let mut scopes = Vec::with_capacity(body.scopes.len());
+    for scope in &body.scopes {
+        if !scopes.contains(scope) {
+            scopes.push(*scope);
+        }
+    }
+    let (details, token) = api_tokens::create_token(
+        &pool,
+        user_id,
+        NewApiToken {
+            name,
+            scopes: &scopes,
+            expires_at,
+        },
+    )
+    .await?;
+    AuditEvent::from_request(&request, AuditAction::ApiTokenCreated)
+        .actor(user_id)
+        .target("api_token", details.token_id)
+        .payload(serde_json::json!({
+            "name": details.name,
+            "scopes": details.scopes,
+            "expires_at": details.expires_at,
+        }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::Created().json(CreatedToken { details, token }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/api_tokens",
+    tag = "api_tokens",
+    summary = "List the API tokens of the logged in user",
+    responses(
+        (status = 200, description = "Tokens, without their secrets", body = Vec<ApiToken>),
+        (status = 401, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "List API tokens",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn list_api_tokens(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiTokenError> {
+    let user_id = login(&request, TwoFactorPolicy::Enforce).await?;
+    let tokens = api_tokens::list_tokens(&pool, user_id).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/api_tokens/{token_id}",
+    tag = "api_tokens",
+    summary = "Revoke an API token",
+    params(("token_id" = String, Path, format = "uuid")),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 401, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Revoke an API token",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn revoke_api_token(
+    token_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiTokenError> {
+    let user_id = login(&request, TwoFactorPolicy::Enforce).await?;
+    let token_id = token_id.into_inner();
+    if !api_tokens::revoke_token(&pool, user_id, token_id).await? {
+        return Err(ApiTokenError::NotFound);
+    }
+    AuditEvent::from_request(&request, AuditAction::ApiTokenRevoked)
+        .actor(user_id)
+        .target("api_token", token_id)
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,302 @@
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authentication::AuthError;
+use crate::authorization::{AuthenticatedUser, Permission};
+use crate::automation::AutomationEvent;
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use crate::routes::{
+    canonical_translations, check_merge_tags, insert_translations, Content, NewsletterError,
+    Translation,
+};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum AutomationError {
+    #[error(transparent)]
+    InvalidEmail(#[from] NewsletterError),
+    #[error("List not found")]
+    ListNotFound,
+    #[error("Automation not found")]
+    AutomationNotFound,
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to access automations")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for AutomationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AutomationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AutomationError::InvalidEmail(e) => e.status_code(),
+            AutomationError::ListNotFound | AutomationError::AutomationNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            AutomationError::AuthError(e) => e.status_code(),
+            AutomationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            AutomationError::InvalidEmail(e) => return e.error_response(),
+            AutomationError::AuthError(e) => return e.error_response(),
+            AutomationError::ListNotFound => Problem::new(status, "list_not_found"),
+            AutomationError::AutomationNotFound => Problem::new(status, "automation_not_found"),
+            AutomationError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+/// Email sent for an event, written like an issue.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AutomationEmail {
+    title: String,
+    content: Content,
+    /// The email in other languages, by locale, as for issues.
+    #[serde(default)]
+    translations: HashMap<String, Translation>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Automation {
+    event: AutomationEvent,
+    /// Issue holding the email.
+    #[schema(value_type = String, format = "uuid")]
+    newsletter_issue_id: Uuid,
+    title: String,
+    updated_at: DateTime<Utc>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/lists/{list_id}/automations",
+    tag = "lists",
+    summary = "List the automated emails of a list",
+    params(("list_id" = String, Path, format = "uuid")),
+    responses(
+        (status = 200, description = "One per event", body = Vec<Automation>),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "List automations",
+    skip(user, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn list_automations(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AutomationError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let rows = sqlx::query!(
+        r#"
+        select a.event, a.newsletter_issue_id, i.title, a.updated_at
+        from automations a
+        join newsletter_issues i on i.newsletter_issue_id = a.newsletter_issue_id
+        where a.list_id = $1
+        order by a.event
+        "#,
+        list_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+    let automations: Vec<Automation> = rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(Automation {
+                // The table only allows known events.
+                event: AutomationEvent::parse(&row.event)?,
+                newsletter_issue_id: row.newsletter_issue_id,
+                title: row.title,
+                updated_at: row.updated_at,
+            })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(automations))
+}
+
+/// Sets the email the list sends when the event happens to one of its
+/// subscribers, e.g. a welcome email for `confirmed`. Emails already queued
+/// keep their old content.
+#[utoipa::path(
+    put,
+    path = "/admin/lists/{list_id}/automations/{event}",
+    tag = "lists",
+    summary = "Set an automated email",
+    params(
+        ("list_id" = String, Path, format = "uuid"),
+        ("event" = AutomationEvent, Path),
+    ),
+    request_body = AutomationEmail,
+    responses(
+        (status = 200, description = "Set", body = Automation),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Set an automation",
+    skip(user, body, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn set_automation(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, AutomationEvent)>,
+    body: web::Json<AutomationEmail>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AutomationError> {
+    let (list_id, event) = path.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let mut transaction = pool.begin().await?;
+    let automation = Automation {
+        event,
+        newsletter_issue_id: body.store(&mut transaction, list_id, user.user_id).await?,
+        title: body.title.clone(),
+        updated_at: Utc::now(),
+    };
+    sqlx::query!(
+        r#"
+        insert into automations (list_id, event, newsletter_issue_id, updated_by, updated_at)
+        values ($1, $2, $3, $4, $5)
+        on conflict (list_id, event) do update
+        set newsletter_issue_id = excluded.newsletter_issue_id,
+            updated_by = excluded.updated_by,
+            updated_at = excluded.updated_at
+        "#,
+        list_id,
+        event.as_str(),
+        automation.newsletter_issue_id,
+        user.user_id,
+        automation.updated_at
+    )
+    .execute(&mut transaction)
+    .await?;
+    AuditEvent::by(&user, AuditAction::AutomationSet)
+        .target("list", list_id)
+        .payload(serde_json::json!({
+            "event": event,
+            "newsletter_issue_id": automation.newsletter_issue_id,
+        }))
+        .record(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(HttpResponse::Ok().json(automation))
+}
+
+/// Stops sending an email for the event. Emails already queued are sent.
+#[utoipa::path(
+    delete,
+    path = "/admin/lists/{list_id}/automations/{event}",
+    tag = "lists",
+    summary = "Remove an automated email",
+    params(
+        ("list_id" = String, Path, format = "uuid"),
+        ("event" = AutomationEvent, Path),
+    ),
+    responses(
+        (status = 204, description = "Removed"),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Delete an automation",
+    skip(user, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn delete_automation(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, AutomationEvent)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AutomationError> {
+    let (list_id, event) = path.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    let deleted = sqlx::query!(
+        "delete from automations where list_id = $1 and event = $2",
+        list_id,
+        event.as_str()
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(AutomationError::AutomationNotFound);
+    }
+    AuditEvent::by(&user, AuditAction::AutomationDeleted)
+        .target("list", list_id)
+        .payload(serde_json::json!({ "event": event }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+impl AutomationEmail {
+    /// Checks the email and stores it as an issue that only automations send.
+    pub(crate) async fn store(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        list_id: Uuid,
+        created_by: Uuid,
+    ) -> Result<Uuid, NewsletterError> {
+        check_merge_tags(&self.title, &self.content, &self.translations)?;
+        let translations = canonical_translations(&self.translations)?;
+        let issue_id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            insert into newsletter_issues (
+                newsletter_issue_id, title, text_content, html_content, list_id, status,
+                created_by
+            )
+            values ($1, $2, $3, $4, $5, 'automation', $6)
+            "#,
+            issue_id,
+            self.title,
+            self.content.text,
+            self.content.html,
+            list_id,
+            created_by
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(NewsletterError::InsertIssueError)?;
+        insert_translations(transaction, issue_id, translations).await?;
+        Ok(issue_id)
+    }
+}
+
+async fn ensure_list_exists(pool: &PgPool, list_id: Uuid) -> Result<(), AutomationError> {
+    sqlx::query!("select list_id from lists where list_id = $1", list_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AutomationError::ListNotFound)?;
+    Ok(())
+}
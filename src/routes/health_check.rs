@@ -1,5 +1,183 @@
-use actix_web::HttpResponse;
+use crate::email_client::EmailClient;
+use actix_web::rt::time::timeout;
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::time::Duration;
+use utoipa::ToSchema;
 
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+pub struct ReadinessConfig {
+    pub check_email_provider: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Unavailable,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ComponentStatus {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<String>,
+}
+
+impl ComponentStatus {
+    fn ok() -> Self {
+        Self {
+            status: Status::Ok,
+            details: None,
+        }
+    }
+
+    fn unavailable(details: impl Into<String>) -> Self {
+        Self {
+            status: Status::Unavailable,
+            details: Some(details.into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Components {
+    database: ComponentStatus,
+    migrations: ComponentStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email_provider: Option<ComponentStatus>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthReport {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    components: Option<Components>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/health_check",
+    tag = "health",
+    summary = "Check that the server responds",
+    responses((status = 200, description = "Up"))
+)]
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    summary = "Liveness probe",
+    responses((status = 200, description = "Alive", body = HealthReport))
+)]
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().json(HealthReport {
+        status: Status::Ok,
+        components: None,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    summary = "Readiness probe",
+    responses(
+        (status = 200, description = "Ready for traffic", body = HealthReport),
+        (status = 503, description = "A dependency is unavailable", body = HealthReport),
+    )
+)]
+#[tracing::instrument(name = "Readiness probe", skip(pool, email_client, config))]
+pub async fn readiness(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    config: web::Data<ReadinessConfig>,
+) -> HttpResponse {
+    let database = check_database(&pool).await;
+    let migrations = check_migrations(&pool).await;
+    let email_provider = if config.check_email_provider {
+        Some(check_email_provider(&email_client).await)
+    } else {
+        None
+    };
+
+    let all_ok = database.status == Status::Ok
+        && migrations.status == Status::Ok
+        && email_provider
+            .as_ref()
+            .is_none_or(|c| c.status == Status::Ok);
+    let report = HealthReport {
+        status: if all_ok { Status::Ok } else { Status::Unavailable },
+        components: Some(Components {
+            database,
+            migrations,
+            email_provider,
+        }),
+    };
+
+    if all_ok {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+#[tracing::instrument(name = "Check database connection", skip(pool))]
+async fn check_database(pool: &PgPool) -> ComponentStatus {
+    match timeout(READINESS_TIMEOUT, sqlx::query("select 1").execute(pool)).await {
+        Ok(Ok(_)) => ComponentStatus::ok(),
+        Ok(Err(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "Database is not reachable");
+            ComponentStatus::unavailable("database query failed")
+        }
+        Err(_) => ComponentStatus::unavailable("database query timed out"),
+    }
+}
+
+#[tracing::instrument(name = "Check applied migrations", skip(pool))]
+async fn check_migrations(pool: &PgPool) -> ComponentStatus {
+    let expected = sqlx::migrate!("./migrations")
+        .migrations
+        .iter()
+        .map(|m| m.version)
+        .max();
+    let query = sqlx::query_as::<_, (Option<i64>,)>(
+        "select max(version) from _sqlx_migrations where success = true",
+    )
+    .fetch_one(pool);
+    let applied = match timeout(READINESS_TIMEOUT, query).await {
+        Ok(Ok((version,))) => version,
+        Ok(Err(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "Failed to read applied migrations");
+            return ComponentStatus::unavailable("failed to read applied migrations");
+        }
+        Err(_) => return ComponentStatus::unavailable("migrations query timed out"),
+    };
+
+    if applied == expected {
+        ComponentStatus::ok()
+    } else {
+        ComponentStatus::unavailable(format!(
+            "expected migration {:?}, database is at {:?}",
+            expected, applied
+        ))
+    }
+}
+
+#[tracing::instrument(name = "Check email provider", skip(email_client))]
+async fn check_email_provider(email_client: &EmailClient) -> ComponentStatus {
+    match timeout(READINESS_TIMEOUT, email_client.ping()).await {
+        Ok(Ok(())) => ComponentStatus::ok(),
+        Ok(Err(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "Email provider is not reachable");
+            ComponentStatus::unavailable("email provider is not reachable")
+        }
+        Err(_) => ComponentStatus::unavailable("email provider check timed out"),
+    }
+}
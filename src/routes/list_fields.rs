@@ -0,0 +1,274 @@
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authentication::AuthError;
+use crate::authorization::{AuthenticatedUser, Permission};
+use crate::domain::{CustomField, CustomFieldError, FieldType};
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum ListFieldError {
+    #[error(transparent)]
+    ValidationError(#[from] CustomFieldError),
+    #[error("The list already has a field with this key")]
+    DuplicateKey,
+    #[error("List not found")]
+    ListNotFound,
+    #[error("Field not found")]
+    FieldNotFound,
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to access list fields")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for ListFieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ListFieldError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ListFieldError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ListFieldError::DuplicateKey => StatusCode::CONFLICT,
+            ListFieldError::ListNotFound | ListFieldError::FieldNotFound => StatusCode::NOT_FOUND,
+            ListFieldError::AuthError(e) => e.status_code(),
+            ListFieldError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            ListFieldError::AuthError(e) => return e.error_response(),
+            ListFieldError::ValidationError(_) => {
+                Problem::new(status, "validation_error").detail(self.to_string())
+            }
+            ListFieldError::DuplicateKey => {
+                Problem::new(status, "duplicate_field").detail(self.to_string())
+            }
+            ListFieldError::ListNotFound => Problem::new(status, "list_not_found"),
+            ListFieldError::FieldNotFound => Problem::new(status, "field_not_found"),
+            ListFieldError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+/// A custom field the subscribers of a list have, usable as a merge tag and
+/// to pick the recipients of an issue.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ListField {
+    /// Lowercase letters, digits and underscores, starting with a letter.
+    #[schema(example = "company")]
+    key: String,
+    #[serde(rename = "type")]
+    field_type: FieldType,
+    /// Allowed values, for enum fields only.
+    #[serde(default)]
+    options: Vec<String>,
+    /// Subscribing fails without a value.
+    #[serde(default)]
+    required: bool,
+}
+
+impl From<CustomField> for ListField {
+    fn from(field: CustomField) -> Self {
+        Self {
+            key: field.key,
+            field_type: field.field_type,
+            options: field.options,
+            required: field.required,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/lists/{list_id}/fields",
+    tag = "lists",
+    summary = "List the custom fields of a list",
+    params(("list_id" = String, Path, format = "uuid")),
+    responses(
+        (status = 200, description = "Oldest first", body = Vec<ListField>),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(name = "List list fields", skip(user, pool), fields(user_id = %user.user_id))]
+pub async fn list_list_fields(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ListFieldError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ViewSubscribers, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let fields: Vec<ListField> = get_list_fields(pool.get_ref(), list_id)
+        .await?
+        .into_iter()
+        .map(ListField::from)
+        .collect();
+    Ok(HttpResponse::Ok().json(fields))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/lists/{list_id}/fields",
+    tag = "lists",
+    summary = "Add a custom field to a list",
+    params(("list_id" = String, Path, format = "uuid")),
+    request_body = ListField,
+    responses(
+        (status = 201, description = "Created", body = ListField),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+        (status = 409, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Create a list field",
+    skip(user, body, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn create_list_field(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    body: web::Json<ListField>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ListFieldError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let body = body.into_inner();
+    let field = CustomField::new(body.key, body.field_type, body.options, body.required)?;
+    let inserted = sqlx::query!(
+        r#"
+        insert into list_fields (list_id, key, field_type, options, required, created_at)
+        values ($1, $2, $3, $4, $5, now())
+        on conflict (list_id, key) do nothing
+        "#,
+        list_id,
+        field.key,
+        field.field_type.as_str(),
+        &field.options[..],
+        field.required
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if inserted.rows_affected() == 0 {
+        return Err(ListFieldError::DuplicateKey);
+    }
+    AuditEvent::by(&user, AuditAction::ListFieldCreated)
+        .target("list", list_id)
+        .payload(serde_json::json!({ "key": field.key, "type": field.field_type }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::Created().json(ListField::from(field)))
+}
+
+/// Removes the field and the values subscribers have for it.
+#[utoipa::path(
+    delete,
+    path = "/admin/lists/{list_id}/fields/{key}",
+    tag = "lists",
+    summary = "Remove a custom field from a list",
+    params(("list_id" = String, Path, format = "uuid"), ("key" = String, Path)),
+    responses(
+        (status = 204, description = "Removed"),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Delete a list field",
+    skip(user, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn delete_list_field(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, String)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ListFieldError> {
+    let (list_id, key) = path.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    let mut transaction = pool.begin().await?;
+    let deleted = sqlx::query!(
+        "delete from list_fields where list_id = $1 and key = $2",
+        list_id,
+        key
+    )
+    .execute(&mut transaction)
+    .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(ListFieldError::FieldNotFound);
+    }
+    sqlx::query!(
+        "update subscriptions set attributes = attributes - $2 where list_id = $1",
+        list_id,
+        key
+    )
+    .execute(&mut transaction)
+    .await?;
+    AuditEvent::by(&user, AuditAction::ListFieldDeleted)
+        .target("list", list_id)
+        .payload(serde_json::json!({ "key": key }))
+        .record(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn ensure_list_exists(pool: &PgPool, list_id: Uuid) -> Result<(), ListFieldError> {
+    sqlx::query!("select list_id from lists where list_id = $1", list_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(ListFieldError::ListNotFound)?;
+    Ok(())
+}
+
+/// The custom fields of a list, oldest first.
+#[tracing::instrument(name = "Get list fields", skip(executor))]
+pub(crate) async fn get_list_fields<'e>(
+    executor: impl PgExecutor<'e>,
+    list_id: Uuid,
+) -> Result<Vec<CustomField>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        select key, field_type, options, required
+        from list_fields
+        where list_id = $1
+        order by created_at, key
+        "#,
+        list_id
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| CustomField {
+            key: row.key,
+            // The table only allows known types.
+            field_type: FieldType::parse(&row.field_type).unwrap_or(FieldType::String),
+            options: row.options,
+            required: row.required,
+        })
+        .collect())
+}
@@ -0,0 +1,264 @@
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authentication::AuthError;
+use crate::authorization::{AuthenticatedUser, Permission};
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use actix_web::http::header;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum ListError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("A list with this name already exists")]
+    DuplicateName,
+    #[error("List not found")]
+    NotFound,
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to access lists")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for ListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ListError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ListError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ListError::DuplicateName => StatusCode::CONFLICT,
+            ListError::NotFound => StatusCode::NOT_FOUND,
+            ListError::AuthError(e) => e.status_code(),
+            ListError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            ListError::AuthError(e) => return e.error_response(),
+            ListError::ValidationError(message) => {
+                Problem::new(status, "validation_error").detail(message.clone())
+            }
+            ListError::DuplicateName => Problem::new(status, "duplicate_list_name")
+                .detail(self.to_string()),
+            ListError::NotFound => Problem::new(status, "list_not_found"),
+            ListError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct List {
+    #[schema(value_type = String, format = "uuid")]
+    list_id: Uuid,
+    name: String,
+    created_at: DateTime<Utc>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/lists",
+    tag = "lists",
+    summary = "List the mailing lists",
+    responses(
+        (status = 200, description = "All lists, oldest first", body = Vec<List>),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(name = "List lists", skip_all, fields(user_id = %user.user_id))]
+pub async fn list_lists(
+    user: AuthenticatedUser,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ListError> {
+    user.require(&pool, Permission::ViewSubscribers, None).await?;
+    let lists = sqlx::query_as!(
+        List,
+        "select list_id, name, created_at from lists order by created_at"
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+    Ok(HttpResponse::Ok().json(lists))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewList {
+    /// Between 1 and 100 characters, unique.
+    name: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/lists",
+    tag = "lists",
+    summary = "Create a mailing list",
+    request_body = NewList,
+    responses(
+        (status = 201, description = "Created", body = List),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 409, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(name = "Create a list", skip(user, pool), fields(user_id = %user.user_id))]
+pub async fn create_list(
+    user: AuthenticatedUser,
+    body: web::Json<NewList>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ListError> {
+    user.require(&pool, Permission::ManageLists, None).await?;
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(ListError::ValidationError(
+            "name must have between 1 and 100 characters".to_string(),
+        ));
+    }
+    let list = sqlx::query_as!(
+        List,
+        r#"
+        insert into lists (list_id, name, created_at)
+        values ($1, $2, now())
+        on conflict (name) do nothing
+        returning list_id, name, created_at
+        "#,
+        Uuid::new_v4(),
+        name
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(ListError::DuplicateName)?;
+    AuditEvent::by(&user, AuditAction::ListCreated)
+        .target("list", list.list_id)
+        .payload(serde_json::json!({ "name": list.name }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::Created().json(list))
+}
+
+/// Downloads all subscribers of a list as CSV.
+#[utoipa::path(
+    get,
+    path = "/admin/lists/{list_id}/subscribers/export",
+    tag = "lists",
+    summary = "Export the subscribers of a list",
+    params(("list_id" = String, Path, format = "uuid")),
+    responses(
+        (
+            status = 200,
+            description = "One row per subscriber",
+            body = String,
+            content_type = "text/csv",
+        ),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(
+    name = "Export subscribers",
+    skip(user, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn export_subscribers(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ListError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ExportSubscribers, Some(list_id))
+        .await?;
+    sqlx::query!("select list_id from lists where list_id = $1", list_id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or(ListError::NotFound)?;
+    let subscribers = sqlx::query!(
+        r#"
+        select email, name, status, subscribed_at
+        from subscriptions
+        where list_id = $1
+        order by subscribed_at
+        "#,
+        list_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    AuditEvent::by(&user, AuditAction::SubscribersExported)
+        .target("list", list_id)
+        .payload(serde_json::json!({ "subscribers": subscribers.len() }))
+        .record(pool.get_ref())
+        .await?;
+
+    let mut csv = String::from("email,name,status,subscribed_at\r\n");
+    for sub in subscribers {
+        let fields = [
+            sub.email,
+            sub.name,
+            sub.status,
+            sub.subscribed_at.to_rfc3339(),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"subscribers-{}.csv\"", list_id),
+        ))
+        .body(csv))
+}
+
+/// Quotes a CSV field when needed. Fields that spreadsheets would evaluate
+/// as formulas get a leading apostrophe.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_field;
+
+    #[test]
+    fn plain_fields_are_not_quoted() {
+        assert_eq!(csv_field("ursula@example.com"), "ursula@example.com");
+    }
+
+    #[test]
+    fn fields_with_separators_are_quoted() {
+        assert_eq!(csv_field("Le Guin, Ursula"), "\"Le Guin, Ursula\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn formulas_are_neutralised() {
+        assert_eq!(csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(csv_field("@sum"), "'@sum");
+    }
+}
@@ -1,9 +1,37 @@
+mod ab_tests;
+mod admin;
+mod api_tokens;
+mod automations;
 mod health_check;
+mod list_fields;
+mod lists;
+mod pages;
+mod segments;
+mod sequences;
+mod sessions;
 mod sub_confirm;
+mod subscribers;
 mod subscriptions;
 mod newsletters;
+mod tracking;
+mod two_factor;
+mod users;
 
+pub use ab_tests::*;
+pub use admin::*;
+pub use api_tokens::*;
+pub use automations::*;
 pub use health_check::*;
+pub use list_fields::*;
+pub use lists::*;
+pub use pages::*;
+pub use segments::*;
+pub use sequences::*;
+pub use sessions::*;
 pub use sub_confirm::*;
+pub use subscribers::*;
 pub use subscriptions::*;
 pub use newsletters::*;
+pub use tracking::*;
+pub use two_factor::*;
+pub use users::*;
@@ -1,173 +1,793 @@
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
-use actix_http::header::{self, HeaderMap, HeaderValue};
+use crate::ab_tests::AbTestMetric;
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authentication::AuthError;
+use crate::authorization::{AuthenticatedUser, Permission, DEFAULT_LIST_ID};
+use crate::domain::{
+    SubscriberAttributes, SubscriberAttributesError, SubscriberTag, SubscriberTagError,
+};
+use crate::error::error_chain_fmt;
+use crate::localisation::canonical_locale;
+use crate::merge_tags::IssueTemplate;
+use crate::problem::{FieldError, Problem};
+use crate::routes::{find_segment, get_list_fields, require_referenced_lists};
+use crate::segments::Rule;
 use actix_web::http::StatusCode;
-use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
-use serde::Deserialize;
-use sqlx::PgPool;
+use actix_web::{web, HttpResponse, ResponseError};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::{BTreeMap, HashMap};
+use utoipa::ToSchema;
 use uuid::Uuid;
-use sha3::Digest;
 
-#[derive(Debug, Deserialize)]
+const MIN_VARIANTS: usize = 2;
+const MAX_VARIANTS: usize = 5;
+const MAX_WINDOW_HOURS: i32 = 720;
+
+/// Both parts may use merge tags like `{{ name }}`, `{{ email }}` and the
+/// keys of the custom fields of the list. A part without
+/// `{{ unsubscribe_url }}` gets an unsubscribe link appended.
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct Content {
-    html: String,
-    text: String,
+    pub(crate) html: String,
+    pub(crate) text: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Translation {
+    title: String,
+    content: Content,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct BodyData {
     title: String,
     content: Content,
+    /// List whose confirmed subscribers receive the issue, the default list
+    /// if left out.
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = "uuid")]
+    list_id: Option<Uuid>,
+    /// The issue in other languages, by locale. Subscribers get the one
+    /// closest to their own locale, falling back to `title` and `content`.
+    #[serde(default)]
+    translations: HashMap<String, Translation>,
+    /// Only subscribers whose custom attributes have these values receive
+    /// the issue, e.g. `{"plan": "pro"}`.
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    recipient_attributes: HashMap<String, Value>,
+    /// Only subscribers with all of these tags receive the issue.
+    #[serde(default)]
+    recipient_tags: Vec<String>,
+    /// Only subscribers in this segment of the list receive the issue. The
+    /// segment is applied as it is when the issue is stored.
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = "uuid")]
+    segment_id: Option<Uuid>,
+    /// Sends variants of the issue to a random share of the recipients
+    /// first, and the variant that does best to the others once the test
+    /// window is over. Cannot be combined with translations.
+    #[serde(default)]
+    ab_test: Option<AbTest>,
 }
 
-fn error_chain_fmt(
-    e: &impl std::error::Error,
-    f: &mut std::fmt::Formatter<'_>,
-) -> std::fmt::Result {
-    writeln!(f, "\n{}", e)?;
-    let mut current = e.source();
-    while let Some(cause) = current {
-        writeln!(f, "caused by:\t{}", cause)?;
-        current = cause.source();
-    }
-    Ok(())
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AbTest {
+    /// Two to five variants, each replacing the title, the content or both
+    /// of the issue.
+    variants: Vec<Variant>,
+    /// Share of the recipients, in percent, that the variants are split
+    /// between evenly.
+    #[schema(example = 20)]
+    test_percent: i32,
+    /// Hours after publication that the winner is picked, at most 720.
+    #[schema(example = 4)]
+    window_hours: i32,
+    #[serde(default)]
+    metric: AbTestMetric,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Variant {
+    title: Option<String>,
+    content: Option<Content>,
+}
+
+/// Who of the confirmed subscribers of the list receive an issue.
+struct Recipients {
+    attributes: SubscriberAttributes,
+    tags: Vec<SubscriberTag>,
+    segment: Option<(Uuid, Rule)>,
 }
 
 #[derive(thiserror::Error)]
 pub enum NewsletterError {
-    #[error("Failed to get confirmed subs")]
-    ConfirmedSubsError(#[source] sqlx::Error),
-    #[error("Failed to send a newsletter")]
-    SendLetterError(#[source] reqwest::Error),
-    #[error("Failed to send a newsletter")]
-    AuthError(String),
+    #[error("Faild to acquire a Postgres connection from the pool")]
+    PoolError(#[source] sqlx::Error),
+    #[error("Failed to store a newsletter issue")]
+    InsertIssueError(#[source] sqlx::Error),
+    #[error("Failed to enqueue delivery tasks")]
+    EnqueueError(#[source] sqlx::Error),
+    #[error("Failed to record an audit event")]
+    AuditError(#[source] sqlx::Error),
+    #[error("Faild to commit sql transaction")]
+    TransactionCommitError(#[source] sqlx::Error),
+    #[error("Failed to look up a newsletter issue")]
+    LookupError(#[source] sqlx::Error),
+    #[error("Newsletter draft not found")]
+    DraftNotFound,
+    #[error("Unknown list {0}")]
+    UnknownList(Uuid),
+    #[error("{0} is not a language tag")]
+    InvalidLocale(String),
+    #[error("Invalid merge tags: {0}")]
+    InvalidTemplate(String),
+    #[error("Invalid A/B test: {0}")]
+    InvalidAbTest(String),
+    #[error("Invalid recipient attributes")]
+    InvalidRecipientAttributes(#[source] SubscriberAttributesError),
+    #[error("Invalid recipient tags")]
+    InvalidRecipientTags(#[source] SubscriberTagError),
+    #[error("Unknown segment {0} of the list")]
+    UnknownSegment(Uuid),
+    #[error("Failed to look up the fields of the list")]
+    FieldsLookupError(#[source] sqlx::Error),
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
 }
 
 impl std::fmt::Debug for NewsletterError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            NewsletterError::AuthError(e) => writeln!(f, "\n{}", e),
-            _ => error_chain_fmt(self, f),
-        }
+        error_chain_fmt(self, f)
     }
 }
 
 impl ResponseError for NewsletterError {
-    fn error_response(&self) -> HttpResponse<actix_http::body::BoxBody> {
+    fn status_code(&self) -> StatusCode {
         match self {
-            NewsletterError::ConfirmedSubsError(_) | NewsletterError::SendLetterError(_) => {
-                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            NewsletterError::PoolError(_)
+            | NewsletterError::InsertIssueError(_)
+            | NewsletterError::EnqueueError(_)
+            | NewsletterError::TransactionCommitError(_)
+            | NewsletterError::AuditError(_)
+            | NewsletterError::LookupError(_)
+            | NewsletterError::FieldsLookupError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            NewsletterError::DraftNotFound => StatusCode::NOT_FOUND,
+            NewsletterError::UnknownList(_)
+            | NewsletterError::InvalidLocale(_)
+            | NewsletterError::InvalidTemplate(_)
+            | NewsletterError::InvalidAbTest(_)
+            | NewsletterError::InvalidRecipientAttributes(_)
+            | NewsletterError::InvalidRecipientTags(_)
+            | NewsletterError::UnknownSegment(_) => StatusCode::BAD_REQUEST,
+            NewsletterError::AuthError(e) => e.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            NewsletterError::PoolError(_)
+            | NewsletterError::InsertIssueError(_)
+            | NewsletterError::EnqueueError(_)
+            | NewsletterError::TransactionCommitError(_)
+            | NewsletterError::AuditError(_)
+            | NewsletterError::LookupError(_)
+            | NewsletterError::FieldsLookupError(_) => Problem::internal(),
+            NewsletterError::DraftNotFound => {
+                Problem::new(status, "draft_not_found").detail(self.to_string())
             }
-            NewsletterError::AuthError(_) => {
-                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header = HeaderValue::from_str("Basic realm=\"publish\"").unwrap();
-                response
-                    .headers_mut()
-                    .insert(header::WWW_AUTHENTICATE, header);
-                response
+            NewsletterError::UnknownList(_) => {
+                Problem::new(status, "unknown_list").detail(self.to_string())
             }
-        }
+            NewsletterError::InvalidLocale(_) => {
+                Problem::new(status, "invalid_locale").detail(self.to_string())
+            }
+            NewsletterError::InvalidRecipientTags(e) => {
+                let error = FieldError::new("recipient_tags", e.code(), e.to_string());
+                Problem::new(status, "validation_error")
+                    .detail(self.to_string())
+                    .field_errors(vec![error])
+            }
+            NewsletterError::UnknownSegment(_) => {
+                Problem::new(status, "unknown_segment").detail(self.to_string())
+            }
+            NewsletterError::InvalidTemplate(_) => {
+                Problem::new(status, "invalid_template").detail(self.to_string())
+            }
+            NewsletterError::InvalidAbTest(_) => {
+                Problem::new(status, "validation_error").detail(self.to_string())
+            }
+            NewsletterError::InvalidRecipientAttributes(e) => {
+                let errors = e
+                    .0
+                    .iter()
+                    .map(|e| {
+                        let field = format!("recipient_attributes.{}", e.key());
+                        FieldError::new(field, e.code(), e.to_string())
+                    })
+                    .collect();
+                Problem::new(status, "validation_error")
+                    .detail(self.to_string())
+                    .field_errors(errors)
+            }
+            NewsletterError::AuthError(e) => return e.error_response(),
+        };
+        problem.error_response()
     }
 }
 
+/// Stores the issue and queues one delivery task per confirmed sub of its
+/// list. The emails themselves are sent by the issue delivery workers.
+#[utoipa::path(
+    post,
+    path = "/newsletters",
+    tag = "newsletters",
+    summary = "Publish a newsletter issue",
+    request_body = BodyData,
+    responses(
+        (status = 200, description = "Queued for delivery"),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request),
-    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+    skip(body, pool, user),
+    fields(user_id = %user.user_id)
 )]
 pub async fn publish_newsletter(
+    user: AuthenticatedUser,
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-    request: HttpRequest,
 ) -> Result<HttpResponse, NewsletterError> {
-    let credentials = basic_auth(request.headers())?;
-    validate_credentials(credentials, &pool).await?;
-    let subs = get_confirmed_subs(&pool).await?;
-    for sub in subs {
-        match sub {
-            Ok(sub) => email_client
-                .send_email(sub, &body.title, &body.content.html, &body.content.text)
-                .await
-                .map_err(NewsletterError::SendLetterError)?,
-            Err(e) => tracing::warn!(error.cause_chain = ?e,
-                    "Skipping a confirmed sub bacause of invalid email"),
-        }
+    let list_id = body.list_id.unwrap_or(DEFAULT_LIST_ID);
+    user.require(&pool, Permission::PublishIssues, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let recipients = check_issue(&user, &pool, &body, list_id).await?;
+
+    let mut transaction = pool.begin().await.map_err(NewsletterError::PoolError)?;
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &body,
+        recipients,
+        list_id,
+        user.user_id,
+        "published",
+    )
+    .await?;
+    enqueue_delivery_tasks(&mut transaction, issue_id, list_id).await?;
+    AuditEvent::by(&user, AuditAction::NewsletterPublished)
+        .target("newsletter_issue", issue_id)
+        .payload(serde_json::json!({ "title": body.title, "list_id": list_id }))
+        .record(&mut transaction)
+        .await
+        .map_err(NewsletterError::AuditError)?;
+    transaction
+        .commit()
+        .await
+        .map_err(NewsletterError::TransactionCommitError)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Serialize, ToSchema)]
+struct DraftCreated {
+    #[schema(value_type = String, format = "uuid")]
+    newsletter_issue_id: Uuid,
+}
+
+/// Stores an issue without sending it, so that somebody allowed to publish
+/// to its list can review and send it later.
+#[utoipa::path(
+    post,
+    path = "/newsletters/drafts",
+    tag = "newsletters",
+    summary = "Draft a newsletter issue",
+    request_body = BodyData,
+    responses(
+        (status = 201, description = "Draft stored", body = DraftCreated),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(
+    name = "Draft a newsletter issue",
+    skip(body, pool, user),
+    fields(user_id = %user.user_id)
+)]
+pub async fn draft_newsletter(
+    user: AuthenticatedUser,
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, NewsletterError> {
+    let list_id = body.list_id.unwrap_or(DEFAULT_LIST_ID);
+    user.require(&pool, Permission::DraftIssues, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let recipients = check_issue(&user, &pool, &body, list_id).await?;
+
+    let mut transaction = pool.begin().await.map_err(NewsletterError::PoolError)?;
+    let newsletter_issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &body,
+        recipients,
+        list_id,
+        user.user_id,
+        "draft",
+    )
+    .await?;
+    AuditEvent::by(&user, AuditAction::NewsletterDrafted)
+        .target("newsletter_issue", newsletter_issue_id)
+        .payload(serde_json::json!({ "title": body.title, "list_id": list_id }))
+        .record(&mut transaction)
+        .await
+        .map_err(NewsletterError::AuditError)?;
+    transaction
+        .commit()
+        .await
+        .map_err(NewsletterError::TransactionCommitError)?;
+
+    Ok(HttpResponse::Created().json(DraftCreated {
+        newsletter_issue_id,
+    }))
+}
+
+/// Sends a draft to the confirmed subs of its list.
+#[utoipa::path(
+    post,
+    path = "/newsletters/drafts/{issue_id}/publish",
+    tag = "newsletters",
+    summary = "Publish a draft",
+    params(("issue_id" = String, Path, format = "uuid", description = "Id of the draft")),
+    responses(
+        (status = 200, description = "Queued for delivery"),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(
+    name = "Publish a newsletter draft",
+    skip(pool, user),
+    fields(user_id = %user.user_id)
+)]
+pub async fn publish_draft(
+    user: AuthenticatedUser,
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, NewsletterError> {
+    let issue_id = issue_id.into_inner();
+    let draft = sqlx::query!(
+        r#"
+        select list_id, segment_rules from newsletter_issues
+        where newsletter_issue_id = $1 and status = 'draft'
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(NewsletterError::LookupError)?
+    .ok_or(NewsletterError::DraftNotFound)?;
+    let list_id = draft.list_id;
+    user.require(&pool, Permission::PublishIssues, Some(list_id))
+        .await?;
+    // The draft may have been written by somebody who sees other lists.
+    if let Some(rules) = draft.segment_rules {
+        let rules: Rule = serde_json::from_value(rules)
+            .map_err(|e| NewsletterError::LookupError(sqlx::Error::Decode(Box::new(e))))?;
+        require_referenced_lists(&user, &pool, &rules.lists()).await?;
     }
+
+    let mut transaction = pool.begin().await.map_err(NewsletterError::PoolError)?;
+    let published = sqlx::query!(
+        r#"
+        update newsletter_issues set status = 'published', published_at = now()
+        where newsletter_issue_id = $1 and status = 'draft'
+        "#,
+        issue_id
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(NewsletterError::InsertIssueError)?;
+    // Somebody else published it in the meantime.
+    if published.rows_affected() == 0 {
+        return Err(NewsletterError::DraftNotFound);
+    }
+    enqueue_delivery_tasks(&mut transaction, issue_id, list_id).await?;
+    AuditEvent::by(&user, AuditAction::NewsletterPublished)
+        .target("newsletter_issue", issue_id)
+        .payload(serde_json::json!({ "list_id": list_id, "from_draft": true }))
+        .record(&mut transaction)
+        .await
+        .map_err(NewsletterError::AuditError)?;
+    transaction
+        .commit()
+        .await
+        .map_err(NewsletterError::TransactionCommitError)?;
+
     Ok(HttpResponse::Ok().finish())
 }
 
-struct Credentials {
-    username: String,
-    password: String,
+async fn ensure_list_exists(pool: &PgPool, list_id: Uuid) -> Result<(), NewsletterError> {
+    let exists = sqlx::query!("select list_id from lists where list_id = $1", list_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(NewsletterError::LookupError)?
+        .is_some();
+    if exists {
+        Ok(())
+    } else {
+        Err(NewsletterError::UnknownList(list_id))
+    }
 }
 
-fn basic_auth(headers: &HeaderMap) -> Result<Credentials, NewsletterError> {
-    let header = match headers.get("Authorization") {
-        Some(header) => header.to_str().map_err(|e| {
-            NewsletterError::AuthError(format!(
-                "Authorization header is not a valid UTF-8 string: {}",
-                e
-            ))
-        })?,
-        None => {
-            return Err(NewsletterError::AuthError(
-                "Authorization header is missing".to_string(),
-            ))
+/// Checks the merge tags of every part of the issue and who it is for.
+#[tracing::instrument(name = "Check a newsletter issue", skip_all)]
+async fn check_issue(
+    user: &AuthenticatedUser,
+    pool: &PgPool,
+    body: &BodyData,
+    list_id: Uuid,
+) -> Result<Recipients, NewsletterError> {
+    check_merge_tags(&body.title, &body.content, &body.translations)?;
+    if let Some(test) = &body.ab_test {
+        check_ab_test(body, test)?;
+    }
+    let fields = get_list_fields(pool, list_id)
+        .await
+        .map_err(NewsletterError::FieldsLookupError)?;
+    let attributes = SubscriberAttributes::parse_partial(&fields, &body.recipient_attributes)
+        .map_err(NewsletterError::InvalidRecipientAttributes)?;
+    let tags = SubscriberTag::parse_all(body.recipient_tags.iter().cloned())
+        .map_err(NewsletterError::InvalidRecipientTags)?;
+    let segment = match body.segment_id {
+        Some(segment_id) => {
+            let rules = find_segment(pool, list_id, segment_id)
+                .await
+                .map_err(NewsletterError::LookupError)?
+                .ok_or(NewsletterError::UnknownSegment(segment_id))?;
+            require_referenced_lists(user, pool, &rules.lists()).await?;
+            Some((segment_id, rules))
         }
+        None => None,
     };
-    let encoded = match header.strip_prefix("Basic ") {
-        Some(encoded) => encoded,
-        None => {
-            return Err(NewsletterError::AuthError(
-                "Authorization scheme was not 'Basic'".to_string(),
-            ))
+    Ok(Recipients {
+        attributes,
+        tags,
+        segment,
+    })
+}
+
+/// Checks the merge tags of an issue and of every translation of it.
+pub(crate) fn check_merge_tags(
+    title: &str,
+    content: &Content,
+    translations: &HashMap<String, Translation>,
+) -> Result<(), NewsletterError> {
+    let templates = std::iter::once((title, content)).chain(
+        translations
+            .values()
+            .map(|translation| (translation.title.as_str(), &translation.content)),
+    );
+    for (title, content) in templates {
+        IssueTemplate {
+            title,
+            html: &content.html,
+            text: &content.text,
         }
+        .check()
+        .map_err(|e| NewsletterError::InvalidTemplate(e.to_string()))?;
+    }
+    Ok(())
+}
+
+fn check_ab_test(body: &BodyData, test: &AbTest) -> Result<(), NewsletterError> {
+    let invalid = |message: &str| Err(NewsletterError::InvalidAbTest(message.into()));
+    if !body.translations.is_empty() {
+        return invalid("issues with translations cannot be tested");
+    }
+    if !(MIN_VARIANTS..=MAX_VARIANTS).contains(&test.variants.len()) {
+        return Err(NewsletterError::InvalidAbTest(format!(
+            "there must be between {} and {} variants",
+            MIN_VARIANTS, MAX_VARIANTS
+        )));
+    }
+    if !(1..=100).contains(&test.test_percent) {
+        return invalid("test_percent must be between 1 and 100");
+    }
+    if !(1..=MAX_WINDOW_HOURS).contains(&test.window_hours) {
+        return Err(NewsletterError::InvalidAbTest(format!(
+            "window_hours must be between 1 and {}",
+            MAX_WINDOW_HOURS
+        )));
+    }
+    for variant in &test.variants {
+        if variant.title.is_none() && variant.content.is_none() {
+            return invalid("every variant must have a title, a content or both");
+        }
+        let (title, content) = variant.resolve(body);
+        check_merge_tags(title, content, &HashMap::new())?;
+    }
+    Ok(())
+}
+
+impl Variant {
+    /// The title and content of the variant, taking what it leaves out from
+    /// the issue.
+    fn resolve<'a>(&'a self, body: &'a BodyData) -> (&'a str, &'a Content) {
+        (
+            self.title.as_deref().unwrap_or(&body.title),
+            self.content.as_ref().unwrap_or(&body.content),
+        )
+    }
+}
+
+#[tracing::instrument(name = "Store a newsletter issue", skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    body: &BodyData,
+    recipients: Recipients,
+    list_id: Uuid,
+    created_by: Uuid,
+    status: &str,
+) -> Result<Uuid, NewsletterError> {
+    let translations = canonical_translations(&body.translations)?;
+    let issue_id = Uuid::new_v4();
+    let published_at = (status == "published").then(Utc::now);
+    let recipient_tags: Vec<&str> = recipients.tags.iter().map(AsRef::as_ref).collect();
+    let (segment_id, segment_rules) = match recipients.segment {
+        Some((segment_id, rules)) => (
+            Some(segment_id),
+            Some(serde_json::to_value(rules).expect("Rules are always valid JSON")),
+        ),
+        None => (None, None),
     };
-    let decoded = base64::decode_config(encoded, base64::STANDARD)
-        .map_err(|e| NewsletterError::AuthError(format!("Faild to decode base64: {}", e)))?;
-    let decoded_credentials = String::from_utf8(decoded).map_err(|e| {
-        NewsletterError::AuthError(format!("Decoded credentials are not valid UTF-8: {}", e))
-    })?;
-
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| NewsletterError::AuthError("Username must be provided".to_string()))?
-        .to_string();
-    let password = credentials
-        .next()
-        .ok_or_else(|| NewsletterError::AuthError("Password must be provided".to_string()))?
-        .to_string();
-    Ok(Credentials { username, password })
-}
-
-async fn validate_credentials(credentials: Credentials, pool: &PgPool) -> Result<Uuid, NewsletterError> {
-    let password_hash = sha3::Sha3_256::digest(credentials.password.as_bytes());
-    let password_hash = format!("{:x}", password_hash);
-    let user_id: Option<_> = sqlx::query!(
-        "select user_id from users where username = $1 and password_hash = $2",
-        credentials.username,
-        password_hash
+    sqlx::query!(
+        r#"
+        insert into newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, published_at,
+            list_id, status, created_by, recipient_attributes, recipient_tags,
+            segment_id, segment_rules
+        )
+        values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        "#,
+        issue_id,
+        body.title,
+        body.content.text,
+        body.content.html,
+        published_at,
+        list_id,
+        status,
+        created_by,
+        recipients.attributes.into_json(),
+        &recipient_tags as &[&str],
+        segment_id,
+        segment_rules
     )
-    .fetch_optional(pool)
+    .execute(&mut *transaction)
     .await
-    .map_err(|e| NewsletterError::AuthError(format!("Faild to perform query to validate credentials: {}", e)))?;
-    user_id
-        .map(|row| row.user_id)
-        .ok_or_else(|| NewsletterError::AuthError("Invalid username or password".to_string()))
+    .map_err(NewsletterError::InsertIssueError)?;
+    insert_translations(transaction, issue_id, translations).await?;
+    if let Some(test) = &body.ab_test {
+        insert_ab_test(transaction, issue_id, body, test).await?;
+    }
+    Ok(issue_id)
 }
 
-async fn get_confirmed_subs(
-    pool: &PgPool,
-) -> Result<Vec<Result<SubscriberEmail, String>>, NewsletterError> {
-    let rows = sqlx::query!("select email from subscriptions where status = 'confirmed'")
-        .fetch_all(pool)
+#[tracing::instrument(name = "Store the A/B test of an issue", skip_all)]
+async fn insert_ab_test(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    body: &BodyData,
+    test: &AbTest,
+) -> Result<(), NewsletterError> {
+    sqlx::query!(
+        r#"
+        insert into issue_ab_tests (newsletter_issue_id, test_percent, window_hours, metric)
+        values ($1, $2, $3, $4)
+        "#,
+        issue_id,
+        test.test_percent,
+        test.window_hours,
+        test.metric.as_str()
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(NewsletterError::InsertIssueError)?;
+    for (variant, values) in test.variants.iter().enumerate() {
+        let (title, content) = values.resolve(body);
+        sqlx::query!(
+            r#"
+            insert into issue_variants (
+                newsletter_issue_id, variant, title, text_content, html_content
+            )
+            values ($1, $2, $3, $4, $5)
+            "#,
+            issue_id,
+            variant as i32,
+            title,
+            content.text,
+            content.html
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(NewsletterError::InsertIssueError)?;
+    }
+    Ok(())
+}
+
+/// The translations of the issue by canonical locale, so that `pt_br` and
+/// `pt-BR` end up as the same one.
+pub(crate) fn canonical_translations(
+    translations: &HashMap<String, Translation>,
+) -> Result<BTreeMap<String, &Translation>, NewsletterError> {
+    translations
+        .iter()
+        .map(|(locale, translation)| {
+            let canonical = canonical_locale(locale)
+                .ok_or_else(|| NewsletterError::InvalidLocale(locale.clone()))?;
+            Ok((canonical, translation))
+        })
+        .collect()
+}
+
+#[tracing::instrument(name = "Store the translations of an issue", skip_all)]
+pub(crate) async fn insert_translations(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    translations: BTreeMap<String, &Translation>,
+) -> Result<(), NewsletterError> {
+    for (locale, translation) in translations {
+        sqlx::query!(
+            r#"
+            insert into newsletter_issue_translations (
+                newsletter_issue_id, locale, title, text_content, html_content
+            )
+            values ($1, $2, $3, $4, $5)
+            "#,
+            issue_id,
+            locale,
+            translation.title,
+            translation.content.text,
+            translation.content.html
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(NewsletterError::InsertIssueError)?;
+    }
+    Ok(())
+}
+
+/// Only confirmed subs whose attributes and tags match the recipient
+/// attributes and tags of the issue, and who are in its segment if it has
+/// one, get a task. If the issue is tested, only its test share does for
+/// now, see [`crate::ab_tests::decide_due_tests`] for the others.
+#[tracing::instrument(name = "Enqueue delivery tasks", skip(transaction))]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), NewsletterError> {
+    let segment_rules = sqlx::query!(
+        "select segment_rules from newsletter_issues where newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(NewsletterError::LookupError)?
+    .segment_rules;
+    let rules = match segment_rules {
+        Some(rules) => serde_json::from_value(rules).map_err(|e| {
+            NewsletterError::EnqueueError(sqlx::Error::Decode(Box::new(e)))
+        })?,
+        None => Rule::All(Vec::new()),
+    };
+    let test = sqlx::query!(
+        r#"
+        select t.test_percent,
+            (select count(*) from issue_variants v where v.newsletter_issue_id = $1) as "variants!"
+        from issue_ab_tests t
+        where t.newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(NewsletterError::LookupError)?;
+    let test = match test {
+        Some(test) => test,
+        None => {
+            let filter = rules.to_sql(3);
+            let sql = format!(
+                r#"
+                insert into issue_delivery_queue (newsletter_issue_id, subscriber_id)
+                select $1, s.id {}
+                "#,
+                recipients_sql(&filter.sql)
+            );
+            filter
+                .bind_to(sqlx::query(&sql).bind(issue_id).bind(list_id))
+                .execute(transaction)
+                .await
+                .map_err(NewsletterError::EnqueueError)?;
+            return Ok(());
+        }
+    };
+    // Recipients are shuffled, the first `test_percent` of them are dealt
+    // the variants in turn and the others wait for the winner.
+    let filter = rules.to_sql(5);
+    let sql = format!(
+        r#"
+        insert into issue_variant_assignments (newsletter_issue_id, subscriber_id, variant)
+        select $1, id, case when n < ceil(total * $3::bigint / 100.0) then (n % $4)::integer end
+        from (
+            select s.id, row_number() over (order by random()) - 1 as n, count(*) over () as total
+            {}
+        ) recipients
+        "#,
+        recipients_sql(&filter.sql)
+    );
+    let query = sqlx::query(&sql)
+        .bind(issue_id)
+        .bind(list_id)
+        .bind(i64::from(test.test_percent))
+        .bind(test.variants);
+    filter
+        .bind_to(query)
+        .execute(&mut *transaction)
         .await
-        .map_err(NewsletterError::ConfirmedSubsError)?;
+        .map_err(NewsletterError::EnqueueError)?;
+    sqlx::query!(
+        r#"
+        insert into issue_delivery_queue (newsletter_issue_id, subscriber_id, variant)
+        select newsletter_issue_id, subscriber_id, variant
+        from issue_variant_assignments
+        where newsletter_issue_id = $1 and variant is not null
+        "#,
+        issue_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(NewsletterError::EnqueueError)?;
+    sqlx::query!(
+        r#"
+        update issue_ab_tests set decide_at = now() + make_interval(hours => window_hours)
+        where newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .execute(transaction)
+    .await
+    .map_err(NewsletterError::EnqueueError)?;
+    Ok(())
+}
 
-    Ok(rows
-        .into_iter()
-        .map(|r| SubscriberEmail::try_from(r.email))
-        .collect())
+/// The `from` and `where` clauses selecting the recipients of issue `$1` on
+/// list `$2` as `s`, narrowed down by `filter`.
+fn recipients_sql(filter: &str) -> String {
+    format!(
+        r#"
+        from subscriptions s, newsletter_issues i
+        where i.newsletter_issue_id = $1
+            and s.status = 'confirmed'
+            and s.list_id = $2
+            and s.attributes @> i.recipient_attributes
+            and i.recipient_tags <@ array(
+                select t.tag from subscriber_tags t where t.subscriber_id = s.id
+            )
+            and {}
+        "#,
+        filter
+    )
 }
@@ -0,0 +1,369 @@
+use crate::authorization::DEFAULT_LIST_ID;
+use crate::automation::{self, AutomationEvent};
+use crate::config::{PageRedirects, SpamTrapConfig};
+use crate::deliverability::Deliverability;
+use crate::email_client::EmailClient;
+use crate::error::error_chain_fmt;
+use crate::localisation::{requested_locales, Translations};
+use crate::pages::{PageList, Pages};
+use crate::problem::Problem;
+use crate::rate_limit::RateLimiter;
+use crate::routes::{
+    confirm_sub, create_subscription, get_sub_id_from_token, FormData, SubscribeError,
+};
+use crate::startup::AppBaseUrl;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use chrono::Utc;
+use fluent_bundle::FluentArgs;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum PageError {
+    #[error("Failed to render a page")]
+    RenderError(#[from] minijinja::Error),
+    #[error("Failed to subscribe")]
+    SubscribeError(#[source] SubscribeError),
+    #[error("Failed to query the database")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for PageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PageError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        Problem::internal().error_response()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SubscribePageParams {
+    list_id: Option<Uuid>,
+    locale: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TokenParams {
+    sub_token: String,
+}
+
+/// A subscription found by its token, with what its pages need.
+struct TokenSubscription {
+    sub_id: Uuid,
+    list: PageList,
+    locale: String,
+}
+
+#[tracing::instrument(
+    name = "Show the subscribe page",
+    skip(req, connection_pool, pages, translations, params)
+)]
+pub async fn subscribe_page(
+    req: HttpRequest,
+    connection_pool: web::Data<PgPool>,
+    pages: web::Data<Pages>,
+    translations: web::Data<Translations>,
+    params: web::Query<SubscribePageParams>,
+) -> Result<HttpResponse, PageError> {
+    let locale = translations.negotiate(&requested_locales(params.locale.as_deref(), &req));
+    let list_id = params.list_id.unwrap_or(DEFAULT_LIST_ID);
+    let list = match find_list(&connection_pool, list_id).await? {
+        Some(list) => list,
+        None => return list_not_found(&pages, list_id, &locale),
+    };
+    render(
+        &pages,
+        StatusCode::OK,
+        &list,
+        &locale,
+        "subscribe.html",
+        minijinja::context! { form_rendered_at => Utc::now().timestamp() },
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Subscribe through the hosted page",
+    skip_all,
+    fields(subscriber_email = %form.email, subscriber_name = %form.name)
+)]
+pub async fn submit_subscribe_page(
+    req: HttpRequest,
+    form: web::Form<FormData>,
+    connection_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<AppBaseUrl>,
+    translations: web::Data<Translations>,
+    rate_limiter: web::Data<RateLimiter>,
+    spam_trap: web::Data<SpamTrapConfig>,
+    deliverability: web::Data<Deliverability>,
+    pages: web::Data<Pages>,
+    redirects: web::Data<PageRedirects>,
+) -> Result<HttpResponse, PageError> {
+    let form = form.into_inner();
+    let locale = translations.negotiate(&requested_locales(form.locale.as_deref(), &req));
+    let list_id = form.list_id.unwrap_or(DEFAULT_LIST_ID);
+    let list = match find_list(&connection_pool, list_id).await? {
+        Some(list) => list,
+        None => return list_not_found(&pages, list_id, &locale),
+    };
+    let (name, email) = (form.name.clone(), form.email.clone());
+    let result = create_subscription(
+        form,
+        &locale,
+        &connection_pool,
+        &email_client,
+        &base_url,
+        &translations,
+        &rate_limiter,
+        &spam_trap,
+        &deliverability,
+    )
+    .await;
+    let e = match result {
+        Ok(_) => {
+            return match &redirects.after_subscribe {
+                Some(url) => Ok(see_other(url)),
+                None => render(&pages, StatusCode::OK, &list, &locale, "check_inbox.html", ()),
+            }
+        }
+        Err(SubscribeError::UnknownList(list_id)) => {
+            return list_not_found(&pages, list_id, &locale)
+        }
+        Err(
+            e @ (SubscribeError::ValidationError(_)
+            | SubscribeError::UndeliverableEmail(_)
+            | SubscribeError::InvalidAttributes(_)
+            | SubscribeError::InvalidTags(_)
+            | SubscribeError::RateLimitError(_)),
+        ) => e,
+        Err(e) => return Err(PageError::SubscribeError(e)),
+    };
+    // Field errors are shown next to their fields, anything else on top.
+    let errors: Vec<_> = e
+        .field_errors()
+        .into_iter()
+        .map(|mut error| {
+            let id = format!("field-error-{}", error.code);
+            let mut args = FluentArgs::new();
+            let field = error.field.trim_start_matches("attributes.");
+            args.set("field", field.to_string());
+            if let Some(message) = translations.lookup(&locale, &id, Some(&args)) {
+                error.message = message;
+            }
+            error
+        })
+        .collect();
+    let message = match e {
+        SubscribeError::RateLimitError(_) => Some("subscribe-rate-limited"),
+        _ => None,
+    }
+    .map(|id| translations.message(&locale, id, None));
+    render(
+        &pages,
+        e.status_code(),
+        &list,
+        &locale,
+        "subscribe.html",
+        minijinja::context! {
+            name,
+            email,
+            errors,
+            message,
+            form_rendered_at => Utc::now().timestamp(),
+        },
+    )
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending sub through the hosted page",
+    skip(req, connection_pool, pages, translations, redirects, params)
+)]
+pub async fn confirm_page(
+    req: HttpRequest,
+    connection_pool: web::Data<PgPool>,
+    pages: web::Data<Pages>,
+    translations: web::Data<Translations>,
+    redirects: web::Data<PageRedirects>,
+    params: web::Query<TokenParams>,
+) -> Result<HttpResponse, PageError> {
+    let sub = match find_subscription(&connection_pool, &params.sub_token).await? {
+        Some(sub) => sub,
+        None => return invalid_token(&req, &connection_pool, &pages, &translations).await,
+    };
+    confirm_sub(&connection_pool, sub.sub_id).await?;
+    match &redirects.after_confirm {
+        Some(url) => Ok(see_other(url)),
+        None => render(&pages, StatusCode::OK, &sub.list, &sub.locale, "confirmed.html", ()),
+    }
+}
+
+#[tracing::instrument(
+    name = "Show the unsubscribe page",
+    skip(req, connection_pool, pages, translations, params)
+)]
+pub async fn unsubscribe_page(
+    req: HttpRequest,
+    connection_pool: web::Data<PgPool>,
+    pages: web::Data<Pages>,
+    translations: web::Data<Translations>,
+    params: web::Query<TokenParams>,
+) -> Result<HttpResponse, PageError> {
+    let sub = match find_subscription(&connection_pool, &params.sub_token).await? {
+        Some(sub) => sub,
+        None => return invalid_token(&req, &connection_pool, &pages, &translations).await,
+    };
+    render(
+        &pages,
+        StatusCode::OK,
+        &sub.list,
+        &sub.locale,
+        "unsubscribe.html",
+        minijinja::context! { sub_token => params.sub_token },
+    )
+}
+
+#[tracing::instrument(
+    name = "Unsubscribe through the hosted page",
+    skip(req, connection_pool, pages, translations, redirects, form)
+)]
+pub async fn submit_unsubscribe_page(
+    req: HttpRequest,
+    connection_pool: web::Data<PgPool>,
+    pages: web::Data<Pages>,
+    translations: web::Data<Translations>,
+    redirects: web::Data<PageRedirects>,
+    form: web::Form<TokenParams>,
+) -> Result<HttpResponse, PageError> {
+    let sub = match find_subscription(&connection_pool, &form.sub_token).await? {
+        Some(sub) => sub,
+        None => return invalid_token(&req, &connection_pool, &pages, &translations).await,
+    };
+    unsubscribe(&connection_pool, sub.sub_id).await?;
+    match &redirects.after_unsubscribe {
+        Some(url) => Ok(see_other(url)),
+        None => render(&pages, StatusCode::OK, &sub.list, &sub.locale, "unsubscribed.html", ()),
+    }
+}
+
+fn render(
+    pages: &Pages,
+    status: StatusCode,
+    list: &PageList,
+    locale: &str,
+    name: &str,
+    context: impl serde::Serialize,
+) -> Result<HttpResponse, PageError> {
+    let body = pages.render(list, locale, name, context)?;
+    Ok(HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+fn see_other(url: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, url))
+        .finish()
+}
+
+fn list_not_found(pages: &Pages, list_id: Uuid, locale: &str) -> Result<HttpResponse, PageError> {
+    let list = PageList {
+        list_id,
+        name: String::new(),
+    };
+    render(pages, StatusCode::NOT_FOUND, &list, locale, "not_found.html", ())
+}
+
+/// Tokens don't tell which list they were meant for, so the page is themed
+/// like the default list and in the language the browser asks for.
+async fn invalid_token(
+    req: &HttpRequest,
+    connection_pool: &PgPool,
+    pages: &Pages,
+    translations: &Translations,
+) -> Result<HttpResponse, PageError> {
+    let locale = translations.negotiate(&requested_locales(None, req));
+    let list = find_list(connection_pool, DEFAULT_LIST_ID)
+        .await?
+        .unwrap_or(PageList {
+            list_id: DEFAULT_LIST_ID,
+            name: String::new(),
+        });
+    render(pages, StatusCode::UNAUTHORIZED, &list, &locale, "invalid_token.html", ())
+}
+
+#[tracing::instrument(name = "Find a list for a page", skip(connection_pool))]
+async fn find_list(
+    connection_pool: &PgPool,
+    list_id: Uuid,
+) -> Result<Option<PageList>, sqlx::Error> {
+    let list = sqlx::query_as!(
+        PageList,
+        "select list_id, name from lists where list_id = $1",
+        list_id
+    )
+    .fetch_optional(connection_pool)
+    .await?;
+    Ok(list)
+}
+
+#[tracing::instrument(name = "Find the subscription of a token", skip(connection_pool, sub_token))]
+async fn find_subscription(
+    connection_pool: &PgPool,
+    sub_token: &str,
+) -> Result<Option<TokenSubscription>, sqlx::Error> {
+    let sub_id = match get_sub_id_from_token(connection_pool, sub_token).await? {
+        Some(sub_id) => sub_id,
+        None => return Ok(None),
+    };
+    let row = sqlx::query!(
+        r#"
+        select lists.list_id, lists.name, subscriptions.locale
+        from subscriptions join lists on lists.list_id = subscriptions.list_id
+        where subscriptions.id = $1
+        "#,
+        sub_id
+    )
+    .fetch_one(connection_pool)
+    .await?;
+    Ok(Some(TokenSubscription {
+        sub_id,
+        list: PageList {
+            list_id: row.list_id,
+            name: row.name,
+        },
+        locale: row.locale,
+    }))
+}
+
+#[tracing::instrument(name = "Unsubscribe a sub", skip(connection_pool))]
+async fn unsubscribe(connection_pool: &PgPool, sub_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = connection_pool.begin().await?;
+    let unsubscribed = sqlx::query!(
+        r#"
+        update subscriptions set status = 'unsubscribed'
+        where id = $1 and status <> 'unsubscribed'
+        "#,
+        sub_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    if unsubscribed.rows_affected() == 1 {
+        // Issues queued before are not sent anymore, only the goodbye email.
+        sqlx::query!("delete from issue_delivery_queue where subscriber_id = $1", sub_id)
+            .execute(&mut transaction)
+            .await?;
+        automation::trigger(&mut transaction, AutomationEvent::Unsubscribed, sub_id).await?;
+    }
+    transaction.commit().await
+}
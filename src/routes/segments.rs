@@ -0,0 +1,447 @@
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authentication::AuthError;
+use crate::authorization::{AuthenticatedUser, Permission};
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use crate::routes::get_list_fields;
+use crate::segments::{Rule, RuleError};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool, Row};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+const SAMPLE_SIZE: i64 = 10;
+
+#[derive(thiserror::Error)]
+pub enum SegmentError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    InvalidRules(#[from] RuleError),
+    #[error("The list already has a segment with this name")]
+    DuplicateName,
+    #[error("List not found")]
+    ListNotFound,
+    #[error("Segment not found")]
+    SegmentNotFound,
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to access segments")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for SegmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SegmentError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SegmentError::ValidationError(_) | SegmentError::InvalidRules(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            SegmentError::DuplicateName => StatusCode::CONFLICT,
+            SegmentError::ListNotFound | SegmentError::SegmentNotFound => StatusCode::NOT_FOUND,
+            SegmentError::AuthError(e) => e.status_code(),
+            SegmentError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            SegmentError::AuthError(e) => return e.error_response(),
+            SegmentError::ValidationError(_) | SegmentError::InvalidRules(_) => {
+                Problem::new(status, "validation_error").detail(self.to_string())
+            }
+            SegmentError::DuplicateName => {
+                Problem::new(status, "duplicate_segment_name").detail(self.to_string())
+            }
+            SegmentError::ListNotFound => Problem::new(status, "list_not_found"),
+            SegmentError::SegmentNotFound => Problem::new(status, "segment_not_found"),
+            SegmentError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NewSegment {
+    #[schema(example = "Pro plans")]
+    name: String,
+    /// A tree of rules like
+    /// `{"all": [{"attribute": {"key": "plan", "op": "eq", "value": "pro"}},
+    /// {"opened": {"within_days": 30}}]}`. Rules are `all`, `any`, `not`,
+    /// `attribute` (ops `eq`, `ne`, `gt`, `gte`, `lt`, `lte`, `exists`),
+    /// `tag`, `list` (also confirmed on that list), `subscribed_after`,
+    /// `subscribed_before`, `opened` and `clicked`.
+    #[schema(value_type = Object)]
+    rules: Rule,
+}
+
+/// Saved rules picking some of the subscribers of a list, usable to restrict
+/// the recipients of an issue.
+#[derive(Serialize, ToSchema)]
+struct Segment {
+    #[schema(value_type = String, format = "uuid")]
+    segment_id: Uuid,
+    name: String,
+    #[schema(value_type = Object)]
+    rules: Rule,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PreviewRequest {
+    /// Rules as in a segment.
+    #[schema(value_type = Object)]
+    rules: Rule,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SampleSubscriber {
+    email: String,
+    name: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SegmentPreview {
+    /// Confirmed subscribers of the list in the segment.
+    count: i64,
+    /// Up to ten of them, the most recent first.
+    sample: Vec<SampleSubscriber>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/lists/{list_id}/segments",
+    tag = "lists",
+    summary = "List the segments of a list",
+    params(("list_id" = String, Path, format = "uuid")),
+    responses(
+        (status = 200, description = "Oldest first", body = Vec<Segment>),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(name = "List segments", skip(user, pool), fields(user_id = %user.user_id))]
+pub async fn list_segments(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SegmentError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ViewSubscribers, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let rows = sqlx::query!(
+        r#"
+        select segment_id, name, rules, created_at
+        from segments
+        where list_id = $1
+        order by created_at, name
+        "#,
+        list_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+    let segments = rows
+        .into_iter()
+        .filter_map(|row| {
+            let rules = parse_rules(row.segment_id, row.rules)?;
+            Some(Segment {
+                segment_id: row.segment_id,
+                name: row.name,
+                rules,
+                created_at: row.created_at,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(segments))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/lists/{list_id}/segments",
+    tag = "lists",
+    summary = "Save a segment of a list",
+    params(("list_id" = String, Path, format = "uuid")),
+    request_body = NewSegment,
+    responses(
+        (status = 201, description = "Created", body = Segment),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+        (status = 409, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Create a segment",
+    skip(user, body, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn create_segment(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    body: web::Json<NewSegment>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SegmentError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let NewSegment { name, rules } = body.into_inner();
+    let name = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(SegmentError::ValidationError(
+            "name must have between 1 and 100 characters".into(),
+        ));
+    }
+    let (rules, lists) = rules.check(&get_list_fields(pool.get_ref(), list_id).await?)?;
+    require_referenced_lists(&user, &pool, &lists).await?;
+    let segment = Segment {
+        segment_id: Uuid::new_v4(),
+        name,
+        rules,
+        created_at: Utc::now(),
+    };
+    let inserted = sqlx::query!(
+        r#"
+        insert into segments (segment_id, list_id, name, rules, created_by, created_at)
+        values ($1, $2, $3, $4, $5, $6)
+        on conflict (list_id, name) do nothing
+        "#,
+        segment.segment_id,
+        list_id,
+        segment.name,
+        serde_json::to_value(&segment.rules).expect("Rules are always valid JSON"),
+        user.user_id,
+        segment.created_at
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if inserted.rows_affected() == 0 {
+        return Err(SegmentError::DuplicateName);
+    }
+    AuditEvent::by(&user, AuditAction::SegmentCreated)
+        .target("segment", segment.segment_id)
+        .payload(serde_json::json!({ "list_id": list_id, "name": segment.name }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::Created().json(segment))
+}
+
+/// Issues already stored with the segment keep going to it as it was.
+#[utoipa::path(
+    delete,
+    path = "/admin/lists/{list_id}/segments/{segment_id}",
+    tag = "lists",
+    summary = "Delete a segment",
+    params(
+        ("list_id" = String, Path, format = "uuid"),
+        ("segment_id" = String, Path, format = "uuid"),
+    ),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(name = "Delete a segment", skip(user, pool), fields(user_id = %user.user_id))]
+pub async fn delete_segment(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SegmentError> {
+    let (list_id, segment_id) = path.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    let deleted = sqlx::query!(
+        "delete from segments where list_id = $1 and segment_id = $2",
+        list_id,
+        segment_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(SegmentError::SegmentNotFound);
+    }
+    AuditEvent::by(&user, AuditAction::SegmentDeleted)
+        .target("segment", segment_id)
+        .payload(serde_json::json!({ "list_id": list_id }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/lists/{list_id}/segments/{segment_id}/preview",
+    tag = "lists",
+    summary = "Count and sample the subscribers in a segment",
+    params(
+        ("list_id" = String, Path, format = "uuid"),
+        ("segment_id" = String, Path, format = "uuid"),
+    ),
+    responses(
+        (status = 200, description = "Subscribers in the segment", body = SegmentPreview),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(
+    name = "Preview a segment",
+    skip(user, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn preview_segment(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SegmentError> {
+    let (list_id, segment_id) = path.into_inner();
+    user.require(&pool, Permission::ViewSubscribers, Some(list_id))
+        .await?;
+    let rules = find_segment(pool.get_ref(), list_id, segment_id)
+        .await?
+        .ok_or(SegmentError::SegmentNotFound)?;
+    require_referenced_lists(&user, &pool, &rules.lists()).await?;
+    Ok(HttpResponse::Ok().json(preview(&pool, list_id, &rules).await?))
+}
+
+/// Like previewing a segment, for rules that are not saved yet.
+#[utoipa::path(
+    post,
+    path = "/admin/lists/{list_id}/segments/preview",
+    tag = "lists",
+    summary = "Count and sample the subscribers matching rules",
+    params(("list_id" = String, Path, format = "uuid")),
+    request_body = PreviewRequest,
+    responses(
+        (status = 200, description = "Subscribers matching the rules", body = SegmentPreview),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(
+    name = "Preview segment rules",
+    skip(user, body, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn preview_rules(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    body: web::Json<PreviewRequest>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SegmentError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ViewSubscribers, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let fields = get_list_fields(pool.get_ref(), list_id).await?;
+    let (rules, lists) = body.into_inner().rules.check(&fields)?;
+    require_referenced_lists(&user, &pool, &lists).await?;
+    Ok(HttpResponse::Ok().json(preview(&pool, list_id, &rules).await?))
+}
+
+async fn ensure_list_exists(pool: &PgPool, list_id: Uuid) -> Result<(), SegmentError> {
+    sqlx::query!("select list_id from lists where list_id = $1", list_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(SegmentError::ListNotFound)?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Preview the subscribers of a segment", skip(pool, rules))]
+async fn preview(
+    pool: &PgPool,
+    list_id: Uuid,
+    rules: &Rule,
+) -> Result<SegmentPreview, sqlx::Error> {
+    let filter = rules.to_sql(2);
+    let count_sql = format!(
+        "select count(*) from subscriptions s \
+         where s.status = 'confirmed' and s.list_id = $1 and {}",
+        filter.sql
+    );
+    let count: i64 = filter
+        .clone()
+        .bind_to(sqlx::query(&count_sql).bind(list_id))
+        .fetch_one(pool)
+        .await?
+        .try_get(0)?;
+    let sample_sql = format!(
+        "select s.email, s.name from subscriptions s \
+         where s.status = 'confirmed' and s.list_id = $1 and {} \
+         order by s.subscribed_at desc limit {}",
+        filter.sql, SAMPLE_SIZE
+    );
+    let sample = filter
+        .bind_to(sqlx::query(&sample_sql).bind(list_id))
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(SampleSubscriber {
+                email: row.try_get("email")?,
+                name: row.try_get("name")?,
+            })
+        })
+        .collect::<Result<_, sqlx::Error>>()?;
+    Ok(SegmentPreview { count, sample })
+}
+
+/// A [`Rule::List`] tells who else is subscribed to another list, so rules
+/// may only refer to lists whose subscribers the user may see.
+pub(crate) async fn require_referenced_lists(
+    user: &AuthenticatedUser,
+    pool: &PgPool,
+    lists: &[Uuid],
+) -> Result<(), AuthError> {
+    for list_id in lists {
+        user.require(pool, Permission::ViewSubscribers, Some(*list_id))
+            .await?;
+    }
+    Ok(())
+}
+
+/// The rules of a segment of the list, if there is one with this id.
+#[tracing::instrument(name = "Find a segment", skip(executor))]
+pub(crate) async fn find_segment<'e>(
+    executor: impl PgExecutor<'e>,
+    list_id: Uuid,
+    segment_id: Uuid,
+) -> Result<Option<Rule>, sqlx::Error> {
+    let row = sqlx::query!(
+        "select rules from segments where list_id = $1 and segment_id = $2",
+        list_id,
+        segment_id
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.and_then(|row| parse_rules(segment_id, row.rules)))
+}
+
+/// Rules are checked before they are stored, so only a schema change can
+/// make them unreadable.
+fn parse_rules(segment_id: Uuid, rules: serde_json::Value) -> Option<Rule> {
+    serde_json::from_value(rules)
+        .map_err(|e| tracing::error!(%segment_id, error = %e, "Failed to read segment rules"))
+        .ok()
+}
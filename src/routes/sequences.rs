@@ -0,0 +1,642 @@
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authentication::AuthError;
+use crate::authorization::{AuthenticatedUser, Permission};
+use crate::domain::SubscriberTagError;
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use crate::routes::{find_segment, require_referenced_lists, AutomationEmail, NewsletterError};
+use crate::sequences::SequenceTrigger;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+const MAX_STEPS: usize = 20;
+const MAX_DELAY_DAYS: i32 = 3650;
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(thiserror::Error)]
+pub enum SequenceError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    InvalidEmail(#[from] NewsletterError),
+    #[error(transparent)]
+    InvalidTrigger(#[from] SubscriberTagError),
+    #[error("Segment {0} not found in the list")]
+    UnknownSegment(Uuid),
+    #[error("The list already has a sequence with this name")]
+    DuplicateName,
+    #[error("List not found")]
+    ListNotFound,
+    #[error("Sequence not found")]
+    SequenceNotFound,
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to access sequences")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for SequenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SequenceError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SequenceError::ValidationError(_)
+            | SequenceError::InvalidTrigger(_)
+            | SequenceError::UnknownSegment(_) => StatusCode::BAD_REQUEST,
+            SequenceError::InvalidEmail(e) => e.status_code(),
+            SequenceError::DuplicateName => StatusCode::CONFLICT,
+            SequenceError::ListNotFound | SequenceError::SequenceNotFound => StatusCode::NOT_FOUND,
+            SequenceError::AuthError(e) => e.status_code(),
+            SequenceError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            SequenceError::InvalidEmail(e) => return e.error_response(),
+            SequenceError::AuthError(e) => return e.error_response(),
+            SequenceError::ValidationError(_) | SequenceError::InvalidTrigger(_) => {
+                Problem::new(status, "validation_error").detail(self.to_string())
+            }
+            SequenceError::UnknownSegment(_) => {
+                Problem::new(status, "unknown_segment").detail(self.to_string())
+            }
+            SequenceError::DuplicateName => {
+                Problem::new(status, "duplicate_sequence_name").detail(self.to_string())
+            }
+            SequenceError::ListNotFound => Problem::new(status, "list_not_found"),
+            SequenceError::SequenceNotFound => Problem::new(status, "sequence_not_found"),
+            SequenceError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SequenceDefinition {
+    #[schema(example = "Onboarding")]
+    name: String,
+    /// `"confirmed"`, or `{"tagged": "<tag>"}` to start when a confirmed
+    /// subscriber gets the tag.
+    trigger: SequenceTrigger,
+    /// Subscribers who are not in the segment when a step is due leave the
+    /// sequence.
+    #[schema(value_type = Option<String>, format = "uuid")]
+    segment_id: Option<Uuid>,
+    /// At most 20, in the order they are sent.
+    steps: Vec<NewStep>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NewStep {
+    /// Days after enrolment the step is sent, at least those of the step
+    /// before.
+    #[schema(example = 3)]
+    delay_days: i32,
+    #[serde(flatten)]
+    email: AutomationEmail,
+}
+
+/// Emails sent to subscribers at day offsets after they confirm or get a
+/// tag. Subscribers leave it when they unsubscribe or leave its segment.
+#[derive(Serialize, ToSchema)]
+struct Sequence {
+    #[schema(value_type = String, format = "uuid")]
+    sequence_id: Uuid,
+    name: String,
+    trigger: SequenceTrigger,
+    #[schema(value_type = Option<String>, format = "uuid")]
+    segment_id: Option<Uuid>,
+    steps: Vec<Step>,
+    enrolments: EnrolmentCounts,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Step {
+    position: i32,
+    delay_days: i32,
+    /// Issue holding the email.
+    #[schema(value_type = String, format = "uuid")]
+    newsletter_issue_id: Uuid,
+    title: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct EnrolmentCounts {
+    active: i64,
+    completed: i64,
+    exited: i64,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EnrolmentQuery {
+    /// Only enrolments with this status: `active`, `completed` or `exited`.
+    status: Option<String>,
+    /// Enrolments to skip, to page through them.
+    offset: Option<i64>,
+    /// Between 1 and 500, 50 if left out.
+    limit: Option<i64>,
+}
+
+/// Where a subscriber is in a sequence.
+#[derive(Serialize, ToSchema)]
+struct Enrolment {
+    email: String,
+    /// `active`, `completed` or `exited`.
+    status: String,
+    /// Position of the step sent next, or of the one after the last sent.
+    next_step: i32,
+    next_step_at: Option<DateTime<Utc>>,
+    /// `unsubscribed` or `left_segment` once exited.
+    exit_reason: Option<String>,
+    enrolled_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/lists/{list_id}/sequences",
+    tag = "lists",
+    summary = "List the sequences of a list",
+    params(("list_id" = String, Path, format = "uuid")),
+    responses(
+        (status = 200, description = "Sequences, oldest first", body = Vec<Sequence>),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(name = "List sequences", skip(user, pool), fields(user_id = %user.user_id))]
+pub async fn list_sequences(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SequenceError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ViewSubscribers, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    Ok(HttpResponse::Ok().json(load_sequences(&pool, list_id, None).await?))
+}
+
+/// Subscribers who confirmed or got the tag before the sequence was created
+/// are not enrolled.
+#[utoipa::path(
+    post,
+    path = "/admin/lists/{list_id}/sequences",
+    tag = "lists",
+    summary = "Create a sequence",
+    params(("list_id" = String, Path, format = "uuid")),
+    request_body = SequenceDefinition,
+    responses(
+        (status = 201, description = "Created", body = Sequence),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+        (status = 409, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Create a sequence",
+    skip(user, body, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn create_sequence(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    body: web::Json<SequenceDefinition>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SequenceError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let definition = body.into_inner();
+    let (name, trigger, segment_rules) = check_definition(&user, &pool, list_id, &definition).await?;
+    let sequence_id = Uuid::new_v4();
+
+    let mut transaction = pool.begin().await?;
+    let inserted = sqlx::query!(
+        r#"
+        insert into sequences (
+            sequence_id, list_id, name, trigger, trigger_tag, segment_id, segment_rules,
+            created_by, created_at, updated_at
+        )
+        values ($1, $2, $3, $4, $5, $6, $7, $8, now(), now())
+        on conflict (list_id, name) do nothing
+        "#,
+        sequence_id,
+        list_id,
+        name,
+        trigger.as_str(),
+        trigger.tag(),
+        definition.segment_id,
+        segment_rules,
+        user.user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    if inserted.rows_affected() == 0 {
+        return Err(SequenceError::DuplicateName);
+    }
+    store_steps(&mut transaction, list_id, sequence_id, &definition.steps, user.user_id).await?;
+    AuditEvent::by(&user, AuditAction::SequenceCreated)
+        .target("sequence", sequence_id)
+        .payload(serde_json::json!({ "list_id": list_id, "name": name }))
+        .record(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    let sequence = load_sequences(&pool, list_id, Some(sequence_id)).await?.pop();
+    Ok(HttpResponse::Created().json(sequence))
+}
+
+/// Replaces the name, trigger, segment and steps of a sequence. Enrolled
+/// subscribers keep their place: the step they get next is the one at the
+/// same position among the new steps, due as many days after their
+/// enrolment as it says. Those past the last new step have completed it.
+#[utoipa::path(
+    put,
+    path = "/admin/lists/{list_id}/sequences/{sequence_id}",
+    tag = "lists",
+    summary = "Update a sequence",
+    params(
+        ("list_id" = String, Path, format = "uuid"),
+        ("sequence_id" = String, Path, format = "uuid"),
+    ),
+    request_body = SequenceDefinition,
+    responses(
+        (status = 200, description = "Updated", body = Sequence),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+        (status = 409, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Update a sequence",
+    skip(user, body, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn update_sequence(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    body: web::Json<SequenceDefinition>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SequenceError> {
+    let (list_id, sequence_id) = path.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    let definition = body.into_inner();
+    let (name, trigger, segment_rules) = check_definition(&user, &pool, list_id, &definition).await?;
+
+    let mut transaction = pool.begin().await?;
+    let updated = sqlx::query!(
+        r#"
+        update sequences
+        set name = $3, trigger = $4, trigger_tag = $5, segment_id = $6, segment_rules = $7,
+            updated_at = now()
+        where list_id = $1 and sequence_id = $2
+        "#,
+        list_id,
+        sequence_id,
+        name,
+        trigger.as_str(),
+        trigger.tag(),
+        definition.segment_id,
+        segment_rules
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db_error) if db_error.code().as_deref() == Some("23505") => {
+            SequenceError::DuplicateName
+        }
+        _ => SequenceError::UnexpectedError(e),
+    })?;
+    if updated.rows_affected() == 0 {
+        return Err(SequenceError::SequenceNotFound);
+    }
+    sqlx::query!("delete from sequence_steps where sequence_id = $1", sequence_id)
+        .execute(&mut transaction)
+        .await?;
+    store_steps(&mut transaction, list_id, sequence_id, &definition.steps, user.user_id).await?;
+    sqlx::query!(
+        r#"
+        update sequence_enrolments e
+        set next_step_at = e.enrolled_at + make_interval(days => step.delay_days),
+            status = case when step.delay_days is null then 'completed' else 'active' end,
+            updated_at = now()
+        from (
+            select e.subscriber_id, s.delay_days
+            from sequence_enrolments e
+            left join sequence_steps s
+                on s.sequence_id = e.sequence_id and s.position = e.next_step
+            where e.sequence_id = $1 and e.status = 'active'
+        ) step
+        where e.sequence_id = $1 and e.subscriber_id = step.subscriber_id
+        "#,
+        sequence_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    AuditEvent::by(&user, AuditAction::SequenceUpdated)
+        .target("sequence", sequence_id)
+        .payload(serde_json::json!({ "list_id": list_id, "name": name }))
+        .record(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    let sequence = load_sequences(&pool, list_id, Some(sequence_id)).await?.pop();
+    Ok(HttpResponse::Ok().json(sequence))
+}
+
+/// Ends every enrolment in the sequence. Steps already queued are sent.
+#[utoipa::path(
+    delete,
+    path = "/admin/lists/{list_id}/sequences/{sequence_id}",
+    tag = "lists",
+    summary = "Delete a sequence",
+    params(
+        ("list_id" = String, Path, format = "uuid"),
+        ("sequence_id" = String, Path, format = "uuid"),
+    ),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Delete a sequence",
+    skip(user, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn delete_sequence(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SequenceError> {
+    let (list_id, sequence_id) = path.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    let deleted = sqlx::query!(
+        "delete from sequences where list_id = $1 and sequence_id = $2",
+        list_id,
+        sequence_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(SequenceError::SequenceNotFound);
+    }
+    AuditEvent::by(&user, AuditAction::SequenceDeleted)
+        .target("sequence", sequence_id)
+        .payload(serde_json::json!({ "list_id": list_id }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Subscribers enrolled in a sequence and where they are, in the order they
+/// were enrolled.
+#[utoipa::path(
+    get,
+    path = "/admin/lists/{list_id}/sequences/{sequence_id}/subscribers",
+    tag = "lists",
+    summary = "List the subscribers of a sequence",
+    params(
+        ("list_id" = String, Path, format = "uuid"),
+        ("sequence_id" = String, Path, format = "uuid"),
+        EnrolmentQuery,
+    ),
+    responses(
+        (status = 200, description = "Matching enrolments", body = Vec<Enrolment>),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(
+    name = "List sequence enrolments",
+    skip(user, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn list_sequence_enrolments(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    query: web::Query<EnrolmentQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SequenceError> {
+    let (list_id, sequence_id) = path.into_inner();
+    user.require(&pool, Permission::ViewSubscribers, Some(list_id))
+        .await?;
+    sqlx::query!(
+        "select sequence_id from sequences where list_id = $1 and sequence_id = $2",
+        list_id,
+        sequence_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(SequenceError::SequenceNotFound)?;
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(SequenceError::ValidationError(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let offset = query.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(SequenceError::ValidationError(
+            "offset must not be negative".into(),
+        ));
+    }
+    let enrolments = sqlx::query_as!(
+        Enrolment,
+        r#"
+        select s.email, e.status, e.next_step, e.next_step_at, e.exit_reason, e.enrolled_at,
+            e.updated_at
+        from sequence_enrolments e
+        join subscriptions s on s.id = e.subscriber_id
+        where e.sequence_id = $1 and ($2::text is null or e.status = $2)
+        order by e.enrolled_at, s.id
+        limit $3 offset $4
+        "#,
+        sequence_id,
+        query.status,
+        limit,
+        offset
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+    Ok(HttpResponse::Ok().json(enrolments))
+}
+
+/// The trimmed name, checked trigger and segment rules of a definition.
+async fn check_definition(
+    user: &AuthenticatedUser,
+    pool: &PgPool,
+    list_id: Uuid,
+    definition: &SequenceDefinition,
+) -> Result<(String, SequenceTrigger, Option<serde_json::Value>), SequenceError> {
+    let name = definition.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(SequenceError::ValidationError(
+            "name must have between 1 and 100 characters".into(),
+        ));
+    }
+    if definition.steps.is_empty() || definition.steps.len() > MAX_STEPS {
+        return Err(SequenceError::ValidationError(format!(
+            "a sequence must have between 1 and {} steps",
+            MAX_STEPS
+        )));
+    }
+    let mut previous = 0;
+    for step in &definition.steps {
+        if !(previous..=MAX_DELAY_DAYS).contains(&step.delay_days) {
+            return Err(SequenceError::ValidationError(format!(
+                "delay_days must be between {} and {} and must not decrease from step to step",
+                previous, MAX_DELAY_DAYS
+            )));
+        }
+        previous = step.delay_days;
+    }
+    let trigger = definition.trigger.clone().check()?;
+    let segment_rules = match definition.segment_id {
+        Some(segment_id) => {
+            let rules = find_segment(pool, list_id, segment_id)
+                .await?
+                .ok_or(SequenceError::UnknownSegment(segment_id))?;
+            require_referenced_lists(user, pool, &rules.lists()).await?;
+            Some(serde_json::to_value(&rules).expect("Rules are always valid JSON"))
+        }
+        None => None,
+    };
+    Ok((name, trigger, segment_rules))
+}
+
+async fn store_steps(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    sequence_id: Uuid,
+    steps: &[NewStep],
+    created_by: Uuid,
+) -> Result<(), SequenceError> {
+    for (position, step) in steps.iter().enumerate() {
+        let issue_id = step.email.store(transaction, list_id, created_by).await?;
+        sqlx::query!(
+            r#"
+            insert into sequence_steps (sequence_id, position, delay_days, newsletter_issue_id)
+            values ($1, $2, $3, $4)
+            "#,
+            sequence_id,
+            position as i32,
+            step.delay_days,
+            issue_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+    Ok(())
+}
+
+/// The sequences of a list, or the one with `sequence_id`.
+async fn load_sequences(
+    pool: &PgPool,
+    list_id: Uuid,
+    sequence_id: Option<Uuid>,
+) -> Result<Vec<Sequence>, sqlx::Error> {
+    let mut steps: HashMap<Uuid, Vec<Step>> = HashMap::new();
+    let rows = sqlx::query!(
+        r#"
+        select st.sequence_id, st.position, st.delay_days, st.newsletter_issue_id, i.title
+        from sequence_steps st
+        join sequences q on q.sequence_id = st.sequence_id
+        join newsletter_issues i on i.newsletter_issue_id = st.newsletter_issue_id
+        where q.list_id = $1 and ($2::uuid is null or q.sequence_id = $2)
+        order by st.position
+        "#,
+        list_id,
+        sequence_id
+    )
+    .fetch_all(pool)
+    .await?;
+    for row in rows {
+        steps.entry(row.sequence_id).or_default().push(Step {
+            position: row.position,
+            delay_days: row.delay_days,
+            newsletter_issue_id: row.newsletter_issue_id,
+            title: row.title,
+        });
+    }
+    let rows = sqlx::query!(
+        r#"
+        select q.sequence_id, q.name, q.trigger, q.trigger_tag, q.segment_id, q.created_at,
+            q.updated_at,
+            count(*) filter (where e.status = 'active') as "active!",
+            count(*) filter (where e.status = 'completed') as "completed!",
+            count(*) filter (where e.status = 'exited') as "exited!"
+        from sequences q
+        left join sequence_enrolments e on e.sequence_id = q.sequence_id
+        where q.list_id = $1 and ($2::uuid is null or q.sequence_id = $2)
+        group by q.sequence_id
+        order by q.created_at, q.sequence_id
+        "#,
+        list_id,
+        sequence_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(Sequence {
+                // The table only allows known triggers.
+                trigger: SequenceTrigger::parse(&row.trigger, row.trigger_tag)?,
+                steps: steps.remove(&row.sequence_id).unwrap_or_default(),
+                sequence_id: row.sequence_id,
+                name: row.name,
+                segment_id: row.segment_id,
+                enrolments: EnrolmentCounts {
+                    active: row.active,
+                    completed: row.completed,
+                    exited: row.exited,
+                },
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+            })
+        })
+        .collect())
+}
+
+async fn ensure_list_exists(pool: &PgPool, list_id: Uuid) -> Result<(), SequenceError> {
+    sqlx::query!("select list_id from lists where list_id = $1", list_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(SequenceError::ListNotFound)?;
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authentication::sessions::{self, Session};
+use crate::authentication::{password_login, AuthError, Credential, TwoFactorPolicy};
+use crate::authorization::AuthenticatedUser;
+use crate::config::TwoFactorConfig;
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+#[derive(thiserror::Error)]
+pub enum SessionError {
+    #[error("The request was not authenticated with a session")]
+    NotASession,
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to access sessions")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SessionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SessionError::NotASession => StatusCode::BAD_REQUEST,
+            SessionError::AuthError(e) => e.status_code(),
+            SessionError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            SessionError::AuthError(e) => return e.error_response(),
+            SessionError::NotASession => {
+                Problem::new(status, "not_a_session").detail(self.to_string())
+            }
+            SessionError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct OpenedSession {
+    #[serde(flatten)]
+    details: Session,
+    /// Sent as Bearer token on later requests. Shown only once.
+    token: String,
+}
+
+/// Opens a session with the password and, for admins with two-factor
+/// authentication, a code. Later requests send the session token instead,
+/// so the code is only entered once per session.
+#[utoipa::path(
+    post,
+    path = "/admin/sessions",
+    tag = "sessions",
+    summary = "Log in",
+    responses(
+        (status = 201, description = "Opened", body = OpenedSession),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 429, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Open a session",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn open_session(
+    pool: web::Data<PgPool>,
+    config: web::Data<TwoFactorConfig>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SessionError> {
+    let login = password_login(&request, TwoFactorPolicy::Enforce).await?;
+    let (details, token) =
+        sessions::open_session(&pool, login.user_id, config.session_ttl()).await?;
+    AuditEvent::from_request(&request, AuditAction::LoginSucceeded)
+        .actor(login.user_id)
+        .payload(serde_json::json!({
+            "session_id": details.session_id,
+            "second_factor": login.second_factor,
+        }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::Created().json(OpenedSession { details, token }))
+}
+
+/// Ends the session the request is authenticated with.
+#[utoipa::path(
+    delete,
+    path = "/admin/sessions/current",
+    tag = "sessions",
+    summary = "Log out",
+    responses(
+        (status = 204, description = "Ended"),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+    ),
+    security(("bearer" = []))
+)]
+#[tracing::instrument(name = "End a session", skip(user, pool), fields(user_id = %user.user_id))]
+pub async fn end_session(
+    user: AuthenticatedUser,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SessionError> {
+    let session_id = match user.credential {
+        Credential::Session { session_id } => session_id,
+        _ => return Err(SessionError::NotASession),
+    };
+    sessions::end_session(&pool, session_id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
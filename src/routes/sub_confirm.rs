@@ -1,31 +1,78 @@
-use actix_web::{web, HttpResponse};
+use crate::automation::{self, AutomationEvent};
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use crate::routes::SubscriptionStatus;
+use crate::sequences;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
 use serde::Deserialize;
 use sqlx::PgPool;
+use utoipa::IntoParams;
 use uuid::Uuid;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Params {
+    /// Token from the confirmation email.
     sub_token: String,
 }
 
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("Unknown subscription token")]
+    UnknownToken,
+    #[error("Failed to confirm a subscription")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ConfirmError::UnknownToken => Problem::new(self.status_code(), "unknown_token")
+                .detail(self.to_string())
+                .error_response(),
+            ConfirmError::UnexpectedError(_) => Problem::internal().error_response(),
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/subscriptions/confirm",
+    tag = "subscriptions",
+    summary = "Confirm a subscription",
+    params(Params),
+    responses(
+        (status = 200, description = "Confirmed", body = SubscriptionStatus),
+        (status = 401, response = Problem),
+    )
+)]
 #[tracing::instrument(name = "Confirm a pending sub", skip(connection_pool, params))]
 pub async fn confirm(
     connection_pool: web::Data<PgPool>,
     params: web::Query<Params>,
-) -> HttpResponse {
-    let id = match get_sub_id_from_token(&connection_pool, &params.sub_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-    match id {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(sub_id) => {
-            if confirm_sub(&connection_pool, sub_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
-        }
-    }
+) -> Result<HttpResponse, ConfirmError> {
+    let sub_id = get_sub_id_from_token(&connection_pool, &params.sub_token)
+        .await?
+        .ok_or(ConfirmError::UnknownToken)?;
+    confirm_sub(&connection_pool, sub_id).await?;
+    Ok(HttpResponse::Ok().json(SubscriptionStatus {
+        subscriber_id: sub_id,
+        status: "confirmed",
+    }))
 }
 
 #[tracing::instrument(name = "Get sub id from token", skip(connection_pool, sub_token))]
@@ -46,17 +93,25 @@ pub async fn get_sub_id_from_token(
     Ok(result.map(|r| r.subscriber_id))
 }
 
+/// Confirms a pending sub and starts the automations and sequences of its
+/// list. Other subs stay as they are: following the link again sends no
+/// second welcome email, and an old link can't undo an unsubscribe.
 #[tracing::instrument(name = "Confirms a sub", skip(connection_pool, sub_id))]
 pub async fn confirm_sub(connection_pool: &PgPool, sub_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        "update subscriptions set status = 'confirmed' where id = $1",
+    let mut transaction = connection_pool.begin().await?;
+    let confirmed = sqlx::query!(
+        "update subscriptions set status = 'confirmed' where id = $1 and status = 'pending'",
         sub_id
     )
-    .execute(connection_pool)
+    .execute(&mut transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
-    Ok(())
+    if confirmed.rows_affected() == 1 {
+        automation::trigger(&mut transaction, AutomationEvent::Confirmed, sub_id).await?;
+        sequences::enrol_confirmed(&mut transaction, sub_id).await?;
+    }
+    transaction.commit().await
 }
@@ -0,0 +1,450 @@
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authentication::AuthError;
+use crate::authorization::{AuthenticatedUser, Permission};
+use crate::domain::{NewSubscriber, SubscriberAttributes, SubscriberTag, SubscriberTagError};
+use crate::error::error_chain_fmt;
+use crate::localisation::Translations;
+use crate::problem::{FieldError, Problem};
+use crate::routes::{attribute_field_errors, field_errors, get_list_fields};
+use crate::sequences;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+const MAX_TAGGED_SUBSCRIBERS: usize = 1000;
+const MAX_IMPORTED_SUBSCRIBERS: usize = 1000;
+
+#[derive(thiserror::Error)]
+pub enum SubscriberError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    InvalidTag(#[from] SubscriberTagError),
+    #[error("Invalid subscribers")]
+    InvalidSubscribers(Vec<FieldError>),
+    #[error("List not found")]
+    ListNotFound,
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to access subscribers")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for SubscriberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscriberError::ValidationError(_)
+            | SubscriberError::InvalidTag(_)
+            | SubscriberError::InvalidSubscribers(_) => StatusCode::BAD_REQUEST,
+            SubscriberError::ListNotFound => StatusCode::NOT_FOUND,
+            SubscriberError::AuthError(e) => e.status_code(),
+            SubscriberError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            SubscriberError::AuthError(e) => return e.error_response(),
+            SubscriberError::ValidationError(_) | SubscriberError::InvalidTag(_) => {
+                Problem::new(status, "validation_error").detail(self.to_string())
+            }
+            SubscriberError::InvalidSubscribers(errors) => {
+                Problem::new(status, "validation_error")
+                    .detail(self.to_string())
+                    .field_errors(errors.clone())
+            }
+            SubscriberError::ListNotFound => Problem::new(status, "list_not_found"),
+            SubscriberError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SubscriberQuery {
+    /// Only subscribers with this tag.
+    tag: Option<String>,
+    /// Only subscribers with this status, e.g. `confirmed`.
+    status: Option<String>,
+    /// Subscribers to skip, to page through the list.
+    offset: Option<i64>,
+    /// Between 1 and 500, 50 if left out.
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Subscriber {
+    #[schema(value_type = String, format = "uuid")]
+    subscriber_id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    locale: String,
+    #[schema(value_type = Object)]
+    attributes: serde_json::Value,
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TagUpdate {
+    /// Email addresses of subscribers of the list, at most 1000.
+    subscribers: Vec<String>,
+    /// Tags to give them.
+    #[serde(default)]
+    add: Vec<String>,
+    /// Tags to take away from them.
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TagUpdateResult {
+    /// Subscribers of the list among those given. Other addresses are
+    /// ignored.
+    matched: usize,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ImportedSubscriber {
+    email: String,
+    name: String,
+    /// Values of the custom fields of the list, by key.
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    attributes: HashMap<String, serde_json::Value>,
+    /// Tags to give the subscriber.
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ImportResult {
+    /// Subscribers added to the list.
+    imported: usize,
+    /// Addresses that were already on the list. They are left as they are.
+    skipped: usize,
+}
+
+/// Subscribers of a list, oldest first.
+#[utoipa::path(
+    get,
+    path = "/admin/lists/{list_id}/subscribers",
+    tag = "lists",
+    summary = "List the subscribers of a list",
+    params(("list_id" = String, Path, format = "uuid"), SubscriberQuery),
+    responses(
+        (status = 200, description = "Matching subscribers", body = Vec<Subscriber>),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []), ("bearer" = []))
+)]
+#[tracing::instrument(
+    name = "List subscribers",
+    skip(user, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn list_subscribers(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    query: web::Query<SubscriberQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ViewSubscribers, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(SubscriberError::ValidationError(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let offset = query.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(SubscriberError::ValidationError(
+            "offset must not be negative".into(),
+        ));
+    }
+    let tag = query.tag.map(SubscriberTag::try_from).transpose()?;
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        select
+            s.id as subscriber_id, s.email, s.name, s.status, s.subscribed_at, s.locale,
+            s.attributes,
+            array(
+                select t.tag from subscriber_tags t where t.subscriber_id = s.id order by t.tag
+            ) as "tags!"
+        from subscriptions s
+        where s.list_id = $1
+            and ($2::text is null or exists (
+                select 1 from subscriber_tags t where t.subscriber_id = s.id and t.tag = $2
+            ))
+            and ($3::text is null or s.status = $3)
+        order by s.subscribed_at, s.id
+        limit $4 offset $5
+        "#,
+        list_id,
+        tag.as_ref().map(|tag| tag.as_ref()),
+        query.status,
+        limit,
+        offset
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+    Ok(HttpResponse::Ok().json(subscribers))
+}
+
+/// Adds and removes tags of many subscribers at once.
+#[utoipa::path(
+    post,
+    path = "/admin/lists/{list_id}/subscribers/tags",
+    tag = "lists",
+    summary = "Tag subscribers of a list",
+    params(("list_id" = String, Path, format = "uuid")),
+    request_body = TagUpdate,
+    responses(
+        (status = 200, description = "Tags updated", body = TagUpdateResult),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Update subscriber tags",
+    skip(user, body, pool),
+    fields(user_id = %user.user_id)
+)]
+pub async fn update_subscriber_tags(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    body: web::Json<TagUpdate>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let TagUpdate {
+        subscribers,
+        add,
+        remove,
+    } = body.into_inner();
+    if subscribers.len() > MAX_TAGGED_SUBSCRIBERS {
+        return Err(SubscriberError::ValidationError(format!(
+            "at most {} subscribers can be tagged at once",
+            MAX_TAGGED_SUBSCRIBERS
+        )));
+    }
+    let add = SubscriberTag::parse_all(add)?;
+    let remove = SubscriberTag::parse_all(remove)?;
+    let emails: Vec<String> = subscribers.iter().map(|e| e.trim().to_lowercase()).collect();
+
+    let mut transaction = pool.begin().await?;
+    let sub_ids: Vec<Uuid> = sqlx::query!(
+        "select id from subscriptions where list_id = $1 and lower(email) = any($2)",
+        list_id,
+        &emails[..]
+    )
+    .fetch_all(&mut transaction)
+    .await?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+    let removed: Vec<&str> = remove.iter().map(AsRef::as_ref).collect();
+    sqlx::query!(
+        "delete from subscriber_tags where subscriber_id = any($1) and tag = any($2)",
+        &sub_ids[..],
+        &removed as &[&str]
+    )
+    .execute(&mut transaction)
+    .await?;
+    tag_subscribers(&mut transaction, &sub_ids, &add).await?;
+    AuditEvent::by(&user, AuditAction::SubscribersTagged)
+        .target("list", list_id)
+        .payload(serde_json::json!({
+            "subscribers": sub_ids.len(),
+            "added": add.iter().map(AsRef::as_ref).collect::<Vec<&str>>(),
+            "removed": removed,
+        }))
+        .record(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(HttpResponse::Ok().json(TagUpdateResult {
+        matched: sub_ids.len(),
+    }))
+}
+
+/// Adds subscribers who agreed to receive the list elsewhere, confirmed and
+/// without sending them anything, with the tags given to them. Either every
+/// subscriber is valid and the new ones are added, or nothing is.
+#[utoipa::path(
+    post,
+    path = "/admin/lists/{list_id}/subscribers/import",
+    tag = "lists",
+    summary = "Import subscribers into a list",
+    params(("list_id" = String, Path, format = "uuid")),
+    request_body = Vec<ImportedSubscriber>,
+    responses(
+        (status = 200, description = "Subscribers imported", body = ImportResult),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(user, body, pool, translations),
+    fields(user_id = %user.user_id)
+)]
+pub async fn import_subscribers(
+    user: AuthenticatedUser,
+    list_id: web::Path<Uuid>,
+    body: web::Json<Vec<ImportedSubscriber>>,
+    pool: web::Data<PgPool>,
+    translations: web::Data<Translations>,
+) -> Result<HttpResponse, SubscriberError> {
+    let list_id = list_id.into_inner();
+    user.require(&pool, Permission::ManageLists, Some(list_id))
+        .await?;
+    ensure_list_exists(&pool, list_id).await?;
+    let rows = body.into_inner();
+    if rows.len() > MAX_IMPORTED_SUBSCRIBERS {
+        return Err(SubscriberError::ValidationError(format!(
+            "at most {} subscribers can be imported at once",
+            MAX_IMPORTED_SUBSCRIBERS
+        )));
+    }
+    let fields = get_list_fields(pool.get_ref(), list_id).await?;
+    let mut subscribers = Vec::with_capacity(rows.len());
+    let mut errors = Vec::new();
+    // Errors name their row, like `3.email` or `0.attributes.plan`.
+    for (row, imported) in rows.into_iter().enumerate() {
+        let new_sub = NewSubscriber::new(imported.name, imported.email);
+        let attributes = SubscriberAttributes::parse(&fields, &imported.attributes);
+        let tags = SubscriberTag::parse_all(imported.tags);
+        match (new_sub, attributes, tags) {
+            (Ok(new_sub), Ok(attributes), Ok(tags)) => {
+                subscribers.push((new_sub, attributes, tags))
+            }
+            (new_sub, attributes, tags) => {
+                let row_errors = new_sub
+                    .err()
+                    .map(|e| field_errors(&e))
+                    .into_iter()
+                    .chain(attributes.err().map(|e| attribute_field_errors(&e)))
+                    .chain(
+                        tags.err()
+                            .map(|e| vec![FieldError::new("tags", e.code(), e.to_string())]),
+                    )
+                    .flatten();
+                errors.extend(row_errors.map(|mut error| {
+                    error.field = format!("{}.{}", row, error.field);
+                    error
+                }));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(SubscriberError::InvalidSubscribers(errors));
+    }
+
+    let total = subscribers.len();
+    let mut imported = 0;
+    let mut transaction = pool.begin().await?;
+    for (new_sub, attributes, tags) in subscribers {
+        let inserted = sqlx::query!(
+            r#"
+            insert into subscriptions (
+                id, email, name, subscribed_at, status, list_id, locale, attributes
+            )
+            values ($1, $2, $3, now(), 'confirmed', $4, $5, $6)
+            on conflict do nothing
+            returning id
+            "#,
+            Uuid::new_v4(),
+            new_sub.email.as_ref(),
+            new_sub.name.as_ref(),
+            list_id,
+            translations.default_locale(),
+            attributes.into_json()
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+        if let Some(row) = inserted {
+            tag_subscribers(&mut transaction, &[row.id], &tags).await?;
+            imported += 1;
+        }
+    }
+    let skipped = total - imported;
+    AuditEvent::by(&user, AuditAction::SubscribersImported)
+        .target("list", list_id)
+        .payload(serde_json::json!({
+            "imported": imported,
+            "skipped": skipped,
+        }))
+        .record(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(HttpResponse::Ok().json(ImportResult { imported, skipped }))
+}
+
+async fn ensure_list_exists(pool: &PgPool, list_id: Uuid) -> Result<(), SubscriberError> {
+    sqlx::query!("select list_id from lists where list_id = $1", list_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(SubscriberError::ListNotFound)?;
+    Ok(())
+}
+
+/// Gives every subscriber every tag, keeping the tags they already have, and
+/// enrols confirmed ones in the sequences that start on the tags.
+#[tracing::instrument(name = "Tag subscribers", skip(transaction, sub_ids))]
+pub(crate) async fn tag_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    sub_ids: &[Uuid],
+    tags: &[SubscriberTag],
+) -> Result<(), sqlx::Error> {
+    if sub_ids.is_empty() || tags.is_empty() {
+        return Ok(());
+    }
+    let tags: Vec<&str> = tags.iter().map(AsRef::as_ref).collect();
+    sqlx::query!(
+        r#"
+        insert into subscriber_tags (subscriber_id, tag, created_at)
+        select subscriber_id, tag, now()
+        from unnest($1::uuid[]) as subscriber_id, unnest($2::text[]) as tag
+        on conflict do nothing
+        "#,
+        sub_ids,
+        &tags as &[&str]
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sequences::enrol_tagged(&mut *transaction, sub_ids, &tags).await
+}
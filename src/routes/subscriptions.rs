@@ -1,47 +1,174 @@
-use crate::domain::NewSubscriber;
+use crate::authorization::DEFAULT_LIST_ID;
+use crate::config::SpamTrapConfig;
+use crate::deliverability::{Deliverability, DeliverabilityError};
+use crate::domain::{
+    NewSubscriber, NewSubscriberError, SubscriberAttributes, SubscriberAttributesError,
+    SubscriberTag, SubscriberTagError,
+};
 use crate::email_client::EmailClient;
+use crate::error::error_chain_fmt;
+use crate::extract::FormOrJson;
+use crate::localisation::{requested_locales, Translations};
+use crate::problem::{FieldError, Problem};
+use crate::rate_limit::{RateLimited, RateLimiter, Scope};
+use crate::routes::{get_list_fields, tag_subscribers};
 use crate::startup::AppBaseUrl;
 use actix_http::StatusCode;
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use chrono::Utc;
+use fluent_bundle::FluentArgs;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_aux::field_attributes::{
+    deserialize_bool_from_anything, deserialize_option_number_from_string,
+};
 use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct FormData {
-    email: String,
-    name: String,
+    pub(crate) email: String,
+    pub(crate) name: String,
+    /// List to subscribe to, the default list if left out.
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = "uuid")]
+    pub(crate) list_id: Option<Uuid>,
+    /// Language of the emails and pages, like `de` or `pt-BR`. Taken from
+    /// `Accept-Language` if left out.
+    #[serde(default)]
+    pub(crate) locale: Option<String>,
+    /// Values of the custom fields of the list, by key. Forms send them as
+    /// `attributes.<key>` fields instead.
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub(crate) attributes: HashMap<String, serde_json::Value>,
+    /// Labels for the subscriber, like `beta-tester`. Forms send them as one
+    /// comma separated field, usually a hidden one.
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    #[schema(value_type = Vec<String>)]
+    pub(crate) tags: Vec<String>,
+    /// Honeypot, hidden from humans by the form. Bots tend to fill it in.
+    #[serde(default)]
+    website: Option<String>,
+    /// Unix timestamp of when the form was rendered.
+    // Fields next to a flattened one get their values as strings from forms.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    form_rendered_at: Option<i64>,
+    /// Set when resubmitting after the typo suggestion was declined.
+    #[serde(default, deserialize_with = "deserialize_bool_from_anything")]
+    pub(crate) skip_typo_check: bool,
+    /// Everything else, where forms keep their `attributes.<key>` fields.
+    #[serde(flatten)]
+    #[schema(ignore)]
+    other_fields: HashMap<String, serde_json::Value>,
+}
+
+/// Body of successful responses about a subscription.
+#[derive(Serialize, ToSchema)]
+pub struct SubscriptionStatus {
+    #[schema(value_type = String, format = "uuid")]
+    pub subscriber_id: Uuid,
+    /// `pending` until the subscriber follows the confirmation link.
+    #[schema(example = "pending")]
+    pub status: &'static str,
+}
+
+/// A list of tags, or one string of comma separated tags as forms send them.
+fn deserialize_tags<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tags {
+        List(Vec<String>),
+        Joined(String),
+    }
+    Ok(match Tags::deserialize(deserializer)? {
+        Tags::List(tags) => tags,
+        Tags::Joined(tags) => tags.split(',').map(str::to_string).collect(),
+    })
+}
+
+impl FormData {
+    /// Custom attributes from either the `attributes` object or the
+    /// `attributes.<key>` fields of forms.
+    fn take_attributes(&mut self) -> HashMap<String, serde_json::Value> {
+        let mut attributes = std::mem::take(&mut self.attributes);
+        for (field, value) in std::mem::take(&mut self.other_fields) {
+            if let Some(key) = field.strip_prefix("attributes.") {
+                attributes.entry(key.to_string()).or_insert(value);
+            }
+        }
+        attributes
+    }
+
+    fn is_spam(&self, spam_trap: &SpamTrapConfig) -> bool {
+        let honeypot_filled = self.website.as_deref().is_some_and(|w| !w.is_empty());
+        let filled_too_fast = spam_trap.min_form_fill_seconds > 0
+            && self.form_rendered_at.is_some_and(|rendered_at| {
+                Utc::now().timestamp() - rendered_at < spam_trap.min_form_fill_seconds as i64
+            });
+        honeypot_filled || filled_too_fast
+    }
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = NewSubscriberError;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
         NewSubscriber::new(value.name, value.email)
     }
 }
 
-fn error_chain_fmt(
-    e: &impl std::error::Error,
-    f: &mut std::fmt::Formatter<'_>,
-) -> std::fmt::Result {
-    writeln!(f, "\n{}", e)?;
-    let mut current = e.source();
-    while let Some(cause) = current {
-        writeln!(f, "caused by:\t{}", cause)?;
-        current = cause.source();
+pub(crate) fn field_errors(error: &NewSubscriberError) -> Vec<FieldError> {
+    let name = error
+        .name
+        .as_ref()
+        .map(|e| FieldError::new("name", e.code(), e.to_string()));
+    let email = error
+        .email
+        .as_ref()
+        .map(|e| FieldError::new("email", e.code(), e.to_string()));
+    name.into_iter().chain(email).collect()
+}
+
+pub(crate) fn attribute_field_errors(error: &SubscriberAttributesError) -> Vec<FieldError> {
+    error
+        .0
+        .iter()
+        .map(|e| {
+            let field = format!("attributes.{}", e.key());
+            FieldError::new(field, e.code(), e.to_string())
+        })
+        .collect()
+}
+
+fn deliverability_field_error(error: &DeliverabilityError) -> FieldError {
+    let field_error = FieldError::new("email", error.code(), error.to_string());
+    match error {
+        DeliverabilityError::PossibleTypo { suggestion } => field_error.suggestion(suggestion),
+        DeliverabilityError::DisposableDomain(_) | DeliverabilityError::NoMailServer(_) => {
+            field_error
+        }
     }
-    Ok(())
 }
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
+    #[error("Invalid subscriber data")]
+    ValidationError(#[source] NewSubscriberError),
+    #[error("The email address is unlikely to receive mail")]
+    UndeliverableEmail(#[source] DeliverabilityError),
+    #[error("Invalid custom attributes")]
+    InvalidAttributes(#[source] SubscriberAttributesError),
+    #[error("Invalid tags")]
+    InvalidTags(#[source] SubscriberTagError),
+    #[error("Unknown list {0}")]
+    UnknownList(Uuid),
     #[error("Faild to acquire a Postgres connection from the pool")]
     PoolError(#[source] sqlx::Error),
+    #[error("Failed to look up the fields of the list")]
+    FieldsLookupError(#[source] sqlx::Error),
     #[error("Faild to insert new sub")]
     InsertSubError(#[source] sqlx::Error),
     #[error("Faild to store sub token")]
@@ -50,6 +177,8 @@ pub enum SubscribeError {
     TransactionCommitError(#[source] sqlx::Error),
     #[error("Faild to send a confirmation email")]
     SendEmailError(#[source] reqwest::Error),
+    #[error(transparent)]
+    RateLimitError(#[from] RateLimited),
 }
 
 impl std::fmt::Debug for SubscribeError {
@@ -58,40 +187,190 @@ impl std::fmt::Debug for SubscribeError {
     }
 }
 
+impl SubscribeError {
+    /// What is wrong with the fields of the form, if that's the problem.
+    pub(crate) fn field_errors(&self) -> Vec<FieldError> {
+        match self {
+            SubscribeError::ValidationError(e) => field_errors(e),
+            SubscribeError::UndeliverableEmail(e) => vec![deliverability_field_error(e)],
+            SubscribeError::InvalidAttributes(e) => attribute_field_errors(e),
+            SubscribeError::InvalidTags(e) => {
+                vec![FieldError::new("tags", e.code(), e.to_string())]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> actix_http::StatusCode {
         match self {
-            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::ValidationError(_)
+            | SubscribeError::UndeliverableEmail(_)
+            | SubscribeError::InvalidAttributes(_)
+            | SubscribeError::InvalidTags(_)
+            | SubscribeError::UnknownList(_) => StatusCode::BAD_REQUEST,
             SubscribeError::PoolError(_)
+            | SubscribeError::FieldsLookupError(_)
             | SubscribeError::InsertSubError(_)
             | SubscribeError::StoreTokenError(_)
             | SubscribeError::TransactionCommitError(_)
             | SubscribeError::SendEmailError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            SubscribeError::RateLimitError(e) => e.status_code(),
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let problem = match self {
+            SubscribeError::ValidationError(_)
+            | SubscribeError::UndeliverableEmail(_)
+            | SubscribeError::InvalidAttributes(_)
+            | SubscribeError::InvalidTags(_) => {
+                Problem::new(self.status_code(), "validation_error")
+                    .detail(self.to_string())
+                    .field_errors(self.field_errors())
+            }
+            SubscribeError::UnknownList(_) => {
+                Problem::new(self.status_code(), "unknown_list").detail(self.to_string())
+            }
+            SubscribeError::RateLimitError(e) => return e.error_response(),
+            SubscribeError::PoolError(_)
+            | SubscribeError::FieldsLookupError(_)
+            | SubscribeError::InsertSubError(_)
+            | SubscribeError::StoreTokenError(_)
+            | SubscribeError::TransactionCommitError(_)
+            | SubscribeError::SendEmailError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "subscriptions",
+    summary = "Subscribe to the newsletter",
+    description = "Sends a confirmation email to the address. The body may be JSON or a form.",
+    request_body(content(
+        (FormData = "application/json"),
+        (FormData = "application/x-www-form-urlencoded"),
+    )),
+    responses(
+        (status = 200, description = "Pending confirmation", body = SubscriptionStatus),
+        (status = 400, response = Problem),
+        (status = 429, response = Problem),
+    )
+)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, connection_pool, email_client),
+    skip_all,
     fields (
         subscriber_email = %form.email,
         subscriber_name = %form.name,
     )
 )]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    req: HttpRequest,
+    form: FormOrJson<FormData>,
     connection_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<AppBaseUrl>,
+    translations: web::Data<Translations>,
+    rate_limiter: web::Data<RateLimiter>,
+    spam_trap: web::Data<SpamTrapConfig>,
+    deliverability: web::Data<Deliverability>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_sub = NewSubscriber::try_from(form.0).map_err(SubscribeError::ValidationError)?;
+    let locale = translations.negotiate(&requested_locales(form.locale.as_deref(), &req));
+    let sub_id = create_subscription(
+        form.into_inner(),
+        &locale,
+        &connection_pool,
+        &email_client,
+        &base_url,
+        &translations,
+        &rate_limiter,
+        &spam_trap,
+        &deliverability,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(SubscriptionStatus {
+        subscriber_id: sub_id,
+        status: "pending",
+    }))
+}
+
+/// Validates the form, stores the pending subscriber and sends the
+/// confirmation email in `locale`. Shared by the API and the hosted
+/// subscribe page.
+///
+/// Submissions caught by the spam trap get a made up id so that bots don't
+/// learn about the trap. Neither do addresses already on the list stand out:
+/// confirmed ones get a made up id as well, while pending and unsubscribed
+/// ones get a new confirmation email.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn create_subscription(
+    mut form: FormData,
+    locale: &str,
+    connection_pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &AppBaseUrl,
+    translations: &Translations,
+    rate_limiter: &RateLimiter,
+    spam_trap: &SpamTrapConfig,
+    deliverability: &Deliverability,
+) -> Result<Uuid, SubscribeError> {
+    if form.is_spam(spam_trap) {
+        tracing::warn!("Dropping a subscription caught by the spam trap");
+        return Ok(Uuid::new_v4());
+    }
+    let skip_typo_check = form.skip_typo_check;
+    let list_id = form.list_id.unwrap_or(DEFAULT_LIST_ID);
+    let raw_attributes = form.take_attributes();
+    let tags = SubscriberTag::parse_all(std::mem::take(&mut form.tags))
+        .map_err(SubscribeError::InvalidTags)?;
+    let new_sub = NewSubscriber::try_from(form).map_err(SubscribeError::ValidationError)?;
+    // Before the deliverability check, which looks up the domain.
+    rate_limiter
+        .check(
+            "subscriptions",
+            Scope::Email,
+            &new_sub.email.as_ref().to_lowercase(),
+        )
+        .await?;
+    let fields = get_list_fields(connection_pool, list_id)
+        .await
+        .map_err(SubscribeError::FieldsLookupError)?;
+    let attributes = SubscriberAttributes::parse(&fields, &raw_attributes)
+        .map_err(SubscribeError::InvalidAttributes)?;
+    deliverability
+        .check(&new_sub.email, skip_typo_check)
+        .await
+        .map_err(SubscribeError::UndeliverableEmail)?;
     let mut transaction = connection_pool
         .begin()
         .await
         .map_err(SubscribeError::PoolError)?;
 
-    let sub_id = insert_subscriber(&mut transaction, &new_sub).await?;
+    let sub_id = match find_subscriber(&mut transaction, &new_sub, list_id).await? {
+        Some(existing) if existing.status == "confirmed" => return Ok(Uuid::new_v4()),
+        Some(existing) => {
+            reset_to_pending(&mut transaction, existing.id).await?;
+            existing.id
+        }
+        None => {
+            let inserted =
+                insert_subscriber(&mut transaction, &new_sub, list_id, locale, attributes).await?;
+            // Someone else subscribed the address in the meantime.
+            let Some(sub_id) = inserted else {
+                return Ok(Uuid::new_v4());
+            };
+            tag_subscribers(&mut transaction, &[sub_id], &tags)
+                .await
+                .map_err(SubscribeError::InsertSubError)?;
+            sub_id
+        }
+    };
     let sub_token = generate_sub_token();
 
     store_token(&mut transaction, sub_id, &sub_token).await?;
@@ -101,11 +380,56 @@ pub async fn subscribe(
         .await
         .map_err(SubscribeError::TransactionCommitError)?;
 
-    send_confirm_email(&email_client, new_sub, &base_url, &sub_token).await?;
+    send_confirm_email(email_client, new_sub, base_url, translations, locale, &sub_token).await?;
+
+    Ok(sub_id)
+}
 
-    Ok(HttpResponse::Ok().finish())
+struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
 }
 
+#[tracing::instrument(
+    name = "Looking for the address on the list",
+    skip(connection, new_sub)
+)]
+async fn find_subscriber(
+    connection: &mut Transaction<'_, Postgres>,
+    new_sub: &NewSubscriber,
+    list_id: Uuid,
+) -> Result<Option<ExistingSubscriber>, SubscribeError> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"
+        select id, status from subscriptions
+        where list_id = $1 and lower(email) = lower($2)
+        for update
+        "#,
+        list_id,
+        new_sub.email.as_ref()
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(SubscribeError::InsertSubError)
+}
+
+/// Lets an unsubscribed address subscribe again once it confirms.
+async fn reset_to_pending(
+    connection: &mut Transaction<'_, Postgres>,
+    sub_id: Uuid,
+) -> Result<(), SubscribeError> {
+    sqlx::query!(
+        "update subscriptions set status = 'pending' where id = $1 and status = 'unsubscribed'",
+        sub_id
+    )
+    .execute(connection)
+    .await
+    .map_err(SubscribeError::InsertSubError)?;
+    Ok(())
+}
+
+/// Stores the pending subscriber, `None` if the address is already on the list.
 #[tracing::instrument(
     name = "Inserting subscriber data in database",
     skip(connection, new_sub)
@@ -113,25 +437,41 @@ pub async fn subscribe(
 pub async fn insert_subscriber(
     connection: &mut Transaction<'_, Postgres>,
     new_sub: &NewSubscriber,
-) -> Result<Uuid, SubscribeError> {
+    list_id: Uuid,
+    locale: &str,
+    attributes: SubscriberAttributes,
+) -> Result<Option<Uuid>, SubscribeError> {
     let sub_id = Uuid::new_v4();
-    sqlx::query!(
+    let result = sqlx::query!(
         r#"
-        insert into subscriptions (id, email, name, subscribed_at, status)
-        values ($1, $2, $3, $4, 'pending')
+        insert into subscriptions (
+            id, email, name, subscribed_at, status, list_id, locale, attributes
+        )
+        values ($1, $2, $3, $4, 'pending', $5, $6, $7)
         "#,
         sub_id,
         new_sub.email.as_ref(),
         new_sub.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        list_id,
+        locale,
+        attributes.into_json()
     )
     .execute(connection)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        SubscribeError::InsertSubError(e)
-    })?;
-    Ok(sub_id)
+    .await;
+    match result {
+        Ok(_) => Ok(Some(sub_id)),
+        Err(sqlx::Error::Database(db_error)) if db_error.code().as_deref() == Some("23505") => {
+            Ok(None)
+        }
+        Err(sqlx::Error::Database(db_error)) if db_error.code().as_deref() == Some("23503") => {
+            Err(SubscribeError::UnknownList(list_id))
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            Err(SubscribeError::InsertSubError(e))
+        }
+    }
 }
 
 #[tracing::instrument(
@@ -159,25 +499,30 @@ pub async fn store_token(
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new sub",
-    skip(email_client, new_sub, base_url)
+    skip(email_client, new_sub, base_url, translations, token)
 )]
 pub async fn send_confirm_email(
     email_client: &EmailClient,
     new_sub: NewSubscriber,
     base_url: &AppBaseUrl,
+    translations: &Translations,
+    locale: &str,
     token: &str,
 ) -> Result<(), SubscribeError> {
-    let confirm_link = format!("{}/subscriptions/confirm?sub_token={}", base_url.0, token);
-    let text_body = format!("visit {} to confirm", confirm_link);
-    let htmp_body = format!("visit <a href=\"{}\">this</a> to confirm", confirm_link);
+    let confirm_link = format!("{}/confirm?sub_token={}", base_url.0, token);
+    let mut args = FluentArgs::new();
+    args.set("link", confirm_link);
+    let subject = translations.message(locale, "confirm-email-subject", None);
+    let text_body = translations.message(locale, "confirm-email-text", Some(&args));
+    let htmp_body = translations.message(locale, "confirm-email-html", Some(&args));
     email_client
-        .send_email(new_sub.email, "subject", &htmp_body, &text_body)
+        .send_email(new_sub.email, &subject, &htmp_body, &text_body)
         .await
         .map_err(SubscribeError::SendEmailError)?;
     Ok(())
 }
 
-fn generate_sub_token() -> String {
+pub(crate) fn generate_sub_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
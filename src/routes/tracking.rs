@@ -0,0 +1,77 @@
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use crate::tracking::{find_link, record_engagement, Engagement};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpResponse, ResponseError};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Transparent 1x1 GIF.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00,
+    0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(thiserror::Error)]
+pub enum TrackingError {
+    #[error("Unknown link")]
+    UnknownLink,
+    #[error("Failed to record engagement")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for TrackingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TrackingError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TrackingError::UnknownLink => StatusCode::NOT_FOUND,
+            TrackingError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            TrackingError::UnknownLink => Problem::new(self.status_code(), "not_found")
+                .detail(self.to_string())
+                .error_response(),
+            TrackingError::UnexpectedError(_) => Problem::internal().error_response(),
+        }
+    }
+}
+
+/// The open pixel of an issue. Unknown tokens get the pixel as well, a
+/// broken image in an old email helps no one.
+#[tracing::instrument(name = "Record an open", skip(connection_pool, path))]
+pub async fn open_pixel(
+    connection_pool: web::Data<PgPool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, TrackingError> {
+    record_engagement(connection_pool.get_ref(), &path, Engagement::Opened).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(PIXEL))
+}
+
+/// Redirects to a link of an issue, recording the click.
+#[tracing::instrument(name = "Record a click", skip(connection_pool, path))]
+pub async fn follow_link(
+    connection_pool: web::Data<PgPool>,
+    path: web::Path<(String, Uuid)>,
+) -> Result<HttpResponse, TrackingError> {
+    let (tracking_token, link_id) = path.into_inner();
+    let url = find_link(connection_pool.get_ref(), &tracking_token, link_id)
+        .await?
+        .ok_or(TrackingError::UnknownLink)?;
+    record_engagement(connection_pool.get_ref(), &tracking_token, Engagement::Clicked).await?;
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .finish())
+}
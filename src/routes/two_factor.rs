@@ -0,0 +1,272 @@
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authentication::two_factor;
+use crate::authentication::{login, AuthError, TwoFactorPolicy};
+use crate::authorization::{AuthenticatedUser, Permission};
+use crate::config::TwoFactorConfig;
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+#[derive(thiserror::Error)]
+pub enum TwoFactorError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to update two-factor settings")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for TwoFactorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TwoFactorError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TwoFactorError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            TwoFactorError::Conflict(_) => StatusCode::CONFLICT,
+            TwoFactorError::AuthError(e) => e.status_code(),
+            TwoFactorError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            TwoFactorError::AuthError(e) => return e.error_response(),
+            TwoFactorError::ValidationError(message) => {
+                Problem::new(status, "validation_error").detail(message.clone())
+            }
+            TwoFactorError::Conflict(message) => {
+                Problem::new(status, "conflict").detail(message.clone())
+            }
+            TwoFactorError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct EnrolmentResponse {
+    /// Base32 encoded TOTP secret.
+    secret: String,
+    /// The secret as a URI for authenticator apps, usually shown as QR code.
+    otpauth_uri: String,
+}
+
+/// Generates a new pending TOTP secret. Allowed with the password alone, so
+/// that users can enrol when two-factor authentication becomes required.
+#[utoipa::path(
+    post,
+    path = "/admin/two_factor/enrol",
+    tag = "two_factor",
+    summary = "Start enrolling in two-factor authentication",
+    responses(
+        (status = 200, description = "Pending secret", body = EnrolmentResponse),
+        (status = 401, response = Problem),
+        (status = 409, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Begin two-factor enrolment",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn begin_two_factor_enrolment(
+    pool: web::Data<PgPool>,
+    config: web::Data<TwoFactorConfig>,
+    request: HttpRequest,
+) -> Result<HttpResponse, TwoFactorError> {
+    let user_id = login(&request, TwoFactorPolicy::AllowEnrolment).await?;
+    if two_factor::two_factor_state(&pool, user_id).await?.enabled {
+        return Err(TwoFactorError::Conflict(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+    let username = sqlx::query!("select username from users where user_id = $1", user_id)
+        .fetch_one(pool.get_ref())
+        .await?
+        .username;
+    let secret = two_factor::begin_enrolment(&pool, user_id).await?;
+    Ok(HttpResponse::Ok().json(EnrolmentResponse {
+        otpauth_uri: secret.otpauth_uri(&config.issuer, &username),
+        secret: secret.as_base32().to_string(),
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CodeData {
+    /// Current code of the authenticator app.
+    code: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+/// Enables two-factor authentication once the user proves their
+/// authenticator app produces the right codes. The response carries the
+/// recovery codes, which are not retrievable afterwards.
+#[utoipa::path(
+    post,
+    path = "/admin/two_factor/confirm",
+    tag = "two_factor",
+    summary = "Finish enrolling in two-factor authentication",
+    request_body = CodeData,
+    responses(
+        (status = 200, description = "Enabled", body = RecoveryCodesResponse),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Confirm two-factor enrolment",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn confirm_two_factor_enrolment(
+    body: web::Json<CodeData>,
+    pool: web::Data<PgPool>,
+    config: web::Data<TwoFactorConfig>,
+    request: HttpRequest,
+) -> Result<HttpResponse, TwoFactorError> {
+    let user_id = login(&request, TwoFactorPolicy::AllowEnrolment).await?;
+    match two_factor::confirm_enrolment(&pool, user_id, &body.code, &config).await? {
+        Some(recovery_codes) => {
+            AuditEvent::from_request(&request, AuditAction::TwoFactorEnabled)
+                .actor(user_id)
+                .target("user", user_id)
+                .record(pool.get_ref())
+                .await?;
+            Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+        }
+        None => Err(TwoFactorError::ValidationError(
+            "The code does not match a pending enrolment".to_string(),
+        )),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/two_factor/recovery_codes",
+    tag = "two_factor",
+    summary = "Replace the recovery codes",
+    responses(
+        (status = 200, description = "New recovery codes", body = RecoveryCodesResponse),
+        (status = 401, response = Problem),
+        (status = 409, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Regenerate recovery codes",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn regenerate_recovery_codes(
+    pool: web::Data<PgPool>,
+    config: web::Data<TwoFactorConfig>,
+    request: HttpRequest,
+) -> Result<HttpResponse, TwoFactorError> {
+    let user_id = login(&request, TwoFactorPolicy::Enforce).await?;
+    if !two_factor::two_factor_state(&pool, user_id).await?.enabled {
+        return Err(TwoFactorError::Conflict(
+            "Two-factor authentication is not enabled".to_string(),
+        ));
+    }
+    let recovery_codes = two_factor::regenerate_recovery_codes(&pool, user_id, &config).await?;
+    AuditEvent::from_request(&request, AuditAction::RecoveryCodesRegenerated)
+        .actor(user_id)
+        .target("user", user_id)
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/two_factor/disable",
+    tag = "two_factor",
+    summary = "Disable two-factor authentication",
+    responses(
+        (status = 200, description = "Disabled"),
+        (status = 401, response = Problem),
+        (status = 409, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Disable two-factor authentication",
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn disable_two_factor(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, TwoFactorError> {
+    let user_id = login(&request, TwoFactorPolicy::Enforce).await?;
+    if two_factor::two_factor_required(&pool).await? {
+        return Err(TwoFactorError::Conflict(
+            "Two-factor authentication is required for all admins".to_string(),
+        ));
+    }
+    two_factor::disable(&pool, user_id).await?;
+    AuditEvent::from_request(&request, AuditAction::TwoFactorDisabled)
+        .actor(user_id)
+        .target("user", user_id)
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TwoFactorRequirement {
+    required: bool,
+}
+
+/// Requires two-factor authentication from every admin. Admins that haven't
+/// enrolled yet can only reach the enrolment endpoints until they do.
+#[utoipa::path(
+    put,
+    path = "/admin/settings/two_factor",
+    tag = "two_factor",
+    summary = "Require two-factor authentication from all admins",
+    request_body = TwoFactorRequirement,
+    responses(
+        (status = 200, description = "Setting changed"),
+        (status = 400, response = Problem),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(
+    name = "Set two-factor requirement",
+    skip(pool, user),
+    fields(user_id = %user.user_id)
+)]
+pub async fn set_two_factor_requirement(
+    user: AuthenticatedUser,
+    body: web::Json<TwoFactorRequirement>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, TwoFactorError> {
+    user.require(&pool, Permission::ManageSettings, None).await?;
+    two_factor::set_two_factor_required(&pool, body.required).await?;
+    AuditEvent::by(&user, AuditAction::SettingsChanged)
+        .payload(serde_json::json!({ "require_two_factor": body.required }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
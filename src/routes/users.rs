@@ -0,0 +1,209 @@
+use crate::audit::{AuditAction, AuditEvent};
+use crate::authentication::AuthError;
+use crate::authorization::{AuthenticatedUser, Permission, Role};
+use crate::error::error_chain_fmt;
+use crate::problem::Problem;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use serde::Deserialize;
+use sqlx::PgPool;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum UserError {
+    #[error("User or list not found")]
+    NotFound,
+    #[error("At least one owner must remain")]
+    LastOwner,
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Failed to update user roles")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+impl std::fmt::Debug for UserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UserError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UserError::NotFound => StatusCode::NOT_FOUND,
+            UserError::LastOwner => StatusCode::CONFLICT,
+            UserError::AuthError(e) => e.status_code(),
+            UserError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let problem = match self {
+            UserError::AuthError(e) => return e.error_response(),
+            UserError::NotFound => Problem::new(status, "not_found").detail(self.to_string()),
+            UserError::LastOwner => Problem::new(status, "last_owner").detail(self.to_string()),
+            UserError::UnexpectedError(_) => Problem::internal(),
+        };
+        problem.error_response()
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RoleData {
+    role: Role,
+}
+
+/// Changes the role a user has on every list.
+#[utoipa::path(
+    put,
+    path = "/admin/users/{user_id}/role",
+    tag = "users",
+    summary = "Set the role of a user",
+    params(("user_id" = String, Path, format = "uuid")),
+    request_body = RoleData,
+    responses(
+        (status = 200, description = "Role changed"),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+        (status = 409, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(name = "Set user role", skip(user, pool), fields(user_id = %user.user_id))]
+pub async fn set_user_role(
+    user: AuthenticatedUser,
+    target: web::Path<Uuid>,
+    body: web::Json<RoleData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UserError> {
+    user.require(&pool, Permission::ManageUsers, None).await?;
+    let target = target.into_inner();
+    let mut transaction = pool.begin().await?;
+    // Locking the owners serialises concurrent demotions.
+    let owners: Vec<Uuid> =
+        sqlx::query!("select user_id from users where role = 'owner' for update")
+            .fetch_all(&mut transaction)
+            .await?
+            .into_iter()
+            .map(|r| r.user_id)
+            .collect();
+    if body.role != Role::Owner && owners == [target] {
+        return Err(UserError::LastOwner);
+    }
+    let updated = sqlx::query!(
+        "update users set role = $2 where user_id = $1",
+        target,
+        body.role.as_str()
+    )
+    .execute(&mut transaction)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Err(UserError::NotFound);
+    }
+    AuditEvent::by(&user, AuditAction::UserRoleChanged)
+        .target("user", target)
+        .payload(serde_json::json!({ "role": body.role }))
+        .record(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Grants a user a role on a single list, on top of their global role.
+#[utoipa::path(
+    put,
+    path = "/admin/users/{user_id}/lists/{list_id}",
+    tag = "users",
+    summary = "Grant a user a role on a list",
+    params(
+        ("user_id" = String, Path, format = "uuid"),
+        ("list_id" = String, Path, format = "uuid"),
+    ),
+    request_body = RoleData,
+    responses(
+        (status = 200, description = "Role granted"),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(name = "Grant list role", skip(user, pool), fields(user_id = %user.user_id))]
+pub async fn grant_list_role(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    body: web::Json<RoleData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UserError> {
+    user.require(&pool, Permission::ManageUsers, None).await?;
+    let (target, list_id) = path.into_inner();
+    let result = sqlx::query!(
+        r#"
+        insert into list_roles (user_id, list_id, role)
+        select u.user_id, l.list_id, $3
+        from users u, lists l
+        where u.user_id = $1 and l.list_id = $2
+        on conflict (user_id, list_id) do update set role = excluded.role
+        "#,
+        target,
+        list_id,
+        body.role.as_str()
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(UserError::NotFound);
+    }
+    AuditEvent::by(&user, AuditAction::ListRoleGranted)
+        .target("user", target)
+        .payload(serde_json::json!({ "list_id": list_id, "role": body.role }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{user_id}/lists/{list_id}",
+    tag = "users",
+    summary = "Revoke the role of a user on a list",
+    params(
+        ("user_id" = String, Path, format = "uuid"),
+        ("list_id" = String, Path, format = "uuid"),
+    ),
+    responses(
+        (status = 204, description = "Role revoked"),
+        (status = 401, response = Problem),
+        (status = 403, response = Problem),
+        (status = 404, response = Problem),
+    ),
+    security(("basic" = []))
+)]
+#[tracing::instrument(name = "Revoke list role", skip(user, pool), fields(user_id = %user.user_id))]
+pub async fn revoke_list_role(
+    user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UserError> {
+    user.require(&pool, Permission::ManageUsers, None).await?;
+    let (target, list_id) = path.into_inner();
+    let result = sqlx::query!(
+        "delete from list_roles where user_id = $1 and list_id = $2",
+        target,
+        list_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(UserError::NotFound);
+    }
+    AuditEvent::by(&user, AuditAction::ListRoleRevoked)
+        .target("user", target)
+        .payload(serde_json::json!({ "list_id": list_id }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
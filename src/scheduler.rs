@@ -0,0 +1,37 @@
+use crate::{ab_tests, sequences};
+use sqlx::PgPool;
+use tokio::sync::watch;
+
+/// Every `poll_interval` until `shutdown` flips to `true`, queues the steps
+/// of sequences that are due and the winners of A/B tests whose window is
+/// over.
+pub async fn run_scheduler_until_stopped(
+    pool: PgPool,
+    poll_interval: std::time::Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        if *shutdown.borrow() {
+            break;
+        }
+        let mut more_due = false;
+        match sequences::advance_due_enrolments(&pool).await {
+            Ok(advanced) => more_due |= advanced as i64 == sequences::BATCH_SIZE,
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, "Failed to schedule sequence steps");
+            }
+        }
+        match ab_tests::decide_due_tests(&pool).await {
+            Ok(decided) => more_due |= decided as i64 == ab_tests::BATCH_SIZE,
+            Err(e) => tracing::error!(error.cause_chain = ?e, "Failed to decide A/B tests"),
+        }
+        if more_due {
+            continue;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(poll_interval) => {}
+            _ = shutdown.changed() => {}
+        }
+    }
+    tracing::info!("Scheduler stopped");
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Deserializer};
+use zeroize::Zeroize;
+
+/// String that holds a credential. It is never printed by `Debug` or `Display`
+/// and its memory is wiped when dropped. Use `expose_secret` at the point where
+/// the raw value is actually needed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_is_redacted() {
+        let secret = Secret::new("hunter2".to_string());
+        assert!(!format!("{:?}", secret).contains("hunter2"));
+    }
+
+    #[test]
+    fn display_is_redacted() {
+        let secret = Secret::new("hunter2".to_string());
+        assert!(!format!("{}", secret).contains("hunter2"));
+    }
+
+    #[test]
+    fn exposes_inner_value() {
+        let secret = Secret::new("hunter2".to_string());
+        assert_eq!(secret.expose_secret(), "hunter2");
+    }
+}
@@ -0,0 +1,399 @@
+use crate::domain::{AttributeError, CustomField, FieldType, SubscriberTag, SubscriberTagError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::postgres::PgArguments;
+use sqlx::query::Query;
+use sqlx::Postgres;
+use uuid::Uuid;
+
+const MAX_RULES: usize = 100;
+const MAX_DEPTH: usize = 8;
+const MAX_WINDOW_DAYS: u32 = 3650;
+
+/// A tree of conditions on the subscribers of a list, e.g.
+///
+/// ```json
+/// {"all": [
+///     {"attribute": {"key": "plan", "op": "eq", "value": "pro"}},
+///     {"not": {"opened": {"within_days": 90}}}
+/// ]}
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    /// Every rule holds, true if there are none.
+    All(Vec<Rule>),
+    /// Some rule holds, false if there are none.
+    Any(Vec<Rule>),
+    Not(Box<Rule>),
+    /// Compares a custom attribute, see [`Comparison`].
+    Attribute {
+        key: String,
+        op: Comparison,
+        #[serde(default, skip_serializing_if = "Value::is_null")]
+        value: Value,
+    },
+    /// The subscriber has this tag.
+    Tag(String),
+    /// The subscriber has also confirmed a subscription to this list.
+    List(Uuid),
+    SubscribedAfter(DateTime<Utc>),
+    SubscribedBefore(DateTime<Utc>),
+    /// The subscriber opened an issue in the last `within_days` days.
+    Opened { within_days: u32 },
+    /// The subscriber clicked a link in an issue in the last `within_days`
+    /// days.
+    Clicked { within_days: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Eq,
+    /// Also true when the subscriber has no value.
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    /// The subscriber has a value, whatever it is. Takes no `value`.
+    Exists,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RuleError {
+    #[error("{0} is not a field of this list")]
+    UnknownField(String),
+    #[error(transparent)]
+    InvalidValue(AttributeError),
+    #[error("{0} has no order, only eq, ne and exists apply")]
+    Unordered(String),
+    #[error("exists takes no value, {0} has one")]
+    UnexpectedValue(String),
+    #[error(transparent)]
+    InvalidTag(SubscriberTagError),
+    #[error("within_days must be between 1 and {MAX_WINDOW_DAYS}")]
+    InvalidWindow,
+    #[error("segments may have at most {MAX_RULES} rules, nested at most {MAX_DEPTH} deep")]
+    TooComplex,
+}
+
+/// A value bound to a placeholder of a [`SqlFilter`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bind {
+    Text(String),
+    Uuid(Uuid),
+    Json(Value),
+    Int(i32),
+    Timestamp(DateTime<Utc>),
+}
+
+impl Bind {
+    pub fn bind_to<'q>(
+        self,
+        query: Query<'q, Postgres, PgArguments>,
+    ) -> Query<'q, Postgres, PgArguments> {
+        match self {
+            Bind::Text(value) => query.bind(value),
+            Bind::Uuid(value) => query.bind(value),
+            Bind::Json(value) => query.bind(value),
+            Bind::Int(value) => query.bind(value),
+            Bind::Timestamp(value) => query.bind(value),
+        }
+    }
+}
+
+/// A boolean SQL expression over the subscriptions aliased `s`, with
+/// numbered placeholders for `binds`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlFilter {
+    pub sql: String,
+    pub binds: Vec<Bind>,
+}
+
+impl SqlFilter {
+    pub fn bind_to<'q>(
+        self,
+        query: Query<'q, Postgres, PgArguments>,
+    ) -> Query<'q, Postgres, PgArguments> {
+        self.binds
+            .into_iter()
+            .fold(query, |query, bind| bind.bind_to(query))
+    }
+}
+
+impl Rule {
+    /// The rule with attribute values stored the way [`CustomField::coerce`]
+    /// stores them, so that they compare equal to those of subscribers, and
+    /// the lists it refers to.
+    pub fn check(self, fields: &[CustomField]) -> Result<(Rule, Vec<Uuid>), RuleError> {
+        let mut count = 0;
+        let rule = self.check_nested(fields, 1, &mut count)?;
+        let lists = rule.lists();
+        Ok((rule, lists))
+    }
+
+    /// The lists of the [`Rule::List`] rules, each once.
+    pub fn lists(&self) -> Vec<Uuid> {
+        let mut lists = Vec::new();
+        self.collect_lists(&mut lists);
+        lists
+    }
+
+    fn collect_lists(&self, lists: &mut Vec<Uuid>) {
+        match self {
+            Rule::All(rules) | Rule::Any(rules) => {
+                rules.iter().for_each(|rule| rule.collect_lists(lists))
+            }
+            Rule::Not(rule) => rule.collect_lists(lists),
+            Rule::List(list_id) if !lists.contains(list_id) => lists.push(*list_id),
+            _ => {}
+        }
+    }
+
+    fn check_nested(
+        self,
+        fields: &[CustomField],
+        depth: usize,
+        count: &mut usize,
+    ) -> Result<Rule, RuleError> {
+        *count += 1;
+        if depth > MAX_DEPTH || *count > MAX_RULES {
+            return Err(RuleError::TooComplex);
+        }
+        let check_all = |rules: Vec<Rule>, count: &mut usize| {
+            rules
+                .into_iter()
+                .map(|rule| rule.check_nested(fields, depth + 1, count))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let rule = match self {
+            Rule::All(rules) => Rule::All(check_all(rules, count)?),
+            Rule::Any(rules) => Rule::Any(check_all(rules, count)?),
+            Rule::Not(rule) => Rule::Not(Box::new(rule.check_nested(fields, depth + 1, count)?)),
+            Rule::Attribute { key, op, value } => {
+                let field = fields
+                    .iter()
+                    .find(|field| field.key == key)
+                    .ok_or_else(|| RuleError::UnknownField(key.clone()))?;
+                let value = match op {
+                    Comparison::Exists if value.is_null() => Value::Null,
+                    Comparison::Exists => return Err(RuleError::UnexpectedValue(key)),
+                    Comparison::Gt | Comparison::Gte | Comparison::Lt | Comparison::Lte
+                        if matches!(field.field_type, FieldType::Boolean | FieldType::Enum) =>
+                    {
+                        return Err(RuleError::Unordered(key))
+                    }
+                    _ => field.coerce(&value).map_err(RuleError::InvalidValue)?,
+                };
+                Rule::Attribute { key, op, value }
+            }
+            Rule::Tag(tag) => {
+                let tag = SubscriberTag::try_from(tag).map_err(RuleError::InvalidTag)?;
+                Rule::Tag(tag.as_ref().to_string())
+            }
+            Rule::Opened { within_days } | Rule::Clicked { within_days }
+                if within_days == 0 || within_days > MAX_WINDOW_DAYS =>
+            {
+                return Err(RuleError::InvalidWindow)
+            }
+            rule => rule,
+        };
+        Ok(rule)
+    }
+
+    /// The rule as SQL, numbering placeholders from `$first_param`.
+    pub fn to_sql(&self, first_param: usize) -> SqlFilter {
+        let mut filter = SqlFilter {
+            sql: String::new(),
+            binds: Vec::new(),
+        };
+        self.write_sql(first_param, &mut filter);
+        filter
+    }
+
+    fn write_sql(&self, first_param: usize, filter: &mut SqlFilter) {
+        let param = |filter: &mut SqlFilter, bind: Bind| {
+            filter.binds.push(bind);
+            format!("${}", first_param + filter.binds.len() - 1)
+        };
+        match self {
+            Rule::All(rules) | Rule::Any(rules) if rules.is_empty() => {
+                let empty = if matches!(self, Rule::All(_)) { "true" } else { "false" };
+                filter.sql.push_str(empty);
+            }
+            Rule::All(rules) | Rule::Any(rules) => {
+                let separator = if matches!(self, Rule::All(_)) { " and " } else { " or " };
+                filter.sql.push('(');
+                for (i, rule) in rules.iter().enumerate() {
+                    if i > 0 {
+                        filter.sql.push_str(separator);
+                    }
+                    rule.write_sql(first_param, filter);
+                }
+                filter.sql.push(')');
+            }
+            Rule::Not(rule) => {
+                filter.sql.push_str("not (");
+                rule.write_sql(first_param, filter);
+                filter.sql.push(')');
+            }
+            Rule::Attribute { key, op, value } => {
+                let key = param(filter, Bind::Text(key.clone()));
+                if *op == Comparison::Exists {
+                    filter.sql.push_str(&format!("s.attributes ? {}", key));
+                    return;
+                }
+                let value = param(filter, Bind::Json(value.clone()));
+                let op = match op {
+                    Comparison::Eq => "=",
+                    Comparison::Ne => "is distinct from",
+                    Comparison::Gt => ">",
+                    Comparison::Gte => ">=",
+                    Comparison::Lt => "<",
+                    Comparison::Lte => "<=",
+                    Comparison::Exists => unreachable!(),
+                };
+                filter.sql.push_str(&format!("s.attributes -> {} {} {}", key, op, value));
+            }
+            Rule::Tag(tag) => {
+                let tag = param(filter, Bind::Text(tag.clone()));
+                filter.sql.push_str(&format!(
+                    "exists (select 1 from subscriber_tags t where t.subscriber_id = s.id \
+                     and t.tag = {})",
+                    tag
+                ));
+            }
+            Rule::List(list_id) => {
+                let list_id = param(filter, Bind::Uuid(*list_id));
+                filter.sql.push_str(&format!(
+                    "exists (select 1 from subscriptions o where lower(o.email) = lower(s.email) \
+                     and o.list_id = {} and o.status = 'confirmed')",
+                    list_id
+                ));
+            }
+            Rule::SubscribedAfter(at) => {
+                let at = param(filter, Bind::Timestamp(*at));
+                filter.sql.push_str(&format!("s.subscribed_at >= {}", at));
+            }
+            Rule::SubscribedBefore(at) => {
+                let at = param(filter, Bind::Timestamp(*at));
+                filter.sql.push_str(&format!("s.subscribed_at < {}", at));
+            }
+            Rule::Opened { within_days } | Rule::Clicked { within_days } => {
+                let kind = if matches!(self, Rule::Opened { .. }) { "opened" } else { "clicked" };
+                let days = param(filter, Bind::Int(*within_days as i32));
+                filter.sql.push_str(&format!(
+                    "exists (select 1 from engagement_events e where e.subscriber_id = s.id \
+                     and e.kind = '{}' and e.occurred_at >= now() - make_interval(days => {}))",
+                    kind, days
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields() -> Vec<CustomField> {
+        vec![
+            CustomField::new("seats".into(), FieldType::Number, vec![], false).unwrap(),
+            CustomField::new("plan".into(), FieldType::Enum, vec!["pro".into()], false).unwrap(),
+        ]
+    }
+
+    fn rule(value: Value) -> Rule {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn attribute_values_are_stored_like_those_of_subscribers() {
+        let (checked, _) = rule(json!({"attribute": {"key": "seats", "op": "gte", "value": "10"}}))
+            .check(&fields())
+            .unwrap();
+
+        assert_eq!(
+            checked,
+            rule(json!({"attribute": {"key": "seats", "op": "gte", "value": 10.0}}))
+        );
+    }
+
+    #[test]
+    fn rules_are_checked_against_the_fields() {
+        let check = |value| rule(value).check(&fields()).unwrap_err();
+
+        assert_eq!(
+            check(json!({"attribute": {"key": "colour", "op": "eq", "value": "red"}})),
+            RuleError::UnknownField("colour".into())
+        );
+        assert_eq!(
+            check(json!({"attribute": {"key": "plan", "op": "gt", "value": "pro"}})),
+            RuleError::Unordered("plan".into())
+        );
+        assert_eq!(
+            check(json!({"attribute": {"key": "plan", "op": "exists", "value": "pro"}})),
+            RuleError::UnexpectedValue("plan".into())
+        );
+        assert!(matches!(
+            check(json!({"attribute": {"key": "seats", "op": "eq", "value": "many"}})),
+            RuleError::InvalidValue(_)
+        ));
+        assert_eq!(check(json!({"opened": {"within_days": 0}})), RuleError::InvalidWindow);
+        assert!(matches!(check(json!({"tag": "beta tester"})), RuleError::InvalidTag(_)));
+    }
+
+    #[test]
+    fn checked_rules_tell_the_lists_they_refer_to() {
+        let (first, second) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let (_, lists) = rule(json!({"any": [
+            {"list": first},
+            {"not": {"all": [{"list": second}, {"tag": "beta"}]}},
+            {"list": first},
+        ]}))
+        .check(&fields())
+        .unwrap();
+
+        assert_eq!(lists, vec![first, second]);
+    }
+
+    #[test]
+    fn deep_or_large_trees_are_rejected() {
+        let mut deep = json!({"opened": {"within_days": 1}});
+        for _ in 0..MAX_DEPTH {
+            deep = json!({"not": deep});
+        }
+        let large = json!({"any": vec![json!({"opened": {"within_days": 1}}); MAX_RULES]});
+
+        assert_eq!(rule(deep).check(&fields()), Err(RuleError::TooComplex));
+        assert_eq!(rule(large).check(&fields()), Err(RuleError::TooComplex));
+    }
+
+    #[test]
+    fn trees_become_sql_with_numbered_placeholders() {
+        let list_id = Uuid::from_u128(2);
+        let filter = rule(json!({"all": [
+            {"attribute": {"key": "plan", "op": "eq", "value": "pro"}},
+            {"any": []},
+            {"not": {"list": list_id}},
+        ]}))
+        .to_sql(3);
+
+        assert_eq!(
+            filter.sql,
+            "(s.attributes -> $3 = $4 and false and not (exists (select 1 from subscriptions o \
+             where lower(o.email) = lower(s.email) and o.list_id = $5 and o.status = 'confirmed')))"
+        );
+        assert_eq!(
+            filter.binds,
+            vec![
+                Bind::Text("plan".into()),
+                Bind::Json(json!("pro")),
+                Bind::Uuid(list_id)
+            ]
+        );
+    }
+}
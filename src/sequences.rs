@@ -0,0 +1,269 @@
+use crate::domain::{SubscriberTag, SubscriberTagError};
+use crate::segments::Rule;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Enrolments moved along per transaction of the scheduler.
+pub const BATCH_SIZE: i64 = 100;
+
+/// What enrols a subscriber in a sequence.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SequenceTrigger {
+    /// The subscriber confirmed.
+    Confirmed,
+    /// A confirmed subscriber got the tag, or confirmed with it.
+    Tagged(String),
+}
+
+impl SequenceTrigger {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SequenceTrigger::Confirmed => "confirmed",
+            SequenceTrigger::Tagged(_) => "tagged",
+        }
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        match self {
+            SequenceTrigger::Confirmed => None,
+            SequenceTrigger::Tagged(tag) => Some(tag),
+        }
+    }
+
+    pub fn parse(trigger: &str, tag: Option<String>) -> Option<Self> {
+        match (trigger, tag) {
+            ("confirmed", None) => Some(SequenceTrigger::Confirmed),
+            ("tagged", Some(tag)) => Some(SequenceTrigger::Tagged(tag)),
+            _ => None,
+        }
+    }
+
+    /// The trigger with its tag checked and stored the way subscriber tags
+    /// are.
+    pub fn check(self) -> Result<Self, SubscriberTagError> {
+        match self {
+            SequenceTrigger::Confirmed => Ok(self),
+            SequenceTrigger::Tagged(tag) => {
+                let tag = SubscriberTag::try_from(tag)?;
+                Ok(SequenceTrigger::Tagged(tag.as_ref().to_string()))
+            }
+        }
+    }
+}
+
+/// Enrols a subscriber who just confirmed in the sequences of their list
+/// that start on confirmation, or on a tag they already have. Subscribers
+/// go through each sequence once.
+#[tracing::instrument(name = "Enrol a confirmed subscriber", skip(executor))]
+pub async fn enrol_confirmed<'e>(
+    executor: impl PgExecutor<'e>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        insert into sequence_enrolments (
+            sequence_id, subscriber_id, enrolled_at, next_step, next_step_at, status, updated_at
+        )
+        select q.sequence_id, s.id, now(), 0, now() + make_interval(days => first.delay_days),
+            'active', now()
+        from subscriptions s
+        join sequences q on q.list_id = s.list_id
+        join sequence_steps first on first.sequence_id = q.sequence_id and first.position = 0
+        where s.id = $1
+            and s.status = 'confirmed'
+            and (q.trigger = 'confirmed' or exists (
+                select 1 from subscriber_tags t
+                where t.subscriber_id = s.id and t.tag = q.trigger_tag
+            ))
+        on conflict do nothing
+        "#,
+        subscriber_id
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Enrols the confirmed ones among subscribers who just got `tags` in the
+/// sequences of their list that start on one of the tags.
+#[tracing::instrument(name = "Enrol tagged subscribers", skip(executor, subscriber_ids))]
+pub async fn enrol_tagged<'e>(
+    executor: impl PgExecutor<'e>,
+    subscriber_ids: &[Uuid],
+    tags: &[&str],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        insert into sequence_enrolments (
+            sequence_id, subscriber_id, enrolled_at, next_step, next_step_at, status, updated_at
+        )
+        select q.sequence_id, s.id, now(), 0, now() + make_interval(days => first.delay_days),
+            'active', now()
+        from subscriptions s
+        join sequences q on q.list_id = s.list_id
+        join sequence_steps first on first.sequence_id = q.sequence_id and first.position = 0
+        where s.id = any($1)
+            and s.status = 'confirmed'
+            and q.trigger = 'tagged'
+            and q.trigger_tag = any($2)
+        on conflict do nothing
+        "#,
+        subscriber_ids,
+        tags as &[&str]
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+struct DueEnrolment {
+    sequence_id: Uuid,
+    subscriber_id: Uuid,
+    next_step: i32,
+    subscriber_status: String,
+    segment_rules: Option<serde_json::Value>,
+}
+
+/// Queues the due step of up to a batch of enrolments for delivery and moves
+/// them to their next step. Enrolments of subscribers who unsubscribed or
+/// left the segment of the sequence end instead. Returns how many
+/// enrolments were looked at.
+#[tracing::instrument(name = "Advance due sequence enrolments", skip_all, err)]
+pub async fn advance_due_enrolments(pool: &PgPool) -> Result<usize, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let due = sqlx::query_as!(
+        DueEnrolment,
+        r#"
+        select e.sequence_id, e.subscriber_id, e.next_step, s.status as subscriber_status,
+            q.segment_rules
+        from sequence_enrolments e
+        join subscriptions s on s.id = e.subscriber_id
+        join sequences q on q.sequence_id = e.sequence_id
+        where e.status = 'active' and e.next_step_at <= now()
+        order by e.next_step_at
+        for update of e
+        skip locked
+        limit $1
+        "#,
+        BATCH_SIZE
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    for enrolment in &due {
+        if enrolment.subscriber_status != "confirmed" {
+            exit(&mut transaction, enrolment, "unsubscribed").await?;
+        } else if !in_segment(&mut transaction, enrolment).await? {
+            exit(&mut transaction, enrolment, "left_segment").await?;
+        } else {
+            send_step(&mut transaction, enrolment).await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(due.len())
+}
+
+async fn in_segment(
+    transaction: &mut Transaction<'_, Postgres>,
+    enrolment: &DueEnrolment,
+) -> Result<bool, sqlx::Error> {
+    let rules: Rule = match &enrolment.segment_rules {
+        Some(rules) => serde_json::from_value(rules.clone())
+            .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+        None => return Ok(true),
+    };
+    let filter = rules.to_sql(2);
+    let sql = format!("select 1 from subscriptions s where s.id = $1 and {}", filter.sql);
+    let row = filter
+        .bind_to(sqlx::query(&sql).bind(enrolment.subscriber_id))
+        .fetch_optional(&mut *transaction)
+        .await?;
+    Ok(row.is_some())
+}
+
+async fn send_step(
+    transaction: &mut Transaction<'_, Postgres>,
+    enrolment: &DueEnrolment,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        insert into issue_delivery_queue (newsletter_issue_id, subscriber_id)
+        select newsletter_issue_id, $3
+        from sequence_steps
+        where sequence_id = $1 and position = $2
+        on conflict do nothing
+        "#,
+        enrolment.sequence_id,
+        enrolment.next_step,
+        enrolment.subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        update sequence_enrolments
+        set next_step = $3,
+            next_step_at = enrolled_at + make_interval(days => step.delay_days),
+            status = case when step.delay_days is null then 'completed' else 'active' end,
+            updated_at = now()
+        from (
+            select (
+                select delay_days from sequence_steps where sequence_id = $1 and position = $3
+            ) as delay_days
+        ) step
+        where sequence_id = $1 and subscriber_id = $2
+        "#,
+        enrolment.sequence_id,
+        enrolment.subscriber_id,
+        enrolment.next_step + 1
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+async fn exit(
+    transaction: &mut Transaction<'_, Postgres>,
+    enrolment: &DueEnrolment,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        update sequence_enrolments
+        set status = 'exited', exit_reason = $3, next_step_at = null, updated_at = now()
+        where sequence_id = $1 and subscriber_id = $2
+        "#,
+        enrolment.sequence_id,
+        enrolment.subscriber_id,
+        reason
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SequenceTrigger;
+    use serde_json::json;
+
+    #[test]
+    fn triggers_are_confirmed_or_a_tag() {
+        let trigger: SequenceTrigger = serde_json::from_value(json!("confirmed")).unwrap();
+        assert_eq!(trigger, SequenceTrigger::Confirmed);
+        let trigger: SequenceTrigger = serde_json::from_value(json!({"tagged": " VIP "})).unwrap();
+        assert_eq!(trigger.check().unwrap(), SequenceTrigger::Tagged("vip".into()));
+        assert!(SequenceTrigger::Tagged("no spaces".into()).check().is_err());
+    }
+
+    #[test]
+    fn stored_triggers_round_trip() {
+        for trigger in [SequenceTrigger::Confirmed, SequenceTrigger::Tagged("vip".into())] {
+            let tag = trigger.tag().map(str::to_string);
+            assert_eq!(SequenceTrigger::parse(trigger.as_str(), tag), Some(trigger));
+        }
+        assert_eq!(SequenceTrigger::parse("tagged", None), None);
+    }
+}
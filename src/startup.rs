@@ -1,75 +1,553 @@
-use crate::config::Config;
+use crate::authentication::prune_failures_until_stopped;
+use crate::config::{Config, LoginThrottleConfig, ReadConfigError};
+use crate::deliverability::{Deliverability, ResolveError};
+use crate::error::error_chain_fmt;
+use crate::issue_delivery_worker::{
+    run_worker_until_stopped, DeliveryContext, DeliveryWorkerConfig,
+};
+use crate::localisation::{LocalisationError, Translations};
+use crate::openapi::{api_docs, api_docs_redirect, openapi_json, OPENAPI_PATH};
+use crate::pages::Pages;
+use crate::problem::{invalid_request, not_found, with_request_id};
+use crate::rate_limit::{limit_by_ip, RateLimiter};
+use crate::scheduler::run_scheduler_until_stopped;
+use crate::telemetry::TelemetryError;
 use crate::{email_client::EmailClient, routes::*};
 use actix_web::dev::Server;
-use actix_web::{web, App, HttpServer};
+use actix_web::http::Method;
+use actix_web::middleware::from_fn;
+use actix_web::{web, App, HttpServer, Route};
 use sqlx::PgPool;
+use std::future::Future;
 use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
 use tracing_actix_web::TracingLogger;
 
+#[derive(thiserror::Error)]
+pub enum StartupError {
+    #[error(transparent)]
+    ConfigError(#[from] ReadConfigError),
+    #[error("Failed to initialise telemetry")]
+    TelemetryError(#[source] TelemetryError),
+    #[error("Failed to load the translation catalogues")]
+    LocalisationError(#[source] LocalisationError),
+    #[error("Failed to load the page templates")]
+    TemplateError(#[source] minijinja::Error),
+    #[error("Failed to read the system resolver configuration")]
+    ResolverError(#[source] ResolveError),
+    #[error("Failed to connect to Postgres")]
+    DatabaseError(#[source] sqlx::Error),
+    #[error("Unable to bind {address}")]
+    BindError {
+        address: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to start the HTTP server")]
+    ServerError(#[source] std::io::Error),
+}
+
+impl std::fmt::Debug for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
 pub struct AppServer {
     port: u16,
     server: Server,
+    connection_pool: PgPool,
+    delivery_pool: PgPool,
+    delivery_email_client: web::Data<EmailClient>,
+    delivery_context: Arc<DeliveryContext>,
+    delivery_workers: usize,
+    delivery_config: DeliveryWorkerConfig,
+    login_throttle: LoginThrottleConfig,
+    shutdown_grace: Duration,
 }
 
 #[derive(Debug)]
 pub struct AppBaseUrl(pub String);
 
 impl AppServer {
-    pub async fn build(config: Config) -> Result<Self, std::io::Error> {
-        let connection = config.database.with_db();
-        let connection_pool = PgPool::connect_with(connection)
+    pub async fn build(config: Config) -> Result<Self, StartupError> {
+        let connection_pool = config
+            .database
+            .connect()
             .await
-            .expect("Failed to connect to Postgres");
-        let sender = config.email_client.sender().expect("Invalid sender email");
-        let email_client = EmailClient::new(
-            config.email_client.base_url,
-            sender,
-            config.email_client.auth_token,
-            std::time::Duration::from_secs(5),
+            .map_err(StartupError::DatabaseError)?;
+        let email_client = config.email_client.client();
+        // Pooled connections are bound to the runtime that opened them. The
+        // actix workers are stopped before the delivery workers are done, so
+        // the latter must not share connection pools with them.
+        let delivery_email_client = web::Data::new(config.email_client.client());
+        let delivery_pool = config
+            .database
+            .connect()
+            .await
+            .map_err(StartupError::DatabaseError)?;
+        let address = config.application.address();
+        let listener = TcpListener::bind(&address).map_err(|source| StartupError::BindError {
+            address: address.clone(),
+            source,
+        })?;
+        let port = listener
+            .local_addr()
+            .map_err(|source| StartupError::BindError { address, source })?
+            .port();
+        let rate_limiter = RateLimiter::new(&config.rate_limit, connection_pool.clone());
+        let translations = Arc::new(
+            Translations::load(&config.localisation).map_err(StartupError::LocalisationError)?,
         );
-        let listener =
-            TcpListener::bind(config.application.address()).expect("Unable to bind port");
-        let port = listener.local_addr().unwrap().port();
+        let pages = Pages::new(&config.application.templates_dir, translations.clone())
+            .map_err(StartupError::TemplateError)?;
+        let deliverability =
+            Deliverability::new(&config.deliverability).map_err(StartupError::ResolverError)?;
+        let delivery_context = Arc::new(DeliveryContext {
+            base_url: config.application.base_url.clone(),
+            translations: translations.clone(),
+        });
         let server = Self::running_server(
             listener,
-            connection_pool,
+            connection_pool.clone(),
             email_client,
-            config.application.base_url,
-        )?;
+            &config,
+            rate_limiter,
+            pages,
+            translations,
+            deliverability,
+        )
+        .map_err(StartupError::ServerError)?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            connection_pool,
+            delivery_pool,
+            delivery_email_client,
+            delivery_context,
+            delivery_workers: config.delivery.workers,
+            delivery_config: (&config.delivery).into(),
+            login_throttle: config.login_throttle.clone(),
+            shutdown_grace: config.application.shutdown_grace(),
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// Runs until SIGTERM or Ctrl-C is received, then shuts down gracefully.
     pub async fn run(self) -> Result<(), std::io::Error> {
-        self.server.await
+        self.run_until_stopped(shutdown_signal()).await
     }
 
+    /// Serves requests and runs the delivery workers, the scheduler and the
+    /// pruning of recorded login failures until `stop` resolves.
+    /// After that no new connections are accepted, while in-flight requests
+    /// and the email each worker is currently sending get `shutdown_grace` to
+    /// finish. Unsent deliveries stay queued in the database.
+    pub async fn run_until_stopped(
+        self,
+        stop: impl Future<Output = ()>,
+    ) -> Result<(), std::io::Error> {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let workers: Vec<_> = (0..self.delivery_workers)
+            .map(|_| {
+                tokio::spawn(run_worker_until_stopped(
+                    self.delivery_pool.clone(),
+                    self.delivery_email_client.clone(),
+                    self.delivery_context.clone(),
+                    self.delivery_config,
+                    shutdown_rx.clone(),
+                ))
+            })
+            .collect();
+        // Whatever the scheduler queues is only worth it if something sends it.
+        let scheduler = (self.delivery_workers > 0).then(|| {
+            tokio::spawn(run_scheduler_until_stopped(
+                self.delivery_pool.clone(),
+                self.delivery_config.poll_interval,
+                shutdown_rx.clone(),
+            ))
+        });
+        let pruning = tokio::spawn(prune_failures_until_stopped(
+            self.delivery_pool.clone(),
+            self.login_throttle,
+            shutdown_rx.clone(),
+        ));
+
+        let handle = self.server.handle();
+        let mut server = tokio::spawn(self.server);
+        let server_result = tokio::select! {
+            result = &mut server => Some(result),
+            _ = stop => None,
+        };
+
+        tracing::info!("Shutting down");
+        let _ = shutdown_tx.send(true);
+        let stop_server = handle.stop(true);
+        let stop_workers = async {
+            for worker in workers.into_iter().chain(scheduler).chain([pruning]) {
+                let _ = worker.await;
+            }
+        };
+        if tokio::time::timeout(self.shutdown_grace, stop_workers)
+            .await
+            .is_err()
+        {
+            tracing::warn!("Delivery workers did not finish within the grace period");
+        }
+        stop_server.await;
+        let server_result = match server_result {
+            Some(result) => result,
+            None => server.await,
+        };
+        self.connection_pool.close().await;
+        self.delivery_pool.close().await;
+        tracing::info!("Shutdown complete");
+
+        server_result.map_err(std::io::Error::other)?
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn running_server(
         listener: TcpListener,
         connection_pool: PgPool,
         email_client: EmailClient,
-        base_url: String,
+        config: &Config,
+        rate_limiter: RateLimiter,
+        pages: Pages,
+        translations: Arc<Translations>,
+        deliverability: Deliverability,
     ) -> Result<Server, std::io::Error> {
+        let application = &config.application;
         let connection_pool = web::Data::new(connection_pool);
         let email_client = web::Data::new(email_client);
-        let base_url = web::Data::new(AppBaseUrl(base_url));
-        let server = HttpServer::new(move || {
+        let base_url = web::Data::new(AppBaseUrl(application.base_url.clone()));
+        let readiness_config = web::Data::new(ReadinessConfig {
+            check_email_provider: config.email_client.check_on_readiness,
+        });
+        let rate_limiter = web::Data::new(rate_limiter);
+        let spam_trap = web::Data::new(config.spam_trap.clone());
+        let login_throttle = web::Data::new(config.login_throttle.clone());
+        let two_factor = web::Data::new(config.two_factor.clone());
+        let deliverability = web::Data::new(deliverability);
+        let pages = web::Data::new(pages);
+        let translations = web::Data::from(translations);
+        let redirects = web::Data::new(application.redirects.clone());
+        let payload_limit = application.payload_limit_bytes;
+        let serve_api_docs = application.serve_api_docs;
+        let mut server = HttpServer::new(move || {
             App::new()
+                // Registered first so that it runs inside the span of the request.
+                .wrap(from_fn(with_request_id))
                 .wrap(TracingLogger::default())
-                .route("/health_check", web::get().to(health_check))
-                .route("/subscriptions", web::post().to(subscribe))
-                .route("/subscriptions/confirm", web::get().to(confirm))
-                .route("/newsletters", web::post().to(publish_newsletter))
+                .configure(|cfg| register(cfg, health_route_table()))
+                .route(OPENAPI_PATH, web::get().to(openapi_json))
+                .configure(|cfg| {
+                    if serve_api_docs {
+                        cfg.route("/api/docs", web::get().to(api_docs_redirect))
+                            .route("/api/docs/{file:.*}", web::get().to(api_docs));
+                    }
+                })
+                .configure(page_routes)
+                // Unversioned paths stay for the clients that predate `/api/v1`.
+                .configure(api_routes)
+                .service(web::scope("/api/v1").configure(api_routes))
                 .app_data(connection_pool.clone())
                 .app_data(email_client.clone())
                 .app_data(base_url.clone())
-        })
-        .listen(listener)?
-        .run();
-        Ok(server)
+                .app_data(readiness_config.clone())
+                .app_data(rate_limiter.clone())
+                .app_data(spam_trap.clone())
+                .app_data(login_throttle.clone())
+                .app_data(two_factor.clone())
+                .app_data(deliverability.clone())
+                .app_data(pages.clone())
+                .app_data(translations.clone())
+                .app_data(redirects.clone())
+                .app_data(
+                    web::JsonConfig::default()
+                        .limit(payload_limit)
+                        .error_handler(|e, _| invalid_request(e)),
+                )
+                .app_data(
+                    web::FormConfig::default()
+                        .limit(payload_limit)
+                        .error_handler(|e, _| invalid_request(e)),
+                )
+                .app_data(web::QueryConfig::default().error_handler(|e, _| invalid_request(e)))
+                .app_data(web::PathConfig::default().error_handler(|e, _| invalid_request(e)))
+                .app_data(web::PayloadConfig::default().limit(payload_limit))
+                .default_service(web::to(not_found))
+        });
+        if let Some(workers) = application.workers {
+            server = server.workers(workers);
+        }
+        if let Some(keep_alive) = application.keep_alive() {
+            server = server.keep_alive(keep_alive);
+        }
+        Ok(server
+            .disable_signals()
+            .shutdown_timeout(application.shutdown_grace_seconds)
+            .listen(listener)?
+            .run())
+    }
+}
+
+/// Pages for people subscribing from a browser, linked from the emails.
+fn page_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/subscribe")
+            .route(web::get().to(subscribe_page))
+            // Only submissions count, loading the form is free.
+            .route(
+                web::post()
+                    .to(submit_subscribe_page)
+                    .wrap(from_fn(|req, next| limit_by_ip("subscriptions", req, next))),
+            ),
+    )
+    .route("/confirm", web::get().to(confirm_page))
+    .service(
+        web::resource("/unsubscribe")
+            .route(web::get().to(unsubscribe_page))
+            .route(web::post().to(submit_unsubscribe_page)),
+    )
+    // Open pixel and click redirect of delivered issues.
+    .route("/t/{tracking_token}/open", web::get().to(open_pixel))
+    .route("/t/{tracking_token}/click/{link_id}", web::get().to(follow_link));
+}
+
+/// A route of the API. Routes are kept in tables, so that the OpenAPI
+/// document can be checked against what is actually served.
+pub struct ApiRoute {
+    pub method: Method,
+    pub path: &'static str,
+    /// Adds the handler, and any middleware of the route, to a route that
+    /// matches the method.
+    to: fn(Route) -> Route,
+}
+
+impl ApiRoute {
+    fn new(method: Method, path: &'static str, to: fn(Route) -> Route) -> Self {
+        Self { method, path, to }
+    }
+}
+
+/// Registers `routes`, with the routes of the same path in one resource so
+/// that other methods get a 405.
+fn register(cfg: &mut web::ServiceConfig, routes: Vec<ApiRoute>) {
+    let mut routes = routes.into_iter().peekable();
+    while let Some(first) = routes.next() {
+        let mut resource = web::resource(first.path).route((first.to)(web::method(first.method)));
+        while let Some(route) = routes.next_if(|route| route.path == first.path) {
+            resource = resource.route((route.to)(web::method(route.method)));
+        }
+        cfg.service(resource);
+    }
+}
+
+/// Routes for infrastructure, served at the root only.
+pub fn health_route_table() -> Vec<ApiRoute> {
+    vec![
+        ApiRoute::new(Method::GET, "/health_check", |route| route.to(health_check)),
+        ApiRoute::new(Method::GET, "/health/live", |route| route.to(liveness)),
+        ApiRoute::new(Method::GET, "/health/ready", |route| route.to(readiness)),
+    ]
+}
+
+/// Routes meant for programs rather than infrastructure, mounted both at the
+/// root and under `/api/v1`.
+pub fn api_route_table() -> Vec<ApiRoute> {
+    vec![
+        ApiRoute::new(Method::POST, "/subscriptions", |route| {
+            route
+                .to(subscribe)
+                .wrap(from_fn(|req, next| limit_by_ip("subscriptions", req, next)))
+        }),
+        ApiRoute::new(Method::GET, "/subscriptions/confirm", |route| {
+            route.to(confirm)
+        }),
+        ApiRoute::new(Method::POST, "/newsletters", |route| {
+            route.to(publish_newsletter)
+        }),
+        ApiRoute::new(Method::POST, "/newsletters/drafts", |route| {
+            route.to(draft_newsletter)
+        }),
+        ApiRoute::new(Method::GET, "/newsletters/{issue_id}/ab-test", |route| {
+            route.to(get_ab_test)
+        }),
+        ApiRoute::new(
+            Method::POST,
+            "/newsletters/drafts/{issue_id}/publish",
+            |route| route.to(publish_draft),
+        ),
+        ApiRoute::new(Method::GET, "/admin/lists", |route| route.to(list_lists)),
+        ApiRoute::new(Method::POST, "/admin/lists", |route| route.to(create_list)),
+        ApiRoute::new(Method::GET, "/admin/lists/{list_id}/fields", |route| {
+            route.to(list_list_fields)
+        }),
+        ApiRoute::new(Method::POST, "/admin/lists/{list_id}/fields", |route| {
+            route.to(create_list_field)
+        }),
+        ApiRoute::new(
+            Method::DELETE,
+            "/admin/lists/{list_id}/fields/{key}",
+            |route| route.to(delete_list_field),
+        ),
+        ApiRoute::new(Method::GET, "/admin/lists/{list_id}/segments", |route| {
+            route.to(list_segments)
+        }),
+        ApiRoute::new(Method::POST, "/admin/lists/{list_id}/segments", |route| {
+            route.to(create_segment)
+        }),
+        ApiRoute::new(
+            Method::POST,
+            "/admin/lists/{list_id}/segments/preview",
+            |route| route.to(preview_rules),
+        ),
+        ApiRoute::new(
+            Method::DELETE,
+            "/admin/lists/{list_id}/segments/{segment_id}",
+            |route| route.to(delete_segment),
+        ),
+        ApiRoute::new(
+            Method::GET,
+            "/admin/lists/{list_id}/segments/{segment_id}/preview",
+            |route| route.to(preview_segment),
+        ),
+        ApiRoute::new(Method::GET, "/admin/lists/{list_id}/automations", |route| {
+            route.to(list_automations)
+        }),
+        ApiRoute::new(
+            Method::PUT,
+            "/admin/lists/{list_id}/automations/{event}",
+            |route| route.to(set_automation),
+        ),
+        ApiRoute::new(
+            Method::DELETE,
+            "/admin/lists/{list_id}/automations/{event}",
+            |route| route.to(delete_automation),
+        ),
+        ApiRoute::new(Method::GET, "/admin/lists/{list_id}/sequences", |route| {
+            route.to(list_sequences)
+        }),
+        ApiRoute::new(Method::POST, "/admin/lists/{list_id}/sequences", |route| {
+            route.to(create_sequence)
+        }),
+        ApiRoute::new(
+            Method::PUT,
+            "/admin/lists/{list_id}/sequences/{sequence_id}",
+            |route| route.to(update_sequence),
+        ),
+        ApiRoute::new(
+            Method::DELETE,
+            "/admin/lists/{list_id}/sequences/{sequence_id}",
+            |route| route.to(delete_sequence),
+        ),
+        ApiRoute::new(
+            Method::GET,
+            "/admin/lists/{list_id}/sequences/{sequence_id}/subscribers",
+            |route| route.to(list_sequence_enrolments),
+        ),
+        ApiRoute::new(Method::GET, "/admin/lists/{list_id}/subscribers", |route| {
+            route.to(list_subscribers)
+        }),
+        ApiRoute::new(
+            Method::POST,
+            "/admin/lists/{list_id}/subscribers/import",
+            |route| route.to(import_subscribers),
+        ),
+        ApiRoute::new(
+            Method::POST,
+            "/admin/lists/{list_id}/subscribers/tags",
+            |route| route.to(update_subscriber_tags),
+        ),
+        ApiRoute::new(
+            Method::GET,
+            "/admin/lists/{list_id}/subscribers/export",
+            |route| route.to(export_subscribers),
+        ),
+        ApiRoute::new(Method::PUT, "/admin/users/{user_id}/role", |route| {
+            route.to(set_user_role)
+        }),
+        ApiRoute::new(
+            Method::PUT,
+            "/admin/users/{user_id}/lists/{list_id}",
+            |route| route.to(grant_list_role),
+        ),
+        ApiRoute::new(
+            Method::DELETE,
+            "/admin/users/{user_id}/lists/{list_id}",
+            |route| route.to(revoke_list_role),
+        ),
+        ApiRoute::new(Method::GET, "/admin/lockouts", |route| {
+            route.to(list_lockouts)
+        }),
+        ApiRoute::new(Method::POST, "/admin/lockouts/unlock", |route| {
+            route.to(unlock_lockout)
+        }),
+        ApiRoute::new(Method::GET, "/admin/audit_events", |route| {
+            route.to(list_audit_events)
+        }),
+        ApiRoute::new(Method::POST, "/admin/sessions", |route| {
+            route.to(open_session)
+        }),
+        ApiRoute::new(Method::DELETE, "/admin/sessions/current", |route| {
+            route.to(end_session)
+        }),
+        ApiRoute::new(Method::POST, "/admin/two_factor/enrol", |route| {
+            route.to(begin_two_factor_enrolment)
+        }),
+        ApiRoute::new(Method::POST, "/admin/two_factor/confirm", |route| {
+            route.to(confirm_two_factor_enrolment)
+        }),
+        ApiRoute::new(Method::POST, "/admin/two_factor/recovery_codes", |route| {
+            route.to(regenerate_recovery_codes)
+        }),
+        ApiRoute::new(Method::POST, "/admin/two_factor/disable", |route| {
+            route.to(disable_two_factor)
+        }),
+        ApiRoute::new(Method::GET, "/admin/api_tokens", |route| {
+            route.to(list_api_tokens)
+        }),
+        ApiRoute::new(Method::POST, "/admin/api_tokens", |route| {
+            route.to(create_api_token)
+        }),
+        ApiRoute::new(Method::DELETE, "/admin/api_tokens/{token_id}", |route| {
+            route.to(revoke_api_token)
+        }),
+        ApiRoute::new(Method::PUT, "/admin/settings/two_factor", |route| {
+            route.to(set_two_factor_requirement)
+        }),
+    ]
+}
+
+fn api_routes(cfg: &mut web::ServiceConfig) {
+    register(cfg, api_route_table());
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl-C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received Ctrl-C"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
     }
 }
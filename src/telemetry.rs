@@ -1,23 +1,91 @@
-use tracing::subscriber::set_global_default;
+use crate::config::TelemetryConfig;
+use crate::error::error_chain_fmt;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::trace::TraceError;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use tracing::subscriber::{set_global_default, SetGlobalDefaultError};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_log::log::SetLoggerError;
 use tracing_log::LogTracer;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 
+#[derive(thiserror::Error)]
+pub enum TelemetryError {
+    #[error("Failed to set the logger")]
+    LoggerError(#[source] SetLoggerError),
+    #[error("Failed to install the OTLP exporter")]
+    ExporterError(#[source] TraceError),
+    #[error("Failed to set the subscriber")]
+    SubscriberError(#[source] SetGlobalDefaultError),
+}
+
+impl std::fmt::Debug for TelemetryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
 pub fn init_logging(
     name: impl AsRef<str>,
     env_filter: impl AsRef<str>,
     sink: impl for<'a> MakeWriter<'a> + Send + Sync + 'static,
 )
 {
-    LogTracer::init().expect("Failed to set logger");
+    // Without an OTLP endpoint there is no exporter that could fail to install.
+    init_telemetry(name, env_filter, sink, &TelemetryConfig::default())
+        .expect("Failed to initialise logging");
+}
+
+/// Same as `init_logging`, but additionally exports spans to an OTLP collector
+/// when `config.otlp_endpoint` is set. Fails if the exporter cannot be
+/// installed, e.g. because the endpoint is not a valid URL, or if logging was
+/// already initialised.
+pub fn init_telemetry(
+    name: impl AsRef<str>,
+    env_filter: impl AsRef<str>,
+    sink: impl for<'a> MakeWriter<'a> + Send + Sync + 'static,
+    config: &TelemetryConfig,
+) -> Result<(), TelemetryError>
+{
+    LogTracer::init().map_err(TelemetryError::LoggerError)?;
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
     let formatting_layer = BunyanFormattingLayer::new(name.as_ref().to_owned(), sink);
+    let otel_layer = config
+        .otlp_endpoint
+        .as_ref()
+        .map(|endpoint| {
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .http()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", name.as_ref().to_owned()),
+                ])))
+                .install_batch(opentelemetry::runtime::TokioCurrentThread)
+        })
+        .transpose()
+        .map_err(TelemetryError::ExporterError)?
+        .map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
     let subscriber = Registry::default()
         .with(env_filter)
         .with(JsonStorageLayer)
-        .with(formatting_layer);
+        .with(formatting_layer)
+        .with(otel_layer);
+
+    set_global_default(subscriber).map_err(TelemetryError::SubscriberError)?;
+    Ok(())
+}
 
-    set_global_default(subscriber).expect("Failed to set subscriber");
+/// Flushes spans that are still buffered by the OTLP exporter.
+pub fn shutdown_telemetry() {
+    opentelemetry::global::shutdown_tracer_provider();
 }
@@ -0,0 +1,258 @@
+use crate::routes::generate_sub_token;
+use sqlx::{PgExecutor, PgPool};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use uuid::Uuid;
+
+/// What a subscriber did with an issue they were sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engagement {
+    /// Their mail client loaded the open pixel.
+    Opened,
+    /// They followed one of the tracked links.
+    Clicked,
+}
+
+impl Engagement {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Engagement::Opened => "opened",
+            Engagement::Clicked => "clicked",
+        }
+    }
+}
+
+pub fn open_url(base_url: &str, tracking_token: &str) -> String {
+    format!("{}/t/{}/open", base_url, tracking_token)
+}
+
+pub fn click_url(base_url: &str, tracking_token: &str, link_id: Uuid) -> String {
+    format!("{}/t/{}/click/{}", base_url, tracking_token, link_id)
+}
+
+/// Stores that the issue goes out to the subscriber, as `variant` if it is
+/// tested. Returns the tracking token, the same one on every attempt.
+#[tracing::instrument(name = "Store the delivery of an issue", skip(executor))]
+pub async fn store_delivery<'e>(
+    executor: impl PgExecutor<'e>,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    variant: Option<i32>,
+) -> Result<String, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        insert into issue_deliveries (
+            tracking_token, newsletter_issue_id, subscriber_id, variant
+        )
+        values ($1, $2, $3, $4)
+        on conflict (newsletter_issue_id, subscriber_id) do update set variant = $4
+        returning tracking_token
+        "#,
+        generate_sub_token(),
+        issue_id,
+        subscriber_id,
+        variant
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(row.tracking_token)
+}
+
+/// Ids of the links of an issue, storing the ones it didn't have yet.
+///
+/// Takes the pool rather than the transaction of a delivery, which is held
+/// while the email is sent. Workers sending the same issue would otherwise
+/// wait on each other's links.
+#[tracing::instrument(name = "Store the links of an issue", skip(pool, urls))]
+pub async fn store_links(
+    pool: &PgPool,
+    issue_id: Uuid,
+    urls: &[String],
+) -> Result<HashMap<String, Uuid>, sqlx::Error> {
+    if urls.is_empty() {
+        return Ok(HashMap::new());
+    }
+    // Duplicates would make the insert conflict with itself.
+    let urls: Vec<String> = urls.iter().cloned().collect::<BTreeSet<_>>().into_iter().collect();
+    let link_ids: Vec<Uuid> = urls.iter().map(|_| Uuid::new_v4()).collect();
+    sqlx::query!(
+        r#"
+        insert into issue_links (link_id, newsletter_issue_id, url)
+        select l.link_id, $1, l.url
+        from unnest($2::uuid[], $3::text[]) as l (link_id, url)
+        on conflict (newsletter_issue_id, url) do nothing
+        "#,
+        issue_id,
+        &link_ids[..],
+        &urls[..]
+    )
+    .execute(pool)
+    .await?;
+    let rows = sqlx::query!(
+        "select link_id, url from issue_links where newsletter_issue_id = $1 and url = any($2)",
+        issue_id,
+        &urls[..]
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.url, r.link_id)).collect())
+}
+
+/// Where a tracked link leads, if the token and the link belong to the same
+/// issue.
+#[tracing::instrument(name = "Find a tracked link", skip(executor, tracking_token))]
+pub async fn find_link<'e>(
+    executor: impl PgExecutor<'e>,
+    tracking_token: &str,
+    link_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        select l.url
+        from issue_links l
+        join issue_deliveries d on d.newsletter_issue_id = l.newsletter_issue_id
+        where d.tracking_token = $1 and l.link_id = $2
+        "#,
+        tracking_token,
+        link_id
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.map(|r| r.url))
+}
+
+/// Records an open or a click for the delivery of `tracking_token`. Returns
+/// whether the token was known.
+#[tracing::instrument(name = "Record engagement", skip(executor, tracking_token))]
+pub async fn record_engagement<'e>(
+    executor: impl PgExecutor<'e>,
+    tracking_token: &str,
+    engagement: Engagement,
+) -> Result<bool, sqlx::Error> {
+    let recorded = sqlx::query!(
+        r#"
+        insert into engagement_events (
+            subscriber_id, newsletter_issue_id, variant, kind, occurred_at
+        )
+        select subscriber_id, newsletter_issue_id, variant, $2, now()
+        from issue_deliveries
+        where tracking_token = $1
+        "#,
+        tracking_token,
+        engagement.as_str()
+    )
+    .execute(executor)
+    .await?;
+    Ok(recorded.rows_affected() > 0)
+}
+
+/// Web links of `html` in order, leaving out those in `untracked`.
+pub fn tracked_links(html: &str, untracked: &[&str]) -> Vec<String> {
+    hrefs(html)
+        .into_iter()
+        .map(|(_, url)| url)
+        .filter(|url| is_web_link(url) && !untracked.contains(&url.as_str()))
+        .collect()
+}
+
+/// Points the links in `link_ids` to the click redirect and adds the open
+/// pixel, at the end of the body.
+pub fn track_html(
+    html: &str,
+    base_url: &str,
+    tracking_token: &str,
+    link_ids: &HashMap<String, Uuid>,
+) -> String {
+    let mut tracked = html.to_string();
+    // Back to front, so that the ranges of the earlier links stay valid.
+    for (range, url) in hrefs(html).into_iter().rev() {
+        if let Some(link_id) = link_ids.get(&url) {
+            tracked.replace_range(range, &click_url(base_url, tracking_token, *link_id));
+        }
+    }
+    let pixel = format!(
+        r#"<img src="{}" width="1" height="1" alt="">"#,
+        open_url(base_url, tracking_token)
+    );
+    match tracked.to_ascii_lowercase().rfind("</body>") {
+        Some(end) => tracked.insert_str(end, &pixel),
+        None => tracked.push_str(&pixel),
+    }
+    tracked
+}
+
+fn is_web_link(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// Values of the quoted `href` attributes of `html`, with where they are and
+/// what they say once the escaping of merge tags is undone.
+fn hrefs(html: &str) -> Vec<(Range<usize>, String)> {
+    let lowercase = html.to_ascii_lowercase();
+    let mut hrefs = Vec::new();
+    let mut position = 0;
+    while let Some(found) = lowercase[position..].find("href=") {
+        let quote_at = position + found + "href=".len();
+        position = quote_at;
+        let quote = match html[quote_at..].chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => continue,
+        };
+        let start = quote_at + 1;
+        let end = match html[start..].find(quote) {
+            Some(length) => start + length,
+            None => break,
+        };
+        hrefs.push((start..end, unescape(&html[start..end])));
+        position = end + 1;
+    }
+    hrefs
+}
+
+fn unescape(value: &str) -> String {
+    [
+        ("&quot;", "\""),
+        ("&#x27;", "'"),
+        ("&#39;", "'"),
+        ("&#x2f;", "/"),
+        ("&#47;", "/"),
+        ("&lt;", "<"),
+        ("&gt;", ">"),
+        // Last, so that the other entities aren't made up from it.
+        ("&amp;", "&"),
+    ]
+    .iter()
+    .fold(value.to_string(), |value, (entity, character)| value.replace(entity, character))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn web_links_are_found_in_order_and_unescaped() {
+        let html = r#"<a href="https://a.example/?x=1&amp;y=2">A</a>
+            <a HREF='mailto:pog@example.com'>Mail</a>
+            <a href="https:&#x2f;&#x2f;b.example">B</a>
+            <a href="https://example.com/unsubscribe">Leave</a>"#;
+
+        let links = tracked_links(html, &["https://example.com/unsubscribe"]);
+
+        assert_eq!(links, vec!["https://a.example/?x=1&y=2", "https://b.example"]);
+    }
+
+    #[test]
+    fn links_go_through_the_redirect_and_the_pixel_ends_the_body() {
+        let link_id = Uuid::new_v4();
+        let link_ids = HashMap::from([("https://a.example".to_string(), link_id)]);
+        let html = r##"<body><a href="https://a.example">A</a><a href="#top">Top</a></body>"##;
+
+        let tracked = track_html(html, "https://news.example", "abc", &link_ids);
+
+        let click = format!(r#"<a href="https://news.example/t/abc/click/{}">A</a>"#, link_id);
+        let pixel = r#"<img src="https://news.example/t/abc/open" width="1" height="1" alt="">"#;
+        let top = r##"<a href="#top">Top</a>"##;
+        assert_eq!(tracked, format!("<body>{}{}{}</body>", click, top, pixel));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use emailer::ab_tests::decide_due_tests;
+use serde_json::{json, Value};
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn spawn_app() -> TestApp {
+    spawn_app_with(|config| config.delivery.workers = 0).await
+}
+
+async fn post_newsletter(app: &TestApp, path: &str, body: &Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}{}", app.address, path))
+        .json(body)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_report(app: &TestApp, issue_id: &str) -> Value {
+    reqwest::Client::new()
+        .get(format!("{}/newsletters/{}/ab-test", app.address, issue_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn confirmed_subs(app: &TestApp, count: usize) {
+    for i in 0..count {
+        let body = json!({"name": "pog dog", "email": format!("sub{}@example.com", i)});
+        app.post_subscriptions_json("/subscriptions", &body)
+            .await
+            .error_for_status()
+            .unwrap();
+        let request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+        reqwest::get(app.get_links(&request).html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+}
+
+/// Recipients and subjects of the emails sent after the first `seen`, which
+/// is moved past them.
+async fn sent(app: &TestApp, seen: &mut usize) -> Vec<(String, String)> {
+    app.dispatch_all_pending_emails().await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let emails: Vec<_> = requests[*seen..]
+        .iter()
+        .map(|request| serde_json::from_slice::<Value>(&request.body).unwrap())
+        .map(|email| {
+            let field = |name: &str| email[name].as_str().unwrap().to_string();
+            (field("To"), field("Subject"))
+        })
+        .collect();
+    *seen = requests.len();
+    emails
+}
+
+/// Open pixel and click redirect of the last issue sent to `to`.
+async fn tracking_links(app: &TestApp, to: &str) -> (reqwest::Url, reqwest::Url) {
+    let requests = app.email_server.received_requests().await.unwrap();
+    let request = requests
+        .iter()
+        .rev()
+        .find(|request| serde_json::from_slice::<Value>(&request.body).unwrap()["To"] == to)
+        .unwrap();
+    let links = app.get_tracking_links(request);
+    let open = links.iter().find(|link| link.path().ends_with("/open")).unwrap();
+    let click = links.iter().find(|link| link.path().contains("/click/")).unwrap();
+    (open.clone(), click.clone())
+}
+
+fn tested_issue() -> Value {
+    json!({
+        "title": "Original",
+        "content": {
+            "text": "Hi {{ name }}",
+            "html": "<p>Hi {{ name }}, <a href=\"https://example.com/offer\">see the offer</a></p>",
+        },
+        "ab_test": {
+            "variants": [{"title": "Variant A"}, {"title": "Variant B"}],
+            "test_percent": 40,
+            "window_hours": 4,
+            "metric": "clicked",
+        },
+    })
+}
+
+#[actix_rt::test]
+async fn the_winning_variant_goes_to_the_rest_after_the_window() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    confirmed_subs(&app, 10).await;
+    let mut seen = 0;
+    sent(&app, &mut seen).await;
+
+    let response = post_newsletter(&app, "/newsletters/drafts", &tested_issue()).await;
+    let issue_id = response.json::<Value>().await.unwrap()["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    post_newsletter(&app, &format!("/newsletters/drafts/{}/publish", issue_id), &json!({}))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let test_share = sent(&app, &mut seen).await;
+    assert_eq!(test_share.len(), 4);
+    let b_recipients: Vec<_> = test_share.iter().filter(|(_, s)| s == "Variant B").collect();
+    assert_eq!(b_recipients.len(), 2);
+    let report = get_report(&app, &issue_id).await;
+    assert_eq!(report["waiting"], 6);
+    assert_eq!(report["winner"], Value::Null);
+    assert!(report["decide_at"].is_string());
+
+    // Opening doesn't count towards the metric of the test, clicking does.
+    let a_recipient = test_share.iter().find(|(_, s)| s == "Variant A").unwrap();
+    let (open, _) = tracking_links(&app, &a_recipient.0).await;
+    let pixel = reqwest::get(open).await.unwrap();
+    assert_eq!(pixel.status().as_u16(), 200);
+    assert_eq!(pixel.headers()["content-type"], "image/gif");
+    let (_, click) = tracking_links(&app, &b_recipients[0].0).await;
+    let redirect = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(click)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(redirect.status().as_u16(), 302);
+    assert_eq!(redirect.headers()["location"], "https://example.com/offer");
+    assert_eq!(decide_due_tests(&app.db_pool).await.unwrap(), 0, "the window is not over");
+    sqlx::query!("update issue_ab_tests set decide_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(decide_due_tests(&app.db_pool).await.unwrap(), 1);
+
+    let rest = sent(&app, &mut seen).await;
+    assert_eq!(rest.len(), 6);
+    assert!(rest.iter().all(|(_, subject)| subject == "Variant B"));
+    let report = get_report(&app, &issue_id).await;
+    assert_eq!(report["winner"], 1);
+    assert_eq!(report["waiting"], 0);
+    assert_eq!(
+        report["variants"],
+        json!([
+            {"variant": 0, "title": "Variant A", "recipients": 2, "engaged": 0},
+            {"variant": 1, "title": "Variant B", "recipients": 2, "engaged": 1},
+        ])
+    );
+}
+
+#[actix_rt::test]
+async fn tracked_links_only_redirect_within_their_issue() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    confirmed_subs(&app, 1).await;
+    let mut issue = tested_issue();
+    issue.as_object_mut().unwrap().remove("ab_test");
+    post_newsletter(&app, "/newsletters", &issue)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let (_, click) = tracking_links(&app, "sub0@example.com").await;
+
+    let mut unknown_link = click.clone();
+    unknown_link.set_path(&format!(
+        "{}/{}",
+        click.path().rsplit_once('/').unwrap().0,
+        uuid::Uuid::new_v4()
+    ));
+    let mut unknown_token = click.clone();
+    unknown_token.set_path(&click.path().replacen("/t/", "/t/x", 1));
+    for link in [unknown_link, unknown_token] {
+        let response = reqwest::get(link.clone()).await.unwrap();
+        assert_eq!(response.status().as_u16(), 404, "{}", link);
+    }
+    let clicks = sqlx::query!("select count(*) as \"count!\" from engagement_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(clicks, 0);
+}
+
+#[actix_rt::test]
+async fn invalid_ab_tests_are_rejected() {
+    let app = spawn_app().await;
+    let cases = [
+        ("/ab_test/variants", json!([{"title": "Only one"}]), "validation_error"),
+        ("/ab_test/variants", json!([{"title": "A"}, {}]), "validation_error"),
+        ("/ab_test/test_percent", json!(0), "validation_error"),
+        ("/ab_test/window_hours", json!(721), "validation_error"),
+        ("/translations", json!({"de": {"title": "T", "content": {"text": "T", "html": "T"}}}),
+            "validation_error"),
+        ("/ab_test/variants/1/title", json!("Hi {{ name"), "invalid_template"),
+    ];
+    for (pointer, value, code) in cases {
+        let mut issue = tested_issue();
+        let (parent, key) = pointer.rsplit_once('/').unwrap();
+        let parent = issue.pointer_mut(parent).unwrap();
+        match parent {
+            Value::Array(items) => items[key.parse::<usize>().unwrap()] = value,
+            _ => parent[key] = value,
+        }
+
+        let response = post_newsletter(&app, "/newsletters", &issue).await;
+
+        assert_eq!(response.status().as_u16(), 400, "{}", pointer);
+        let problem: Value = response.json().await.unwrap();
+        assert_eq!(problem["code"], code, "{}", pointer);
+    }
+}
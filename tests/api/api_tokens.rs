@@ -0,0 +1,188 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_token(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/api_tokens", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn create_token_with_scopes(app: &TestApp, scopes: &[&str]) -> (String, String) {
+    let response = create_token(
+        app,
+        serde_json::json!({ "name": "ci", "scopes": scopes, "expires_in_days": 30 }),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    (
+        body["token_id"].as_str().unwrap().to_string(),
+        body["token"].as_str().unwrap().to_string(),
+    )
+}
+
+async fn publish_with_token(app: &TestApp, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .bearer_auth(token)
+        .json(&serde_json::json!({
+            "title": "Release notes",
+            "content": {
+                "text": "Text body",
+                "html": "<p>Html body</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[actix_rt::test]
+async fn tokens_with_the_publish_scope_can_publish() {
+    let app = spawn_app().await;
+    let (_, token) = create_token_with_scopes(&app, &["newsletters:publish"]).await;
+    assert!(token.starts_with("emt_"));
+
+    let response = publish_with_token(&app, &token).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let tokens: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/api_tokens", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .unwrap();
+    let tokens = tokens.as_array().unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0]["name"], "ci");
+    assert!(!tokens[0]["last_used_at"].is_null());
+    assert!(tokens[0].get("token").is_none());
+}
+
+#[actix_rt::test]
+async fn tokens_are_stored_hashed() {
+    let app = spawn_app().await;
+    let (_, token) = create_token_with_scopes(&app, &["newsletters:publish"]).await;
+
+    let stored = sqlx::query!("select token_hash from api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+}
+
+#[actix_rt::test]
+async fn tokens_without_the_scope_are_forbidden() {
+    let app = spawn_app().await;
+    let (_, token) = create_token_with_scopes(&app, &["subscribers:read"]).await;
+
+    let response = publish_with_token(&app, &token).await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[actix_rt::test]
+async fn revoked_and_expired_tokens_are_rejected() {
+    let app = spawn_app().await;
+    let (revoked_id, revoked) = create_token_with_scopes(&app, &["newsletters:publish"]).await;
+    let (_, expired) = create_token_with_scopes(&app, &["newsletters:publish"]).await;
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/admin/api_tokens/{}", &app.address, revoked_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 204);
+    sqlx::query!(
+        "update api_tokens set expires_at = now() - interval '1 day' where revoked_at is null"
+    )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(publish_with_token(&app, &revoked).await.status().as_u16(), 401);
+    assert_eq!(publish_with_token(&app, &expired).await.status().as_u16(), 401);
+    assert_eq!(publish_with_token(&app, "emt_unknown").await.status().as_u16(), 401);
+}
+
+#[actix_rt::test]
+async fn revoking_an_unknown_token_returns_404() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/admin/api_tokens/{}", &app.address, uuid::Uuid::new_v4()))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_rt::test]
+async fn invalid_token_requests_are_rejected() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (serde_json::json!({ "name": "ci", "scopes": [] }), "no scopes"),
+        (serde_json::json!({ "name": " ", "scopes": ["newsletters:publish"] }), "empty name"),
+        (serde_json::json!({ "name": "ci", "scopes": ["admin"] }), "unknown scope"),
+        (
+            serde_json::json!({
+                "name": "ci",
+                "scopes": ["newsletters:publish"],
+                "expires_in_days": 0,
+            }),
+            "zero expiry",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = create_token(&app, body).await;
+        assert_eq!(response.status().as_u16(), 400, "{}", description);
+    }
+}
+
+#[actix_rt::test]
+async fn tokens_cannot_manage_tokens() {
+    let app = spawn_app().await;
+    let (_, token) = create_token_with_scopes(&app, &["newsletters:publish"]).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/api_tokens", &app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[actix_rt::test]
+async fn admin_routes_are_served_under_the_versioned_path() {
+    let app = spawn_app().await;
+    let (_, token) = create_token_with_scopes(&app, &["newsletters:publish"]).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/api/v1/admin/api_tokens", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+    let tokens: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(tokens.as_array().unwrap().len(), 1);
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/api/v1/admin/unknown", &app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 404);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "not_found");
+}
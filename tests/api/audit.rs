@@ -0,0 +1,131 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn audit_events(app: &TestApp, query: &str) -> Vec<serde_json::Value> {
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/audit_events?{}", &app.address, query))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("User-Agent", "audit-test")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+    let events: serde_json::Value = response.json().await.unwrap();
+    events.as_array().unwrap().clone()
+}
+
+async fn publish(app: &TestApp, title: &str) {
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("User-Agent", "audit-test")
+        .json(&serde_json::json!({
+            "title": title,
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn publishing_is_audited() {
+    let app = spawn_app().await;
+    publish(&app, "Release notes").await;
+
+    let events = audit_events(&app, "action=newsletter.published").await;
+    assert_eq!(events.len(), 1);
+    let event = &events[0];
+    assert_eq!(event["actor_id"], app.test_user.user_id.to_string());
+    assert_eq!(event["target_type"], "newsletter_issue");
+    assert_eq!(event["ip"], "127.0.0.1");
+    assert_eq!(event["user_agent"], "audit-test");
+    assert_eq!(event["payload"]["title"], "Release notes");
+}
+
+#[actix_rt::test]
+async fn failed_logins_are_audited() {
+    let app = spawn_app().await;
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/audit_events", &app.address))
+        .basic_auth(&app.test_user.username, Some("wrong password"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 401);
+
+    let events = audit_events(&app, "action=login.failed").await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["actor_name"], app.test_user.username);
+    assert!(events[0]["actor_id"].is_null());
+}
+
+#[actix_rt::test]
+async fn opening_a_session_is_audited_as_a_login() {
+    let app = spawn_app().await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/sessions", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 201);
+    let session: serde_json::Value = response.json().await.unwrap();
+    publish(&app, "Release notes").await;
+
+    let events = audit_events(&app, "action=login.succeeded").await;
+    assert_eq!(events.len(), 1, "plain Basic requests are not logins of their own");
+    assert_eq!(events[0]["actor_id"], app.test_user.user_id.to_string());
+    assert_eq!(events[0]["payload"]["session_id"], session["session_id"]);
+    assert_eq!(events[0]["payload"]["second_factor"], false);
+}
+
+#[actix_rt::test]
+async fn events_are_listed_newest_first_and_paged() {
+    let app = spawn_app().await;
+    for title in ["first", "second", "third"] {
+        publish(&app, title).await;
+    }
+
+    let page = audit_events(&app, "action=newsletter.published&limit=2").await;
+    let titles: Vec<_> = page.iter().map(|e| e["payload"]["title"].clone()).collect();
+    assert_eq!(titles, ["third", "second"]);
+
+    let before = page[1]["audit_event_id"].as_i64().unwrap();
+    let page = audit_events(&app, &format!("action=newsletter.published&before={}", before)).await;
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0]["payload"]["title"], "first");
+}
+
+#[actix_rt::test]
+async fn audit_events_cannot_be_changed() {
+    let app = spawn_app().await;
+    publish(&app, "Release notes").await;
+
+    let result = sqlx::query!("update audit_events set action = 'nothing'")
+        .execute(&app.db_pool)
+        .await;
+    assert!(result.is_err());
+    let result = sqlx::query!("delete from audit_events")
+        .execute(&app.db_pool)
+        .await;
+    assert!(result.is_err());
+}
+
+#[actix_rt::test]
+async fn viewers_cannot_read_the_audit_log() {
+    let app = spawn_app().await;
+    let viewer = crate::helpers::TestUser::generate();
+    viewer.store(&app.db_pool, "viewer").await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/audit_events", &app.address))
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 403);
+}
@@ -0,0 +1,152 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use serde_json::{json, Value};
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+const DEFAULT_LIST: &str = "00000000-0000-0000-0000-000000000001";
+
+async fn spawn_app() -> TestApp {
+    spawn_app_with(|config| config.delivery.workers = 0).await
+}
+
+async fn put_automation(app: &TestApp, event: &str, body: &Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .put(format!("{}/admin/lists/{}/automations/{}", app.address, DEFAULT_LIST, event))
+        .json(body)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Subscribes `email` and returns the confirmation link.
+async fn subscribe(app: &TestApp, email: &str) -> reqwest::Url {
+    let _mg = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = json!({"name": "pog dog", "email": email});
+    app.post_subscriptions_json("/subscriptions", &body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    app.get_links(&request).html
+}
+
+async fn sent_emails(app: &TestApp) -> Vec<Value> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect()
+}
+
+#[actix_rt::test]
+async fn confirmed_subscribers_get_the_welcome_email_once() {
+    let app = spawn_app().await;
+    let welcome = json!({
+        "title": "Welcome, {{ name }}",
+        "content": {"text": "Hi {{ name }}", "html": "<p>Hi {{ name }}</p>"},
+    });
+    let response = put_automation(&app, "confirmed", &welcome).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let confirm_link = subscribe(&app, "pogolius@gmail.com").await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::get(confirm_link.clone()).await.unwrap().error_for_status().unwrap();
+    reqwest::get(confirm_link).await.unwrap().error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let emails = sent_emails(&app).await;
+    assert_eq!(emails.len(), 2, "the confirmation request and one welcome email");
+    assert_eq!(emails[1]["To"], "pogolius@gmail.com");
+    assert_eq!(emails[1]["Subject"], "Welcome, pog dog");
+    assert!(emails[1]["TextBody"].as_str().unwrap().starts_with("Hi pog dog\n"));
+}
+
+#[actix_rt::test]
+async fn unsubscribing_sends_the_goodbye_email() {
+    let app = spawn_app().await;
+    let goodbye = json!({
+        "title": "Sorry to see you go",
+        "content": {"text": "Bye {{ name }}", "html": "<p>Bye {{ name }}</p>"},
+    });
+    put_automation(&app, "unsubscribed", &goodbye)
+        .await
+        .error_for_status()
+        .unwrap();
+    let confirm_link = subscribe(&app, "pogolius@gmail.com").await;
+    let (_, token) = confirm_link.query_pairs().find(|(k, _)| k == "sub_token").unwrap();
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/unsubscribe", app.address))
+        .form(&[("sub_token", token.as_ref())])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let emails = sent_emails(&app).await;
+    assert_eq!(emails.len(), 2);
+    assert_eq!(emails[1]["Subject"], "Sorry to see you go");
+    assert!(emails[1]["TextBody"].as_str().unwrap().starts_with("Bye pog dog\n"));
+}
+
+#[actix_rt::test]
+async fn automations_can_be_listed_replaced_and_removed() {
+    let app = spawn_app().await;
+    let path = format!("{}/admin/lists/{}/automations", app.address, DEFAULT_LIST);
+    let email = |title: &str| json!({"title": title, "content": {"text": "Hi", "html": "Hi"}});
+    put_automation(&app, "confirmed", &email("First")).await.error_for_status().unwrap();
+    put_automation(&app, "confirmed", &email("Second")).await.error_for_status().unwrap();
+
+    let automations: Value = reqwest::Client::new()
+        .get(&path)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(automations.as_array().unwrap().len(), 1);
+    assert_eq!(automations[0]["event"], "confirmed");
+    assert_eq!(automations[0]["title"], "Second");
+
+    let delete = || {
+        reqwest::Client::new()
+            .delete(format!("{}/confirmed", path))
+            .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+            .send()
+    };
+    assert_eq!(delete().await.unwrap().status().as_u16(), 204);
+    assert_eq!(delete().await.unwrap().status().as_u16(), 404);
+}
+
+#[actix_rt::test]
+async fn automations_with_broken_merge_tags_are_rejected() {
+    let app = spawn_app().await;
+
+    let welcome = json!({
+        "title": "Welcome",
+        "content": {"text": "Hi {{ name", "html": "<p>Hi</p>"},
+    });
+    let response = put_automation(&app, "confirmed", &welcome).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_template");
+    assert_eq!(put_automation(&app, "bounced", &welcome).await.status().as_u16(), 400);
+}
@@ -15,3 +15,93 @@ async fn health_check_test() {
     assert!(responce.status().is_success());
     assert_eq!(responce.content_length(), Some(0));
 }
+
+#[actix_rt::test]
+async fn liveness_returns_ok_status() {
+    let test_app = helpers::spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health/live", test_app.address))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+}
+
+#[actix_rt::test]
+async fn readiness_reports_each_component() {
+    let test_app = helpers::spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health/ready", test_app.address))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["components"]["database"]["status"], "ok");
+    assert_eq!(body["components"]["migrations"]["status"], "ok");
+    assert!(body["components"].get("email_provider").is_none());
+}
+
+#[actix_rt::test]
+async fn readiness_fails_if_migrations_are_missing() {
+    let test_app = helpers::spawn_app().await;
+
+    sqlx::query("delete from _sqlx_migrations")
+        .execute(&test_app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(format!("{}/health/ready", test_app.address))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["components"]["database"]["status"], "ok");
+    assert_eq!(body["components"]["migrations"]["status"], "unavailable");
+}
+
+#[actix_rt::test]
+async fn app_starts_while_database_is_down() {
+    let test_app = helpers::spawn_app_with(|config| {
+        config.database.port = 1;
+        config.database.connect_lazy = true;
+        config.database.acquire_timeout_milliseconds = 200;
+    })
+    .await;
+
+    let live = reqwest::get(format!("{}/health/live", test_app.address))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(live.status().as_u16(), 200);
+
+    let ready = reqwest::get(format!("{}/health/ready", test_app.address))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(ready.status().as_u16(), 503);
+    let body: serde_json::Value = ready.json().await.unwrap();
+    assert_eq!(body["components"]["database"]["status"], "unavailable");
+}
+
+#[actix_rt::test]
+async fn unknown_routes_return_problem_details() {
+    let test_app = helpers::spawn_app().await;
+
+    let response = reqwest::get(format!("{}/no/such/route", test_app.address))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "not_found");
+    assert_eq!(problem["type"], "about:blank");
+    assert_eq!(problem["title"], "Not Found");
+}
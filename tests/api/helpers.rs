@@ -1,8 +1,13 @@
 use emailer::config::{read_config, Config};
+use emailer::email_client::EmailClient;
+use emailer::issue_delivery_worker::{try_execute_task, DeliveryContext, ExecutionOutcome};
+use emailer::localisation::Translations;
 use emailer::startup::AppServer;
 use emailer::telemetry::init_logging;
 use once_cell::sync::Lazy;
+use sha3::Digest;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::sync::Arc;
 use uuid::Uuid;
 use wiremock::MockServer;
 
@@ -19,6 +24,46 @@ pub struct TestApp {
     pub port: u16,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub test_user: TestUser,
+    pub email_client: EmailClient,
+    pub delivery_context: DeliveryContext,
+    shutdown: Option<tokio::sync::oneshot::Sender<()>>,
+    server: Option<std::thread::JoinHandle<Result<(), std::io::Error>>>,
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// Stores the user with `role` on every list.
+    pub async fn store(&self, pool: &PgPool, role: &str) {
+        let password_hash = sha3::Sha3_256::digest(self.password.as_bytes());
+        let password_hash = format!("{:x}", password_hash);
+        sqlx::query!(
+            r#"
+            insert into users (user_id, username, password_hash, role)
+            values ($1, $2, $3, $4)
+            "#,
+            self.user_id,
+            self.username,
+            password_hash,
+            role
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user");
+    }
 }
 
 pub struct Links {
@@ -29,7 +74,7 @@ pub struct Links {
 impl TestApp {
     pub async fn post_subsciptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", self.address))
+            .post(format!("{}/subscriptions", self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -37,6 +82,59 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_subscriptions_json(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}{}", self.address, path))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Sends every queued delivery. Tasks that a background worker already
+    /// picked up are waited for.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(&self.db_pool, &self.email_client, &self.delivery_context, 0)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+        for _ in 0..100 {
+            let pending = sqlx::query!("select count(*) as \"count!\" from issue_delivery_queue")
+                .fetch_one(&self.db_pool)
+                .await
+                .unwrap()
+                .count;
+            if pending == 0 {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("Delivery queue was not drained");
+    }
+
+    /// Triggers a graceful shutdown and waits for the server to stop.
+    pub async fn shutdown(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(server) = self.server.take() {
+            tokio::task::spawn_blocking(|| server.join())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+        }
+    }
+
     pub fn get_links(&self, request: &wiremock::Request) -> Links {
         let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
 
@@ -60,9 +158,30 @@ impl TestApp {
             text,
         }
     }
+
+    /// Open pixel and click redirects in the HTML body of a delivered issue.
+    pub fn get_tracking_links(&self, request: &wiremock::Request) -> Vec<reqwest::Url> {
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        linkify::LinkFinder::new()
+            .links(body["HtmlBody"].as_str().unwrap())
+            .filter(|l| l.kind() == &linkify::LinkKind::Url)
+            .map(|l| reqwest::Url::parse(l.as_str()).unwrap())
+            .filter(|link| link.path().starts_with("/t/"))
+            .map(|mut link| {
+                link.set_port(Some(self.port)).unwrap();
+                link
+            })
+            .collect()
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawns the app after the test database is set up, letting the test adjust
+/// the configuration the server is built with.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Config)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -71,19 +190,48 @@ pub async fn spawn_app() -> TestApp {
     config.database.database_name = Uuid::new_v4().to_string();
     config.application.port = 0;
     config.email_client.base_url = email_server.uri();
+    config.application.shutdown_grace_seconds = 5;
+    config.delivery.poll_interval_milliseconds = 50;
 
     let db_pool = configure_database(&config).await;
+    configure(&mut config);
 
-    let server = AppServer::build(config).await.unwrap();
-    let port = server.port();
+    let email_client = config.email_client.client();
+    let delivery_context = DeliveryContext {
+        base_url: config.application.base_url.clone(),
+        translations: Arc::new(Translations::load(&config.localisation).unwrap()),
+    };
+    // The server gets a runtime of its own, so that it still shuts down and
+    // closes its connection pools when a test drops the app without calling
+    // `shutdown`: dropping `shutdown_tx` stops it as well.
+    let (port_tx, port_rx) = tokio::sync::oneshot::channel();
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = std::thread::spawn(move || {
+        actix_rt::System::new().block_on(async move {
+            let server = AppServer::build(config).await.unwrap();
+            let _ = port_tx.send(server.port());
+            server
+                .run_until_stopped(async {
+                    let _ = shutdown_rx.await;
+                })
+                .await
+        })
+    });
+    let port = port_rx.await.expect("Failed to build the app");
 
-    let _ = tokio::spawn(server.run());
+    let test_user = TestUser::generate();
+    test_user.store(&db_pool, "owner").await;
 
     TestApp {
         address: format!("http://127.0.0.1:{port}"),
         port,
         db_pool,
         email_server,
+        test_user,
+        email_client,
+        delivery_context,
+        shutdown: Some(shutdown_tx),
+        server: Some(server),
     }
 }
 
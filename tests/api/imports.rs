@@ -0,0 +1,192 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use serde_json::{json, Value};
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+const DEFAULT_LIST: &str = "00000000-0000-0000-0000-000000000001";
+
+async fn spawn_app() -> TestApp {
+    spawn_app_with(|config| config.delivery.workers = 0).await
+}
+
+async fn post_admin(app: &TestApp, path: &str, body: &Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}{}", app.address, path))
+        .json(body)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_admin(app: &TestApp, path: &str) -> Value {
+    reqwest::Client::new()
+        .get(format!("{}{}", app.address, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn import(app: &TestApp, subscribers: Value) -> reqwest::Response {
+    let path = format!("/admin/lists/{}/subscribers/import", DEFAULT_LIST);
+    post_admin(app, &path, &subscribers).await
+}
+
+async fn add_plan_field(app: &TestApp) {
+    let path = format!("/admin/lists/{}/fields", DEFAULT_LIST);
+    let field = json!({
+        "key": "plan",
+        "type": "enum",
+        "options": ["free", "pro"],
+        "required": true,
+    });
+    post_admin(app, &path, &field).await.error_for_status().unwrap();
+}
+
+#[actix_rt::test]
+async fn imported_subscribers_are_confirmed_with_their_attributes() {
+    let app = spawn_app().await;
+    add_plan_field(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = import(
+        &app,
+        json!([
+            {"email": "ada@example.com", "name": "Ada", "attributes": {"plan": "pro"}},
+            {"email": "bob@example.com", "name": "Bob", "attributes": {"plan": "free"}},
+        ]),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.json::<Value>().await.unwrap(), json!({"imported": 2, "skipped": 0}));
+
+    // Addresses already on the list are left alone.
+    let response = import(
+        &app,
+        json!([
+            {"email": "ADA@example.com", "name": "Someone else", "attributes": {"plan": "free"}},
+            {"email": "cy@example.com", "name": "Cy", "attributes": {"plan": "free"}},
+        ]),
+    )
+    .await;
+    assert_eq!(response.json::<Value>().await.unwrap(), json!({"imported": 1, "skipped": 1}));
+
+    let subscribers = get_admin(&app, &format!("/admin/lists/{}/subscribers", DEFAULT_LIST)).await;
+    let mut subscribers: Vec<_> = subscribers
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| {
+            let field = |name: &str| s[name].clone();
+            (field("email"), field("name"), field("status"), field("attributes"))
+        })
+        .collect();
+    // Subscribers imported together were subscribed at the same time.
+    subscribers.sort_by_key(|s| s.0.as_str().unwrap().to_string());
+    assert_eq!(
+        subscribers,
+        vec![
+            (json!("ada@example.com"), json!("Ada"), json!("confirmed"), json!({"plan": "pro"})),
+            (json!("bob@example.com"), json!("Bob"), json!("confirmed"), json!({"plan": "free"})),
+            (json!("cy@example.com"), json!("Cy"), json!("confirmed"), json!({"plan": "free"})),
+        ]
+    );
+    let events = get_admin(&app, "/admin/audit_events?action=subscribers.imported").await;
+    let payloads: Vec<_> = events.as_array().unwrap().iter().map(|e| &e["payload"]).collect();
+    assert_eq!(payloads.len(), 2);
+    assert!(payloads.contains(&&json!({"imported": 1, "skipped": 1})));
+}
+
+#[actix_rt::test]
+async fn imported_subscribers_are_given_their_tags() {
+    let app = spawn_app().await;
+
+    let response = import(
+        &app,
+        json!([
+            {"email": "ada@example.com", "name": "Ada", "tags": ["Conference-2026", "beta"]},
+            {"email": "bob@example.com", "name": "Bob"},
+        ]),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let path = format!("/admin/lists/{}/subscribers?tag=conference-2026", DEFAULT_LIST);
+    let subscribers = get_admin(&app, &path).await;
+    let emails: Vec<_> = subscribers.as_array().unwrap().iter().map(|s| &s["email"]).collect();
+    assert_eq!(emails, vec!["ada@example.com"]);
+}
+
+#[actix_rt::test]
+async fn an_invalid_subscriber_rejects_the_whole_import() {
+    let app = spawn_app().await;
+    add_plan_field(&app).await;
+
+    let response = import(
+        &app,
+        json!([
+            {"email": "ada@example.com", "name": "Ada", "attributes": {"plan": "pro"}},
+            {"email": "not-an-address", "name": "Bob", "attributes": {"plan": "free"}},
+            {"email": "cy@example.com", "name": "Cy", "attributes": {"plan": "gold", "x": 1}},
+            {"email": "di@example.com", "name": "Di", "attributes": {"plan": "pro"}, "tags": ["a b"]},
+        ]),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: Value = response.json().await.unwrap();
+    let fields: Vec<_> = problem["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["field"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        fields,
+        vec!["1.email", "2.attributes.x", "2.attributes.plan", "3.tags"]
+    );
+    let count = sqlx::query!("select count(*) as \"count!\" from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(count, 0);
+}
+
+#[actix_rt::test]
+async fn imported_subscribers_get_issues_they_can_unsubscribe_from() {
+    let app = spawn_app().await;
+    import(&app, json!([{"email": "ada@example.com", "name": "Ada"}]))
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue = json!({"title": "News", "content": {"text": "Hi {{ name }}", "html": "<p>Hi</p>"}});
+    post_admin(&app, "/newsletters", &issue).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let email: Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(email["To"], "ada@example.com");
+    let text = email["TextBody"].as_str().unwrap();
+    assert!(text.starts_with("Hi Ada"));
+    let link = linkify::LinkFinder::new().links(text).next().unwrap();
+    let mut link = reqwest::Url::parse(link.as_str()).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    assert_eq!(link.path(), "/unsubscribe");
+    let page = reqwest::get(link).await.unwrap();
+    assert_eq!(page.status().as_u16(), 200);
+}
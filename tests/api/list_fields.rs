@@ -0,0 +1,223 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use serde_json::{json, Value};
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+const DEFAULT_LIST: &str = "00000000-0000-0000-0000-000000000001";
+
+async fn spawn_app() -> TestApp {
+    spawn_app_with(|config| config.delivery.workers = 0).await
+}
+
+async fn create_field(app: &TestApp, field: &Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/lists/{}/fields", app.address, DEFAULT_LIST))
+        .json(field)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn post_newsletter(app: &TestApp, body: &Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .json(body)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Subscribes and confirms `email` with `attributes`.
+async fn confirmed_sub(app: &TestApp, email: &str, attributes: Value) {
+    let _mg = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = json!({"name": "pog dog", "email": email, "attributes": attributes});
+    app.post_subscriptions_json("/subscriptions", &body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    reqwest::get(app.get_links(&email).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[actix_rt::test]
+async fn fields_can_be_added_listed_and_removed() {
+    let app = spawn_app().await;
+
+    let response = create_field(&app, &json!({"key": "company", "type": "string"})).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let response = create_field(&app, &json!({"key": "company", "type": "number"})).await;
+    assert_eq!(response.status().as_u16(), 409);
+    let response = create_field(&app, &json!({"key": "email", "type": "string"})).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let fields_url = format!("{}/admin/lists/{}/fields", app.address, DEFAULT_LIST);
+    let fields: Value = reqwest::Client::new()
+        .get(&fields_url)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        fields,
+        json!([{"key": "company", "type": "string", "options": [], "required": false}])
+    );
+
+    confirmed_sub(&app, "pogolius@gmail.com", json!({"company": "Acme"})).await;
+    let response = reqwest::Client::new()
+        .delete(format!("{}/company", fields_url))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    let saved = sqlx::query!("select attributes from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.attributes, json!({}));
+}
+
+#[actix_rt::test]
+async fn subscribers_attributes_are_checked_against_the_fields() {
+    let app = spawn_app().await;
+    create_field(&app, &json!({"key": "seats", "type": "number"})).await;
+    let plan = json!({"key": "plan", "type": "enum", "options": ["free", "pro"], "required": true});
+    create_field(&app, &plan).await;
+
+    let body = json!({
+        "name": "pog dog",
+        "email": "pogolius@gmail.com",
+        "attributes": {"seats": "a dozen", "colour": "red"},
+    });
+    let response = app.post_subscriptions_json("/subscriptions", &body).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: Value = response.json().await.unwrap();
+    let errors: Vec<_> = problem["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["field"].as_str().unwrap(), e["code"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            ("attributes.colour", "unknown_attribute"),
+            ("attributes.seats", "invalid_attribute"),
+            ("attributes.plan", "missing_attribute"),
+        ]
+    );
+}
+
+#[actix_rt::test]
+async fn forms_send_attributes_as_prefixed_fields() {
+    let app = spawn_app().await;
+    create_field(&app, &json!({"key": "seats", "type": "number"})).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=pog%20dog&email=pogolius%40gmail.com&attributes.seats=12";
+    app.post_subsciptions(body.to_string())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("select attributes from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.attributes, json!({"seats": 12.0}));
+}
+
+#[actix_rt::test]
+async fn merge_tags_are_filled_in_for_each_subscriber() {
+    let app = spawn_app().await;
+    create_field(&app, &json!({"key": "company", "type": "string"})).await;
+    confirmed_sub(&app, "pogolius@gmail.com", json!({"company": "<Acme>"})).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue = json!({
+        "title": "News for {{ company }}",
+        "content": {
+            "text": "Hi {{ name }}",
+            "html": "<p>Hi {{ name }} from {{ company }}</p>",
+        },
+    });
+    post_newsletter(&app, &issue).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let email: Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(email["Subject"], "News for <Acme>");
+    // Followed by the unsubscribe footer.
+    assert!(email["TextBody"].as_str().unwrap().starts_with("Hi pog dog\n"));
+    let html = email["HtmlBody"].as_str().unwrap();
+    assert!(html.starts_with("<p>Hi pog dog from &lt;Acme&gt;</p>\n"));
+}
+
+#[actix_rt::test]
+async fn broken_merge_tags_are_rejected() {
+    let app = spawn_app().await;
+
+    let issue = json!({
+        "title": "Title",
+        "content": {"text": "Hi {{ name", "html": "<p>Hi</p>"},
+    });
+    let response = post_newsletter(&app, &issue).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_template");
+}
+
+#[actix_rt::test]
+async fn issues_can_go_to_subscribers_with_some_attributes_only() {
+    let app = spawn_app().await;
+    let plan = json!({"key": "plan", "type": "enum", "options": ["free", "pro"]});
+    create_field(&app, &plan).await;
+    confirmed_sub(&app, "free@example.com", json!({"plan": "free"})).await;
+    confirmed_sub(&app, "pro@example.com", json!({"plan": "pro"})).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue = json!({
+        "title": "Pro news",
+        "content": {"text": "Text", "html": "<p>Text</p>"},
+        "recipient_attributes": {"plan": "pro"},
+    });
+    post_newsletter(&app, &issue).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let email: Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(email["To"], "pro@example.com");
+
+    let issue = json!({
+        "title": "Gold news",
+        "content": {"text": "Text", "html": "<p>Text</p>"},
+        "recipient_attributes": {"plan": "gold"},
+    });
+    let response = post_newsletter(&app, &issue).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,215 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use emailer::authentication::prune_failures;
+use emailer::config::LoginThrottleConfig;
+
+async fn spawn_throttled_app() -> TestApp {
+    spawn_app_with(|config| {
+        config.login_throttle.free_attempts_per_username = 2;
+        config.login_throttle.base_lockout_seconds = 60;
+    })
+    .await
+}
+
+async fn post_newsletters_as(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter titile",
+        "content": {
+            "text": "Text body",
+            "html": "<p>Html body</p>",
+        }
+    });
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&newsletter_req_body)
+        .basic_auth(username, Some(password))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[actix_rt::test]
+async fn repeated_failed_logins_lock_the_username_out() {
+    let app = spawn_throttled_app().await;
+    let username = app.test_user.username.clone();
+
+    for _ in 0..3 {
+        let response = post_newsletters_as(&app, &username, "wrong-password").await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    let response = post_newsletters_as(&app, &username, &app.test_user.password).await;
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 60);
+}
+
+#[actix_rt::test]
+async fn unknown_usernames_are_locked_out_the_same_way() {
+    let app = spawn_throttled_app().await;
+
+    for _ in 0..3 {
+        let response = post_newsletters_as(&app, "nobody", "wrong-password").await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let response = post_newsletters_as(&app, "nobody", "wrong-password").await;
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[actix_rt::test]
+async fn successful_login_resets_the_failure_count() {
+    let app = spawn_throttled_app().await;
+    let username = app.test_user.username.clone();
+
+    for _ in 0..2 {
+        post_newsletters_as(&app, &username, "wrong-password").await;
+    }
+    let response = post_newsletters_as(&app, &username, &app.test_user.password).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    for _ in 0..2 {
+        let response = post_newsletters_as(&app, &username, "wrong-password").await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let response = post_newsletters_as(&app, &username, &app.test_user.password).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn failed_logins_are_recorded() {
+    let app = spawn_throttled_app().await;
+
+    for _ in 0..4 {
+        post_newsletters_as(&app, "nobody", "wrong-password").await;
+    }
+
+    let reasons: Vec<String> = sqlx::query!(
+        "select reason from login_failures where username = 'nobody' order by attempted_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.reason)
+    .collect();
+    // The attempt while locked out is rejected without being recorded.
+    assert_eq!(
+        reasons,
+        vec!["invalid_credentials", "invalid_credentials", "invalid_credentials"]
+    );
+}
+
+async fn recorded_usernames(app: &TestApp) -> Vec<String> {
+    sqlx::query!("select username from login_failures order by username")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.username)
+        .collect()
+}
+
+#[actix_rt::test]
+async fn successful_logins_keep_the_failure_history() {
+    let app = spawn_throttled_app().await;
+    post_newsletters_as(&app, "nobody", "wrong-password").await;
+    sqlx::query!("update login_failures set attempted_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response =
+        post_newsletters_as(&app, &app.test_user.username, &app.test_user.password).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert_eq!(recorded_usernames(&app).await, vec!["nobody"]);
+}
+
+#[actix_rt::test]
+async fn failures_are_pruned_after_their_retention() {
+    let app = spawn_throttled_app().await;
+    post_newsletters_as(&app, "nobody", "wrong-password").await;
+    post_newsletters_as(&app, "somebody", "wrong-password").await;
+    sqlx::query!(
+        "update login_failures set attempted_at = now() - interval '2 days' \
+        where username = 'nobody'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let config = LoginThrottleConfig {
+        failure_retention_seconds: 24 * 60 * 60,
+        ..LoginThrottleConfig::default()
+    };
+    let pruned = prune_failures(&app.db_pool, &config).await.unwrap();
+
+    assert_eq!(pruned, 1);
+    assert_eq!(recorded_usernames(&app).await, vec!["somebody"]);
+}
+
+#[actix_rt::test]
+async fn admins_can_list_and_lift_lockouts() {
+    let app = spawn_throttled_app().await;
+    for _ in 0..3 {
+        post_newsletters_as(&app, "nobody", "wrong-password").await;
+    }
+
+    let lockouts: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/lockouts", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .unwrap();
+    let keys: Vec<_> = lockouts
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|l| l["key"].as_str().unwrap())
+        .collect();
+    assert_eq!(keys, vec!["user:nobody"]);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/lockouts/unlock", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "username": "nobody" }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = post_newsletters_as(&app, "nobody", "wrong-password").await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[actix_rt::test]
+async fn unlock_requires_a_username_or_ip() {
+    let app = spawn_throttled_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/lockouts/unlock", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_rt::test]
+async fn admin_endpoints_require_authentication() {
+    let app = spawn_throttled_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/lockouts", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -1,5 +1,21 @@
+mod ab_tests;
+mod api_tokens;
+mod audit;
+mod automations;
 mod health_check;
 mod helpers;
+mod imports;
+mod list_fields;
+mod login_throttle;
 mod newsletters;
+mod openapi;
+mod pages;
+mod rate_limit;
+mod roles;
+mod segments;
+mod sessions;
+mod sequences;
 mod sub_confirm;
 mod subscriptions;
+mod tags;
+mod two_factor;
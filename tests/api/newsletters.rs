@@ -1,5 +1,5 @@
-use crate::helpers::{spawn_app, Links, TestApp};
-use uuid::Uuid;
+use crate::helpers::{spawn_app, spawn_app_with, Links, TestApp};
+use emailer::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -16,7 +16,7 @@ async fn request_missing_auth_rejected() {
     });
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &test_app.address))
+        .post(format!("{}/newsletters", &test_app.address))
         .json(&newsletter_req_body)
         .send()
         .await
@@ -48,14 +48,15 @@ async fn newsletter_are_not_send_to_unconfirmed_subs() {
         }
     });
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &test_app.address))
+        .post(format!("{}/newsletters", &test_app.address))
         .json(&newsletter_req_body)
-        .basic_auth(Uuid::new_v4().to_string(), Some(Uuid::new_v4().to_string()))
+        .basic_auth(&test_app.test_user.username, Some(&test_app.test_user.password))
         .send()
         .await
         .expect("Failed to execute request");
 
     assert_eq!(response.status().as_u16(), 200);
+    test_app.dispatch_all_pending_emails().await;
 }
 
 #[actix_rt::test]
@@ -77,14 +78,232 @@ async fn newsletter_are_send_to_confirmed_subs() {
         }
     });
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &test_app.address))
+        .post(format!("{}/newsletters", &test_app.address))
         .json(&newsletter_req_body)
-        .basic_auth(Uuid::new_v4().to_string(), Some(Uuid::new_v4().to_string()))
+        .basic_auth(&test_app.test_user.username, Some(&test_app.test_user.password))
         .send()
         .await
         .expect("Failed to execute request");
 
     assert_eq!(response.status().as_u16(), 200);
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn failed_deliveries_are_retried() {
+    // Executes the tasks by hand, a background worker would race for them.
+    let test_app = spawn_app_with(|config| config.delivery.workers = 0).await;
+    create_confirmed_sub(&test_app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    publish_newsletter(&test_app).await;
+    let outcome = try_execute_task(
+        &test_app.db_pool,
+        &test_app.email_client,
+        &test_app.delivery_context,
+        3,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(outcome, ExecutionOutcome::TaskCompleted));
+
+    let task = sqlx::query!("select n_retries from issue_delivery_queue")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed delivery was not kept in the queue");
+    assert_eq!(task.n_retries, 1);
+
+    sqlx::query!("update issue_delivery_queue set execute_after = now()")
+        .execute(&test_app.db_pool)
+        .await
+        .unwrap();
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn shutdown_lets_inflight_delivery_finish() {
+    let mut test_app = spawn_app().await;
+    create_confirmed_sub(&test_app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_millis(500)))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    publish_newsletter(&test_app).await;
+    // Give the background worker time to pick the task up before stopping.
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    test_app.shutdown().await;
+
+    let pending = sqlx::query!("select count(*) as \"count!\" from issue_delivery_queue")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(pending, 0);
+}
+
+#[actix_rt::test]
+async fn shutdown_keeps_unsent_deliveries_queued() {
+    let mut test_app = spawn_app_with(|config| config.delivery.workers = 0).await;
+    create_confirmed_sub(&test_app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    publish_newsletter(&test_app).await;
+    test_app.shutdown().await;
+
+    let pending = sqlx::query!("select count(*) as \"count!\" from issue_delivery_queue")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(pending, 1);
+}
+
+#[actix_rt::test]
+async fn queued_issues_are_not_sent_to_subs_who_unsubscribe() {
+    let test_app = spawn_app_with(|config| config.delivery.workers = 0).await;
+    let links = create_confirmed_sub(&test_app).await;
+    let (_, token) = links.html.query_pairs().find(|(k, _)| k == "sub_token").unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    publish_newsletter(&test_app).await;
+    reqwest::Client::new()
+        .post(format!("{}/unsubscribe", test_app.address))
+        .form(&[("sub_token", token.as_ref())])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let pending = sqlx::query!("select count(*) as \"count!\" from issue_delivery_queue")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(pending, 0);
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn workers_skip_subs_who_are_no_longer_confirmed() {
+    let test_app = spawn_app_with(|config| config.delivery.workers = 0).await;
+    create_confirmed_sub(&test_app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    publish_newsletter(&test_app).await;
+    // As if the sub unsubscribed while the issue was being queued.
+    sqlx::query!("update subscriptions set status = 'unsubscribed'")
+        .execute(&test_app.db_pool)
+        .await
+        .unwrap();
+    let outcome = try_execute_task(
+        &test_app.db_pool,
+        &test_app.email_client,
+        &test_app.delivery_context,
+        3,
+    )
+    .await
+    .unwrap();
+
+    assert!(matches!(outcome, ExecutionOutcome::TaskCompleted));
+    let pending = sqlx::query!("select count(*) as \"count!\" from issue_delivery_queue")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(pending, 0);
+}
+
+#[actix_rt::test]
+async fn issues_link_to_the_unsubscribe_page() {
+    let test_app = spawn_app_with(|config| config.delivery.workers = 0).await;
+    create_confirmed_sub(&test_app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    publish_newsletter(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    let requests = test_app.email_server.received_requests().await.unwrap();
+    let issue: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    let links: Vec<_> = linkify::LinkFinder::new()
+        .links(issue["TextBody"].as_str().unwrap())
+        .collect();
+    assert_eq!(links.len(), 1);
+    assert!(issue["HtmlBody"].as_str().unwrap().contains(links[0].as_str()));
+    let mut link = reqwest::Url::parse(links[0].as_str()).unwrap();
+    link.set_port(Some(test_app.port)).unwrap();
+    assert_eq!(link.path(), "/unsubscribe");
+    let page = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(page.status().as_u16(), 200);
+    let (_, sub_token) = link.query_pairs().find(|(k, _)| k == "sub_token").unwrap();
+    reqwest::Client::new()
+        .post(link.clone())
+        .form(&[("sub_token", sub_token.as_ref())])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+async fn publish_newsletter(app: &TestApp) {
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter titile",
+        "content": {
+            "text": "Text body",
+            "html": "<p>Html body</p>",
+        }
+    });
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&newsletter_req_body)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .error_for_status()
+        .unwrap();
 }
 
 async fn create_unconfirmed_sub(app: &TestApp) -> Links {
@@ -113,11 +332,98 @@ async fn create_unconfirmed_sub(app: &TestApp) -> Links {
     app.get_links(email)
 }
 
-async fn create_confirmed_sub(app: &TestApp) {
+async fn create_confirmed_sub(app: &TestApp) -> Links {
     let links = create_unconfirmed_sub(app).await;
-    reqwest::get(links.html)
+    reqwest::get(links.html.clone())
         .await
         .unwrap()
         .error_for_status()
         .unwrap();
+    links
+}
+
+#[actix_rt::test]
+async fn subscribers_get_the_translation_closest_to_their_locale() {
+    let test_app = spawn_app_with(|config| config.delivery.workers = 0).await;
+    let _mg = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+    let body = serde_json::json!({
+        "name": "pog dog",
+        "email": "pogolius@gmail.com",
+        "locale": "de-AT",
+    });
+    test_app
+        .post_subscriptions_json("/subscriptions", &body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email = &test_app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(test_app.get_links(email).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    drop(_mg);
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {"text": "Text body", "html": "<p>Html body</p>"},
+        "translations": {
+            "de": {
+                "title": "Newsletter-Titel",
+                "content": {"text": "Text", "html": "<p>Text</p>"},
+            },
+            "fr": {
+                "title": "Titre",
+                "content": {"text": "Texte", "html": "<p>Texte</p>"},
+            },
+        },
+    });
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", &test_app.address))
+        .json(&newsletter_req_body)
+        .basic_auth(&test_app.test_user.username, Some(&test_app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    test_app.dispatch_all_pending_emails().await;
+
+    let requests = test_app.email_server.received_requests().await.unwrap();
+    let issue: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(issue["Subject"], "Newsletter-Titel");
+}
+
+#[actix_rt::test]
+async fn translations_must_be_keyed_by_language_tags() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &test_app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Text body", "html": "<p>Html body</p>"},
+            "translations": {
+                "not a locale": {
+                    "title": "Title",
+                    "content": {"text": "Text", "html": "<p>Text</p>"},
+                },
+            },
+        }))
+        .basic_auth(&test_app.test_user.username, Some(&test_app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_locale");
 }
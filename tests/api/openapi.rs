@@ -0,0 +1,63 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use emailer::startup::{api_route_table, health_route_table, ApiRoute};
+
+/// Method and path of every route the OpenAPI document should describe.
+/// Routes of the API table are served under `/api/v1`.
+fn registered_routes() -> Vec<(String, String)> {
+    let route = |route: &ApiRoute, prefix: &str| {
+        let method = route.method.as_str().to_lowercase();
+        (method, format!("{}{}", prefix, route.path))
+    };
+    health_route_table()
+        .iter()
+        .map(|r| route(r, ""))
+        .chain(api_route_table().iter().map(|r| route(r, "/api/v1")))
+        .collect()
+}
+
+#[actix_rt::test]
+async fn every_registered_route_is_in_the_spec() {
+    let app = spawn_app().await;
+    let routes = registered_routes();
+
+    let response = reqwest::get(format!("{}/api/openapi.json", app.address))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let spec: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(spec["openapi"].as_str().unwrap().chars().next(), Some('3'));
+
+    let missing: Vec<_> = routes
+        .iter()
+        .filter(|(method, path)| spec["paths"][path][method].is_null())
+        .collect();
+    assert!(missing.is_empty(), "Routes missing from the spec: {:?}", missing);
+    let documented = spec["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .flat_map(|(path, methods)| methods.as_object().unwrap().keys().map(move |m| (m, path)));
+    let unknown: Vec<_> = documented
+        .filter(|(method, path)| !routes.contains(&(method.to_string(), path.to_string())))
+        .collect();
+    assert!(unknown.is_empty(), "Documented routes that are not served: {:?}", unknown);
+}
+
+#[actix_rt::test]
+async fn the_docs_page_is_only_served_when_enabled() {
+    let app = spawn_app_with(|config| config.application.serve_api_docs = true).await;
+    let response = reqwest::get(format!("{}/api/docs", app.address)).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.url().path(), "/api/docs/");
+    let page = response.text().await.unwrap();
+    assert!(!page.contains("https://"), "The page loads scripts from elsewhere");
+    let initializer = reqwest::get(format!("{}/api/docs/swagger-initializer.js", app.address))
+        .await
+        .unwrap();
+    assert_eq!(initializer.status().as_u16(), 200);
+    assert!(initializer.text().await.unwrap().contains("/api/openapi.json"));
+
+    let app = spawn_app_with(|config| config.application.serve_api_docs = false).await;
+    let response = reqwest::get(format!("{}/api/docs", app.address)).await.unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
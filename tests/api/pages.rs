@@ -0,0 +1,268 @@
+use crate::helpers::{self, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn post_page(app: &TestApp, page: &str, body: &str) -> reqwest::Response {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .post(format!("{}{}", app.address, page))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body.to_string())
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_page(app: &TestApp, page: &str) -> reqwest::Response {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{}{}", app.address, page))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Subscribes through the page and returns the token from the email.
+async fn subscribe(app: &TestApp) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = post_page(app, "/subscribe", "name=pog%20dog&email=pogolius%40gmail.com").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_links(email_request).html;
+    assert_eq!(link.path(), "/confirm");
+    let (_, token) = link.query_pairs().find(|(k, _)| k == "sub_token").unwrap();
+    token.into_owned()
+}
+
+#[actix_rt::test]
+async fn subscribe_page_renders_a_form() {
+    let app = helpers::spawn_app().await;
+
+    let response = get_page(&app, "/subscribe").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"<form method="post" action="/subscribe">"#));
+    assert!(page.contains(r#"name="email""#));
+}
+
+#[actix_rt::test]
+async fn loading_the_subscribe_page_does_not_count_towards_the_ip_limit() {
+    use emailer::config::{Limit, RouteLimits};
+    let app = helpers::spawn_app_with(|config| {
+        config.rate_limit.routes.insert(
+            "subscriptions".to_string(),
+            RouteLimits {
+                per_ip: Some(Limit {
+                    max_requests: 2,
+                    window_seconds: 3600,
+                }),
+                per_email: None,
+            },
+        );
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..5 {
+        assert_eq!(get_page(&app, "/subscribe").await.status().as_u16(), 200);
+    }
+    for i in 0..2 {
+        let body = format!("name=pog%20dog&email=pogolius{}%40gmail.com", i);
+        assert_eq!(post_page(&app, "/subscribe", &body).await.status().as_u16(), 200);
+    }
+    let body = "name=pog%20dog&email=another%40gmail.com";
+    assert_eq!(post_page(&app, "/subscribe", body).await.status().as_u16(), 429);
+    assert_eq!(get_page(&app, "/subscribe").await.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn subscribe_page_of_an_unknown_list_is_not_found() {
+    let app = helpers::spawn_app().await;
+
+    let response = get_page(&app, &format!("/subscribe?list_id={}", Uuid::new_v4())).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_rt::test]
+async fn submitting_the_subscribe_page_asks_to_check_the_inbox() {
+    let app = helpers::spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_page(&app, "/subscribe", "name=pog%20dog&email=pogolius%40gmail.com").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Check your inbox"));
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending");
+}
+
+#[actix_rt::test]
+async fn submitting_an_address_already_on_the_list_asks_to_check_the_inbox() {
+    let app = helpers::spawn_app().await;
+    let token = subscribe(&app).await;
+    get_page(&app, &format!("/confirm?sub_token={}", token)).await;
+
+    let response = post_page(&app, "/subscribe", "name=pog%20dog&email=pogolius%40gmail.com").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Check your inbox"));
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+}
+
+#[actix_rt::test]
+async fn invalid_submissions_show_the_form_again_with_the_errors() {
+    let app = helpers::spawn_app().await;
+
+    let response = post_page(&app, "/subscribe", "name=pog%20dog&email=not-an-address").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"value="pog dog""#));
+    assert!(page.contains(r#"class="error""#));
+}
+
+#[actix_rt::test]
+async fn confirm_page_confirms_the_subscription() {
+    let app = helpers::spawn_app().await;
+    let token = subscribe(&app).await;
+
+    let response = get_page(&app, &format!("/confirm?sub_token={}", token)).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Thanks for confirming"));
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[actix_rt::test]
+async fn confirm_page_explains_unknown_tokens() {
+    let app = helpers::spawn_app().await;
+
+    let response = get_page(&app, "/confirm?sub_token=nonsense").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(response.text().await.unwrap().contains("not valid"));
+}
+
+#[actix_rt::test]
+async fn unsubscribe_page_asks_before_unsubscribing() {
+    let app = helpers::spawn_app().await;
+    let token = subscribe(&app).await;
+
+    let response = get_page(&app, &format!("/unsubscribe?sub_token={}", token)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains(r#"action="/unsubscribe""#));
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending");
+
+    let response = post_page(&app, "/unsubscribe", &format!("sub_token={}", token)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("You will no longer receive"));
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[actix_rt::test]
+async fn lists_can_have_their_own_pages() {
+    let list_id = Uuid::new_v4();
+    let templates = std::env::temp_dir().join(format!("pages-{}", Uuid::new_v4()));
+    let themed = templates.join(format!("lists/{}", list_id));
+    std::fs::create_dir_all(&themed).unwrap();
+    for entry in std::fs::read_dir("templates/pages").unwrap() {
+        let entry = entry.unwrap();
+        std::fs::copy(entry.path(), templates.join(entry.file_name())).unwrap();
+    }
+    std::fs::write(themed.join("subscribe.html"), "Themed {{ list.name }}").unwrap();
+    let app = helpers::spawn_app_with(|config| {
+        config.application.templates_dir = templates.to_str().unwrap().to_string();
+    })
+    .await;
+    sqlx::query!(
+        "insert into lists (list_id, name, created_at) values ($1, 'Weekly', now())",
+        list_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let themed = get_page(&app, &format!("/subscribe?list_id={}", list_id)).await;
+    let default = get_page(&app, "/subscribe").await;
+
+    assert_eq!(themed.text().await.unwrap(), "Themed Weekly");
+    assert!(default.text().await.unwrap().contains("<form"));
+}
+
+#[actix_rt::test]
+async fn redirects_replace_the_success_pages() {
+    let app = helpers::spawn_app_with(|config| {
+        config.application.redirects.after_confirm = Some("http://127.0.0.1/thanks".to_string());
+    })
+    .await;
+    let token = subscribe(&app).await;
+
+    let response = get_page(&app, &format!("/confirm?sub_token={}", token)).await;
+
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers()["location"], "http://127.0.0.1/thanks");
+}
+
+#[actix_rt::test]
+async fn pages_speak_the_language_of_the_browser_then_of_the_subscriber() {
+    let app = helpers::spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscribe", app.address))
+        .header("Accept-Language", "de-DE,de;q=0.9,en;q=0.5")
+        .send()
+        .await
+        .unwrap();
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"<html lang="de">"#));
+    assert!(page.contains("Abonnieren"));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = "name=pog%20dog&email=pogolius%40gmail.com&locale=fr";
+    let response = post_page(&app, "/subscribe", body).await;
+    assert!(response.text().await.unwrap().contains("Consultez votre boîte de réception"));
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let response = reqwest::get(app.get_links(email_request).html).await.unwrap();
+    assert!(response.text().await.unwrap().contains("Merci de votre confirmation"));
+}
@@ -0,0 +1,29 @@
+use crate::helpers::spawn_app;
+use chrono::{Duration, Utc};
+use emailer::rate_limit::{PostgresStore, RateLimitStore};
+
+#[actix_rt::test]
+async fn postgres_store_deletes_counters_whose_window_is_over() {
+    let app = spawn_app().await;
+    let store = PostgresStore::new(app.db_pool.clone());
+    let now = Utc::now();
+
+    store
+        .hit("subscriptions:ip:gone", now - Duration::hours(2), now - Duration::hours(1))
+        .await
+        .unwrap();
+    let hits = store
+        .hit("subscriptions:ip:here", now, now + Duration::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(hits, 1);
+
+    let keys: Vec<_> = sqlx::query!("select key from rate_limit_buckets")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.key)
+        .collect();
+    assert_eq!(keys, vec!["subscriptions:ip:here"]);
+}
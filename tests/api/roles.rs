@@ -0,0 +1,172 @@
+use crate::helpers::{spawn_app, TestApp, TestUser};
+use uuid::Uuid;
+
+async fn add_user(app: &TestApp, role: &str) -> TestUser {
+    let user = TestUser::generate();
+    user.store(&app.db_pool, role).await;
+    user
+}
+
+fn issue(list_id: Option<Uuid>) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "list_id": list_id,
+    })
+}
+
+async fn post_as(
+    app: &TestApp,
+    user: &TestUser,
+    path: &str,
+    body: &serde_json::Value,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}{}", &app.address, path))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(body)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn export_as(app: &TestApp, user: &TestUser, list_id: Uuid) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!(
+            "{}/admin/lists/{}/subscribers/export",
+            &app.address, list_id
+        ))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn create_list(app: &TestApp, name: &str) -> Uuid {
+    let response = post_as(
+        app,
+        &app.test_user,
+        "/admin/lists",
+        &serde_json::json!({ "name": name }),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["list_id"].as_str().unwrap().parse().unwrap()
+}
+
+#[actix_rt::test]
+async fn editors_can_draft_but_only_owners_publish() {
+    let app = spawn_app().await;
+    let editor = add_user(&app, "editor").await;
+
+    let response = post_as(&app, &editor, "/newsletters", &issue(None)).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = post_as(&app, &editor, "/newsletters/drafts", &issue(None)).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap();
+    let publish = format!("/newsletters/drafts/{}/publish", issue_id);
+
+    let response = post_as(&app, &editor, &publish, &serde_json::json!({})).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = post_as(&app, &app.test_user, &publish, &serde_json::json!({})).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = post_as(&app, &app.test_user, &publish, &serde_json::json!({})).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_rt::test]
+async fn viewers_cannot_draft() {
+    let app = spawn_app().await;
+    let viewer = add_user(&app, "viewer").await;
+
+    let response = post_as(&app, &viewer, "/newsletters/drafts", &issue(None)).await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[actix_rt::test]
+async fn list_roles_only_apply_to_their_list() {
+    let app = spawn_app().await;
+    let list_id = create_list(&app, "Release notes").await;
+    let editor = add_user(&app, "editor").await;
+
+    let response = reqwest::Client::new()
+        .put(format!(
+            "{}/admin/users/{}/lists/{}",
+            &app.address, editor.user_id, list_id
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "role": "owner" }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = post_as(&app, &editor, "/newsletters", &issue(Some(list_id))).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = post_as(&app, &editor, "/newsletters", &issue(None)).await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[actix_rt::test]
+async fn only_owners_export_subscribers() {
+    let app = spawn_app().await;
+    app.post_subsciptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let viewer = add_user(&app, "viewer").await;
+    let list_id = Uuid::from_u128(1);
+
+    let response = export_as(&app, &viewer, list_id).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = export_as(&app, &app.test_user, list_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let csv = response.text().await.unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("email,name,status,subscribed_at"));
+    assert!(lines.next().unwrap().starts_with("ursula_le_guin@gmail.com,le guin,"));
+}
+
+#[actix_rt::test]
+async fn publishing_to_an_unknown_list_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = post_as(&app, &app.test_user, "/newsletters", &issue(Some(Uuid::new_v4())))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_rt::test]
+async fn the_last_owner_cannot_be_demoted() {
+    let app = spawn_app().await;
+    let change_role = |user_id: Uuid, role: &str| {
+        reqwest::Client::new()
+            .put(format!("{}/admin/users/{}/role", &app.address, user_id))
+            .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+            .json(&serde_json::json!({ "role": role }))
+            .send()
+    };
+
+    let response = change_role(app.test_user.user_id, "editor").await.unwrap();
+    assert_eq!(response.status().as_u16(), 409);
+
+    let response = change_role(Uuid::new_v4(), "editor").await.unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+
+    let editor = add_user(&app, "editor").await;
+    let response = change_role(editor.user_id, "owner").await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let response = change_role(app.test_user.user_id, "viewer").await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
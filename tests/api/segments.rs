@@ -0,0 +1,331 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+const DEFAULT_LIST: &str = "00000000-0000-0000-0000-000000000001";
+
+async fn spawn_app() -> TestApp {
+    spawn_app_with(|config| config.delivery.workers = 0).await
+}
+
+async fn post_admin(app: &TestApp, path: &str, body: &Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}{}", app.address, path))
+        .json(body)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_admin(app: &TestApp, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}{}", app.address, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn create_segment(app: &TestApp, rules: Value) -> Uuid {
+    let path = format!("/admin/lists/{}/segments", DEFAULT_LIST);
+    let name = format!("Segment {}", Uuid::new_v4());
+    let response = post_admin(app, &path, &json!({"name": name, "rules": rules})).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let segment: Value = response.json().await.unwrap();
+    segment["segment_id"].as_str().unwrap().parse().unwrap()
+}
+
+/// Subscribes and confirms `email` on `list_id`, returning the subscriber id.
+async fn confirmed_sub(app: &TestApp, list_id: &str, email: &str, attributes: Value) -> Uuid {
+    let _mg = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = json!({
+        "name": "pog dog",
+        "email": email,
+        "list_id": list_id,
+        "attributes": attributes,
+    });
+    app.post_subscriptions_json("/subscriptions", &body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    reqwest::get(app.get_links(&request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!(
+        "select id from subscriptions where email = $1 and list_id = $2",
+        email,
+        list_id.parse::<Uuid>().unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .id
+}
+
+async fn add_plan_field(app: &TestApp) {
+    let path = format!("/admin/lists/{}/fields", DEFAULT_LIST);
+    let field = json!({"key": "plan", "type": "enum", "options": ["free", "pro"]});
+    post_admin(app, &path, &field).await.error_for_status().unwrap();
+}
+
+#[actix_rt::test]
+async fn segments_can_be_previewed_before_and_after_saving() {
+    let app = spawn_app().await;
+    add_plan_field(&app).await;
+    confirmed_sub(&app, DEFAULT_LIST, "free@example.com", json!({"plan": "free"})).await;
+    confirmed_sub(&app, DEFAULT_LIST, "pro@example.com", json!({"plan": "pro"})).await;
+    let rules = json!({"attribute": {"key": "plan", "op": "eq", "value": "pro"}});
+
+    let path = format!("/admin/lists/{}/segments/preview", DEFAULT_LIST);
+    let preview: Value = post_admin(&app, &path, &json!({"rules": rules}))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        preview,
+        json!({"count": 1, "sample": [{"email": "pro@example.com", "name": "pog dog"}]})
+    );
+
+    let segment_id = create_segment(&app, rules).await;
+    let path = format!("/admin/lists/{}/segments/{}/preview", DEFAULT_LIST, segment_id);
+    let preview: Value = get_admin(&app, &path).await.json().await.unwrap();
+    assert_eq!(preview["count"], 1);
+
+    let segments: Value = get_admin(&app, &format!("/admin/lists/{}/segments", DEFAULT_LIST))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(segments[0]["segment_id"], segment_id.to_string());
+}
+
+#[actix_rt::test]
+async fn invalid_rules_are_rejected() {
+    let app = spawn_app().await;
+    let path = format!("/admin/lists/{}/segments", DEFAULT_LIST);
+
+    for rules in [
+        json!({"attribute": {"key": "plan", "op": "eq", "value": "pro"}}),
+        json!({"opened": {"within_days": 0}}),
+    ] {
+        let response = post_admin(&app, &path, &json!({"name": "Broken", "rules": rules})).await;
+
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[actix_rt::test]
+async fn segments_can_pick_engaged_subscribers_of_other_lists() {
+    let app = spawn_app().await;
+    let list: Value = post_admin(&app, "/admin/lists", &json!({"name": "Weekly"}))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let weekly = list["list_id"].as_str().unwrap();
+    let both = confirmed_sub(&app, DEFAULT_LIST, "both@example.com", json!({})).await;
+    // The same address, as far as lists are concerned.
+    confirmed_sub(&app, weekly, "Both@example.com", json!({})).await;
+    let default_only = confirmed_sub(&app, DEFAULT_LIST, "one@example.com", json!({})).await;
+    let issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        insert into newsletter_issues (newsletter_issue_id, title, text_content, html_content)
+        values ($1, 'Old news', 'Text', '<p>Text</p>')
+        "#,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    for (subscriber_id, days_ago) in [(both, 40), (default_only, 5)] {
+        sqlx::query!(
+            r#"
+            insert into engagement_events (subscriber_id, newsletter_issue_id, kind, occurred_at)
+            values ($1, $2, 'opened', now() - make_interval(days => $3))
+            "#,
+            subscriber_id,
+            issue_id,
+            days_ago
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    let path = format!("/admin/lists/{}/segments/preview", DEFAULT_LIST);
+    let count = |rules: Value| {
+        let app = &app;
+        let path = &path;
+        async move {
+            let preview: Value = post_admin(app, path, &json!({"rules": rules}))
+                .await
+                .json()
+                .await
+                .unwrap();
+            preview["count"].as_i64().unwrap()
+        }
+    };
+
+    assert_eq!(count(json!({"opened": {"within_days": 30}})).await, 1);
+    assert_eq!(count(json!({"opened": {"within_days": 60}})).await, 2);
+    assert_eq!(count(json!({"clicked": {"within_days": 60}})).await, 0);
+    assert_eq!(count(json!({"list": weekly})).await, 1);
+    assert_eq!(count(json!({"not": {"list": weekly}})).await, 1);
+    assert_eq!(count(json!({"subscribed_before": "2000-01-01T00:00:00Z"})).await, 0);
+}
+
+#[actix_rt::test]
+async fn issues_can_go_to_a_segment_only() {
+    let app = spawn_app().await;
+    add_plan_field(&app).await;
+    confirmed_sub(&app, DEFAULT_LIST, "free@example.com", json!({"plan": "free"})).await;
+    confirmed_sub(&app, DEFAULT_LIST, "pro@example.com", json!({"plan": "pro"})).await;
+    let segment_id =
+        create_segment(&app, json!({"attribute": {"key": "plan", "op": "ne", "value": "pro"}}))
+            .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue = json!({
+        "title": "Upgrade",
+        "content": {"text": "Text", "html": "<p>Text</p>"},
+        "segment_id": segment_id,
+    });
+    let response = post_admin(&app, "/newsletters/drafts", &issue).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let draft: Value = response.json().await.unwrap();
+    // The draft keeps the segment as it was.
+    let path = format!("/admin/lists/{}/segments/{}", DEFAULT_LIST, segment_id);
+    let response = reqwest::Client::new()
+        .delete(format!("{}{}", app.address, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    let draft_id = draft["newsletter_issue_id"].as_str().unwrap();
+    let path = format!("/newsletters/drafts/{}/publish", draft_id);
+    post_admin(&app, &path, &json!({})).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let email: Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(email["To"], "free@example.com");
+
+    let response = post_admin(&app, "/newsletters", &issue).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_rt::test]
+async fn issues_only_go_to_segments_referring_to_lists_the_user_may_see() {
+    let app = spawn_app().await;
+    let list: Value = post_admin(&app, "/admin/lists", &json!({"name": "Weekly"}))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let weekly = list["list_id"].as_str().unwrap();
+    let tagged = create_segment(&app, json!({"tag": "beta"})).await;
+    let probing = create_segment(&app, json!({"any": [{"tag": "beta"}, {"list": weekly}]})).await;
+    let draft = |segment_id: Uuid| {
+        json!({
+            "title": "Beta",
+            "content": {"text": "Text", "html": "<p>Text</p>"},
+            "segment_id": segment_id,
+        })
+    };
+    let response = post_admin(&app, "/newsletters/drafts", &draft(probing)).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let probing_draft: Value = response.json().await.unwrap();
+    // Tokens without the subscribers:read scope may not see any subscribers.
+    let response = post_admin(
+        &app,
+        "/admin/api_tokens",
+        &json!({"name": "ci", "scopes": ["newsletters:publish"], "expires_in_days": 30}),
+    )
+    .await;
+    let token: Value = response.json().await.unwrap();
+    let post_with_token = |path: String, body: Value| {
+        let token = token["token"].as_str().unwrap().to_string();
+        let url = format!("{}{}", app.address, path);
+        async move {
+            reqwest::Client::new()
+                .post(url)
+                .bearer_auth(token)
+                .json(&body)
+                .send()
+                .await
+                .unwrap()
+                .status()
+                .as_u16()
+        }
+    };
+
+    assert_eq!(post_with_token("/newsletters/drafts".into(), draft(tagged)).await, 201);
+    assert_eq!(post_with_token("/newsletters/drafts".into(), draft(probing)).await, 403);
+    let draft_id = probing_draft["newsletter_issue_id"].as_str().unwrap();
+    let path = format!("/newsletters/drafts/{}/publish", draft_id);
+    assert_eq!(post_with_token(path, json!({})).await, 403);
+}
+
+#[actix_rt::test]
+async fn opens_and_clicks_of_delivered_issues_make_subscribers_engaged() {
+    let app = spawn_app().await;
+    confirmed_sub(&app, DEFAULT_LIST, "opener@example.com", json!({})).await;
+    confirmed_sub(&app, DEFAULT_LIST, "clicker@example.com", json!({})).await;
+    confirmed_sub(&app, DEFAULT_LIST, "idle@example.com", json!({})).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let issue = json!({
+        "title": "News",
+        "content": {"text": "Text", "html": "<a href=\"https://example.com/news\">News</a>"},
+    });
+    post_admin(&app, "/newsletters", &issue).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    for request in &requests[requests.len() - 3..] {
+        let email: Value = serde_json::from_slice(&request.body).unwrap();
+        let links = app.get_tracking_links(request);
+        let kind = match email["To"].as_str().unwrap() {
+            "opener@example.com" => "/open",
+            "clicker@example.com" => "/click/",
+            _ => continue,
+        };
+        let link = links.into_iter().find(|l| l.path().contains(kind)).unwrap();
+        client.get(link).send().await.unwrap().error_for_status().unwrap();
+    }
+
+    let path = format!("/admin/lists/{}/segments/preview", DEFAULT_LIST);
+    for (rules, email) in [
+        (json!({"opened": {"within_days": 1}}), "opener@example.com"),
+        (json!({"clicked": {"within_days": 1}}), "clicker@example.com"),
+    ] {
+        let preview: Value = post_admin(&app, &path, &json!({"rules": rules}))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(preview["count"], 1, "{}", rules);
+        assert_eq!(preview["sample"][0]["email"], email, "{}", rules);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use emailer::sequences::advance_due_enrolments;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+const DEFAULT_LIST: &str = "00000000-0000-0000-0000-000000000001";
+
+async fn spawn_app() -> TestApp {
+    spawn_app_with(|config| config.delivery.workers = 0).await
+}
+
+fn admin(app: &TestApp, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .request(method, format!("{}/admin/lists/{}{}", app.address, DEFAULT_LIST, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+}
+
+async fn create_sequence(app: &TestApp, sequence: &Value) -> reqwest::Response {
+    admin(app, reqwest::Method::POST, "/sequences")
+        .json(sequence)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn enrolments(app: &TestApp, sequence_id: &str) -> Vec<Value> {
+    admin(app, reqwest::Method::GET, &format!("/sequences/{}/subscribers", sequence_id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+fn step(delay_days: i32, title: &str) -> Value {
+    json!({
+        "delay_days": delay_days,
+        "title": title,
+        "content": {"text": "Hi {{ name }}", "html": "<p>Hi {{ name }}</p>"},
+    })
+}
+
+/// Subscribes and confirms `email` with `tags`, returning the confirmation
+/// link.
+async fn confirmed_sub(app: &TestApp, email: &str, tags: Value) -> reqwest::Url {
+    let _mg = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = json!({"name": "pog dog", "email": email, "tags": tags});
+    app.post_subscriptions_json("/subscriptions", &body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let link = app.get_links(&request).html;
+    reqwest::get(link.clone()).await.unwrap().error_for_status().unwrap();
+    link
+}
+
+/// Subjects of the emails sent to subscribers so far, leaving out
+/// confirmation requests.
+async fn sent_titles(app: &TestApp) -> Vec<String> {
+    app.dispatch_all_pending_emails().await;
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice::<Value>(&request.body).unwrap())
+        .map(|email| email["Subject"].as_str().unwrap().to_string())
+        .filter(|subject| !subject.contains("onfirm"))
+        .collect()
+}
+
+/// Moves every enrolment `days` into the past.
+async fn travel(app: &TestApp, days: i32) {
+    sqlx::query!(
+        r#"
+        update sequence_enrolments
+        set enrolled_at = enrolled_at - make_interval(days => $1),
+            next_step_at = next_step_at - make_interval(days => $1)
+        "#,
+        days
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[actix_rt::test]
+async fn steps_are_sent_at_their_offsets_after_confirmation() {
+    let app = spawn_app().await;
+    let sequence = json!({
+        "name": "Onboarding",
+        "trigger": "confirmed",
+        "steps": [step(0, "Welcome"), step(3, "Tips"), step(7, "Pro features")],
+    });
+    let response = create_sequence(&app, &sequence).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let sequence: Value = response.json().await.unwrap();
+    let sequence_id = sequence["sequence_id"].as_str().unwrap();
+    assert_eq!(sequence["steps"].as_array().unwrap().len(), 3);
+    confirmed_sub(&app, "pogolius@gmail.com", json!([])).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    advance_due_enrolments(&app.db_pool).await.unwrap();
+    assert_eq!(sent_titles(&app).await, vec!["Welcome"]);
+    let enrolment = &enrolments(&app, sequence_id).await[0];
+    assert_eq!(enrolment["email"], "pogolius@gmail.com");
+    assert_eq!(enrolment["status"], "active");
+    assert_eq!(enrolment["next_step"], 1);
+
+    advance_due_enrolments(&app.db_pool).await.unwrap();
+    travel(&app, 3).await;
+    advance_due_enrolments(&app.db_pool).await.unwrap();
+    assert_eq!(sent_titles(&app).await, vec!["Welcome", "Tips"]);
+
+    travel(&app, 4).await;
+    advance_due_enrolments(&app.db_pool).await.unwrap();
+    assert_eq!(sent_titles(&app).await, vec!["Welcome", "Tips", "Pro features"]);
+    let enrolment = &enrolments(&app, sequence_id).await[0];
+    assert_eq!(enrolment["status"], "completed");
+    assert_eq!(enrolment["next_step_at"], Value::Null);
+}
+
+#[actix_rt::test]
+async fn tagging_confirmed_subscribers_enrols_them() {
+    let app = spawn_app().await;
+    let sequence = json!({
+        "name": "Beta",
+        "trigger": {"tagged": "Beta-Tester"},
+        "steps": [step(0, "Welcome to the beta")],
+    });
+    let sequence: Value = create_sequence(&app, &sequence).await.json().await.unwrap();
+    assert_eq!(sequence["trigger"], json!({"tagged": "beta-tester"}));
+    let sequence_id = sequence["sequence_id"].as_str().unwrap();
+    confirmed_sub(&app, "form@example.com", json!(["beta-tester"])).await;
+    confirmed_sub(&app, "bulk@example.com", json!([])).await;
+    confirmed_sub(&app, "other@example.com", json!([])).await;
+
+    admin(&app, reqwest::Method::POST, "/subscribers/tags")
+        .json(&json!({"subscribers": ["bulk@example.com"], "add": ["beta-tester"]}))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let enrolled: Vec<_> = enrolments(&app, sequence_id)
+        .await
+        .into_iter()
+        .map(|e| e["email"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(enrolled, vec!["form@example.com", "bulk@example.com"]);
+}
+
+#[actix_rt::test]
+async fn subscribers_leave_when_they_unsubscribe_or_leave_the_segment() {
+    let app = spawn_app().await;
+    let segment: Value = admin(&app, reqwest::Method::POST, "/segments")
+        .json(&json!({"name": "VIPs", "rules": {"tag": "vip"}}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let sequence = json!({
+        "name": "VIP onboarding",
+        "trigger": "confirmed",
+        "segment_id": segment["segment_id"],
+        "steps": [step(1, "Hello VIP")],
+    });
+    let sequence: Value = create_sequence(&app, &sequence).await.json().await.unwrap();
+    let sequence_id = sequence["sequence_id"].as_str().unwrap();
+    let link = confirmed_sub(&app, "leaving@example.com", json!(["vip"])).await;
+    confirmed_sub(&app, "regular@example.com", json!([])).await;
+    let (_, token) = link.query_pairs().find(|(k, _)| k == "sub_token").unwrap();
+    reqwest::Client::new()
+        .post(format!("{}/unsubscribe", app.address))
+        .form(&[("sub_token", token.as_ref())])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    travel(&app, 1).await;
+    advance_due_enrolments(&app.db_pool).await.unwrap();
+
+    assert!(sent_titles(&app).await.is_empty());
+    let exits: Vec<_> = enrolments(&app, sequence_id)
+        .await
+        .into_iter()
+        .map(|e| (e["email"].clone(), e["status"].clone(), e["exit_reason"].clone()))
+        .collect();
+    assert_eq!(
+        exits,
+        vec![
+            (json!("leaving@example.com"), json!("exited"), json!("unsubscribed")),
+            (json!("regular@example.com"), json!("exited"), json!("left_segment")),
+        ]
+    );
+}
+
+#[actix_rt::test]
+async fn sequences_are_checked_and_can_be_updated_and_deleted() {
+    let app = spawn_app().await;
+    let backwards = json!({
+        "name": "Onboarding",
+        "trigger": "confirmed",
+        "steps": [step(3, "Later"), step(1, "Sooner")],
+    });
+    assert_eq!(create_sequence(&app, &backwards).await.status().as_u16(), 400);
+    let sequence = json!({
+        "name": "Onboarding",
+        "trigger": "confirmed",
+        "steps": [step(0, "Welcome"), step(2, "Tips")],
+    });
+    let created: Value = create_sequence(&app, &sequence).await.json().await.unwrap();
+    assert_eq!(create_sequence(&app, &sequence).await.status().as_u16(), 409);
+    let path = format!("/sequences/{}", created["sequence_id"].as_str().unwrap());
+    confirmed_sub(&app, "pogolius@gmail.com", json!([])).await;
+
+    let shorter = json!({"name": "Onboarding", "trigger": "confirmed", "steps": [step(1, "Hi")]});
+    let response = admin(&app, reqwest::Method::PUT, &path)
+        .json(&shorter)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let updated: Value = response.json().await.unwrap();
+    assert_eq!(updated["steps"][0]["title"], "Hi");
+    assert_eq!(updated["enrolments"], json!({"active": 1, "completed": 0, "exited": 0}));
+
+    let delete = || admin(&app, reqwest::Method::DELETE, &path).send();
+    assert_eq!(delete().await.unwrap().status().as_u16(), 204);
+    assert_eq!(delete().await.unwrap().status().as_u16(), 404);
+}
+
+#[actix_rt::test]
+async fn issues_of_sequence_steps_can_not_be_deleted() {
+    let app = spawn_app().await;
+    let sequence = json!({
+        "name": "Onboarding",
+        "trigger": "confirmed",
+        "steps": [step(0, "Welcome")],
+    });
+    let created: Value = create_sequence(&app, &sequence).await.json().await.unwrap();
+    let sequence_id: Uuid = created["sequence_id"].as_str().unwrap().parse().unwrap();
+    let issue_id = sqlx::query!(
+        "select newsletter_issue_id from sequence_steps where sequence_id = $1",
+        sequence_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id;
+
+    let error = sqlx::query!(
+        "delete from newsletter_issues where newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap_err();
+    let code = error.as_database_error().and_then(|e| e.code()).unwrap();
+    assert_eq!(code, "23503");
+
+    let delete = admin(&app, reqwest::Method::DELETE, &format!("/sequences/{}", sequence_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(delete.status().as_u16(), 204);
+    sqlx::query!(
+        "delete from newsletter_issues where newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
@@ -0,0 +1,74 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn open_session(app: &TestApp) -> String {
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/sessions", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["expires_at"].is_string());
+    let token = body["token"].as_str().unwrap().to_string();
+    assert!(token.starts_with("ems_"));
+    token
+}
+
+async fn list_api_tokens(app: &TestApp, session: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/api_tokens", &app.address))
+        .bearer_auth(session)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[actix_rt::test]
+async fn sessions_work_like_the_password_until_they_end() {
+    let app = spawn_app().await;
+    let session = open_session(&app).await;
+
+    // Account management needs an interactive login, which a session is.
+    let response = list_api_tokens(&app, &session).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/admin/sessions/current", &app.address))
+        .bearer_auth(&session)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = list_api_tokens(&app, &session).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[actix_rt::test]
+async fn expired_sessions_are_rejected() {
+    let app = spawn_app().await;
+    let session = open_session(&app).await;
+
+    sqlx::query!("update login_sessions set expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = list_api_tokens(&app, &session).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[actix_rt::test]
+async fn sessions_cannot_open_further_sessions() {
+    let app = spawn_app().await;
+    let session = open_session(&app).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/sessions", &app.address))
+        .bearer_auth(&session)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -34,6 +34,32 @@ async fn confirmation_link_from_subsribe_returns_200() {
         .unwrap();
 
     assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+    assert!(response.text().await.unwrap().contains("Thanks for confirming"));
+}
+
+#[actix_rt::test]
+async fn api_confirmation_returns_the_confirmed_status() {
+    let test_app = helpers::spawn_app().await;
+    let body = "name=pog%20dog&email=pogolius%40gmail.com".to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let _ = test_app.post_subsciptions(body).await;
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let mut link = test_app.get_links(email_request).html;
+    link.set_path("/api/v1/subscriptions/confirm");
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let confirmed: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(confirmed["status"], "confirmed");
 }
 
 #[actix_rt::test]
@@ -66,3 +92,46 @@ async fn confirmation_link_confirms() {
     assert_eq!(saved.name, "pog dog");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[actix_rt::test]
+async fn old_confirmation_links_do_not_undo_an_unsubscribe() {
+    let test_app = helpers::spawn_app().await;
+    let body = "name=pog%20dog&email=pogolius%40gmail.com".to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let _ = test_app.post_subsciptions(body).await;
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let links = test_app.get_links(email_request);
+    reqwest::get(links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let (_, token) = links.html.query_pairs().find(|(k, _)| k == "sub_token").unwrap();
+    reqwest::Client::new()
+        .post(format!("{}/unsubscribe", test_app.address))
+        .form(&[("sub_token", token.as_ref())])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    reqwest::get(links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved sub");
+    assert_eq!(saved.status, "unsubscribed");
+}
@@ -71,6 +71,66 @@ async fn subscribe_ret_400_if_invalid_form() {
     }
 }
 
+#[actix_rt::test]
+async fn subscribe_reports_invalid_fields_as_problem_details() {
+    let test_app = helpers::spawn_app().await;
+
+    let responce = test_app
+        .post_subsciptions("name=%3Cpog%3E&email=some_mail_address".to_string())
+        .await;
+
+    assert_eq!(responce.status().as_u16(), 400);
+    assert_eq!(
+        responce.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let request_id = responce.headers()["X-Request-Id"].to_str().unwrap().to_string();
+    let problem: serde_json::Value = responce.json().await.unwrap();
+    assert_eq!(problem["status"], 400);
+    assert_eq!(problem["code"], "validation_error");
+    assert_eq!(problem["request_id"], request_id);
+    let fields: Vec<_> = problem["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["field"].as_str().unwrap(), e["code"].as_str().unwrap()))
+        .collect();
+    assert_eq!(fields, [("name", "forbidden_char"), ("email", "invalid_syntax")]);
+}
+
+#[actix_rt::test]
+async fn subscribe_reports_why_a_field_is_invalid() {
+    let test_app = helpers::spawn_app().await;
+    let cases = [
+        (format!("name=%20&email={}", "pog%40dog.log"), "name", "empty"),
+        (format!("name={}&email=pog%40dog.log", "a".repeat(257)), "name", "too_long"),
+        ("name=pog&email=".to_string(), "email", "empty"),
+    ];
+
+    for (form, field, code) in cases {
+        let responce = test_app.post_subsciptions(form).await;
+
+        assert_eq!(responce.status().as_u16(), 400);
+        let problem: serde_json::Value = responce.json().await.unwrap();
+        let errors = problem["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0]["field"], field);
+        assert_eq!(errors[0]["code"], code);
+    }
+}
+
+#[actix_rt::test]
+async fn subscribe_reports_malformed_forms_as_problem_details() {
+    let test_app = helpers::spawn_app().await;
+
+    let responce = test_app.post_subsciptions("name=pog%20dog".to_string()).await;
+
+    assert_eq!(responce.status().as_u16(), 400);
+    let problem: serde_json::Value = responce.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_request");
+    assert!(problem["detail"].as_str().unwrap().contains("email"));
+}
+
 #[actix_rt::test]
 async fn subscribe_fails_if_database_error() {
     let test_app = helpers::spawn_app().await;
@@ -84,4 +144,436 @@ async fn subscribe_fails_if_database_error() {
     let responce = test_app.post_subsciptions(body).await;
 
     assert_eq!(responce.status().as_u16(), 500);
+    let problem: serde_json::Value = responce.json().await.unwrap();
+    assert_eq!(problem["code"], "internal_error");
+    assert!(problem.get("detail").is_none());
+}
+
+#[actix_rt::test]
+async fn subscribe_ret_413_if_payload_too_large() {
+    let test_app = helpers::spawn_app_with(|config| {
+        config.application.payload_limit_bytes = 64;
+    })
+    .await;
+    let body = format!("name={}&email=pogolius%40gmail.com", "a".repeat(128));
+
+    let responce = test_app.post_subsciptions(body).await;
+
+    assert_eq!(responce.status().as_u16(), 413);
+}
+
+fn limited_config(config: &mut emailer::config::Config, per_ip: u32, per_email: u32) {
+    use emailer::config::{Limit, RouteLimits};
+    config.rate_limit.routes.insert(
+        "subscriptions".to_string(),
+        RouteLimits {
+            per_ip: Some(Limit {
+                max_requests: per_ip,
+                window_seconds: 3600,
+            }),
+            per_email: Some(Limit {
+                max_requests: per_email,
+                window_seconds: 3600,
+            }),
+        },
+    );
+}
+
+#[actix_rt::test]
+async fn subscribe_ret_429_when_ip_limit_exceeded() {
+    let test_app = helpers::spawn_app_with(|config| limited_config(config, 2, 100)).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    for i in 0..2 {
+        let body = format!("name=pog%20dog&email=pogolius{}%40gmail.com", i);
+        let responce = test_app.post_subsciptions(body).await;
+        assert_eq!(responce.status().as_u16(), 200);
+    }
+
+    let body = "name=pog%20dog&email=another%40gmail.com".to_string();
+    let responce = test_app.post_subsciptions(body).await;
+
+    assert_eq!(responce.status().as_u16(), 429);
+    let retry_after: u64 = responce.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 3600);
+    // Rejected by middleware, before the handler ran.
+    let request_id = responce.headers()["X-Request-Id"].to_str().unwrap().to_string();
+    let problem: serde_json::Value = responce.json().await.unwrap();
+    assert_eq!(problem["code"], "rate_limited");
+    assert_eq!(problem["request_id"], request_id);
+}
+
+#[actix_rt::test]
+async fn subscribe_ret_429_when_email_limit_exceeded() {
+    let test_app = helpers::spawn_app_with(|config| {
+        limited_config(config, 100, 1);
+        config.rate_limit.store = emailer::config::RateLimitStoreKind::Postgres;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = "name=pog%20dog&email=pogolius%40gmail.com".to_string();
+    let responce = test_app.post_subsciptions(body).await;
+    assert_eq!(responce.status().as_u16(), 200);
+
+    let body = "name=pog%20dog&email=POGOLIUS%40gmail.com".to_string();
+    let responce = test_app.post_subsciptions(body).await;
+    assert_eq!(responce.status().as_u16(), 429);
+    assert!(responce.headers().contains_key("Retry-After"));
+}
+
+#[actix_rt::test]
+async fn email_limit_is_checked_before_looking_up_mail_servers() {
+    let test_app = helpers::spawn_app_with(|config| limited_config(config, 100, 1)).await;
+
+    let body = "name=pog%20dog&email=pog%40no-mx.example.com".to_string();
+    let responce = test_app.post_subsciptions(body.clone()).await;
+    assert_eq!(responce.status().as_u16(), 400);
+
+    let responce = test_app.post_subsciptions(body).await;
+    assert_eq!(responce.status().as_u16(), 429);
+}
+
+#[actix_rt::test]
+async fn subscribe_silently_drops_filled_honeypot() {
+    let test_app = helpers::spawn_app().await;
+    let body = "name=pog%20dog&email=pogolius%40gmail.com&website=spam.com".to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    let responce = test_app.post_subsciptions(body).await;
+
+    assert_eq!(responce.status().as_u16(), 200);
+    let saved = sqlx::query!("select email from subscriptions")
+        .fetch_optional(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[actix_rt::test]
+async fn subscribe_silently_drops_forms_filled_too_fast() {
+    let test_app = helpers::spawn_app_with(|config| {
+        config.spam_trap.min_form_fill_seconds = 5;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let now = chrono::Utc::now().timestamp();
+    let body = format!(
+        "name=pog%20dog&email=pogolius%40gmail.com&form_rendered_at={}",
+        now
+    );
+    let responce = test_app.post_subsciptions(body).await;
+    assert_eq!(responce.status().as_u16(), 200);
+
+    let body = format!(
+        "name=pog%20dog&email=pogolius%40gmail.com&form_rendered_at={}",
+        now - 10
+    );
+    let responce = test_app.post_subsciptions(body).await;
+    assert_eq!(responce.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn subscribe_stores_the_domain_in_lowercase() {
+    let test_app = helpers::spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = "name=pog%20dog&email=%20Pogolius%40GMail.COM%20".to_string();
+    let responce = test_app.post_subsciptions(body).await;
+    assert_eq!(responce.status().as_u16(), 200);
+
+    let saved = sqlx::query!("select email from subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "Pogolius@gmail.com");
+}
+
+#[actix_rt::test]
+async fn subscribing_a_pending_address_again_resends_the_confirmation() {
+    let test_app = helpers::spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = "name=pog%20dog&email=pogolius%40gmail.com".to_string();
+    let first: serde_json::Value = test_app.post_subsciptions(body).await.json().await.unwrap();
+
+    // The address differs only in case, so it is the same subscriber.
+    let body = "name=pog%20dog&email=POGOLIUS%40gmail.com".to_string();
+    let responce = test_app.post_subsciptions(body).await;
+    assert_eq!(responce.status().as_u16(), 200);
+    let second: serde_json::Value = responce.json().await.unwrap();
+    assert_eq!(second["subscriber_id"], first["subscriber_id"]);
+    assert_eq!(second["status"], "pending");
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[1];
+    reqwest::get(test_app.get_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[actix_rt::test]
+async fn subscribing_a_confirmed_address_again_looks_like_a_new_subscription() {
+    let test_app = helpers::spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = "name=pog%20dog&email=pogolius%40gmail.com".to_string();
+    let first: serde_json::Value = test_app.post_subsciptions(body).await.json().await.unwrap();
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(test_app.get_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let body = "name=pog%20dog&email=pogolius%40gmail.com".to_string();
+    let responce = test_app.post_subsciptions(body).await;
+    assert_eq!(responce.status().as_u16(), 200);
+    let second: serde_json::Value = responce.json().await.unwrap();
+    assert_ne!(second["subscriber_id"], first["subscriber_id"]);
+    assert_eq!(second["status"], "pending");
+
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[actix_rt::test]
+async fn subscribe_rejects_undeliverable_addresses() {
+    let test_app = helpers::spawn_app().await;
+    let cases = [
+        ("pog%40mailinator.com", "disposable_domain"),
+        ("pog%40eu.mailinator.com", "disposable_domain"),
+        ("pog%40no-mx.example.com", "no_mail_server"),
+    ];
+
+    for (email, code) in cases {
+        let responce = test_app
+            .post_subsciptions(format!("name=pog%20dog&email={}", email))
+            .await;
+
+        assert_eq!(responce.status().as_u16(), 400);
+        let problem: serde_json::Value = responce.json().await.unwrap();
+        assert_eq!(problem["code"], "validation_error");
+        assert_eq!(problem["errors"][0]["field"], "email");
+        assert_eq!(problem["errors"][0]["code"], code, "{}", email);
+    }
+}
+
+#[actix_rt::test]
+async fn subscribe_suggests_a_fix_for_typos_unless_told_otherwise() {
+    let test_app = helpers::spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = "name=pog%20dog&email=pogolius%40gmial.com".to_string();
+    let responce = test_app.post_subsciptions(body).await;
+    assert_eq!(responce.status().as_u16(), 400);
+    let problem: serde_json::Value = responce.json().await.unwrap();
+    assert_eq!(problem["errors"][0]["code"], "possible_typo");
+    assert_eq!(problem["errors"][0]["suggestion"], "pogolius@gmail.com");
+
+    let body = "name=pog%20dog&email=pogolius%40gmial.com&skip_typo_check=true".to_string();
+    let responce = test_app.post_subsciptions(body).await;
+    assert_eq!(responce.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn subscribe_accepts_json_under_the_versioned_path() {
+    let test_app = helpers::spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = serde_json::json!({"name": "pog dog", "email": "pogolius@gmail.com"});
+    let responce = test_app
+        .post_subscriptions_json("/api/v1/subscriptions", &body)
+        .await;
+
+    assert_eq!(responce.status().as_u16(), 200);
+    let created: serde_json::Value = responce.json().await.unwrap();
+    assert_eq!(created["status"], "pending");
+    let saved = sqlx::query!("select id, name from subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(created["subscriber_id"], saved.id.to_string());
+    assert_eq!(saved.name, "pog dog");
+}
+
+#[actix_rt::test]
+async fn subscribe_reports_invalid_json_like_invalid_forms() {
+    let test_app = helpers::spawn_app().await;
+
+    let body = serde_json::json!({"name": "pog dog", "email": "some_mail_address"});
+    let responce = test_app.post_subscriptions_json("/subscriptions", &body).await;
+    assert_eq!(responce.status().as_u16(), 400);
+    let problem: serde_json::Value = responce.json().await.unwrap();
+    assert_eq!(problem["code"], "validation_error");
+    assert_eq!(problem["errors"][0]["code"], "invalid_syntax");
+
+    let body = serde_json::json!({"name": "pog dog"});
+    let responce = test_app.post_subscriptions_json("/subscriptions", &body).await;
+    assert_eq!(responce.status().as_u16(), 400);
+    let problem: serde_json::Value = responce.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_request");
+}
+
+#[actix_rt::test]
+async fn subscribe_rejects_other_content_types() {
+    let test_app = helpers::spawn_app().await;
+
+    let responce = reqwest::Client::new()
+        .post(format!("{}/api/v1/subscriptions", test_app.address))
+        .header("Content-Type", "text/plain")
+        .body("name=pog%20dog&email=pogolius%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(responce.status().as_u16(), 415);
+    let problem: serde_json::Value = responce.json().await.unwrap();
+    assert_eq!(problem["code"], "unsupported_media_type");
+}
+
+#[actix_rt::test]
+async fn subscribe_ret_400_for_unknown_lists() {
+    let test_app = helpers::spawn_app().await;
+
+    let body = serde_json::json!({
+        "name": "pog dog",
+        "email": "pogolius@gmail.com",
+        "list_id": uuid::Uuid::new_v4(),
+    });
+    let responce = test_app.post_subscriptions_json("/subscriptions", &body).await;
+
+    assert_eq!(responce.status().as_u16(), 400);
+    let problem: serde_json::Value = responce.json().await.unwrap();
+    assert_eq!(problem["code"], "unknown_list");
+}
+
+#[actix_rt::test]
+async fn subscribe_confirms_in_the_requested_locale() {
+    let test_app = helpers::spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = serde_json::json!({
+        "name": "pog dog",
+        "email": "pogolius@gmail.com",
+        "locale": "de-AT",
+    });
+    let responce = test_app.post_subscriptions_json("/subscriptions", &body).await;
+
+    assert_eq!(responce.status().as_u16(), 200);
+    let saved = sqlx::query!("select locale from subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.locale, "de");
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["Subject"], "Bestätigen Sie Ihr Abonnement");
+    test_app.get_links(email_request);
+}
+
+#[actix_rt::test]
+async fn subscribe_falls_back_to_accept_language_then_the_default_locale() {
+    let test_app = helpers::spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    for (email, accept_language) in [
+        ("first%40gmail.com", "xx, fr-CH;q=0.9, de;q=0.8"),
+        ("second%40gmail.com", "xx"),
+    ] {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", test_app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept-Language", accept_language)
+            .body(format!("name=pog%20dog&email={}", email))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    let saved = sqlx::query!("select email, locale from subscriptions order by email")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved[0].locale, "fr");
+    assert_eq!(saved[1].locale, "en");
 }
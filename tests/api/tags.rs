@@ -0,0 +1,161 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use serde_json::{json, Value};
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+const DEFAULT_LIST: &str = "00000000-0000-0000-0000-000000000001";
+
+async fn spawn_app() -> TestApp {
+    spawn_app_with(|config| config.delivery.workers = 0).await
+}
+
+async fn post_admin(app: &TestApp, path: &str, body: &Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}{}", app.address, path))
+        .json(body)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Emails of the subscribers of the default list with `tag`, and their tags.
+async fn tagged(app: &TestApp, tag: &str) -> Vec<(String, Value)> {
+    let subscribers: Value = reqwest::Client::new()
+        .get(format!("{}/admin/lists/{}/subscribers", app.address, DEFAULT_LIST))
+        .query(&[("tag", tag)])
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    subscribers
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| (s["email"].as_str().unwrap().to_string(), s["tags"].clone()))
+        .collect()
+}
+
+async fn confirmed_sub(app: &TestApp, email: &str, tags: Value) {
+    let _mg = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = json!({"name": "pog dog", "email": email, "tags": tags});
+    app.post_subscriptions_json("/subscriptions", &body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    reqwest::get(app.get_links(&request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[actix_rt::test]
+async fn forms_can_tag_subscribers_through_a_hidden_field() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=pog%20dog&email=pogolius%40gmail.com&tags=Conference-2026%2Cbeta-tester";
+    app.post_subsciptions(body.to_string())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    assert_eq!(
+        tagged(&app, "conference-2026").await,
+        vec![(
+            "pogolius@gmail.com".to_string(),
+            json!(["beta-tester", "conference-2026"])
+        )]
+    );
+}
+
+#[actix_rt::test]
+async fn invalid_tags_are_rejected() {
+    let app = spawn_app().await;
+
+    let body = json!({"name": "pog dog", "email": "pogolius@gmail.com", "tags": ["beta tester"]});
+    let response = app.post_subscriptions_json("/subscriptions", &body).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: Value = response.json().await.unwrap();
+    assert_eq!(problem["errors"][0]["field"], "tags");
+    assert_eq!(problem["errors"][0]["code"], "invalid_tag");
+}
+
+#[actix_rt::test]
+async fn subscribers_can_be_tagged_in_bulk() {
+    let app = spawn_app().await;
+    confirmed_sub(&app, "one@example.com", json!(["old"])).await;
+    confirmed_sub(&app, "two@example.com", json!(["old"])).await;
+    confirmed_sub(&app, "three@example.com", json!([])).await;
+    let path = format!("/admin/lists/{}/subscribers/tags", DEFAULT_LIST);
+
+    let update = json!({
+        "subscribers": ["ONE@example.com", "two@example.com", "nobody@example.com"],
+        "add": ["beta-tester"],
+        "remove": ["old"],
+    });
+    let response = post_admin(&app, &path, &update).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.json::<Value>().await.unwrap(), json!({"matched": 2}));
+    let beta: Vec<_> = tagged(&app, "beta-tester").await.into_iter().map(|(e, _)| e).collect();
+    assert_eq!(beta, vec!["one@example.com", "two@example.com"]);
+    assert!(tagged(&app, "old").await.is_empty());
+
+    let update = json!({"subscribers": ["one@example.com"], "add": ["no spaces"]});
+    let response = post_admin(&app, &path, &update).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_rt::test]
+async fn issues_can_go_to_tagged_subscribers_only() {
+    let app = spawn_app().await;
+    confirmed_sub(&app, "beta@example.com", json!(["beta-tester", "staff"])).await;
+    confirmed_sub(&app, "staff@example.com", json!(["staff"])).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue = json!({
+        "title": "Beta news",
+        "content": {"text": "Text", "html": "<p>Text</p>"},
+        "recipient_tags": ["Beta-Tester", "staff"],
+    });
+    post_admin(&app, "/newsletters", &issue).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let email: Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(email["To"], "beta@example.com");
+}
+
+#[actix_rt::test]
+async fn segments_can_pick_tagged_subscribers() {
+    let app = spawn_app().await;
+    confirmed_sub(&app, "beta@example.com", json!(["beta-tester"])).await;
+    confirmed_sub(&app, "other@example.com", json!([])).await;
+
+    let path = format!("/admin/lists/{}/segments/preview", DEFAULT_LIST);
+    let preview: Value = post_admin(&app, &path, &json!({"rules": {"tag": "Beta-Tester"}}))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(preview["count"], 1);
+    assert_eq!(preview["sample"][0]["email"], "beta@example.com");
+}
@@ -0,0 +1,234 @@
+use crate::helpers::{spawn_app, TestApp};
+use emailer::authentication::{TotpSecret, TWO_FACTOR_HEADER};
+
+struct Enrolment {
+    secret: TotpSecret,
+    step: i64,
+    recovery_codes: Vec<String>,
+}
+
+fn current_step() -> i64 {
+    chrono::Utc::now().timestamp().div_euclid(30)
+}
+
+async fn post_as(app: &TestApp, path: &str, code: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .post(format!("{}{}", &app.address, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password));
+    if let Some(code) = code {
+        request = request.header(TWO_FACTOR_HEADER, code);
+    }
+    request.send().await.expect("Failed to execute request")
+}
+
+fn newsletter_request(app: &TestApp) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter titile",
+            "content": {
+                "text": "Text body",
+                "html": "<p>Html body</p>",
+            }
+        }))
+}
+
+async fn publish_newsletter(app: &TestApp, code: Option<&str>) -> reqwest::Response {
+    let mut request = newsletter_request(app)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password));
+    if let Some(code) = code {
+        request = request.header(TWO_FACTOR_HEADER, code);
+    }
+    request.send().await.expect("Failed to execute request")
+}
+
+async fn enrol(app: &TestApp) -> Enrolment {
+    let response = post_as(app, "/admin/two_factor/enrol", None).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let secret = TotpSecret::parse(body["secret"].as_str().unwrap()).unwrap();
+    assert!(body["otpauth_uri"]
+        .as_str()
+        .unwrap()
+        .starts_with("otpauth://totp/emailer%3A"));
+
+    let step = current_step();
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/two_factor/confirm", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "code": secret.code_at(step) }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let recovery_codes = body["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c.as_str().unwrap().to_string())
+        .collect();
+    Enrolment {
+        secret,
+        step,
+        recovery_codes,
+    }
+}
+
+#[actix_rt::test]
+async fn enrolled_users_need_a_code_to_log_in() {
+    let app = spawn_app().await;
+    let enrolment = enrol(&app).await;
+    assert_eq!(enrolment.recovery_codes.len(), 10);
+
+    let response = publish_newsletter(&app, None).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "second_factor_required");
+
+    let next_code = enrolment.secret.code_at(enrolment.step + 1);
+    let response = publish_newsletter(&app, Some(&next_code)).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn a_session_opened_with_a_code_serves_many_requests() {
+    let app = spawn_app().await;
+    let enrolment = enrol(&app).await;
+
+    // The code of the enrolment was used up, the next one is already valid.
+    let next_code = enrolment.secret.code_at(enrolment.step + 1);
+    let response = post_as(&app, "/admin/sessions", Some(&next_code)).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let session: serde_json::Value = response.json().await.unwrap();
+    let token = session["token"].as_str().unwrap();
+
+    for _ in 0..3 {
+        let response = newsletter_request(&app)
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = post_as(&app, "/admin/sessions", Some(&next_code)).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let login = sqlx::query!("select payload from audit_events where action = 'login.succeeded'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(login.payload["second_factor"], true);
+}
+
+#[actix_rt::test]
+async fn reused_totp_codes_are_rejected_without_counting_towards_a_lockout() {
+    let app = spawn_app().await;
+    let enrolment = enrol(&app).await;
+
+    let confirmation_code = enrolment.secret.code_at(enrolment.step);
+    for _ in 0..10 {
+        let response = publish_newsletter(&app, Some(&confirmation_code)).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    let reasons: Vec<String> = sqlx::query!("select distinct reason from login_failures")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.reason)
+        .collect();
+    assert_eq!(reasons, vec!["replayed_second_factor"]);
+    let next_code = enrolment.secret.code_at(enrolment.step + 1);
+    let response = publish_newsletter(&app, Some(&next_code)).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn wrong_totp_codes_count_as_failures() {
+    let app = spawn_app().await;
+    enrol(&app).await;
+
+    let response = publish_newsletter(&app, Some("000000x")).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let failures = sqlx::query!(
+        "select count(*) as \"count!\" from login_failures where reason = 'invalid_second_factor'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(failures, 1);
+}
+
+#[actix_rt::test]
+async fn recovery_codes_are_single_use() {
+    let app = spawn_app().await;
+    let enrolment = enrol(&app).await;
+    let recovery_code = &enrolment.recovery_codes[0];
+
+    let response = publish_newsletter(&app, Some(recovery_code)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = publish_newsletter(&app, Some(recovery_code)).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[actix_rt::test]
+async fn wrong_confirmation_code_does_not_enable_two_factor() {
+    let app = spawn_app().await;
+    let response = post_as(&app, "/admin/two_factor/enrol", None).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/two_factor/confirm", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "code": "000000x" }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = publish_newsletter(&app, None).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_rt::test]
+async fn required_two_factor_blocks_users_without_it() {
+    let app = spawn_app().await;
+    let response = reqwest::Client::new()
+        .put(format!("{}/admin/settings/two_factor", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "required": true }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = publish_newsletter(&app, None).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let enrolment = enrol(&app).await;
+    let next_code = enrolment.secret.code_at(enrolment.step + 1);
+    let response = publish_newsletter(&app, Some(&next_code)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let recovery_code = &enrolment.recovery_codes[0];
+    let response = post_as(&app, "/admin/two_factor/disable", Some(recovery_code)).await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[actix_rt::test]
+async fn enrolment_cannot_replace_an_active_secret() {
+    let app = spawn_app().await;
+    let enrolment = enrol(&app).await;
+
+    let response = post_as(&app, "/admin/two_factor/enrol", None).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let recovery_code = &enrolment.recovery_codes[0];
+    let response = post_as(&app, "/admin/two_factor/enrol", Some(recovery_code)).await;
+    assert_eq!(response.status().as_u16(), 409);
+}